@@ -1,16 +1,25 @@
-use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex, MutexGuard}};
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex, RwLock}};
 
 use colored::Colorize;
-use rayon::result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
 
 use crate::{
-    dtype::Dtype, fn_edge::{DummyFnEdge, FnEdge, FnEdgeID}, logger::LOGGER, nten::{Nten, NtenID}, tensor::{Tensor, Tensor2d}
+    dtype::{BinaryOp, Dtype, Shape}, graph_opt, scheduler, state_dict, fn_edge::{grad_graph, DummyFnEdge, FnEdge, FnEdgeID, HumanCreatedFnEdge}, logger::LOGGER, nten::{Nten, NtenID}, tensor::{Storage, Tensor, Tensor2d}
 };
 
 #[derive(Clone)]
 pub struct VarStore {
     pub(crate) body: Arc<Mutex<HashMap<NtenID, Nten>>>,
     pub parameter_ids: Arc<Mutex<HashSet<NtenID>>>,
+    // BatchNormのrunning statsのような学習しないが保存する値。zero_gradで消えず，optimizerは更新しない
+    pub buffer_ids: Arc<Mutex<HashSet<NtenID>>>,
+    // parameterとbufferの名前 -> id。idは作った順番で変わるので，state_dictは名前で読み書きする
+    keys: Arc<Mutex<HashMap<String, NtenID>>>,
+    // 行ごとの勾配（Embedding）。denseのgradがあるntenには持たない
+    sparse_grads: Arc<Mutex<HashMap<NtenID, SparseGrad>>>,
+    // sub()で下げた名前空間。ここで登録した名前の前に付く
+    prefix: String,
     lending: HashSet<NtenID>,
 }
 impl VarStore {
@@ -18,10 +27,23 @@ impl VarStore {
         Self {
             body: Arc::new(Mutex::new(HashMap::new())),
             parameter_ids: Arc::new(Mutex::new(HashSet::new())),
+            buffer_ids: Arc::new(Mutex::new(HashSet::new())),
+            keys: Arc::new(Mutex::new(HashMap::new())),
+            sparse_grads: Arc::new(Mutex::new(HashMap::new())),
+            prefix: String::new(),
             lending: HashSet::new(),
         }
     }
 
+    // 名前空間を1段下げたVarStore。中身は共有する
+    // ex) let encoder = Encoder::new(&mut vs.sub("encoder")); -> "encoder/Linear weight"
+    pub fn sub(&self, name: &str) -> Self {
+        let mut vs = self.clone();
+        vs.prefix = format!("{}{}/", self.prefix, name);
+        vs.lending = HashSet::new();
+        vs
+    }
+
     fn remove_nten(&mut self, id: &NtenID) -> Option<Nten> {
         // 返し忘れるとnot foundのリスク
         if let Some(nten) = self.body.lock().unwrap().remove(id) {
@@ -37,9 +59,140 @@ impl VarStore {
         self.body.lock().unwrap().insert(nten.id, nten);
     }
 
-    pub fn resister_parameter(&mut self, nten: Nten) {
+    pub fn resister_parameter(&mut self, nten: Nten) -> Result<(), String> {
+        self._resister_key(&nten)?;
         self.parameter_ids.lock().unwrap().insert(nten.id);
         self.body.lock().unwrap().insert(nten.id, nten);
+        Ok(())
+    }
+    pub fn resister_buffer(&mut self, nten: Nten) -> Result<(), String> {
+        self._resister_key(&nten)?;
+        self.buffer_ids.lock().unwrap().insert(nten.id);
+        self.body.lock().unwrap().insert(nten.id, nten);
+        Ok(())
+    }
+
+    // 名前はprefix + ntenのname。idや作った順番によらないので，state_dictは別のスレッドや順番で作ったモデルにも読める
+    // 同じ名前はErr。同じモジュールを複数持つときはsub()で名前空間を分ける
+    // 名前のない（"no_name"）ntenはkeyを持たず，state_dictに入らない
+    fn _resister_key(&self, nten: &Nten) -> Result<(), String> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.values().any(|id| *id == nten.id) {
+            return Ok(());
+        }
+        if nten.name == "no_name" {
+            LOGGER.warning(format!("VarStore::resister() >> nten id: {} has no name. it is not saved in state_dict. \
+                give it a name with .name()", nten.id));
+            return Ok(());
+        }
+        let key = format!("{}{}", self.prefix, nten.name);
+        if let Some(id) = keys.get(&key) {
+            return Err(format!("VarStore::resister() >> name '{}' of nten id: {} is already used by nten id: {}. \
+                give each module its own scope with vs.sub(), or give the nten another name", key, nten.id, id));
+        }
+        keys.insert(key, nten.id);
+        Ok(())
+    }
+
+    // このVarStoreの名前空間にあるparameterとbufferの名前（prefixは除く） -> id
+    fn _scoped_keys(&self) -> Vec<(String, NtenID)> {
+        let mut keys: Vec<(String, NtenID)> = self.keys.lock().unwrap().iter()
+            .filter_map(|(key, id)| key.strip_prefix(&self.prefix).map(|key| (key.to_string(), *id)))
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        keys
+    }
+
+    pub(crate) fn parameter_keys(&self) -> Vec<(String, NtenID)> {
+        let parameter_ids = self.parameter_ids.lock().unwrap().clone();
+        self._scoped_keys().into_iter().filter(|(_, id)| parameter_ids.contains(id)).collect()
+    }
+    pub(crate) fn buffer_keys(&self) -> Vec<(String, NtenID)> {
+        let buffer_ids = self.buffer_ids.lock().unwrap().clone();
+        self._scoped_keys().into_iter().filter(|(_, id)| buffer_ids.contains(id)).collect()
+    }
+
+    pub fn key_of(&self, id: &NtenID) -> Option<String> {
+        self._scoped_keys().into_iter().find(|(_, key_id)| key_id == id).map(|(key, _)| key)
+    }
+
+    // parameterとbufferの値のコピー。sub()したVarStoreではその名前空間の中だけ
+    pub fn state_dict(&self) -> HashMap<String, Tensor> {
+        let body = self.body.lock().unwrap();
+        self._scoped_keys().into_iter().filter_map(|(key, id)| {
+            let val = body.get(&id)?.val.as_ref()?;
+            let copied = Tensor { name: val.name.clone(), shape: val.shape, storage: Arc::new(RwLock::new(val.storage().clone())) };
+            Some((key, copied))
+        }).collect()
+    }
+
+    // 値はArcの中を書き換えるので，モデル側のntenにも反映される
+    pub fn load_state_dict(&mut self, state: &HashMap<String, Tensor>) {
+        let keys = self._scoped_keys();
+        let body = self.body.lock().unwrap();
+        for (key, id) in keys.iter() {
+            let Some(tensor) = state.get(key) else {
+                LOGGER.error(format!("VarStore::load_state_dict() >> '{}{}' is not found in state dict", self.prefix, key));
+                panic!("")
+            };
+            let Some(val) = body.get(id).and_then(|nten| nten.val.as_ref()) else {
+                LOGGER.error(format!("VarStore::load_state_dict() >> val of '{}{}' nten id: {} is not found in VarStore", self.prefix, key, id));
+                panic!("")
+            };
+            if val.shape != tensor.shape {
+                LOGGER.error(format!("VarStore::load_state_dict() >> '{}{}' expected {}, found {} in state dict", self.prefix, key, val.shape, tensor.shape));
+                panic!("")
+            }
+            val.override_value(tensor.clone());
+        }
+        for key in state.keys() {
+            if !keys.iter().any(|(k, _)| k == key) {
+                LOGGER.warning(format!("VarStore::load_state_dict() >> '{}' in state dict is not used", key));
+            }
+        }
+    }
+
+    // data parallelのreplica用。同じ名前のparameterの値をsourceとArcごと共有する
+    // sourceの値をoptimizerが書き換えると，こちらの値も変わる
+    pub(crate) fn share_parameters_from(&mut self, source: &VarStore) {
+        for (key, id, val) in self._matched_vals(source, &self.parameter_keys(), &source.parameter_keys()) {
+            let mut body = self.body.lock().unwrap();
+            let nten = body.get_mut(&id).unwrap();
+            if nten.shape != val.shape {
+                LOGGER.error(format!("VarStore::share_parameters_from() >> '{}' expected {}, found {}", key, nten.shape, val.shape));
+                panic!("")
+            }
+            nten.val = Some(val);
+        }
+    }
+    // bufferは共有せず値をコピーする
+    pub(crate) fn copy_buffers_from(&mut self, source: &VarStore) {
+        for (_, id, val) in self._matched_vals(source, &self.buffer_keys(), &source.buffer_keys()) {
+            let Some(own) = self.body.lock().unwrap().get(&id).and_then(|nten| nten.val.clone()) else { continue };
+            own.override_value(val);
+        }
+    }
+    // 名前が同じntenの(名前, こちらのid, sourceの値)。名前がそろっていなければエラー
+    fn _matched_vals(&self, source: &VarStore, keys: &[(String, NtenID)], source_keys: &[(String, NtenID)]) -> Vec<(String, NtenID, Tensor)> {
+        if keys.len() != source_keys.len() || keys.iter().zip(source_keys).any(|(a, b)| a.0 != b.0) {
+            LOGGER.error(format!("VarStore::_matched_vals() >> names are unmatched. {:?} and {:?}",
+                keys.iter().map(|(key, _)| key).collect::<Vec<_>>(), source_keys.iter().map(|(key, _)| key).collect::<Vec<_>>()));
+            panic!("")
+        }
+        let source_body = source.body.lock().unwrap();
+        keys.iter().zip(source_keys).filter_map(|((key, id), (_, source_id))| {
+            let val = source_body.get(source_id)?.val.clone()?;
+            Some((key.clone(), *id, val))
+        }).collect()
+    }
+
+    // state_dict.rsの形式で保存，読み込みする
+    pub fn save(&self, path: &str) {
+        state_dict::save(path, &self.state_dict());
+    }
+    pub fn load(&mut self, path: &str) {
+        let state = state_dict::load(path);
+        self.load_state_dict(&state);
     }
     pub fn resister_input(&mut self, nten: Nten) {
         self.body.lock().unwrap().insert(nten.id, nten);
@@ -54,20 +207,76 @@ impl VarStore {
             LOGGER.debug(format!("- {} id:{} name:\"{}\" shape:{}", "Nten".green(), id, nten.name, nten.shape.to_string()));
         }
         LOGGER.debug(format!("paramter ids are: {:?}", self.parameter_ids));
+        LOGGER.debug(format!("buffer ids are: {:?}", self.buffer_ids));
         LOGGER.debug(format!("lending: {:?}", self.lending.iter()))
 
     }
 }
 
+/*
+Embeddingのweightの勾配。触れた行のindexとその行の勾配だけを持ち，V×Dの勾配を作らない
+backwardごとに積んでいき，Sgdはその行だけを更新する
+get_grad()などでdenseの勾配が要るときや，denseの勾配が足されたときはdenseにする
+*/
+#[derive(Clone)]
+pub struct SparseGrad {
+    pub shape: Shape,
+    // (N, 1) u32
+    pub indices: Vec<Tensor>,
+    // (N, D)
+    pub rows: Vec<Tensor>,
+}
+impl SparseGrad {
+    // targetの行にscale倍して足す
+    pub fn scatter_into(&self, target: &mut Storage, scale: f32) {
+        let Shape::D2(_, cols) = self.shape else { unreachable!() };
+        for (indices, rows) in self.indices.iter().zip(self.rows.iter()) {
+            let rows = if scale == 1.0 { rows.clone() } else { rows.mul_scalar(scale) };
+            target.scatter_add_rows(cols, &indices.storage(), &rows.storage());
+        }
+    }
+    pub fn to_dense(&self) -> Tensor {
+        let dense = Tensor::new_zeros::<f32>(self.shape);
+        self.scatter_into(&mut dense.storage.write().unwrap(), 1.0);
+        dense
+    }
+}
+
+// BatchNormやDropoutは学習時と推論時で動作が変わる
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Mode {
+    Train,
+    Inference,
+}
+
 pub struct Context {
     pub varstore: VarStore,
-    temp_tensors: HashMap<NtenID, Tensor>,
+    // fork()したContextと共有する
+    temp_tensors: Arc<Mutex<HashMap<NtenID, Tensor>>>,
+    inserted_tensor_ids: Vec<NtenID>,
+    pub mode: Mode,
+    // Dropoutなどの乱数。Autograd::manual_seed()で再現できる
+    pub(crate) rng: StdRng,
 }
 impl Context {
     pub fn new() -> Self {
         Self {
             varstore: VarStore::new(),
-            temp_tensors: HashMap::new(),
+            temp_tensors: Arc::new(Mutex::new(HashMap::new())),
+            inserted_tensor_ids: Vec::new(),
+            mode: Mode::Train,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    // 並列実行用。varstoreとtemp tensorを共有し，乱数とinsert_tensorの記録は別に持つ
+    pub(crate) fn fork(&self, rng: StdRng) -> Self {
+        Self {
+            varstore: self.varstore.clone(),
+            temp_tensors: self.temp_tensors.clone(),
+            inserted_tensor_ids: Vec::new(),
+            mode: self.mode,
+            rng,
         }
     }
 
@@ -114,6 +323,8 @@ impl Context {
         if let Some(nten) = self.varstore.body.lock().unwrap().get(id) {
             if let Some(grad) = &nten.grad {
                 grad.clone()
+            } else if let Some(sparse) = self.varstore.sparse_grads.lock().unwrap().get(id) {
+                sparse.to_dense()
             } else {
                 panic!("grad of Nten id: {} is None", id);
             }
@@ -122,10 +333,23 @@ impl Context {
         }
     }
 
+    // 多出力のFnEdgeで使われなかった出力などgradがないときはNone
+    pub fn try_get_grad(&self, id: &NtenID) -> Option<Tensor> {
+        let body = self.varstore.body.lock().unwrap();
+        body.get(id).and_then(|nten| nten.grad.clone())
+            .or_else(|| self.varstore.sparse_grads.lock().unwrap().get(id).map(|sparse| sparse.to_dense()))
+    }
+    // add_assign_grad_rows()だけで勾配が足されたときはSome。optimizerが触れた行だけを更新するのに使う
+    pub fn try_get_sparse_grad(&self, id: &NtenID) -> Option<SparseGrad> {
+        self.varstore.sparse_grads.lock().unwrap().get(id).cloned()
+    }
+
     pub fn get_grad_as_2d<const R: usize, const C: usize, T: Dtype>(&self, id: &NtenID) -> Tensor2d<R, C, T> {
         if let Some(nten) = self.varstore.body.lock().unwrap().get(id) {
             if let Some(grad) = &nten.grad {
                 grad.clone().to_typed2d().unwrap()
+            } else if let Some(sparse) = self.varstore.sparse_grads.lock().unwrap().get(id) {
+                sparse.to_dense().to_typed2d().unwrap()
             } else {
                 panic!("grad of Nten id: {} is None", id);
             }
@@ -152,24 +376,62 @@ impl Context {
         }
     }
 
+    // 並列実行で同じntenに同時に足されることがあるので，lockしたまま書き換える
     pub fn add_assign_grad(&mut self, id: &NtenID, new_grad: &Tensor) {
-        if let Some(mut nten) = self.varstore.remove_nten(id) {
+        let mut body = self.varstore.body.lock().unwrap();
+        if let Some(nten) = body.get_mut(id) {
+            // 行ごとの勾配があればdenseにしてから足す
+            if let Some(sparse) = self.varstore.sparse_grads.lock().unwrap().remove(id) {
+                nten.grad = Some(sparse.to_dense());
+            }
             if let Some(old_grad) = &nten.grad {
                 nten.grad = Some(old_grad.add(&new_grad).unwrap());
             } else {
                 nten.grad = Some(new_grad.clone());
             }
-            self.varstore.return_nten(nten);
+        } else {
+            panic!("Nten id: {} not found in Context", id)
+        }
+    }
+
+    // Embeddingのbackward用。denseのgradがなければSparseGradに積むだけで，V×Dの勾配を作らない
+    pub fn add_assign_grad_rows(&mut self, id: &NtenID, shape: Shape, indices: &Tensor, rows_grad: &Tensor) {
+        let cols = match shape {
+            Shape::D2(_, cols) => cols,
+            _ => {
+                LOGGER.error(format!("Context::add_assign_grad_rows() >> expected Shape::D2, found {}", shape));
+                panic!("")
+            }
+        };
+        let mut body = self.varstore.body.lock().unwrap();
+        if let Some(nten) = body.get_mut(id) {
+            match nten.grad.take() {
+                Some(grad) => {
+                    // 他のTensorとArcを共有していたら書き換えないようにコピーする
+                    let grad = if Arc::strong_count(&grad.storage) == 1 { grad } else {
+                        Tensor { name: grad.name.clone(), shape: grad.shape, storage: Arc::new(RwLock::new(grad.storage().clone())) }
+                    };
+                    grad.storage.write().unwrap().scatter_add_rows(cols, &indices.storage(), &rows_grad.storage());
+                    nten.grad = Some(grad);
+                },
+                None => {
+                    let mut sparse_grads = self.varstore.sparse_grads.lock().unwrap();
+                    let sparse = sparse_grads.entry(*id).or_insert_with(|| SparseGrad { shape, indices: Vec::new(), rows: Vec::new() });
+                    sparse.indices.push(indices.clone());
+                    sparse.rows.push(rows_grad.clone());
+                },
+            }
         } else {
             panic!("Nten id: {} not found in Context", id)
         }
     }
 
     pub fn insert_tensor(&mut self, id: &NtenID, tensor: Tensor) {
-        self.temp_tensors.insert(*id, tensor);
+        self.inserted_tensor_ids.push(*id);
+        self.temp_tensors.lock().unwrap().insert(*id, tensor);
     }
     pub fn get_tensor(&mut self, id: &NtenID) -> Tensor {
-        if let Some(tensor) = self.temp_tensors.get(id) {
+        if let Some(tensor) = self.temp_tensors.lock().unwrap().get(id) {
             // TensorはstoreageがArcでほかがnameとshapeなのでクローンしてよい。
             tensor.clone()
         } else {
@@ -177,21 +439,100 @@ impl Context {
         }
     }
     pub fn get_tensor_as_2d<const R: usize, const C: usize, T: Dtype>(&mut self, id: &NtenID) -> Tensor2d<R, C, T> {
-        if let Some(tensor) = self.temp_tensors.get(id) {
+        if let Some(tensor) = self.temp_tensors.lock().unwrap().get(id) {
             // TensorはstoreageがArcでほかがnameとshapeなのでクローンしてよい。
             tensor.clone().to_typed2d().unwrap()
         } else {
             panic!("temp tensor of Nten id: {} not found in Context", id);
         }
     }
+
+    // checkpointで区間内のtemp tensorを捨てるときに使う
+    // temp tensorはfork()したContextと共有しているので，このContextでinsertしたものだけを見る
+    pub(crate) fn inserted_tensor_mark(&self) -> usize {
+        self.inserted_tensor_ids.len()
+    }
+    pub(crate) fn inserted_tensor_ids_since(&self, mark: usize) -> Vec<NtenID> {
+        self.inserted_tensor_ids[mark..].to_vec()
+    }
+    pub fn remove_tensor(&mut self, id: &NtenID) {
+        self.temp_tensors.lock().unwrap().remove(id);
+    }
+    // valとgradをまとめて捨てる
+    pub fn remove_nten(&mut self, id: &NtenID) {
+        self.varstore.body.lock().unwrap().remove(id);
+        self.varstore.sparse_grads.lock().unwrap().remove(id);
+    }
+    // memory plannerで使う。valとgradのstorageはbuffer poolに返す
+    pub fn release_nten(&mut self, id: &NtenID) {
+        if let Some(nten) = self.varstore.body.lock().unwrap().remove(id) {
+            if let Some(val) = nten.val {
+                val.recycle();
+            }
+            if let Some(grad) = nten.grad {
+                grad.recycle();
+            }
+        }
+    }
+    // valだけ捨ててgradを足せるようにntenは残す
+    pub fn release_val(&mut self, id: &NtenID) {
+        let val = self.varstore.body.lock().unwrap().get_mut(id).and_then(|nten| nten.val.take());
+        if let Some(val) = val {
+            val.recycle();
+        }
+    }
+    pub fn release_tensor(&mut self, id: &NtenID) {
+        let tensor = self.temp_tensors.lock().unwrap().remove(id);
+        if let Some(tensor) = tensor {
+            tensor.recycle();
+        }
+    }
+    // 直前のdrainから後にinsert_tensorされたid。どのFnEdgeのtemp tensorかを記録するのに使う
+    pub(crate) fn drain_inserted_tensor_ids(&mut self) -> Vec<NtenID> {
+        std::mem::take(&mut self.inserted_tensor_ids)
+    }
+
+    // 足すのではなく置き換える。grad hookで使う
+    pub fn replace_grad(&mut self, id: &NtenID, grad: Tensor) {
+        if let Some(nten) = self.varstore.body.lock().unwrap().get_mut(id) {
+            self.varstore.sparse_grads.lock().unwrap().remove(id);
+            nten.grad = Some(grad);
+        } else {
+            panic!("Nten id: {} not found in Context", id)
+        }
+    }
 }
 
+/*
+hook。Autograd::register_*_hook()で登録し，返ってきたHookHandleで外す
+grad hook: ntenのgradが確定したとき（creatorのbackwardの直前，葉はbackwardの最後）に呼ばれる
+    Someを返すとgradをそれで置き換える。Noneなら見るだけ
+forward hook: FnEdgeのforwardの直後に出力の値を渡して呼ばれる
+*/
+// Send: Autogradごと別のスレッドに渡せるように（data_parallel.rs）
+pub type GradHook = Box<dyn FnMut(&Tensor) -> Option<Tensor> + Send>;
+pub type ForwardHook = Box<dyn FnMut(&dyn FnEdge, &[Tensor]) + Send>;
+
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub struct HookHandle(u32);
+
 pub struct Autograd {
     already_executed: HashSet<FnEdgeID>,
     next_execute_index: usize,
     tape: Vec<Box<dyn FnEdge>>,
 
     ctx: Context,
+
+    grad_hooks: HashMap<NtenID, Vec<(HookHandle, GradHook)>>,
+    forward_hooks: HashMap<FnEdgeID, Vec<(HookHandle, ForwardHook)>>,
+    next_hook_handle: u32,
+
+    memory_planner: bool,
+    // FnEdgeがforwardで作ったtemp tensor。backwardの後に捨てる
+    edge_temp_tensors: HashMap<FnEdgeID, Vec<NtenID>>,
+
+    graph_optimization: bool,
+    parallel: bool,
 }
 impl Autograd {
     pub fn new() -> Self {
@@ -201,11 +542,93 @@ impl Autograd {
             tape: Vec::new(),
 
             ctx: Context::new(),
+
+            grad_hooks: HashMap::new(),
+            forward_hooks: HashMap::new(),
+            next_hook_handle: 0,
+
+            memory_planner: false,
+            edge_temp_tensors: HashMap::new(),
+
+            graph_optimization: false,
+            parallel: false,
         }
     }
     pub fn get_vs(&mut self) -> VarStore {
         self.ctx.varstore.clone()
     }
+    pub fn train(&mut self) {
+        self.ctx.mode = Mode::Train;
+    }
+    pub fn eval(&mut self) {
+        self.ctx.mode = Mode::Inference;
+    }
+    pub fn mode(&self) -> Mode {
+        self.ctx.mode
+    }
+    pub fn manual_seed(&mut self, seed: u64) {
+        self.ctx.rng = StdRng::seed_from_u64(seed);
+    }
+    // backwardの外でContextを触るとき（data_parallel.rsの勾配の平均など）
+    pub(crate) fn context_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+
+    /*
+    memory planner。テープの生存区間を見て途中の値をもう使われなくなった時点で捨て，storageをbuffer poolに返す
+    Train: backwardで読まない途中の値（Addの入力など）は最後にforwardで使われた直後に値だけ捨てる
+        それ以外の途中の値とtemp tensorはそのcreatorのbackwardの直後に捨てる（そのあとのbackwardでは使われない）
+    Inference: backwardしないので，最後にforwardで使われた直後に捨てる。step_forwardの結果は残る
+    捨てた値はctxから消えるので，backward後やstep_forwardの結果以外をget_valすることはできなくなる
+    途中の値を読むcreate_graph（grad_graph）とは一緒に使えない
+    */
+    pub fn set_memory_planner(&mut self, enable: bool) {
+        self.memory_planner = enable;
+    }
+
+    /*
+    テープを実行する前にCSE, op fusion, DCEをする（graph_opt.rs）
+    まとめられた途中のntenの値はctxに作られないので，step_forwardの結果以外をget_valすることはできなくなる
+    */
+    pub fn set_graph_optimization(&mut self, enable: bool) {
+        self.graph_optimization = enable;
+    }
+
+    /*
+    テープの中で互いに依存しないFnEdge（siamese netの2つの枝など）をrayonで同時に実行する（scheduler.rs）
+    forwardのDropoutの乱数はFnEdgeごとに分けるので，同じseedでも逐次実行とは違うmaskになる（並列実行どうしでは再現する）
+    同じntenへの勾配を足す順番は実行ごとに変わるので，結果は丸め誤差の範囲で変わることがある
+    hookは並列に実行されたwaveの前後でまとめて呼ばれる
+    */
+    pub fn set_parallel(&mut self, enable: bool) {
+        self.parallel = enable;
+    }
+
+    // zero_gradでは消えない。parameterのidに登録すると毎iteration呼ばれる
+    pub fn register_grad_hook(&mut self, id: NtenID, hook: impl FnMut(&Tensor) -> Option<Tensor> + Send + 'static) -> HookHandle {
+        let handle = self._new_hook_handle();
+        self.grad_hooks.entry(id).or_default().push((handle, Box::new(hook)));
+        handle
+    }
+    pub fn register_forward_hook(&mut self, id: FnEdgeID, hook: impl FnMut(&dyn FnEdge, &[Tensor]) + Send + 'static) -> HookHandle {
+        let handle = self._new_hook_handle();
+        self.forward_hooks.entry(id).or_default().push((handle, Box::new(hook)));
+        handle
+    }
+    pub fn remove_hook(&mut self, handle: HookHandle) {
+        for hooks in self.grad_hooks.values_mut() {
+            hooks.retain(|(h, _)| *h != handle);
+        }
+        for hooks in self.forward_hooks.values_mut() {
+            hooks.retain(|(h, _)| *h != handle);
+        }
+        self.grad_hooks.retain(|_, hooks| !hooks.is_empty());
+        self.forward_hooks.retain(|_, hooks| !hooks.is_empty());
+    }
+    fn _new_hook_handle(&mut self) -> HookHandle {
+        self.next_hook_handle += 1;
+        HookHandle(self.next_hook_handle)
+    }
     // return value
     pub fn step_forward<const N: usize>(&mut self, mut results: [Nten; N]) -> [Nten; N] {
 
@@ -214,11 +637,58 @@ impl Autograd {
 
         // グラフ探索してテープを構築，self.tapeに追加される
         self._build_tape(&results);
+        if self.graph_optimization {
+            self._optimize_tape(&results);
+        }
+        // 逐次実行ではFnEdge1つずつのwave
+        let waves = if self.parallel {
+            let buffer_ids = self.ctx.varstore.buffer_ids.lock().unwrap().clone();
+            scheduler::forward_waves(&self.tape, self.next_execute_index, &buffer_ids)
+        } else {
+            (self.next_execute_index..self.tape.len()).map(|i| vec![i]).collect()
+        };
+        let release_in_forward = self.memory_planner && self.ctx.mode == Mode::Inference;
+        let release_after = if self.memory_planner {
+            let mut release_after = self._last_forward_use(&results, &waves);
+            // Trainではbackwardで読む値は残す
+            if !release_in_forward {
+                let saved: HashSet<NtenID> = self.tape.iter().flat_map(|fn_edge| fn_edge.saved_for_backward()).collect();
+                for ids in release_after.values_mut() {
+                    ids.retain(|id| !saved.contains(id));
+                }
+            }
+            release_after
+        } else {
+            HashMap::new()
+        };
+        self.ctx.drain_inserted_tensor_ids();
         // 実行
-        for i in self.next_execute_index..self.tape.len() {
-            LOGGER.debug(format!("{}: execute {} of FnEdge id: {}, name: {}", "Autograd".cyan(), "forward".blue(), self.tape[i].get_id(), self.tape[i].name()));
-            self.tape[i].forward(&mut self.ctx);
-            self.already_executed.insert(self.tape[i].get_id());
+        for (step, wave) in waves.iter().enumerate() {
+            let temp_ids = self._forward_wave(wave);
+            for (&i, temp_ids) in wave.iter().zip(temp_ids) {
+                self.already_executed.insert(self.tape[i].get_id());
+                if let Some(hooks) = self.forward_hooks.get_mut(&self.tape[i].get_id()) {
+                    let outputs: Vec<Tensor> = self.tape[i].outputs().iter().map(|id| self.ctx.get_val(id)).collect();
+                    for (_, hook) in hooks.iter_mut() {
+                        hook(self.tape[i].as_ref(), &outputs);
+                    }
+                }
+                if release_in_forward {
+                    for id in temp_ids.iter() {
+                        self._release(id);
+                    }
+                } else if self.memory_planner {
+                    self.edge_temp_tensors.insert(self.tape[i].get_id(), temp_ids);
+                }
+            }
+            for id in release_after.get(&step).into_iter().flatten() {
+                if release_in_forward {
+                    self._release(id);
+                } else {
+                    // backwardでgradを足すのでntenは残す
+                    self._release_val(id);
+                }
+            }
         }
         // 次回のために開始位置をずらす
         self.next_execute_index = self.tape.len();
@@ -232,47 +702,319 @@ impl Autograd {
         results
     }
 
+    /*
+    resultがgradを持っていればそこから始める（loss_fn::softmax_cross_entropy_f32など）
+    持っていなければscalar（要素数1）のlossとみなして1.0で始める
+    */
     pub fn backward<'a>(&'a mut self, result: &Nten) -> &'a mut Context {
-        let result = result.clone();
-        if let None = &result.grad {
-            panic!("cannot start backward. grad of result nten is None");
+        self.backward_roots([result])
+    }
+
+    // vector-Jacobian product。resultの勾配をgradとして始める
+    pub fn backward_with<'a>(&'a mut self, result: &Nten, grad: Tensor) -> &'a mut Context {
+        self._seed_grad(result, grad);
+        self._run_backward()
+    }
+
+    // 複数のrootから一度にbackwardする。共有している枝の勾配は足し合わされる
+    pub fn backward_roots<'a, const N: usize>(&'a mut self, results: [&Nten; N]) -> &'a mut Context {
+        for result in results {
+            let grad = match &result.grad {
+                Some(grad) => grad.clone(),
+                None if result.shape.numel() == 1 => Tensor::new_ones::<f32>(result.shape),
+                None => {
+                    LOGGER.error(format!("Autograd::backward() >> cannot seed nten id: {}, name: '{}' of {}. \
+                        only a scalar result is seeded with 1.0. use backward_with() for non scalar result.", result.id, result.name, result.shape));
+                    panic!("")
+                },
+            };
+            self._seed_grad(result, grad);
         }
-        // loss fn したntenはgradを持っているがctxワールドのntenにはgradがないのでいれる
-        self.ctx.insert_nten(result);
+        self._run_backward()
+    }
 
-        for i in (0..self.tape.len()).rev() {
-            LOGGER.debug(format!("{}: execute {} of FnEdge id: {}, name: {}", "Autograd".cyan(), "backward".purple(), self.tape[i].get_id(), self.tape[i].name()));
-            self.tape[i].backward(&mut self.ctx);
+    fn _seed_grad(&mut self, result: &Nten, grad: Tensor) {
+        if grad.shape != result.shape {
+            LOGGER.error(format!("Autograd::backward() >> grad {} is unmatched with nten id: {}, name: '{}' of {}", grad.shape, result.id, result.name, result.shape));
+            panic!("")
+        }
+        // step_forwardの結果はctxにいるのでそこに足す
+        self.ctx.add_assign_grad(&result.id, &grad);
+    }
+
+    fn _run_backward(&mut self) -> &mut Context {
+        let mut fired = HashSet::new();
+        let waves = if self.parallel {
+            scheduler::backward_waves(&self.tape)
+        } else {
+            (0..self.tape.len()).rev().map(|i| vec![i]).collect()
+        };
+        for wave in waves {
+            // lossにつながっていない枝（使われなかったsplitの出力など）はgradがないので飛ばす
+            let (run, skip): (Vec<usize>, Vec<usize>) = wave.into_iter()
+                .partition(|&i| self.tape[i].outputs().iter().any(|id| self.ctx.try_get_grad(id).is_some()));
+            if self.memory_planner {
+                for &i in skip.iter() {
+                    self._release_edge(i);
+                }
+            }
+            // 出力を使うFnEdgeはすべてbackward済みなので，ここで出力のgradが確定している
+            for &i in run.iter() {
+                for id in self.tape[i].outputs() {
+                    self._fire_grad_hooks(&id, &mut fired);
+                }
+            }
+            self._backward_wave(&run);
+            if self.memory_planner {
+                for &i in run.iter() {
+                    self._release_edge(i);
+                }
+            }
+        }
+        // 葉（parameter, input）
+        let rest: Vec<NtenID> = self.grad_hooks.keys().copied().collect();
+        for id in rest {
+            self._fire_grad_hooks(&id, &mut fired);
         }
         &mut self.ctx
     }
 
+    // waveのFnEdgeをforwardして，それぞれがinsert_tensorしたidを返す
+    fn _forward_wave(&mut self, wave: &[usize]) -> Vec<Vec<NtenID>> {
+        for &i in wave {
+            LOGGER.debug(format!("{}: execute {} of FnEdge id: {}, name: {}", "Autograd".cyan(), "forward".blue(), self.tape[i].get_id(), self.tape[i].name()));
+        }
+        if let [i] = wave {
+            self.tape[*i].forward(&mut self.ctx);
+            return vec![self.ctx.drain_inserted_tensor_ids()];
+        }
+        // 乱数はテープの順に分けるので，スレッドの実行順によらず再現する
+        let rngs: Vec<StdRng> = wave.iter().map(|_| StdRng::seed_from_u64(self.ctx.rng.gen())).collect();
+        let (tape, ctx) = (&self.tape, &self.ctx);
+        wave.par_iter().zip(rngs).map(|(&i, rng)| {
+            let mut forked = ctx.fork(rng);
+            tape[i].forward(&mut forked);
+            forked.drain_inserted_tensor_ids()
+        }).collect()
+    }
+
+    fn _backward_wave(&mut self, wave: &[usize]) {
+        for &i in wave {
+            LOGGER.debug(format!("{}: execute {} of FnEdge id: {}, name: {}", "Autograd".cyan(), "backward".purple(), self.tape[i].get_id(), self.tape[i].name()));
+        }
+        if let [i] = wave {
+            self.tape[*i].backward(&mut self.ctx);
+            return;
+        }
+        let (tape, ctx) = (&self.tape, &self.ctx);
+        wave.par_iter().for_each(|&i| {
+            let mut forked = ctx.fork(ctx.rng.clone());
+            tape[i].backward(&mut forked);
+        });
+    }
+
+    fn _optimize_tape<const N: usize>(&mut self, results: &[Nten; N]) {
+        let mut keep_ids: HashSet<NtenID> = results.iter().map(|result| result.id).collect();
+        keep_ids.extend(self.grad_hooks.keys());
+        keep_ids.extend(self.ctx.varstore.parameter_ids.lock().unwrap().iter());
+        keep_ids.extend(self.ctx.varstore.buffer_ids.lock().unwrap().iter());
+        let pinned: HashSet<FnEdgeID> = self.forward_hooks.keys().copied().collect();
+
+        let new_edges = self.tape.split_off(self.next_execute_index);
+        let optimized = graph_opt::optimize(new_edges, &keep_ids, &pinned);
+        self.tape.extend(optimized.tape);
+        self.already_executed.extend(optimized.replaced);
+    }
+
+    // i番目のFnEdgeの出力とtemp tensorを捨てる。backwardで使うのはこのFnEdgeが最後
+    fn _release_edge(&mut self, i: usize) {
+        for id in self.tape[i].outputs() {
+            self._release(&id);
+        }
+        if let Some(temp_ids) = self.edge_temp_tensors.remove(&self.tape[i].get_id()) {
+            for id in temp_ids {
+                self.ctx.release_tensor(&id);
+            }
+        }
+    }
+
+    fn _release(&mut self, id: &NtenID) {
+        if self._is_state(id) {
+            return;
+        }
+        self.ctx.release_nten(id);
+    }
+    fn _release_val(&mut self, id: &NtenID) {
+        if self._is_state(id) {
+            return;
+        }
+        self.ctx.release_val(id);
+    }
+    // parameterとbufferは捨てない
+    fn _is_state(&self, id: &NtenID) -> bool {
+        let is_parameter = self.ctx.varstore.parameter_ids.lock().unwrap().contains(id);
+        let is_buffer = self.ctx.varstore.buffer_ids.lock().unwrap().contains(id);
+        is_parameter || is_buffer
+    }
+
+    // 今回実行するFnEdgeの出力が最後に使われるwave -> そのwaveの後で捨てるid。resultは捨てない
+    fn _last_forward_use<const N: usize>(&self, results: &[Nten; N], waves: &[Vec<usize>]) -> HashMap<usize, Vec<NtenID>> {
+        let produced: HashSet<NtenID> = waves.iter().flatten().flat_map(|&i| self.tape[i].outputs()).collect();
+        let mut last_use: HashMap<NtenID, usize> = HashMap::new();
+        for (step, wave) in waves.iter().enumerate() {
+            for &i in wave {
+                for id in self.tape[i].inputs().into_iter().chain(self.tape[i].outputs()) {
+                    if produced.contains(&id) {
+                        last_use.insert(id, step);
+                    }
+                }
+            }
+        }
+        for result in results.iter() {
+            last_use.remove(&result.id);
+        }
+        let mut release_after: HashMap<usize, Vec<NtenID>> = HashMap::new();
+        for (id, i) in last_use {
+            release_after.entry(i).or_default().push(id);
+        }
+        release_after
+    }
+
+    fn _fire_grad_hooks(&mut self, id: &NtenID, fired: &mut HashSet<NtenID>) {
+        let Some(hooks) = self.grad_hooks.get_mut(id) else { return };
+        if fired.contains(id) {
+            return;
+        }
+        let Some(mut grad) = self.ctx.try_get_grad(id) else { return };
+        fired.insert(*id);
+        for (_, hook) in hooks.iter_mut() {
+            if let Some(new_grad) = hook(&grad) {
+                if new_grad.shape != grad.shape {
+                    LOGGER.error(format!("Autograd::backward() >> grad hook of nten id: {} returns {}, expected {}", id, new_grad.shape, grad.shape));
+                    panic!("")
+                }
+                grad = new_grad;
+                self.ctx.replace_grad(id, grad.clone());
+            }
+        }
+    }
+
+    /*
+    create_graph。resultのwrtに対する勾配をFnEdgeの計算グラフとして組み立てて返す
+    resultはstep_forward済みであること。返したntenをstep_forwardすると勾配の値になり，
+    それをさらにbackwardすると高階の微分（gradient penaltyなど）が得られる
+    */
+    pub fn grad_graph<const N: usize>(&mut self, result: &Nten, wrt: [NtenID; N]) -> [Nten; N] {
+        let seed = match &result.grad {
+            Some(grad) => grad.clone(),
+            None if result.shape.numel() == 1 => Tensor::new_ones::<f32>(result.shape),
+            None => {
+                LOGGER.error(format!("Autograd::grad_graph() >> cannot seed nten id: {}, name: '{}' of {}. \
+                    only a scalar result is seeded with 1.0. use grad_graph_with() for non scalar result.", result.id, result.name, result.shape));
+                panic!("")
+            },
+        };
+        let seed = grad_graph::constant(&mut self.ctx, seed);
+        self.grad_graph_with(result, &seed, wrt)
+    }
+
+    // resultの勾配をgradのntenとして始める。gradに対しても微分できる（jvpのdouble-vjpなど）
+    pub fn grad_graph_with<const N: usize>(&mut self, result: &Nten, grad: &Nten, wrt: [NtenID; N]) -> [Nten; N] {
+        self.try_grad_graph_with(result, grad, wrt).unwrap_or_else(|e| {
+            LOGGER.error(format!("Autograd::grad_graph() >> {}", e));
+            panic!("")
+        })
+    }
+
+    // backward_graph()に対応していないFnEdgeがあればその名前をErrで返す
+    pub fn try_grad_graph_with<const N: usize>(&mut self, result: &Nten, grad: &Nten, wrt: [NtenID; N]) -> Result<[Nten; N], String> {
+        if grad.shape != result.shape {
+            LOGGER.error(format!("Autograd::grad_graph() >> grad {} is unmatched with nten id: {}, name: '{}' of {}", grad.shape, result.id, result.name, result.shape));
+            panic!("")
+        }
+        let mut creators: HashMap<NtenID, Box<dyn FnEdge>> = HashMap::new();
+        for fn_edge in self.tape.iter() {
+            for id in fn_edge.outputs() {
+                creators.insert(id, fn_edge.clone_box());
+            }
+        }
+        // テープ上のntenを指すハンドル。valはctxにあるのでNone
+        let node = |ctx: &Context, id: NtenID| Nten {
+            id,
+            name: "node of grad_graph".to_string(),
+            creator: creators.get(&id).map(|creator| creator.clone_box()).unwrap_or_else(|| Box::new(HumanCreatedFnEdge::new())),
+            shape: ctx.get_val(&id).shape,
+            val: None,
+            grad: None,
+        };
+
+        let mut grads: HashMap<NtenID, Nten> = HashMap::new();
+        grads.insert(result.id, grad.clone());
+
+        for i in (0..self.tape.len()).rev() {
+            let output_grads: Vec<Option<Nten>> = self.tape[i].outputs().iter().map(|id| grads.get(id).cloned()).collect();
+            if output_grads.iter().all(|grad| grad.is_none()) {
+                continue;
+            }
+            let input_ids = self.tape[i].inputs();
+            let inputs: Vec<Nten> = input_ids.iter().map(|id| node(&self.ctx, *id)).collect();
+            let outputs: Vec<Nten> = self.tape[i].outputs().iter().map(|id| node(&self.ctx, *id)).collect();
+
+            let Some(input_grads) = self.tape[i].backward_graph(&mut self.ctx, &inputs, &outputs, &output_grads) else {
+                return Err(format!("FnEdge id: {}, name: {} does not support create_graph", self.tape[i].get_id(), self.tape[i].name()));
+            };
+            for (id, grad) in input_ids.into_iter().zip(input_grads) {
+                let Some(grad) = grad else { continue };
+                let accumulated = match grads.remove(&id) {
+                    Some(prev) => grad_graph::binary::<f32>(BinaryOp::Add, &prev, &grad),
+                    None => grad,
+                };
+                grads.insert(id, accumulated);
+            }
+        }
+
+        // resultにつながっていないntenの勾配は0
+        Ok(wrt.map(|id| match grads.remove(&id) {
+            Some(grad) => grad,
+            None => {
+                let shape = self.ctx.get_val(&id).shape;
+                grad_graph::constant(&mut self.ctx, Tensor::new_zeros::<f32>(shape))
+            },
+        }))
+    }
+
     pub fn _build_tape<const N: usize>(&mut self, results: &[Nten; N]) {
         //! 1, 結果側からグラフ探索を行って結果をテープにする
+        // 帰りがけ順（sourcesを全部積んでから自分を積む）にすることで，
+        // 合流のあるグラフ（splitした枝をconcatするなど）でもsourcesが先に実行される
         let mut already_seen = HashSet::new();
-        let mut stack = Vec::new();
-        for tensor in results.iter() {
-            stack.push(tensor.creator.clone_box());
+        // (fn_edge, sourcesを展開済みか)
+        let mut stack: Vec<(Box<dyn FnEdge>, bool)> = Vec::new();
+        for tensor in results.iter().rev() {
+            stack.push((tensor.creator.clone_box(), false));
         }
 
-        while let Some(fn_edge) = stack.pop() {
+        while let Some((fn_edge, expanded)) = stack.pop() {
+            if expanded {
+                // tape
+                self.tape.push(fn_edge);
+                continue;
+            }
             // when every fn_edge is already executed, stack become empty and end loop
             if self.already_executed.contains(&fn_edge.get_id()) {
                 continue;
             }
             // if this is first time to see it
-            if !already_seen.contains(&fn_edge.get_id()) {
-                already_seen.insert(fn_edge.get_id());
-
-                // tape
-                self.tape.push(fn_edge.clone_box());
-                for input in fn_edge.sources() {
-                    stack.push(input.clone_box());
+            if already_seen.insert(fn_edge.get_id()) {
+                let sources = fn_edge.sources();
+                stack.push((fn_edge, true));
+                for input in sources.into_iter().rev() {
+                    if !already_seen.contains(&input.get_id()) {
+                        stack.push((input, false));
+                    }
                 }
             }
         }
-
-        self.tape.reverse();
     }
 
     pub fn zero_grad(&mut self) {
@@ -281,7 +1023,8 @@ impl Autograd {
             .iter()
             .filter_map(|(id, _)| {
                 let is_parameter = self.ctx.varstore.parameter_ids.lock().unwrap().contains(id);
-                if !is_parameter {
+                let is_buffer = self.ctx.varstore.buffer_ids.lock().unwrap().contains(id);
+                if !is_parameter && !is_buffer {
                     Some(*id)
                 } else {
                     None
@@ -294,11 +1037,275 @@ impl Autograd {
             self.ctx.varstore.body.lock().unwrap().remove(&id);
         }
 
-        // parameterのgradを削除
+        // parameterとbufferのgradを削除
         for (id, nten) in self.ctx.varstore.body.lock().unwrap().iter_mut() {
             nten.grad = None;
         }
+        self.ctx.varstore.sparse_grads.lock().unwrap().clear();
         self.tape.clear();
         self.next_execute_index = 0;
+        self.edge_temp_tensors.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{dtype::{Reduction, Shape}, fn_edge::{Conv2dConfig, CrossEntropyConfig, Pool2dConfig}, loss_fn, nn::Linear, nten::{self, Nten, Nten2d, Nten4d, NtenID}, optimizer::{Optimizer, Sgd}, tensor::{Tensor, Tensor2d}, test_utils::{as_parameters, assert_close, sample, typed4d}};
+
+    use super::{Autograd, VarStore};
+
+    // y = x W, loss = sum(y^2)。(weightのid, y, loss)を返す
+    fn build(ag: &mut Autograd) -> (NtenID, Nten, Nten) {
+        let mut vs = ag.get_vs();
+        let x: Nten2d<2, 3, f32> = Nten2d::new_from_val(sample(Shape::D2(2, 3), 1).to_typed2d().unwrap()).name("x").as_input(&mut vs);
+        let weight: Nten2d<3, 2, f32> = Nten2d::new_from_val(sample(Shape::D2(3, 2), 2).to_typed2d().unwrap()).name("weight").as_parameter(&mut vs);
+        let zeros = Nten2d::new_from_val(Tensor2d::<2, 2, f32>::new_zeros()).name("zeros").as_input(&mut vs);
+        let y: Nten2d<2, 2, f32> = nten::matmul(&x, &weight);
+        let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&y, &zeros, Reduction::Sum);
+        let [y, loss] = ag.step_forward([y.to_untyped(), loss.to_untyped()]);
+        (weight.id, y, loss)
+    }
+
+    // scalarのlossは1.0で始まる。d loss/dy = 2yなのでbackward_with(y, 2y)と同じ
+    #[test]
+    fn backward_seeds_scalar_with_one() {
+        let mut ag = Autograd::new();
+        let (weight, _, loss) = build(&mut ag);
+        let seeded = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        let mut ag = Autograd::new();
+        let (weight, y, _) = build(&mut ag);
+        let dy = y.val.as_ref().unwrap().mul_scalar(2.0);
+        let vjp = ag.backward_with(&y, dy).get_grad(&weight).to_vec_f32();
+        assert_close(&seeded, &vjp, 1e-6);
+    }
+
+    // 複数のrootの勾配は足し合わされる
+    #[test]
+    fn backward_roots_accumulates() {
+        let mut ag = Autograd::new();
+        let (weight, _, loss) = build(&mut ag);
+        let single = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        let mut ag = Autograd::new();
+        let (weight, y, loss) = build(&mut ag);
+        let y = Nten { grad: Some(Tensor::new_ones::<f32>(y.shape)), ..y };
+        let both = ag.backward_roots([&loss, &y]).get_grad(&weight).to_vec_f32();
+
+        let mut ag = Autograd::new();
+        let (weight, y, _) = build(&mut ag);
+        let ones = ag.backward_with(&y, Tensor::new_ones::<f32>(y.shape)).get_grad(&weight).to_vec_f32();
+
+        let expected: Vec<f32> = single.iter().zip(ones.iter()).map(|(a, b)| a + b).collect();
+        assert_close(&both, &expected, 1e-6);
+    }
+
+    // scalarでない結果はgradを指定しないとbackwardできない
+    #[test]
+    #[should_panic]
+    fn backward_rejects_non_scalar() {
+        let mut ag = Autograd::new();
+        let (_, y, _) = build(&mut ag);
+        ag.backward(&y);
+    }
+
+    // grad hookで途中のntenのgradを置き換えると，それより前のbackwardに伝わる
+    #[test]
+    fn grad_hook_replaces_intermediate_grad() {
+        let mut ag = Autograd::new();
+        let (weight, _, loss) = build(&mut ag);
+        let plain = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        let mut ag = Autograd::new();
+        let (weight, y, loss) = build(&mut ag);
+        ag.register_grad_hook(y.id, |grad| Some(grad.mul_scalar(0.5)));
+        let hooked = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        let expected: Vec<f32> = plain.iter().map(|g| g * 0.5).collect();
+        assert_close(&hooked, &expected, 1e-6);
+    }
+
+    // Noneを返すhookは見るだけ。葉のhookには確定したgradが渡る
+    #[test]
+    fn grad_hook_observes_leaf_grad() {
+        let mut ag = Autograd::new();
+        let (weight, _, loss) = build(&mut ag);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_hook = seen.clone();
+        ag.register_grad_hook(weight, move |grad| {
+            seen_by_hook.lock().unwrap().push(grad.to_vec_f32());
+            None
+        });
+        let grad = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_close(&seen[0], &grad, 1e-6);
+    }
+
+    #[test]
+    fn forward_hook_sees_outputs() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let x: Nten2d<2, 3, f32> = Nten2d::new_from_val(sample(Shape::D2(2, 3), 1).to_typed2d().unwrap()).name("x").as_input(&mut vs);
+        let y = x.relu();
+        let relu_id = y.creator.get_id();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_hook = seen.clone();
+        ag.register_forward_hook(relu_id, move |fn_edge, outputs| {
+            seen_by_hook.lock().unwrap().push((fn_edge.get_id(), outputs[0].to_vec_f32()));
+        });
+        let [y] = ag.step_forward([y.to_untyped()]);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].0 == relu_id);
+        assert_close(&seen[0].1, &y.val.unwrap().to_vec_f32(), 1e-6);
+    }
+
+    // 外したhookは呼ばれない
+    #[test]
+    fn removed_hook_is_not_called() {
+        let mut ag = Autograd::new();
+        let (weight, y, loss) = build(&mut ag);
+        let calls = Arc::new(Mutex::new(0));
+        let calls_by_hook = calls.clone();
+        let handle = ag.register_grad_hook(y.id, move |grad| {
+            *calls_by_hook.lock().unwrap() += 1;
+            Some(grad.mul_scalar(0.0))
+        });
+        ag.remove_hook(handle);
+        let grad = ag.backward(&loss).get_grad(&weight).to_vec_f32();
+
+        assert_eq!(*calls.lock().unwrap(), 0);
+        assert!(grad.iter().any(|g| *g != 0.0));
+    }
+
+    // ps: input, conv weight, conv bias, linear weight, linear bias
+    fn conv_net_params() -> Vec<Tensor> {
+        vec![
+            sample(Shape::D4(2, 1, 6, 6), 1),
+            sample(Shape::D4(3, 1, 3, 3), 2), sample(Shape::D2(1, 3), 3),
+            sample(Shape::D2(12, 4), 4), sample(Shape::D2(1, 4), 5),
+        ]
+    }
+    // (running_mean, running_var)
+    fn conv_net_buffers(vs: &mut VarStore) -> (Nten2d<1, 3, f32>, Nten2d<1, 3, f32>) {
+        (
+            Nten2d::new_from_val(Tensor2d::new_zeros()).name("running_mean").as_buffer(vs),
+            Nten2d::new_from_val(Tensor2d::new_ones()).name("running_var").as_buffer(vs),
+        )
+    }
+    // conv -> batch norm -> tanh -> max pool -> relu -> dropout -> linear -> cross entropy。(途中のnten, loss)を返す
+    fn conv_net(vs: &mut VarStore, ps: &[Nten], buffers: &(Nten2d<1, 3, f32>, Nten2d<1, 3, f32>)) -> (Nten, Nten) {
+        let x = typed4d::<2, 1, 6, 6>(&ps[0]);
+        let h: Nten4d<2, 3, 4, 4, f32> = nten::conv2d(&x, &typed4d::<3, 1, 3, 3>(&ps[1]), Some(&ps[2].clone().to_typed2d().unwrap()), Conv2dConfig::new());
+        let h: Nten4d<2, 3, 2, 2, f32> = nten::batch_norm2d(&h, &buffers.0, &buffers.1, 0.1, 1e-5).tanh().max_pool2d(Pool2dConfig::new(2, 2));
+        let h: Nten2d<2, 12, f32> = h.flatten();
+        let h = h.relu().dropout(0.5);
+        let linear: Linear<12, 4> = Linear { weight: ps[3].clone().to_typed2d().unwrap(), bias: ps[4].clone().to_typed2d().unwrap() };
+        let target = Nten2d::new_from_val(Tensor2d::new_from_indices(vec![1, 3]).unwrap()).name("target").as_input(vs);
+        let loss: Nten2d<1, 1, f32> = loss_fn::cross_entropy(&linear.forward(&h), &target, CrossEntropyConfig::new());
+        (h.to_untyped(), loss.to_untyped())
+    }
+
+    // 2 step学習して(各stepのloss, 学習後のparameter, running_mean)を返す
+    fn train_conv_net(memory_planner: bool) -> (Vec<f32>, Vec<Vec<f32>>, Vec<f32>) {
+        let mut ag = Autograd::new();
+        ag.manual_seed(0);
+        ag.set_memory_planner(memory_planner);
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &conv_net_params());
+        let buffers = conv_net_buffers(&mut vs);
+        let mut optimizer = Sgd::new(0.1);
+        let mut losses = Vec::new();
+        for _ in 0..2 {
+            let (_, loss) = conv_net(&mut vs, &ps, &buffers);
+            let [loss] = ag.step_forward([loss]);
+            losses.push(loss.val.as_ref().unwrap().to_vec_f32()[0]);
+            optimizer.update(ag.backward(&loss));
+            ag.zero_grad();
+        }
+        let ctx = ag.context_mut();
+        let params = ps.iter().map(|p| ctx.get_val(&p.id).to_vec_f32()).collect();
+        (losses, params, ctx.get_val(&buffers.0.id).to_vec_f32())
+    }
+
+    // 途中の値を捨ててbuffer poolで使い回しても，学習の結果は変わらない
+    #[test]
+    fn memory_planner_matches_plain_training() {
+        let (losses, params, running_mean) = train_conv_net(false);
+        let (losses_planned, params_planned, running_mean_planned) = train_conv_net(true);
+        assert_close(&losses_planned, &losses, 1e-6);
+        for (p_planned, p) in params_planned.iter().zip(params.iter()) {
+            assert_close(p_planned, p, 1e-6);
+        }
+        assert_close(&running_mean_planned, &running_mean, 1e-6);
+    }
+
+    // 学習時もbackwardで読まない値（Addの入力）はforwardで捨てるので，深いAddの鎖でも同時に持つ値は増えない
+    #[test]
+    fn memory_planner_bounds_live_values_in_train() {
+        const DEPTH: usize = 32;
+        let train = |memory_planner: bool| {
+            let mut ag = Autograd::new();
+            ag.set_memory_planner(memory_planner);
+            let mut vs = ag.get_vs();
+            let ps = as_parameters(&mut vs, &[sample(Shape::D2(2, 3), 1), sample(Shape::D2(1, 3), 2)]);
+            let bias: Nten2d<1, 3, f32> = ps[1].clone().to_typed2d().unwrap();
+            let mut h: Nten2d<2, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+            let mut edge_ids = Vec::new();
+            for _ in 0..DEPTH {
+                h = h.add_broadcast(&bias);
+                edge_ids.push(h.creator.get_id());
+            }
+            // forwardの各FnEdgeの直後にctxが値を持っているntenの数を数える
+            let peak = Arc::new(Mutex::new(0));
+            for id in edge_ids {
+                let (body, peak) = (vs.body.clone(), peak.clone());
+                ag.register_forward_hook(id, move |_, _| {
+                    let live = body.lock().unwrap().values().filter(|nten| nten.val.is_some()).count();
+                    let mut peak = peak.lock().unwrap();
+                    *peak = (*peak).max(live);
+                });
+            }
+            let [h] = ag.step_forward([h.to_untyped()]);
+            let ctx = ag.backward_with(&h, sample(h.shape, 3));
+            let grads: Vec<Vec<f32>> = ps.iter().map(|p| ctx.get_grad(&p.id).to_vec_f32()).collect();
+            let peak = *peak.lock().unwrap();
+            (grads, peak)
+        };
+        let (grads, peak) = train(false);
+        let (grads_planned, peak_planned) = train(true);
+        assert!(peak > DEPTH, "without memory planner {} values are live", peak);
+        // parameter 2つと，作ったばかりの出力とその入力
+        assert!(peak_planned <= 4, "memory planner keeps {} values live", peak_planned);
+        for (g_planned, g) in grads_planned.iter().zip(grads.iter()) {
+            assert_close(g_planned, g, 1e-6);
+        }
+    }
+
+    // 推論時は最後に使われた時点で途中の値を捨てる。結果は同じ
+    #[test]
+    fn memory_planner_releases_in_inference() {
+        let infer = |memory_planner: bool| {
+            let mut ag = Autograd::new();
+            ag.eval();
+            ag.set_memory_planner(memory_planner);
+            let mut vs = ag.get_vs();
+            let ps = as_parameters(&mut vs, &conv_net_params());
+            let buffers = conv_net_buffers(&mut vs);
+            let (h, loss) = conv_net(&mut vs, &ps, &buffers);
+            let [loss] = ag.step_forward([loss]);
+            let kept = ag.context_mut().varstore.body.lock().unwrap().contains_key(&h.id);
+            (loss.val.unwrap().to_vec_f32(), kept)
+        };
+        let (loss, kept) = infer(false);
+        let (loss_planned, kept_planned) = infer(true);
+        assert!(kept);
+        assert!(!kept_planned);
+        assert_close(&loss_planned, &loss, 1e-6);
     }
 }
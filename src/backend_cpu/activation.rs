@@ -0,0 +1,27 @@
+use crate::{dtype::UnaryOp, logger::LOGGER};
+
+use super::RawDense;
+
+
+impl RawDense<f32> {
+    pub fn unary_op(&self, op: UnaryOp) -> Self {
+        let body = match op {
+            UnaryOp::Sigmoid => self.body.iter().map(|x| 1.0 / (1.0 + (-x).exp())).collect(),
+            UnaryOp::Tanh => self.body.iter().map(|x| x.tanh()).collect(),
+        };
+        RawDense { body }
+    }
+
+    // 出力yから微分を計算する。sigmoid: y(1 - y)，tanh: 1 - y^2
+    pub fn unary_op_backward(op: UnaryOp, dout: &Self, output: &Self) -> Self {
+        if dout.body.len() != output.body.len() {
+            LOGGER.error(format!("RawDense<f32>::unary_op_backward() >> {} dout length {} is unmatched with output length {}", op.name(), dout.body.len(), output.body.len()));
+            panic!("")
+        }
+        let body = dout.body.iter().zip(output.body.iter()).map(|(d, y)| match op {
+            UnaryOp::Sigmoid => d * y * (1.0 - y),
+            UnaryOp::Tanh => d * (1.0 - y * y),
+        }).collect();
+        RawDense { body }
+    }
+}
@@ -0,0 +1,76 @@
+use rayon::prelude::*;
+
+use crate::logger::LOGGER;
+
+use super::{buffer_pool, RawDense};
+
+
+/*
+転置を作らずに op(lhs) x op(rhs) を計算する。batchごとに独立
+op(lhs)は(n, m)，op(rhs)は(m, o)，出力は(batch, n, o)
+transpose_lhsのときlhsは(batch, m, n)で保存されている。rhsも同じ
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatmulGeometry {
+    pub batch: usize,
+    pub n: usize,
+    pub m: usize,
+    pub o: usize,
+    pub transpose_lhs: bool,
+    pub transpose_rhs: bool,
+}
+
+impl RawDense<f32> {
+    pub fn matmul_t(lhs: &Self, rhs: &Self, geometry: &MatmulGeometry) -> Self {
+        let MatmulGeometry { batch, n, m, o, transpose_lhs, transpose_rhs } = *geometry;
+        if lhs.body.len() != batch * n * m || rhs.body.len() != batch * m * o {
+            LOGGER.error(format!("RawDense<f32>::matmul_t() >> lhs length: {}, rhs length: {} are unmatched with {:?}", lhs.body.len(), rhs.body.len(), geometry));
+            panic!("")
+        }
+
+        let mut result = buffer_pool::take_zeroed(batch * n * o);
+        result.par_chunks_mut(o.max(1)).enumerate().for_each(|(row, result_row)| {
+            let (b, i) = (row / n, row % n);
+            let lhs_b = &lhs.body[b * n * m..(b + 1) * n * m];
+            let rhs_b = &rhs.body[b * m * o..(b + 1) * m * o];
+            let lhs_at = |k: usize| if transpose_lhs { lhs_b[k * n + i] } else { lhs_b[i * m + k] };
+            if transpose_rhs {
+                // rhsの行とlhsの行の内積
+                for (j, r) in result_row.iter_mut().enumerate() {
+                    *r = (0..m).map(|k| lhs_at(k) * rhs_b[j * m + k]).sum();
+                }
+            } else {
+                // ikj
+                for k in 0..m {
+                    let a = lhs_at(k);
+                    for (r, b) in result_row.iter_mut().zip(rhs_b[k * o..(k + 1) * o].iter()) {
+                        *r += a * *b;
+                    }
+                }
+            }
+        });
+        RawDense { body: result }
+    }
+
+    // C = op(A) x op(B) のdA, dB。どちらもmatmul_tで計算する
+    pub fn matmul_t_backward(dout: &Self, lhs: &Self, rhs: &Self, geometry: &MatmulGeometry) -> (Self, Self) {
+        let MatmulGeometry { batch, n, m, o, transpose_lhs, transpose_rhs } = *geometry;
+        let geo = |n, m, o, transpose_lhs, transpose_rhs| MatmulGeometry { batch, n, m, o, transpose_lhs, transpose_rhs };
+
+        let dlhs = if !transpose_lhs {
+            // dA = dC x op(B)^T : (n, o) x (o, m)
+            Self::matmul_t(dout, rhs, &geo(n, o, m, false, !transpose_rhs))
+        } else {
+            // dA = (dC x op(B)^T)^T = op(B) x dC^T : (m, o) x (o, n)
+            Self::matmul_t(rhs, dout, &geo(m, o, n, transpose_rhs, true))
+        };
+        let drhs = if !transpose_rhs {
+            // dB = op(A)^T x dC : (m, n) x (n, o)
+            Self::matmul_t(lhs, dout, &geo(m, n, o, !transpose_lhs, false))
+        } else {
+            // dB = (op(A)^T x dC)^T = dC^T x op(A) : (o, n) x (n, m)
+            Self::matmul_t(dout, lhs, &geo(o, n, m, true, transpose_lhs))
+        };
+        (dlhs, drhs)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{dtype::{BinaryOp, Shape}, logger::LOGGER};
+
+use super::{buffer_pool, RawDense};
+
+
+/*
+NumPy方式のbroadcast
+
+out_shapeの各要素について，lhsとrhsのどの要素を使うかをstrideで求める。
+broadcastされる軸（dim == 1）はstrideを0にすることで同じ要素を繰り返し参照する。
+*/
+
+// 右詰めしてout_shapeのrankにそろえたstride. broadcastされる軸は0
+fn broadcast_strides(shape: Shape, out_shape: Shape) -> Vec<usize> {
+    let dims = shape.dims();
+    let out_dims = out_shape.dims();
+    let offset = out_dims.len() - dims.len();
+
+    let mut strides = vec![0; out_dims.len()];
+    let mut stride = 1;
+    for i in (0..dims.len()).rev() {
+        if dims[i] != 1 {
+            strides[i + offset] = stride;
+        }
+        stride *= dims[i];
+    }
+    strides
+}
+
+// out_shapeの線形indexを順番に回し，lhs, rhsのindexを返すイテレーター
+struct BroadcastIndexer {
+    out_dims: Vec<usize>,
+    lhs_strides: Vec<usize>,
+    rhs_strides: Vec<usize>,
+    counter: Vec<usize>,
+    lhs_index: usize,
+    rhs_index: usize,
+    remaining: usize,
+}
+impl BroadcastIndexer {
+    fn new(lhs_shape: Shape, rhs_shape: Shape, out_shape: Shape) -> Self {
+        let out_dims = out_shape.dims();
+        Self {
+            lhs_strides: broadcast_strides(lhs_shape, out_shape),
+            rhs_strides: broadcast_strides(rhs_shape, out_shape),
+            counter: vec![0; out_dims.len()],
+            lhs_index: 0,
+            rhs_index: 0,
+            remaining: out_shape.numel(),
+            out_dims,
+        }
+    }
+}
+impl Iterator for BroadcastIndexer {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let current = (self.lhs_index, self.rhs_index);
+
+        // 繰り上がりしながら次のindexへ
+        for axis in (0..self.out_dims.len()).rev() {
+            self.counter[axis] += 1;
+            self.lhs_index += self.lhs_strides[axis];
+            self.rhs_index += self.rhs_strides[axis];
+            if self.counter[axis] < self.out_dims[axis] {
+                break;
+            }
+            self.lhs_index -= self.lhs_strides[axis] * self.out_dims[axis];
+            self.rhs_index -= self.rhs_strides[axis] * self.out_dims[axis];
+            self.counter[axis] = 0;
+        }
+        Some(current)
+    }
+}
+
+fn out_shape_or_panic(lhs_shape: Shape, rhs_shape: Shape, op_type: &str) -> Shape {
+    match lhs_shape.broadcast(&rhs_shape) {
+        Ok(shape) => shape,
+        Err(e) => {
+            LOGGER.error(format!("RawDense<f32>::{}() >> {}", op_type, e));
+            panic!("")
+        }
+    }
+}
+
+impl RawDense<f32> {
+    pub fn broadcast_op(op: BinaryOp, lhs: &Self, lhs_shape: Shape, rhs: &Self, rhs_shape: Shape) -> Self {
+        let out_shape = out_shape_or_panic(lhs_shape, rhs_shape, "broadcast_op");
+        let operation: fn(f32, f32) -> f32 = match op {
+            BinaryOp::Add => |a, b| a + b,
+            BinaryOp::Sub => |a, b| a - b,
+            BinaryOp::Mul => |a, b| a * b,
+            BinaryOp::Div => |a, b| a / b,
+            BinaryOp::Rem => |a, b| a % b,
+        };
+
+        // 同じ形状なら要素ごとの演算（multi threadあり）
+        if lhs_shape == rhs_shape {
+            return lhs.template_op(rhs, op.name(), operation);
+        }
+        // R×C + 1×C はbiasの足し算
+        if let (BinaryOp::Add, Shape::D2(_, c), Shape::D2(1, rc)) = (op, lhs_shape, rhs_shape) {
+            if c == rc && out_shape == lhs_shape {
+                let mut out = lhs.clone();
+                out.add_broadcast(rhs, lhs_shape);
+                return out;
+            }
+        }
+
+        let body = BroadcastIndexer::new(lhs_shape, rhs_shape, out_shape)
+            .map(|(l, r)| operation(lhs.body[l], rhs.body[r]))
+            .collect();
+        RawDense { body }
+    }
+
+    // returns (dlhs, drhs). broadcastされた軸の勾配はその場で足し合わされる
+    pub fn broadcast_op_backward(op: BinaryOp, dout: &Self, lhs: &Self, lhs_shape: Shape, rhs: &Self, rhs_shape: Shape) -> (Self, Self) {
+        let out_shape = out_shape_or_panic(lhs_shape, rhs_shape, "broadcast_op_backward");
+        if dout.body.len() != out_shape.numel() {
+            LOGGER.error(format!("RawDense<f32>::broadcast_op_backward() >> dout len is {} but output shape is {}", dout.body.len(), out_shape));
+            panic!("")
+        }
+
+        let mut dlhs = buffer_pool::take_zeroed(lhs.body.len());
+        let mut drhs = buffer_pool::take_zeroed(rhs.body.len());
+        for (d, (l, r)) in dout.body.iter().zip(BroadcastIndexer::new(lhs_shape, rhs_shape, out_shape)) {
+            let (a, b) = (lhs.body[l], rhs.body[r]);
+            let (da, db) = match op {
+                BinaryOp::Add => (*d, *d),
+                BinaryOp::Sub => (*d, -*d),
+                BinaryOp::Mul => (*d * b, *d * a),
+                BinaryOp::Div => (*d / b, -*d * a / (b * b)),
+                // a % b = a - b * trunc(a / b)
+                BinaryOp::Rem => (*d, -*d * (a / b).trunc()),
+            };
+            dlhs[l] += da;
+            drhs[r] += db;
+        }
+        (RawDense { body: dlhs }, RawDense { body: drhs })
+    }
+
+    // broadcastの逆。from_shapeからto_shapeへ，伸ばされた軸を足し合わせて縮める
+    pub fn sum_to_shape(&self, from_shape: Shape, to_shape: Shape) -> Self {
+        if out_shape_or_panic(from_shape, to_shape, "sum_to_shape") != from_shape {
+            LOGGER.error(format!("RawDense<f32>::sum_to_shape() >> {} can not be reduced to {}", from_shape, to_shape));
+            panic!("")
+        }
+        // R×C -> 1×C はbatch方向の和
+        if let (Shape::D2(_, c), Shape::D2(1, tc)) = (from_shape, to_shape) {
+            if c == tc {
+                return self.sum_batch(from_shape);
+            }
+        }
+        let mut body = buffer_pool::take_zeroed(to_shape.numel());
+        for (x, (_, t)) in self.body.iter().zip(BroadcastIndexer::new(from_shape, to_shape, from_shape)) {
+            body[t] += *x;
+        }
+        RawDense { body }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend_cpu::RawDense, dtype::{BinaryOp, Shape}};
+
+    fn raw(body: &[f32]) -> RawDense<f32> {
+        RawDense { body: body.to_vec() }
+    }
+
+    #[test]
+    fn same_shape_matches_elementwise_op() {
+        let (lhs, rhs) = (raw(&[1.0, 2.0, 3.0]), raw(&[4.0, 5.0, 6.0]));
+        let out = RawDense::broadcast_op(BinaryOp::Sub, &lhs, Shape::D1(3), &rhs, Shape::D1(3));
+        assert_eq!(out.body, (&lhs - &rhs).body);
+    }
+
+    #[test]
+    fn bias_row_matches_general_path() {
+        let lhs = raw(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let bias = raw(&[10.0, 20.0, 30.0]);
+        let fast = RawDense::broadcast_op(BinaryOp::Add, &lhs, Shape::D2(2, 3), &bias, Shape::D2(1, 3));
+        // D1(3)は右詰めでD2(1, 3)と同じ
+        let general = RawDense::broadcast_op(BinaryOp::Add, &lhs, Shape::D2(2, 3), &bias, Shape::D1(3));
+        assert_eq!(fast.body, vec![11.0, 22.0, 33.0, 14.0, 25.0, 36.0]);
+        assert_eq!(fast.body, general.body);
+    }
+
+    #[test]
+    fn backward_reduces_broadcast_axes() {
+        let lhs = raw(&[1.0, 2.0]);
+        let rhs = raw(&[1.0, 2.0, 3.0]);
+        let dout = raw(&[1.0; 6]);
+        let (dlhs, drhs) = RawDense::broadcast_op_backward(BinaryOp::Mul, &dout, &lhs, Shape::D2(2, 1), &rhs, Shape::D2(1, 3));
+        assert_eq!(dlhs.body, vec![6.0, 6.0]);
+        assert_eq!(drhs.body, vec![3.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn sum_to_shape() {
+        let x = raw(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(x.sum_to_shape(Shape::D2(2, 3), Shape::D2(1, 3)).body, vec![5.0, 7.0, 9.0]);
+        assert_eq!(x.sum_to_shape(Shape::D2(2, 3), Shape::D2(2, 1)).body, vec![6.0, 15.0]);
+        assert_eq!(x.sum_to_shape(Shape::D2(2, 3), Shape::D1(1)).body, vec![21.0]);
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Mutex;
+
+
+/*
+解放されたf32のbodyを同じ長さの確保で使い回す
+Autogradのmemory plannerが捨てたTensorのstorageがここに返ってくる
+*/
+
+// 持ちすぎないように上限を決める
+const MAX_POOLED_BUFFERS: usize = 64;
+
+static POOL: Mutex<Vec<Vec<f32>>> = Mutex::new(Vec::new());
+
+// 長さlenの0埋めのVec。同じ長さのbufferがあれば使い回す
+pub fn take_zeroed(len: usize) -> Vec<f32> {
+    let reused = {
+        let mut pool = POOL.lock().unwrap();
+        pool.iter().position(|buffer| buffer.len() == len).map(|i| pool.swap_remove(i))
+    };
+    match reused {
+        Some(mut buffer) => {
+            buffer.fill(0.0);
+            buffer
+        },
+        None => vec![0.0; len],
+    }
+}
+
+pub fn recycle(buffer: Vec<f32>) {
+    if buffer.is_empty() {
+        return;
+    }
+    let mut pool = POOL.lock().unwrap();
+    if pool.len() < MAX_POOLED_BUFFERS {
+        pool.push(buffer);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{recycle, take_zeroed};
+
+    // 使い回したbufferも0埋めされている
+    #[test]
+    fn reused_buffer_is_zeroed() {
+        recycle(vec![1.0; 13]);
+        assert_eq!(take_zeroed(13), vec![0.0; 13]);
+    }
+}
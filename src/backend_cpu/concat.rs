@@ -0,0 +1,49 @@
+use crate::logger::LOGGER;
+
+use super::RawDense;
+
+
+// 各テンソルを(outer, axis_len, inner)とみなしてaxisの方向につなげる，分ける
+impl RawDense<f32> {
+    // 出力は(outer, sum(axis_lens), inner)
+    pub fn concat(inputs: &[&Self], outer: usize, axis_lens: &[usize], inner: usize) -> Self {
+        if inputs.len() != axis_lens.len() {
+            LOGGER.error(format!("RawDense<f32>::concat() >> {} inputs but {} axis_lens", inputs.len(), axis_lens.len()));
+            panic!("")
+        }
+        for (input, axis_len) in inputs.iter().zip(axis_lens.iter()) {
+            if input.body.len() != outer * axis_len * inner {
+                LOGGER.error(format!("RawDense<f32>::concat() >> input length: {} is unmatched with ({}, {}, {})", input.body.len(), outer, axis_len, inner));
+                panic!("")
+            }
+        }
+        let total: usize = axis_lens.iter().sum();
+        let mut body = Vec::with_capacity(outer * total * inner);
+        for o in 0..outer {
+            for (input, axis_len) in inputs.iter().zip(axis_lens.iter()) {
+                let block = axis_len * inner;
+                body.extend_from_slice(&input.body[o * block..(o + 1) * block]);
+            }
+        }
+        RawDense { body }
+    }
+
+    // concatの逆。selfは(outer, sum(axis_lens), inner)
+    pub fn split(&self, outer: usize, axis_lens: &[usize], inner: usize) -> Vec<Self> {
+        let total: usize = axis_lens.iter().sum();
+        if self.body.len() != outer * total * inner {
+            LOGGER.error(format!("RawDense<f32>::split() >> length: {} is unmatched with ({}, {}, {})", self.body.len(), outer, total, inner));
+            panic!("")
+        }
+        let mut outputs: Vec<Vec<f32>> = axis_lens.iter().map(|axis_len| Vec::with_capacity(outer * axis_len * inner)).collect();
+        for o in 0..outer {
+            let mut offset = o * total * inner;
+            for (output, axis_len) in outputs.iter_mut().zip(axis_lens.iter()) {
+                let block = axis_len * inner;
+                output.extend_from_slice(&self.body[offset..offset + block]);
+                offset += block;
+            }
+        }
+        outputs.into_iter().map(|body| RawDense { body }).collect()
+    }
+}
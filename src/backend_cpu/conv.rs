@@ -0,0 +1,200 @@
+use rayon::prelude::*;
+
+use crate::{dtype::Shape, logger::LOGGER};
+
+use super::{buffer_pool, RawDense};
+
+
+/*
+im2colによる畳み込み
+
+入力(N, C, H, W)を出力位置ごとにカーネルが見る範囲を1行に並べた行列
+col(N * HO * WO, C/groups * KH * KW)に展開し，重みとのmatmul一回で畳み込みを計算する。
+backwardではcolの勾配をcol2imで元の入力の位置に足し戻す。
+*/
+
+// 畳み込みの形状をまとめたもの。FnEdgeのフロントで検査済みであること
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conv2dGeometry {
+    pub batch: usize,
+    pub in_channels: usize,
+    pub in_height: usize,
+    pub in_width: usize,
+    pub out_channels: usize,
+    pub kernel_height: usize,
+    pub kernel_width: usize,
+    pub out_height: usize,
+    pub out_width: usize,
+
+    // (height, width)
+    pub stride: (usize, usize),
+    pub padding: (usize, usize),
+    pub dilation: (usize, usize),
+    pub groups: usize,
+}
+impl Conv2dGeometry {
+    // pytorchと同じ出力サイズ。カーネルが入りきらないときはNone
+    pub fn out_size(in_size: usize, kernel: usize, stride: usize, padding: usize, dilation: usize) -> Option<usize> {
+        let span = dilation * (kernel - 1) + 1;
+        if stride == 0 || in_size + 2 * padding < span {
+            return None;
+        }
+        Some((in_size + 2 * padding - span) / stride + 1)
+    }
+
+    fn in_channels_per_group(&self) -> usize {
+        self.in_channels / self.groups
+    }
+    fn out_channels_per_group(&self) -> usize {
+        self.out_channels / self.groups
+    }
+    // colの列数
+    fn col_width(&self) -> usize {
+        self.in_channels_per_group() * self.kernel_height * self.kernel_width
+    }
+    // colの行数
+    fn col_height(&self) -> usize {
+        self.batch * self.out_height * self.out_width
+    }
+
+    // 出力位置(oh, ow)とカーネル位置(kh, kw)に対応する入力の位置。paddingの中ならNone
+    #[inline(always)]
+    fn input_position(&self, oh: usize, ow: usize, kh: usize, kw: usize) -> Option<(usize, usize)> {
+        let ih = (oh * self.stride.0 + kh * self.dilation.0).checked_sub(self.padding.0)?;
+        let iw = (ow * self.stride.1 + kw * self.dilation.1).checked_sub(self.padding.1)?;
+        if ih < self.in_height && iw < self.in_width {
+            Some((ih, iw))
+        } else {
+            None
+        }
+    }
+}
+
+impl RawDense<f32> {
+    // returns col of Shape::D2(N * HO * WO, C/groups * KH * KW) for the group
+    pub fn im2col(&self, geometry: &Conv2dGeometry, group: usize) -> Self {
+        let g = geometry;
+        let col_width = g.col_width();
+        let cig = g.in_channels_per_group();
+        let mut col = buffer_pool::take_zeroed(g.col_height() * col_width);
+
+        col.par_chunks_mut(col_width).enumerate().for_each(|(row, col_row)| {
+            let n = row / (g.out_height * g.out_width);
+            let oh = (row / g.out_width) % g.out_height;
+            let ow = row % g.out_width;
+            for ci in 0..cig {
+                let channel = group * cig + ci;
+                let channel_offset = (n * g.in_channels + channel) * g.in_height * g.in_width;
+                for kh in 0..g.kernel_height {
+                    for kw in 0..g.kernel_width {
+                        if let Some((ih, iw)) = g.input_position(oh, ow, kh, kw) {
+                            col_row[(ci * g.kernel_height + kh) * g.kernel_width + kw] = self.body[channel_offset + ih * g.in_width + iw];
+                        }
+                    }
+                }
+            }
+        });
+        RawDense { body: col }
+    }
+
+    // im2colの逆。colの値を入力の位置に足し合わせる
+    pub fn col2im(&self, geometry: &Conv2dGeometry, group: usize, dinput: &mut [f32]) {
+        let g = geometry;
+        let col_width = g.col_width();
+        let cig = g.in_channels_per_group();
+
+        for (row, col_row) in self.body.chunks(col_width).enumerate() {
+            let n = row / (g.out_height * g.out_width);
+            let oh = (row / g.out_width) % g.out_height;
+            let ow = row % g.out_width;
+            for ci in 0..cig {
+                let channel = group * cig + ci;
+                let channel_offset = (n * g.in_channels + channel) * g.in_height * g.in_width;
+                for kh in 0..g.kernel_height {
+                    for kw in 0..g.kernel_width {
+                        if let Some((ih, iw)) = g.input_position(oh, ow, kh, kw) {
+                            dinput[channel_offset + ih * g.in_width + iw] += col_row[(ci * g.kernel_height + kh) * g.kernel_width + kw];
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // weight of groupを(CO/groups, C/groups * KH * KW)の行列として取り出す
+    fn group_weight(weight: &Self, geometry: &Conv2dGeometry, group: usize) -> Self {
+        let size = geometry.out_channels_per_group() * geometry.col_width();
+        RawDense { body: weight.body[group * size..(group + 1) * size].to_vec() }
+    }
+
+    // input: (N, C, H, W), weight: (CO, C/groups, KH, KW), bias: (CO)
+    pub fn conv2d(input: &Self, weight: &Self, bias: Option<&Self>, geometry: &Conv2dGeometry) -> Self {
+        let g = geometry;
+        if input.body.len() != g.batch * g.in_channels * g.in_height * g.in_width
+            || weight.body.len() != g.out_channels * g.col_width() {
+            LOGGER.error(format!("RawDense<f32>::conv2d() >> length unmatched. input: {}, weight: {}, geometry: {:?}", input.body.len(), weight.body.len(), g));
+            panic!("")
+        }
+        let cog = g.out_channels_per_group();
+        let out_plane = g.out_height * g.out_width;
+        let mut output = buffer_pool::take_zeroed(g.batch * g.out_channels * out_plane);
+
+        for group in 0..g.groups {
+            let col = input.im2col(g, group);
+            let mut weight_t = Self::group_weight(weight, g, group);
+            weight_t.transpose(Shape::D2(cog, g.col_width()));
+            // (N * HO * WO, CO/groups)
+            let out_group = RawDense::matmul(&col, Shape::D2(g.col_height(), g.col_width()), &weight_t, Shape::D2(g.col_width(), cog));
+
+            for (row, out_row) in out_group.body.chunks(cog).enumerate() {
+                let n = row / out_plane;
+                let position = row % out_plane;
+                for (co, value) in out_row.iter().enumerate() {
+                    let channel = group * cog + co;
+                    let b = bias.map_or(0.0, |bias| bias.body[channel]);
+                    output[(n * g.out_channels + channel) * out_plane + position] = *value + b;
+                }
+            }
+        }
+        RawDense { body: output }
+    }
+
+    // returns (dinput, dweight, dbias)
+    pub fn conv2d_backward(dout: &Self, input: &Self, weight: &Self, geometry: &Conv2dGeometry) -> (Self, Self, Self) {
+        let g = geometry;
+        let cog = g.out_channels_per_group();
+        let out_plane = g.out_height * g.out_width;
+
+        let mut dinput = buffer_pool::take_zeroed(input.body.len());
+        let mut dweight = buffer_pool::take_zeroed(weight.body.len());
+        let mut dbias = buffer_pool::take_zeroed(g.out_channels);
+
+        for group in 0..g.groups {
+            // doutをmatmulの出力と同じ(N * HO * WO, CO/groups)の並びに戻す
+            let mut dout_group = buffer_pool::take_zeroed(g.col_height() * cog);
+            for (row, dout_row) in dout_group.chunks_mut(cog).enumerate() {
+                let n = row / out_plane;
+                let position = row % out_plane;
+                for (co, d) in dout_row.iter_mut().enumerate() {
+                    *d = dout.body[(n * g.out_channels + group * cog + co) * out_plane + position];
+                    dbias[group * cog + co] += *d;
+                }
+            }
+            let dout_group = RawDense { body: dout_group };
+
+            // dweight = dout^T x col
+            let col = input.im2col(g, group);
+            let mut dout_t = dout_group.clone();
+            dout_t.transpose(Shape::D2(g.col_height(), cog));
+            let dweight_group = RawDense::matmul(&dout_t, Shape::D2(cog, g.col_height()), &col, Shape::D2(g.col_height(), g.col_width()));
+            let size = cog * g.col_width();
+            dweight[group * size..(group + 1) * size].copy_from_slice(&dweight_group.body);
+
+            // dcol = dout x weight
+            let weight_group = Self::group_weight(weight, g, group);
+            let dcol = RawDense::matmul(&dout_group, Shape::D2(g.col_height(), cog), &weight_group, Shape::D2(cog, g.col_width()));
+            dcol.col2im(g, group, &mut dinput);
+        }
+        (RawDense { body: dinput }, RawDense { body: dweight }, RawDense { body: dbias })
+    }
+}
@@ -0,0 +1,29 @@
+use rand::Rng;
+
+use crate::logger::LOGGER;
+
+use super::{RawBool, RawDense};
+
+
+impl RawBool {
+    // 確率pでtrueになるmask。Dropoutではtrueの要素を落とす
+    pub fn new_bernoulli<R: Rng>(len: usize, p: f32, rng: &mut R) -> Self {
+        let mut mask = RawBool::with_capacity(len);
+        for _ in 0..len {
+            mask.push(rng.gen::<f32>() < p);
+        }
+        mask
+    }
+}
+
+impl RawDense<f32> {
+    // maskがtrueの要素は0，それ以外はscale倍する
+    pub fn masked_scale(&self, mask: &RawBool, scale: f32) -> Self {
+        if self.body.len() != mask.len {
+            LOGGER.error(format!("RawDense<f32>::masked_scale() >> length unmatched. self: {}, mask: {}", self.body.len(), mask.len));
+            panic!("")
+        }
+        let body = self.body.iter().zip(mask.iter()).map(|(x, drop)| if drop { 0.0 } else { *x * scale }).collect();
+        RawDense { body }
+    }
+}
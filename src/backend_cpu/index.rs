@@ -0,0 +1,137 @@
+use crate::logger::LOGGER;
+
+use super::{RawBool, RawDense};
+
+
+// 行単位のgatherとscatter。Embeddingで使う
+
+fn check_index(index: u32, rows: usize, op_type: &str) -> usize {
+    let index = index as usize;
+    if index >= rows {
+        LOGGER.error(format!("RawDense<f32>::{}() >> index {} is out of range for {} rows", op_type, index, rows));
+        panic!("")
+    }
+    index
+}
+
+impl RawDense<f32> {
+    // selfを(rows, cols)とみなし，indicesの行を順に並べた(indices.len(), cols)を返す
+    pub fn gather_rows(&self, cols: usize, indices: &RawDense<u32>) -> Self {
+        let rows = self.body.len() / cols;
+        let mut body = Vec::with_capacity(indices.body.len() * cols);
+        for index in indices.body.iter() {
+            let r = check_index(*index, rows, "gather_rows");
+            body.extend_from_slice(&self.body[r * cols..(r + 1) * cols]);
+        }
+        RawDense { body }
+    }
+
+    // gather_rowsの逆。src(indices.len(), cols)をself(rows, cols)のindicesの行に足す
+    // 同じindexが複数回出てきたら全部足される。触れた行以外は変更しない
+    pub fn scatter_add_rows(&mut self, cols: usize, indices: &RawDense<u32>, src: &Self) {
+        let rows = self.body.len() / cols;
+        if indices.body.len() * cols != src.body.len() {
+            LOGGER.error(format!("RawDense<f32>::scatter_add_rows() >> src length: {} is unmatched with {} indices and {} cols", src.body.len(), indices.body.len(), cols));
+            panic!("")
+        }
+        for (index, src_row) in indices.body.iter().zip(src.body.chunks(cols)) {
+            let r = check_index(*index, rows, "scatter_add_rows");
+            for (dst, s) in self.body[r * cols..(r + 1) * cols].iter_mut().zip(src_row.iter()) {
+                *dst += *s;
+            }
+        }
+    }
+}
+
+
+/*
+2次元のgather, scatter_add, index_select, index_add
+shapeは(rows, cols)，dimは0 (行方向)か1 (列方向)
+
+gather     dim 0: out[i][j] = self[index[i][j]][j]    dim 1: out[i][j] = self[i][index[i][j]]
+scatter_add dim 0: self[index[i][j]][j] += src[i][j]  dim 1: self[i][index[i][j]] += src[i][j]
+*/
+
+impl RawDense<f32> {
+    // index[i][j]が指すselfの要素の位置
+    fn index_position(shape: (usize, usize), dim: usize, i: usize, j: usize, index: u32, op_type: &str) -> usize {
+        let (rows, cols) = shape;
+        match dim {
+            0 => check_index(index, rows, op_type) * cols + j,
+            1 => i * cols + check_index(index, cols, op_type),
+            _ => {
+                LOGGER.error(format!("RawDense<f32>::{}() >> dim must be 0 or 1, found {}", op_type, dim));
+                panic!("")
+            }
+        }
+    }
+
+    pub fn gather2d(&self, shape: (usize, usize), dim: usize, index: &RawDense<u32>, index_shape: (usize, usize)) -> Self {
+        let (_, index_cols) = index_shape;
+        let body = index.body.iter().enumerate().map(|(k, idx)| {
+            self.body[Self::index_position(shape, dim, k / index_cols, k % index_cols, *idx, "gather2d")]
+        }).collect();
+        RawDense { body }
+    }
+
+    // inplace
+    pub fn scatter_add2d(&mut self, shape: (usize, usize), dim: usize, index: &RawDense<u32>, index_shape: (usize, usize), src: &Self) {
+        let (_, index_cols) = index_shape;
+        for (k, (idx, s)) in index.body.iter().zip(src.body.iter()).enumerate() {
+            self.body[Self::index_position(shape, dim, k / index_cols, k % index_cols, *idx, "scatter_add2d")] += *s;
+        }
+    }
+
+    // dim 0: indicesの行を並べた(indices.len(), cols)，dim 1: indicesの列を並べた(rows, indices.len())
+    pub fn index_select2d(&self, shape: (usize, usize), dim: usize, indices: &RawDense<u32>) -> Self {
+        let (rows, cols) = shape;
+        match dim {
+            0 => self.gather_rows(cols, indices),
+            1 => {
+                let mut body = Vec::with_capacity(rows * indices.body.len());
+                for r in 0..rows {
+                    for idx in indices.body.iter() {
+                        body.push(self.body[r * cols + check_index(*idx, cols, "index_select2d")]);
+                    }
+                }
+                RawDense { body }
+            }
+            _ => {
+                LOGGER.error(format!("RawDense<f32>::index_select2d() >> dim must be 0 or 1, found {}", dim));
+                panic!("")
+            }
+        }
+    }
+
+    // index_select2dの逆。inplace
+    pub fn index_add2d(&mut self, shape: (usize, usize), dim: usize, indices: &RawDense<u32>, src: &Self) {
+        let (rows, cols) = shape;
+        match dim {
+            0 => self.scatter_add_rows(cols, indices, src),
+            1 => {
+                let k = indices.body.len();
+                for r in 0..rows {
+                    for (i, idx) in indices.body.iter().enumerate() {
+                        self.body[r * cols + check_index(*idx, cols, "index_add2d")] += src.body[r * k + i];
+                    }
+                }
+            }
+            _ => {
+                LOGGER.error(format!("RawDense<f32>::index_add2d() >> dim must be 0 or 1, found {}", dim));
+                panic!("")
+            }
+        }
+    }
+
+    // maskがtrueの要素を順に並べる
+    pub fn masked_select(&self, mask: &RawBool) -> Self {
+        let body = self.body.iter().zip(mask.iter()).filter(|(_, m)| *m).map(|(x, _)| *x).collect();
+        RawDense { body }
+    }
+
+    pub fn masked_fill(&self, mask: &RawBool, value: f32) -> Self {
+        let mut new = self.clone();
+        new.replace_where_to_scalar(mask, value);
+        new
+    }
+}
@@ -0,0 +1,70 @@
+use rayon::prelude::*;
+
+use crate::logger::LOGGER;
+
+use super::{buffer_pool, MatmulGeometry, RawDense};
+
+
+/*
+matmul + bias + relu を1回のループで計算するfused kernel。graph最適化のop fusionで使う
+input (n, m), weight (m, o), bias (1, o), 出力 (n, o)
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearGeometry {
+    pub n: usize,
+    pub m: usize,
+    pub o: usize,
+    pub relu: bool,
+}
+impl LinearGeometry {
+    fn matmul(&self) -> MatmulGeometry {
+        MatmulGeometry { batch: 1, n: self.n, m: self.m, o: self.o, transpose_lhs: false, transpose_rhs: false }
+    }
+}
+
+impl RawDense<f32> {
+    pub fn fused_linear(input: &Self, weight: &Self, bias: &Self, geometry: &LinearGeometry) -> Self {
+        let LinearGeometry { n, m, o, relu } = *geometry;
+        if input.body.len() != n * m || weight.body.len() != m * o || bias.body.len() != o {
+            LOGGER.error(format!("RawDense<f32>::fused_linear() >> input length: {}, weight length: {}, bias length: {} are unmatched with {:?}",
+                input.body.len(), weight.body.len(), bias.body.len(), geometry));
+            panic!("")
+        }
+
+        let mut result = buffer_pool::take_zeroed(n * o);
+        result.par_chunks_mut(o.max(1)).enumerate().for_each(|(i, result_row)| {
+            result_row.copy_from_slice(&bias.body);
+            // ikj
+            for k in 0..m {
+                let a = input.body[i * m + k];
+                for (r, w) in result_row.iter_mut().zip(weight.body[k * o..(k + 1) * o].iter()) {
+                    *r += a * *w;
+                }
+            }
+            if relu {
+                for r in result_row.iter_mut() {
+                    *r = r.max(0.0);
+                }
+            }
+        });
+        RawDense { body: result }
+    }
+
+    // returns (dinput, dweight, dbias)。reluのmaskはoutputから作る
+    pub fn fused_linear_backward(dout: &Self, input: &Self, weight: &Self, output: &Self, geometry: &LinearGeometry) -> (Self, Self, Self) {
+        let LinearGeometry { o, relu, .. } = *geometry;
+        let dz = if relu {
+            RawDense { body: dout.body.iter().zip(output.body.iter()).map(|(d, y)| if *y > 0.0 { *d } else { 0.0 }).collect() }
+        } else {
+            dout.clone()
+        };
+        let mut dbias = buffer_pool::take_zeroed(o);
+        for row in dz.body.chunks(o.max(1)) {
+            for (db, d) in dbias.iter_mut().zip(row.iter()) {
+                *db += *d;
+            }
+        }
+        let (dinput, dweight) = Self::matmul_t_backward(&dz, input, weight, &geometry.matmul());
+        (dinput, dweight, RawDense { body: dbias })
+    }
+}
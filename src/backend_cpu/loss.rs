@@ -0,0 +1,197 @@
+use crate::{dtype::{PointwiseLoss, Reduction}, logger::LOGGER};
+
+use super::{buffer_pool, RawDense};
+
+
+// BCEでlog(0)にならないようにpytorchと同じく-100で止める
+const LOG_CLAMP: f32 = -100.0;
+
+fn reduce(losses: Vec<f32>, reduction: Reduction) -> RawDense<f32> {
+    match reduction {
+        Reduction::None => RawDense { body: losses },
+        Reduction::Sum => RawDense { body: vec![losses.iter().sum()] },
+        Reduction::Mean => RawDense { body: vec![losses.iter().sum::<f32>() / losses.len().max(1) as f32] },
+    }
+}
+
+// reductionされたdoutを要素iの上流勾配にする
+fn upstream<'a>(dout: &'a RawDense<f32>, len: usize, reduction: Reduction, op_type: &str) -> impl Fn(usize) -> f32 + 'a {
+    let expected = if reduction == Reduction::None { len } else { 1 };
+    if dout.body.len() != expected {
+        LOGGER.error(format!("RawDense<f32>::{}() >> dout length {} is unmatched with {} for {:?}", op_type, dout.body.len(), expected, reduction));
+        panic!("")
+    }
+    let scale = if reduction == Reduction::Mean { 1.0 / len.max(1) as f32 } else { 1.0 };
+    move |i| if reduction == Reduction::None { dout.body[i] } else { dout.body[0] * scale }
+}
+
+impl RawDense<f32> {
+    fn check_loss_pair(&self, target: &Self, op_type: &str) {
+        if self.body.len() != target.body.len() {
+            LOGGER.error(format!("RawDense<f32>::{}() >> predict length {} is unmatched with target length {}", op_type, self.body.len(), target.body.len()));
+            panic!("")
+        }
+    }
+
+    pub fn pointwise_loss(&self, target: &Self, loss: PointwiseLoss, reduction: Reduction) -> Self {
+        self.check_loss_pair(target, "pointwise_loss");
+        let losses = self.body.iter().zip(target.body.iter()).map(|(x, y)| {
+            let d = x - y;
+            match loss {
+                PointwiseLoss::Mse => d * d,
+                PointwiseLoss::L1 => d.abs(),
+                PointwiseLoss::Huber(delta) => if d.abs() <= delta { 0.5 * d * d } else { delta * (d.abs() - 0.5 * delta) },
+                PointwiseLoss::Bce => -(y * x.ln().max(LOG_CLAMP) + (1.0 - y) * (1.0 - x).ln().max(LOG_CLAMP)),
+                // max(x, 0) - xy + log(1 + exp(-|x|))
+                PointwiseLoss::BceWithLogits => x.max(0.0) - x * y + (-x.abs()).exp().ln_1p(),
+            }
+        }).collect();
+        reduce(losses, reduction)
+    }
+
+    // predictへの勾配。targetには勾配を流さない
+    pub fn pointwise_loss_backward(dout: &Self, predict: &Self, target: &Self, loss: PointwiseLoss, reduction: Reduction) -> Self {
+        predict.check_loss_pair(target, "pointwise_loss_backward");
+        let upstream = upstream(dout, predict.body.len(), reduction, "pointwise_loss_backward");
+        let body = predict.body.iter().zip(target.body.iter()).enumerate().map(|(i, (x, y))| {
+            let d = x - y;
+            let local = match loss {
+                PointwiseLoss::Mse => 2.0 * d,
+                PointwiseLoss::L1 => if d == 0.0 { 0.0 } else { d.signum() },
+                PointwiseLoss::Huber(delta) => d.clamp(-delta, delta),
+                PointwiseLoss::Bce => d / (x * (1.0 - x)).max(1e-12),
+                PointwiseLoss::BceWithLogits => 1.0 / (1.0 + (-x).exp()) - y,
+            };
+            upstream(i) * local
+        }).collect();
+        RawDense { body }
+    }
+
+    // selfは(rows, cols)のlog確率。loss_i = -self[i][target_i]
+    pub fn nll_loss(&self, cols: usize, target: &RawDense<u32>, reduction: Reduction) -> Self {
+        let rows = self.body.len() / cols;
+        if target.body.len() != rows {
+            LOGGER.error(format!("RawDense<f32>::nll_loss() >> target length {} is unmatched with rows {}", target.body.len(), rows));
+            panic!("")
+        }
+        let losses = target.body.iter().enumerate().map(|(i, t)| -self.body[i * cols + check_class(*t, cols, "nll_loss")]).collect();
+        reduce(losses, reduction)
+    }
+
+    pub fn nll_loss_backward(dout: &Self, shape: (usize, usize), target: &RawDense<u32>, reduction: Reduction) -> Self {
+        let (rows, cols) = shape;
+        let upstream = upstream(dout, rows, reduction, "nll_loss_backward");
+        let mut body = buffer_pool::take_zeroed(rows * cols);
+        for (i, t) in target.body.iter().enumerate() {
+            body[i * cols + check_class(*t, cols, "nll_loss_backward")] = -upstream(i);
+        }
+        RawDense { body }
+    }
+}
+
+fn check_class(class: u32, cols: usize, op_type: &str) -> usize {
+    let class = class as usize;
+    if class >= cols {
+        LOGGER.error(format!("RawDense<f32>::{}() >> class {} is out of range for {} classes", op_type, class, cols));
+        panic!("")
+    }
+    class
+}
+
+
+/*
+logits (rows, cols)とclass indexのtargetからのcross entropy。pytorchと同じ定義
+q = (1 - label_smoothing) onehot(t) + label_smoothing / cols
+loss_i = -sum_c w_c q_c log_softmax(x_i)_c （wがないときw_c = 1。ただしonehotの項はw_t）
+ignore_indexの行はloss 0で勾配も流さない
+Meanは無視しない行のw_tの合計で割る
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrossEntropyGeometry {
+    pub rows: usize,
+    pub cols: usize,
+    pub label_smoothing: f32,
+    pub ignore_index: Option<u32>,
+    pub reduction: Reduction,
+}
+impl CrossEntropyGeometry {
+    // 行ごとの(target class, w_t)。無視する行はNone
+    fn targets(&self, target: &RawDense<u32>, weight: Option<&[f32]>, op_type: &str) -> Vec<Option<(usize, f32)>> {
+        if target.body.len() != self.rows || weight.is_some_and(|w| w.len() != self.cols) {
+            LOGGER.error(format!("RawDense<f32>::{}() >> target length {} or weight length {:?} is unmatched with {:?}",
+                op_type, target.body.len(), weight.map(|w| w.len()), self));
+            panic!("")
+        }
+        target.body.iter().map(|t| {
+            if Some(*t) == self.ignore_index {
+                return None;
+            }
+            let t = check_class(*t, self.cols, op_type);
+            Some((t, weight.map_or(1.0, |w| w[t])))
+        }).collect()
+    }
+
+    fn mean_denominator(targets: &[Option<(usize, f32)>]) -> f32 {
+        targets.iter().flatten().map(|(_, w)| w).sum()
+    }
+
+    // loss_i = -sum_c a_c logp_c となる係数a_c
+    fn coefficient(&self, c: usize, t: usize, w_t: f32, weight: Option<&[f32]>) -> f32 {
+        let onehot = if c == t { (1.0 - self.label_smoothing) * w_t } else { 0.0 };
+        onehot + self.label_smoothing / self.cols as f32 * weight.map_or(1.0, |w| w[c])
+    }
+}
+
+impl RawDense<f32> {
+    // returns (loss, softmax)。softmaxはbackward用
+    pub fn cross_entropy(&self, target: &RawDense<u32>, weight: Option<&[f32]>, geometry: &CrossEntropyGeometry) -> (Self, Self) {
+        let cols = geometry.cols;
+        let targets = geometry.targets(target, weight, "cross_entropy");
+        let mut softmax = buffer_pool::take_zeroed(self.body.len());
+        let mut losses = buffer_pool::take_zeroed(geometry.rows);
+        for (i, row) in self.body.chunks(cols).enumerate() {
+            let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            let log_sum = row.iter().map(|x| (x - max).exp()).sum::<f32>().ln();
+            for (j, x) in row.iter().enumerate() {
+                softmax[i * cols + j] = (x - max - log_sum).exp();
+            }
+            if let Some((t, w_t)) = targets[i] {
+                losses[i] = -(0..cols).map(|c| geometry.coefficient(c, t, w_t, weight) * (row[c] - max - log_sum)).sum::<f32>();
+            }
+        }
+        let loss = match geometry.reduction {
+            Reduction::Mean => {
+                let denominator = CrossEntropyGeometry::mean_denominator(&targets);
+                // 全部無視されたときは0にする
+                RawDense { body: vec![if denominator > 0.0 { losses.iter().sum::<f32>() / denominator } else { 0.0 }] }
+            },
+            reduction => reduce(losses, reduction),
+        };
+        (loss, RawDense { body: softmax })
+    }
+
+    // dx_j = (sum_c a_c) p_j - a_j
+    pub fn cross_entropy_backward(dout: &Self, softmax: &Self, target: &RawDense<u32>, weight: Option<&[f32]>, geometry: &CrossEntropyGeometry) -> Self {
+        let cols = geometry.cols;
+        let targets = geometry.targets(target, weight, "cross_entropy_backward");
+        let upstream: Box<dyn Fn(usize) -> f32> = match geometry.reduction {
+            Reduction::Mean => {
+                let denominator = CrossEntropyGeometry::mean_denominator(&targets);
+                let scale = if denominator > 0.0 { dout.body[0] / denominator } else { 0.0 };
+                Box::new(move |_| scale)
+            },
+            reduction => Box::new(upstream(dout, geometry.rows, reduction, "cross_entropy_backward")),
+        };
+        let mut body = buffer_pool::take_zeroed(softmax.body.len());
+        for (i, target) in targets.iter().enumerate() {
+            let Some((t, w_t)) = *target else { continue };
+            let coefficients: Vec<f32> = (0..cols).map(|c| geometry.coefficient(c, t, w_t, weight)).collect();
+            let total: f32 = coefficients.iter().sum();
+            let up = upstream(i);
+            for (j, a) in coefficients.iter().enumerate() {
+                body[i * cols + j] = up * (total * softmax.body[i * cols + j] - a);
+            }
+        }
+        RawDense { body }
+    }
+}
@@ -1,5 +1,24 @@
-
 mod raw_bool;
 pub use raw_bool::RawBool;
 mod raw_dense;
-pub use raw_dense::RawDense;
\ No newline at end of file
+pub use raw_dense::RawDense;
+pub mod buffer_pool;
+mod broadcast;
+mod conv;
+pub use conv::Conv2dGeometry;
+mod pool;
+pub use pool::{Pool2dGeometry, PoolWindow};
+mod norm;
+mod dropout;
+mod index;
+mod concat;
+mod permute;
+mod bmm;
+mod softmax;
+mod activation;
+mod loss;
+pub use loss::CrossEntropyGeometry;
+pub use bmm::MatmulGeometry;
+mod linear;
+pub use linear::LinearGeometry;
+pub use norm::NormGeometry;
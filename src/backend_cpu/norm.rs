@@ -0,0 +1,114 @@
+use crate::logger::LOGGER;
+
+use super::{buffer_pool, RawDense};
+
+
+/*
+BatchNorm, LayerNormの正規化部分（affineはbroadcastの掛け算と足し算で行う）
+
+データを(outer, channels, inner)とみなし，channelごとにouterとinnerの要素で平均と分散をとる。
+BatchNorm1d (B, F): outer = B, channels = F, inner = 1
+BatchNorm2d (N, C, H, W): outer = N, channels = C, inner = H * W
+LayerNorm (B, F): outer = 1, channels = B, inner = F
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NormGeometry {
+    pub outer: usize,
+    pub channels: usize,
+    pub inner: usize,
+}
+impl NormGeometry {
+    // 1つのchannelの統計をとる要素数
+    pub fn reduce_size(&self) -> usize {
+        self.outer * self.inner
+    }
+
+    #[inline(always)]
+    fn channel_of(&self, index: usize) -> usize {
+        (index / self.inner) % self.channels
+    }
+
+    fn check_len(&self, len: usize, op_type: &str) {
+        if len != self.outer * self.channels * self.inner {
+            LOGGER.error(format!("RawDense<f32>::{}() >> length: {} is unmatched with {:?}", op_type, len, self));
+            panic!("")
+        }
+    }
+}
+
+impl RawDense<f32> {
+    // returns (mean, var) of each channel. varは標本分散（Nで割る）
+    pub fn channel_mean_var(&self, geometry: &NormGeometry) -> (Self, Self) {
+        let g = geometry;
+        g.check_len(self.body.len(), "channel_mean_var");
+        let count = g.reduce_size() as f32;
+
+        let mut mean = buffer_pool::take_zeroed(g.channels);
+        for (i, x) in self.body.iter().enumerate() {
+            mean[g.channel_of(i)] += *x;
+        }
+        mean.iter_mut().for_each(|m| *m /= count);
+
+        let mut var = buffer_pool::take_zeroed(g.channels);
+        for (i, x) in self.body.iter().enumerate() {
+            let c = g.channel_of(i);
+            var[c] += (*x - mean[c]) * (*x - mean[c]);
+        }
+        var.iter_mut().for_each(|v| *v /= count);
+
+        (RawDense { body: mean }, RawDense { body: var })
+    }
+
+    // returns (x_hat, inv_std). x_hat = (x - mean) / sqrt(var + eps)
+    pub fn normalize(&self, geometry: &NormGeometry, mean: &Self, var: &Self, eps: f32) -> (Self, Self) {
+        let g = geometry;
+        g.check_len(self.body.len(), "normalize");
+        let inv_std: Vec<f32> = var.body.iter().map(|v| 1.0 / (*v + eps).sqrt()).collect();
+
+        let x_hat = self.body.iter().enumerate().map(|(i, x)| {
+            let c = g.channel_of(i);
+            (*x - mean.body[c]) * inv_std[c]
+        }).collect();
+        (RawDense { body: x_hat }, RawDense { body: inv_std })
+    }
+
+    // batch_stats: 平均と分散をこのbatchから計算したか。
+    // trueのときは平均と分散もxの関数なのでその分の勾配を含める
+    pub fn normalize_backward(dx_hat: &Self, x_hat: &Self, inv_std: &Self, geometry: &NormGeometry, batch_stats: bool) -> Self {
+        let g = geometry;
+        g.check_len(dx_hat.body.len(), "normalize_backward");
+        if !batch_stats {
+            let body = dx_hat.body.iter().enumerate().map(|(i, d)| *d * inv_std.body[g.channel_of(i)]).collect();
+            return RawDense { body };
+        }
+
+        // dx = inv_std / M * (M * dx_hat - sum(dx_hat) - x_hat * sum(dx_hat * x_hat))
+        let count = g.reduce_size() as f32;
+        let mut sum = buffer_pool::take_zeroed(g.channels);
+        let mut sum_x_hat = buffer_pool::take_zeroed(g.channels);
+        for (i, (d, x)) in dx_hat.body.iter().zip(x_hat.body.iter()).enumerate() {
+            let c = g.channel_of(i);
+            sum[c] += *d;
+            sum_x_hat[c] += *d * *x;
+        }
+        let body = dx_hat.body.iter().zip(x_hat.body.iter()).enumerate().map(|(i, (d, x))| {
+            let c = g.channel_of(i);
+            inv_std.body[c] / count * (count * *d - sum[c] - *x * sum_x_hat[c])
+        }).collect();
+        RawDense { body }
+    }
+}
+
+impl RawDense<f32> {
+    // running statsの更新。(1 - momentum) * self + momentum * scale * batch
+    // scaleは分散の不偏推定への補正 M / (M - 1) に使う
+    pub fn momentum_update(&self, batch: &Self, momentum: f32, scale: f32) -> Self {
+        if self.body.len() != batch.body.len() {
+            LOGGER.error(format!("RawDense<f32>::momentum_update() >> length unmatched. self: {}, batch: {}", self.body.len(), batch.body.len()));
+            panic!("")
+        }
+        let body = self.body.iter().zip(batch.body.iter()).map(|(r, b)| (1.0 - momentum) * *r + momentum * scale * *b).collect();
+        RawDense { body }
+    }
+}
@@ -0,0 +1,41 @@
+use crate::logger::LOGGER;
+
+use super::RawDense;
+
+
+impl RawDense<f32> {
+    // 軸の並べ替え。出力のi番目の軸は入力のperm[i]番目の軸
+    // ex) dims [2, 3], perm [1, 0] -> transpose
+    pub fn permute(&self, dims: &[usize], perm: &[usize]) -> Self {
+        let rank = dims.len();
+        let mut seen = vec![false; rank];
+        if perm.len() != rank || perm.iter().any(|p| *p >= rank || std::mem::replace(&mut seen[*p], true)) {
+            LOGGER.error(format!("RawDense<f32>::permute() >> {:?} is not a permutation of {} axes", perm, rank));
+            panic!("")
+        }
+
+        // 入力の各軸のstride
+        let mut in_strides = vec![1; rank];
+        for i in (0..rank.saturating_sub(1)).rev() {
+            in_strides[i] = in_strides[i + 1] * dims[i + 1];
+        }
+        let out_dims: Vec<usize> = perm.iter().map(|p| dims[*p]).collect();
+        let strides: Vec<usize> = perm.iter().map(|p| in_strides[*p]).collect();
+
+        let mut body = Vec::with_capacity(self.body.len());
+        let mut index = vec![0; rank];
+        for _ in 0..self.body.len() {
+            let offset: usize = index.iter().zip(strides.iter()).map(|(i, s)| i * s).sum();
+            body.push(self.body[offset]);
+            // 出力のindexを1つ進める
+            for axis in (0..rank).rev() {
+                index[axis] += 1;
+                if index[axis] < out_dims[axis] {
+                    break;
+                }
+                index[axis] = 0;
+            }
+        }
+        RawDense { body }
+    }
+}
@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use crate::logger::LOGGER;
+
+use super::{buffer_pool, RawDense};
+
+
+// (height, width)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolWindow {
+    Fixed {
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        padding: (usize, usize),
+    },
+    // 出力サイズから窓を決める。pytorchのAdaptiveAvgPool2dと同じ
+    Adaptive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pool2dGeometry {
+    pub batch: usize,
+    pub channels: usize,
+    pub in_height: usize,
+    pub in_width: usize,
+    pub out_height: usize,
+    pub out_width: usize,
+    pub window: PoolWindow,
+}
+impl Pool2dGeometry {
+    // 窓が入りきらないときはNone
+    pub fn out_size(in_size: usize, kernel: usize, stride: usize, padding: usize) -> Option<usize> {
+        if stride == 0 || kernel == 0 || in_size + 2 * padding < kernel {
+            return None;
+        }
+        Some((in_size + 2 * padding - kernel) / stride + 1)
+    }
+
+    // 出力位置(oh, ow)の窓をpaddingを除いた入力の範囲で返す。
+    // 3つ目は平均をとるときの割る数で，Fixedではpaddingを含めたカーネルの面積
+    fn window(&self, oh: usize, ow: usize) -> (Range<usize>, Range<usize>, usize) {
+        match self.window {
+            PoolWindow::Fixed { kernel, stride, padding } => {
+                let clip = |start: usize, size: usize, pad: usize, limit: usize| {
+                    start.saturating_sub(pad)..(start + size).saturating_sub(pad).min(limit)
+                };
+                (
+                    clip(oh * stride.0, kernel.0, padding.0, self.in_height),
+                    clip(ow * stride.1, kernel.1, padding.1, self.in_width),
+                    kernel.0 * kernel.1,
+                )
+            }
+            PoolWindow::Adaptive => {
+                let h = (oh * self.in_height / self.out_height)..((oh + 1) * self.in_height).div_ceil(self.out_height);
+                let w = (ow * self.in_width / self.out_width)..((ow + 1) * self.in_width).div_ceil(self.out_width);
+                let count = h.len() * w.len();
+                (h, w, count)
+            }
+        }
+    }
+
+    fn check_len(&self, len: usize, op_type: &str) {
+        if len != self.batch * self.channels * self.in_height * self.in_width {
+            LOGGER.error(format!("RawDense<f32>::{}() >> length: {} is unmatched with {:?}", op_type, len, self));
+            panic!("")
+        }
+    }
+}
+
+impl RawDense<f32> {
+    // returns (output, argmax). argmaxはinputのbodyでのindex
+    pub fn max_pool2d(&self, geometry: &Pool2dGeometry) -> (Self, RawDense<u32>) {
+        let g = geometry;
+        g.check_len(self.body.len(), "max_pool2d");
+        let mut output = Vec::with_capacity(g.batch * g.channels * g.out_height * g.out_width);
+        let mut argmax = Vec::with_capacity(output.capacity());
+
+        for plane in 0..g.batch * g.channels {
+            let offset = plane * g.in_height * g.in_width;
+            for oh in 0..g.out_height {
+                for ow in 0..g.out_width {
+                    let (h_range, w_range, _) = g.window(oh, ow);
+                    // 窓がすべてpaddingのときは0を出力し，勾配は流さない
+                    let mut largest = f32::NEG_INFINITY;
+                    let mut index = u32::MAX;
+                    for ih in h_range {
+                        for iw in w_range.clone() {
+                            let i = offset + ih * g.in_width + iw;
+                            if self.body[i] > largest {
+                                largest = self.body[i];
+                                index = i as u32;
+                            }
+                        }
+                    }
+                    if index == u32::MAX {
+                        largest = 0.0;
+                    }
+                    output.push(largest);
+                    argmax.push(index);
+                }
+            }
+        }
+        (RawDense { body: output }, RawDense { body: argmax })
+    }
+
+    pub fn max_pool2d_backward(dout: &Self, argmax: &RawDense<u32>, geometry: &Pool2dGeometry) -> Self {
+        let g = geometry;
+        let mut dinput = buffer_pool::take_zeroed(g.batch * g.channels * g.in_height * g.in_width);
+        for (d, i) in dout.body.iter().zip(argmax.body.iter()) {
+            if *i != u32::MAX {
+                dinput[*i as usize] += *d;
+            }
+        }
+        RawDense { body: dinput }
+    }
+
+    pub fn avg_pool2d(&self, geometry: &Pool2dGeometry) -> Self {
+        let g = geometry;
+        g.check_len(self.body.len(), "avg_pool2d");
+        let mut output = Vec::with_capacity(g.batch * g.channels * g.out_height * g.out_width);
+
+        for plane in 0..g.batch * g.channels {
+            let offset = plane * g.in_height * g.in_width;
+            for oh in 0..g.out_height {
+                for ow in 0..g.out_width {
+                    let (h_range, w_range, count) = g.window(oh, ow);
+                    let mut sum = 0.0;
+                    for ih in h_range {
+                        for iw in w_range.clone() {
+                            sum += self.body[offset + ih * g.in_width + iw];
+                        }
+                    }
+                    output.push(sum / count as f32);
+                }
+            }
+        }
+        RawDense { body: output }
+    }
+
+    pub fn avg_pool2d_backward(dout: &Self, geometry: &Pool2dGeometry) -> Self {
+        let g = geometry;
+        let mut dinput = buffer_pool::take_zeroed(g.batch * g.channels * g.in_height * g.in_width);
+
+        for plane in 0..g.batch * g.channels {
+            let offset = plane * g.in_height * g.in_width;
+            for oh in 0..g.out_height {
+                for ow in 0..g.out_width {
+                    let (h_range, w_range, count) = g.window(oh, ow);
+                    let d = dout.body[(plane * g.out_height + oh) * g.out_width + ow] / count as f32;
+                    for ih in h_range {
+                        for iw in w_range.clone() {
+                            dinput[offset + ih * g.in_width + iw] += d;
+                        }
+                    }
+                }
+            }
+        }
+        RawDense { body: dinput }
+    }
+}
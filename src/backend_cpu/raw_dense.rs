@@ -3,7 +3,7 @@ use std::{fmt::{write, Debug}, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAss
 
 use crate::{dtype::Shape, logger::LOGGER, machine_config::MACHINE_CONFIG};
 
-use super::RawBool;
+use super::{buffer_pool, RawBool};
 
 
 // todo!
@@ -31,7 +31,7 @@ impl RawDense<f32>
     pub fn matmul(lhs: &Self, lhs_shape: Shape, rhs: &Self, rhs_shape: Shape) -> RawDense<f32> {
         match (lhs_shape, rhs_shape) {
             (Shape::D2(lhs_rows, lhs_cols), Shape::D2(rhs_rows, rhs_cols)) if lhs_cols == rhs_rows => {
-                let mut result = vec![0.0; lhs_rows * rhs_cols];
+                let mut result = buffer_pool::take_zeroed(lhs_rows * rhs_cols);
 
                 // ikj
                 // mnist程度では１コアでやったほうがはやいのでpar_chanks_mutからparをとっている
@@ -49,12 +49,12 @@ impl RawDense<f32>
     }
 
     pub fn mul_scalar(&mut self, scalar: f32) -> &mut Self {
-        self.body.iter_mut().map(|i| *i * scalar);
+        self.body.iter_mut().for_each(|i| *i *= scalar);
         self
     }
 
     pub fn div_scalar(&mut self, scalar: f32) -> &mut Self {
-        self.body.iter_mut().map(|i| *i / scalar);
+        self.body.iter_mut().for_each(|i| *i /= scalar);
         self
     }
 
@@ -83,7 +83,7 @@ impl RawDense<f32>
                 self.clone()
             },
             Shape::D2(r, c) => {
-                let mut sum = vec![0.0; c];
+                let mut sum = buffer_pool::take_zeroed(c);
                 for chunk in self.body.chunks(c) {
                     for (s, i) in sum.iter_mut().zip(chunk.iter()) {
                         *s += *i;
@@ -93,6 +93,10 @@ impl RawDense<f32>
                     body: sum,
                 }
             }
+            _ => {
+                LOGGER.error(format!("RawDense<f32>.sum_batch() >> {} is not supported", shape.to_string()));
+                panic!("");
+            }
         }
     }
 
@@ -144,6 +148,10 @@ impl RawDense<f32>
                 }
                 self.body = transposed_body;
             }
+            _ => {
+                LOGGER.error(format!("RawDense<f32>.transpose() >> {} is not supported", shape.to_string()));
+                panic!("");
+            }
         }
     }
 
@@ -153,7 +161,7 @@ impl RawDense<f32>
     <F: Fn(T, T) -> T>(operation: F)、コンパイル時に型情報を解決、オーバーヘッドなし
      */
     #[inline(always)]
-    fn template_op<F: Fn(f32, f32) -> f32 + std::marker::Sync>(&self, other: &Self, op_type: &str, operation: F) -> Self {
+    pub(super) fn template_op<F: Fn(f32, f32) -> f32 + std::marker::Sync>(&self, other: &Self, op_type: &str, operation: F) -> Self {
         if self.body.len() != other.body.len() {
             panic!("Error: failed to excute {op_type}. left body.len() is {} but ritht body.len() is {}", self.body.len(), other.body.len());
         }
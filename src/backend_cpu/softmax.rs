@@ -0,0 +1,61 @@
+use rayon::prelude::*;
+
+use crate::logger::LOGGER;
+
+use super::{buffer_pool, RawBool, RawDense};
+
+
+/*
+最後の軸(cols)でのsoftmax(scale * x)
+maskは(mask_rows, cols)で先頭の軸にはbroadcastする。falseの要素は確率0
+すべてmaskされた行は0になる
+*/
+impl RawDense<f32> {
+    fn check_softmax_mask(&self, cols: usize, mask: Option<&RawBool>, op_type: &str) -> usize {
+        let rows = self.body.len() / cols;
+        match mask {
+            Some(mask) if mask.len == 0 || !mask.len.is_multiple_of(cols) || !rows.is_multiple_of(mask.len / cols) => {
+                LOGGER.error(format!("RawDense<f32>::{}() >> mask length {} can not broadcast to ({}, {})", op_type, mask.len, rows, cols));
+                panic!("")
+            },
+            Some(mask) => mask.len / cols,
+            None => rows,
+        }
+    }
+
+    pub fn softmax(&self, cols: usize, scale: f32, mask: Option<&RawBool>) -> Self {
+        let mask_rows = self.check_softmax_mask(cols, mask, "softmax");
+        let mask: Option<Vec<bool>> = mask.map(|m| m.iter().collect());
+        let mut result = self.body.clone();
+        result.par_chunks_mut(cols).enumerate().for_each(|(row, result_row)| {
+            let keep = |j: usize| mask.as_ref().is_none_or(|m| m[(row % mask_rows) * cols + j]);
+            let max = (0..cols).filter(|j| keep(*j)).map(|j| result_row[j] * scale).fold(f32::NEG_INFINITY, f32::max);
+            let mut sum = 0.0;
+            for (j, r) in result_row.iter_mut().enumerate() {
+                *r = if keep(j) { (*r * scale - max).exp() } else { 0.0 };
+                sum += *r;
+            }
+            if sum > 0.0 {
+                result_row.iter_mut().for_each(|r| *r /= sum);
+            }
+        });
+        RawDense { body: result }
+    }
+
+    // y = softmax(scale * x) のとき dx = scale * y * (dy - sum(dy * y))
+    // maskされた要素はy = 0なので勾配も0になる
+    pub fn softmax_backward(dout: &Self, output: &Self, cols: usize, scale: f32) -> Self {
+        if dout.body.len() != output.body.len() {
+            LOGGER.error(format!("RawDense<f32>::softmax_backward() >> dout length {} is unmatched with output length {}", dout.body.len(), output.body.len()));
+            panic!("")
+        }
+        let mut result = buffer_pool::take_zeroed(dout.body.len());
+        result.par_chunks_mut(cols).zip(dout.body.par_chunks(cols).zip(output.body.par_chunks(cols))).for_each(|(result_row, (dy, y))| {
+            let dot: f32 = dy.iter().zip(y.iter()).map(|(a, b)| a * b).sum();
+            for ((r, dy), y) in result_row.iter_mut().zip(dy.iter()).zip(y.iter()) {
+                *r = scale * y * (dy - dot);
+            }
+        });
+        RawDense { body: result }
+    }
+}
@@ -0,0 +1,200 @@
+use rayon::prelude::*;
+
+use crate::{
+    autograd::{Autograd, VarStore}, logger::LOGGER, nten::Nten2d, optimizer::Optimizer, tensor::Tensor
+};
+
+/*
+data parallel。モデルのreplicaをN個作り，それぞれ別のスレッドで自分のAutogradを使ってbatchの一部（shard）をforward, backwardする
+parameterの勾配はtree all-reduceで平均してreplica 0に集め，optimizerのupdateは1回だけ行う
+replicaのparameterの値はreplica 0とArcを共有しているので，update後に配り直さなくてよい
+bufferは共有しない（BatchNormのrunning statsを同時に書き換えないように）。stepの後にreplica 0の値を配る（pytorchのDDPと同じ）
+replicaどうしはVarStoreの名前（key）で対応させるので，buildは毎回同じ名前のparameterを作ること
+
+ex)
+let mut dp = DataParallel::new(4, |vs| Mlp::new(vs));
+let loss = dp.step(&mut optimizer, |i, model, vs| {
+    let x = Nten2d::new_from_val(shards[i].clone()).as_input(vs);
+    loss_fn::mse_loss(&model.forward(&x), &targets[i], Reduction::Mean)
+});
+*/
+
+struct Replica<M> {
+    ag: Autograd,
+    vs: VarStore,
+    model: M,
+}
+
+pub struct DataParallel<M> {
+    replicas: Vec<Replica<M>>,
+}
+impl<M: Send> DataParallel<M> {
+    pub fn new(n_replicas: usize, build: impl Fn(&mut VarStore) -> M) -> Self {
+        if n_replicas == 0 {
+            LOGGER.error("DataParallel::new() >> n_replicas must be at least 1".to_string());
+            panic!("")
+        }
+        let mut replicas: Vec<Replica<M>> = (0..n_replicas).map(|_| {
+            let mut ag = Autograd::new();
+            let mut vs = ag.get_vs();
+            let model = build(&mut vs);
+            Replica { ag, vs, model }
+        }).collect();
+
+        let (first, rest) = replicas.split_first_mut().unwrap();
+        for replica in rest {
+            replica.vs.share_parameters_from(&first.vs);
+            replica.vs.copy_buffers_from(&first.vs);
+        }
+        Self { replicas }
+    }
+
+    pub fn n_replicas(&self) -> usize {
+        self.replicas.len()
+    }
+    // replica 0のVarStore。state_dictで保存や推論用のモデルに移す
+    pub fn varstore(&self) -> VarStore {
+        self.replicas[0].vs.clone()
+    }
+
+    // replicaごとにseed + iで初期化する
+    pub fn manual_seed(&mut self, seed: u64) {
+        for (i, replica) in self.replicas.iter_mut().enumerate() {
+            replica.ag.manual_seed(seed + i as u64);
+        }
+    }
+    pub fn train(&mut self) {
+        for replica in self.replicas.iter_mut() {
+            replica.ag.train();
+        }
+    }
+
+    /*
+    fはreplicaの番号，モデル，VarStoreを受け取ってそのshardのlossを返す
+    lossはshardの平均（Reduction::Mean）にして，shardの大きさをそろえること
+    そうすれば勾配の平均がbatch全体の平均のlossの勾配になる
+    返り値はreplicaのlossの平均
+    */
+    pub fn step<F>(&mut self, optimizer: &mut dyn Optimizer, f: F) -> f32
+    where F: Fn(usize, &M, &mut VarStore) -> Nten2d<1, 1, f32> + Sync {
+        let losses: Vec<f32> = self.replicas.par_iter_mut().enumerate().map(|(i, replica)| {
+            replica.ag.zero_grad();
+            let loss = f(i, &replica.model, &mut replica.vs);
+            let [loss] = replica.ag.step_forward([loss.to_untyped()]);
+            replica.ag.backward(&loss);
+            loss.val.unwrap().to_vec_f32()[0]
+        }).collect();
+
+        self._all_reduce_grads();
+        optimizer.update(self.replicas[0].ag.context_mut());
+
+        let (first, rest) = self.replicas.split_first_mut().unwrap();
+        for replica in rest {
+            replica.vs.copy_buffers_from(&first.vs);
+        }
+        losses.iter().sum::<f32>() / losses.len() as f32
+    }
+
+    // 勾配を名前順に並べ，replicaを2つずつ足していく（tree all-reduce）。平均をreplica 0のgradにする
+    fn _all_reduce_grads(&mut self) {
+        let mut grads: Vec<Vec<Option<Tensor>>> = self.replicas.iter_mut().map(|replica| {
+            let keys = replica.vs.parameter_keys();
+            let ctx = replica.ag.context_mut();
+            keys.iter().map(|(_, id)| ctx.try_get_grad(id)).collect()
+        }).collect();
+
+        let mut stride = 1;
+        while stride < grads.len() {
+            grads.par_chunks_mut(2 * stride).for_each(|chunk| {
+                if chunk.len() <= stride {
+                    return;
+                }
+                let (left, right) = chunk.split_at_mut(stride);
+                for (sum, grad) in left[0].iter_mut().zip(right[0].iter_mut()) {
+                    *sum = match (sum.take(), grad.take()) {
+                        (Some(sum), Some(grad)) => Some(sum.add(&grad).unwrap()),
+                        (sum, grad) => sum.or(grad),
+                    };
+                }
+            });
+            stride *= 2;
+        }
+
+        let scale = 1.0 / self.replicas.len() as f32;
+        let keys = self.replicas[0].vs.parameter_keys();
+        let ctx = self.replicas[0].ag.context_mut();
+        for ((_, id), grad) in keys.iter().zip(grads.swap_remove(0)) {
+            if let Some(grad) = grad {
+                ctx.replace_grad(id, grad.mul_scalar(scale));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{autograd::{Autograd, VarStore}, dtype::{Reduction, Shape}, loss_fn, nn::Linear, nten::Nten2d, optimizer::{Optimizer, Sgd}, tensor::{Tensor, Tensor2d}, test_utils::{assert_close, sample}};
+
+    use super::DataParallel;
+
+    const REPLICAS: usize = 4;
+    const SHARD: usize = 2;
+    const BATCH: usize = REPLICAS * SHARD;
+
+    struct Mlp {
+        linear1: Linear<3, 4>,
+        linear2: Linear<4, 1>,
+    }
+    impl Mlp {
+        fn new(vs: &mut VarStore) -> Self {
+            Self { linear1: Linear::new(&mut vs.sub("linear1")), linear2: Linear::new(&mut vs.sub("linear2")) }
+        }
+        fn loss<const B: usize>(&self, vs: &mut VarStore, x: Vec<f32>, y: Vec<f32>) -> Nten2d<1, 1, f32> {
+            let x = Nten2d::new_from_val(Tensor2d::<B, 3, f32>::new_from_vec(x).unwrap()).name("input").as_input(vs);
+            let y = Nten2d::new_from_val(Tensor2d::<B, 1, f32>::new_from_vec(y).unwrap()).name("target").as_input(vs);
+            loss_fn::mse_loss(&self.linear2.forward(&self.linear1.forward(&x).tanh()), &y, Reduction::Mean)
+        }
+    }
+    // stepごとのreplicaのshard (x, y)
+    fn shards(step: u64) -> Vec<(Vec<f32>, Vec<f32>)> {
+        (0..REPLICAS as u64).map(|i| {
+            (sample(Shape::D2(SHARD, 3), 10 * step + i).to_vec_f32(), sample(Shape::D2(SHARD, 1), 100 * step + i).to_vec_f32())
+        }).collect()
+    }
+
+    // shardに分けて平均した勾配で更新しても，batch全体で1回更新したのと同じになる
+    #[test]
+    fn data_parallel_matches_full_batch() {
+        let mut dp = DataParallel::new(REPLICAS, Mlp::new);
+        let initial = dp.varstore().state_dict();
+
+        let mut autograd = Autograd::new();
+        let mut vs = autograd.get_vs();
+        let model = Mlp::new(&mut vs);
+        vs.load_state_dict(&initial);
+
+        let mut dp_optimizer = Sgd::new(0.1);
+        let mut optimizer = Sgd::new(0.1);
+        for step in 0..3 {
+            let shards = shards(step);
+            let dp_loss = dp.step(&mut dp_optimizer, |i, model, vs| model.loss::<SHARD>(vs, shards[i].0.clone(), shards[i].1.clone()));
+
+            let x = shards.iter().flat_map(|(x, _)| x.clone()).collect();
+            let y = shards.iter().flat_map(|(_, y)| y.clone()).collect();
+            let [loss] = autograd.step_forward([model.loss::<BATCH>(&mut vs, x, y).to_untyped()]);
+            optimizer.update(autograd.backward(&loss));
+            autograd.zero_grad();
+
+            assert_close(&[dp_loss], &loss.val.unwrap().to_vec_f32(), 1e-5);
+        }
+
+        let (trained, dp_trained): (HashMap<String, Tensor>, HashMap<String, Tensor>) = (vs.state_dict(), dp.varstore().state_dict());
+        assert_eq!(trained.len(), dp_trained.len());
+        for (key, val) in trained.iter() {
+            assert_close(&dp_trained[key].to_vec_f32(), &val.to_vec_f32(), 1e-5);
+        }
+    }
+}
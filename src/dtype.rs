@@ -4,10 +4,11 @@ use std::fmt::{format, Debug, Display, Formatter};
 
 
 // トレイト'staticは内部に参照を含まないことを保証する
-pub trait Dtype: Copy + Debug + PartialOrd + Sized + 'static {
+pub trait Dtype: Copy + Debug + PartialOrd + Sized + Send + Sync + 'static {
     /*
     PartialOrd: 
     Sized: for transmute
+    Send + Sync: FnEdgeを並列に実行するため（Autograd::set_parallel）
      */
     fn default() -> Self;
     fn type_name() -> String;
@@ -44,6 +45,29 @@ impl Dtype for f32 {
     }
 }
 
+// Embeddingなどのindex用
+impl Dtype for u32 {
+    fn default() -> Self {
+        0
+    }
+
+    fn type_name() -> String {
+        "u32".to_string()
+    }
+
+    fn from_f32(x: f32) -> Self {
+        x as u32
+    }
+
+    fn to_f32(&self) -> Result<f32, ()> {
+        Ok(*self as f32)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
 impl Dtype for bool {
     fn default() -> Self {
         false
@@ -71,12 +95,18 @@ impl Dtype for bool {
 pub enum Shape {
     D1(usize),
     D2(usize, usize),
+    // B, R, C. batch of matrix
+    D3(usize, usize, usize),
+    // N, C, H, W
+    D4(usize, usize, usize, usize),
 }
 impl Shape {
     pub fn to_string(&self) -> String {
         match self {
             Self::D1(i) => format!("Shape::D1({})", i),
             Self::D2(i, j) => format!("Shape::D2({}, {})", i, j),
+            Self::D3(b, i, j) => format!("Shape::D3({}, {}, {})", b, i, j),
+            Self::D4(n, c, h, w) => format!("Shape::D4({}, {}, {}, {})", n, c, h, w),
         }
     }
 }
@@ -85,6 +115,157 @@ impl Display for Shape {
         write!(f, "{}", self.to_string())
     }
 }
+impl Shape {
+    // 次元を外側から順に並べたもの。D2(r, c) -> [r, c]
+    pub fn dims(&self) -> Vec<usize> {
+        match self {
+            Self::D1(i) => vec![*i],
+            Self::D2(i, j) => vec![*i, *j],
+            Self::D3(b, i, j) => vec![*b, *i, *j],
+            Self::D4(n, c, h, w) => vec![*n, *c, *h, *w],
+        }
+    }
+
+    pub fn from_dims(dims: &[usize]) -> Result<Self, String> {
+        match dims {
+            [i] => Ok(Self::D1(*i)),
+            [i, j] => Ok(Self::D2(*i, *j)),
+            [b, i, j] => Ok(Self::D3(*b, *i, *j)),
+            [n, c, h, w] => Ok(Self::D4(*n, *c, *h, *w)),
+            _ => Err(format!("Shape::from_dims() >> rank {} is not supported", dims.len())),
+        }
+    }
+
+    // number of elements
+    pub fn numel(&self) -> usize {
+        self.dims().iter().product()
+    }
+
+    // axisの外側の要素数，axisの長さ，内側の要素数。concatやsplitで使う
+    // ex) D4(n, c, h, w), axis 1 -> (n, c, h * w)
+    pub fn around_axis(&self, axis: usize) -> Result<(usize, usize, usize), String> {
+        let dims = self.dims();
+        if axis >= dims.len() {
+            return Err(format!("Shape::around_axis() >> axis {} is out of range for {}", axis, self));
+        }
+        Ok((dims[..axis].iter().product(), dims[axis], dims[axis + 1..].iter().product()))
+    }
+
+    // NumPy style broadcasting. 次元は右詰めで比較し，1の軸が相手に合わせて伸びる
+    pub fn broadcast(&self, other: &Self) -> Result<Self, String> {
+        let lhs = self.dims();
+        let rhs = other.dims();
+        let rank = lhs.len().max(rhs.len());
+        let mut out = vec![0; rank];
+        for i in 0..rank {
+            // 足りない次元は1として扱う
+            let l = if i < rank - lhs.len() { 1 } else { lhs[i - (rank - lhs.len())] };
+            let r = if i < rank - rhs.len() { 1 } else { rhs[i - (rank - rhs.len())] };
+            out[i] = if l == r || r == 1 {
+                l
+            } else if l == 1 {
+                r
+            } else {
+                return Err(format!("Shape::broadcast() >> {} and {} can not be broadcasted", self, other));
+            };
+        }
+        Self::from_dims(&out)
+    }
+}
+
+// for compile time shape check of const generics.
+// use in const { assert!(..) } of typed front fn
+pub const fn is_broadcastable(lhs: usize, rhs: usize, out: usize) -> bool {
+    (lhs == rhs && out == lhs) || (lhs == 1 && out == rhs) || (rhs == 1 && out == lhs)
+}
+
+// permの各値が0..perm.len()にちょうど1回ずつ出てくるか
+pub const fn is_permutation(perm: &[usize]) -> bool {
+    let mut i = 0;
+    while i < perm.len() {
+        if perm[i] >= perm.len() {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < perm.len() {
+            if perm[i] == perm[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+// element wise binary operations shared by Storage, backend and FnEdge
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+impl BinaryOp {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Add => "Add",
+            Self::Sub => "Sub",
+            Self::Mul => "Mul",
+            Self::Div => "Div",
+            Self::Rem => "Rem",
+        }
+    }
+}
+
+// element wise activations whose derivative is written by the output
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum UnaryOp {
+    Sigmoid,
+    Tanh,
+}
+impl UnaryOp {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Sigmoid => "Sigmoid",
+            Self::Tanh => "Tanh",
+        }
+    }
+}
+
+// losses computed element by element from (predict, target)
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PointwiseLoss {
+    Mse,
+    L1,
+    // delta
+    Huber(f32),
+    // predictは確率
+    Bce,
+    // predictはlogit
+    BceWithLogits,
+}
+impl PointwiseLoss {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Mse => "MseLoss",
+            Self::L1 => "L1Loss",
+            Self::Huber(_) => "HuberLoss",
+            Self::Bce => "BceLoss",
+            Self::BceWithLogits => "BceWithLogitsLoss",
+        }
+    }
+}
+
+// how a loss is reduced to a scalar
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Reduction {
+    // 要素ごとのloss。入力と同じ形状
+    None,
+    Mean,
+    Sum,
+}
 
 // Sparse
 pub struct Sf16;
@@ -1,6 +1,7 @@
 use indicatif::ProgressBar;
+use rand::Rng;
 
-use crate::{autograd::{Autograd, Context, VarStore}, lantern_datasets, loss_fn, nten::{self, Nten2d}, optimizer::{Optimizer, Sgd}, tensor::Tensor2d};
+use crate::{autograd::{Autograd, Context, VarStore}, data_parallel::DataParallel, fn_edge::{Conv2dConfig, Pool2dConfig}, lantern_datasets, loss_fn::{self, Reduction}, nn, nten::{self, Nten2d, Nten3d, Nten4d}, optimizer::{Optimizer, Sgd}, tensor::{Tensor2d, Tensor3d, Tensor4d}};
 
 
 
@@ -39,14 +40,16 @@ struct Model<const B: usize, const H: usize> {
 impl<const B: usize, const H: usize> Model<B, H> {
     fn new(vs: &mut VarStore) -> Self {
         Self {
-            linear1: Linear::new(vs),
-            linear2: Linear::new(vs),
+            linear1: Linear::new(&mut vs.sub("linear1")),
+            linear2: Linear::new(&mut vs.sub("linear2")),
         }
     }
     fn forward(&self, input: &Nten2d<B, 784, f32>) -> Nten2d<B, 10, f32> {
         // layer層の操作
         let x: Nten2d<B, H, f32> = self.linear1.forward(input);
         let x: Nten2d<B, H, f32> = x.relu();
+        // 過学習を抑える。Autograd::eval()のときは何もしない
+        let x: Nten2d<B, H, f32> = x.dropout(0.2);
         let x: Nten2d<B, 10, f32> = self.linear2.forward(&x);
         // return x
         x
@@ -70,6 +73,10 @@ pub fn mnist() {
     // tools for learning
     // Autogradは構築した計算グラフを最適化・実行する
     let mut autograd = Autograd::new();
+    // dropoutのmaskを再現できるようにする
+    autograd.manual_seed(0);
+    // 実行前にテープを最適化する。matmul -> bias -> reluはFusedLinearにまとめて計算する
+    autograd.set_graph_optimization(true);
     // VarStoreはパラメーターと入力変数を格納する
     let mut vs = autograd.get_vs();
     // 
@@ -124,4 +131,479 @@ pub fn mnist() {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+
+// LeNet-5。conv -> tanh -> avg pool を2回，linearを3層
+// K: kernel size
+struct Conv<const CI: usize, const CO: usize, const K: usize> {
+    weight: Nten4d<CO, CI, K, K, f32>,
+    bias: Nten2d<1, CO, f32>,
+    config: Conv2dConfig,
+}
+impl<const CI: usize, const CO: usize, const K: usize> Conv<CI, CO, K> {
+    fn new(vs: &mut VarStore, config: Conv2dConfig) -> Self {
+        // Linearと同じくU(-\sqrt{k}, \sqrt{k}), k = 1 / (CI * K * K)
+        let k: f32 = 1.0 / (CI * K * K) as f32;
+        let weight: Tensor4d<CO, CI, K, K, f32> = Tensor4d::new_uniform(-k.sqrt(), k.sqrt());
+        let bias: Tensor2d<1, CO, f32> = Tensor2d::new_zeros();
+        Self {
+            weight: Nten4d::new_from_val(weight).name("Conv weight").as_parameter(vs),
+            bias: Nten2d::new_from_val(bias).name("Conv bias").as_parameter(vs),
+            config,
+        }
+    }
+    fn forward<const N: usize, const H: usize, const W: usize, const HO: usize, const WO: usize>(&self, input: &Nten4d<N, CI, H, W, f32>) -> Nten4d<N, CO, HO, WO, f32> {
+        nten::conv2d(input, &self.weight, Some(&self.bias), self.config)
+    }
+}
+
+struct LeNet<const B: usize> {
+    conv1: Conv<1, 6, 5>,
+    conv2: Conv<6, 16, 5>,
+    linear1: Linear<400, 120>,
+    linear2: Linear<120, 84>,
+    linear3: Linear<84, 10>,
+}
+impl<const B: usize> LeNet<B> {
+    fn new(vs: &mut VarStore) -> Self {
+        Self {
+            // 28x28のMNISTを元論文の32x32に合わせる
+            conv1: Conv::new(&mut vs.sub("conv1"), Conv2dConfig::new().padding(2, 2)),
+            conv2: Conv::new(&mut vs.sub("conv2"), Conv2dConfig::new()),
+            linear1: Linear::new(&mut vs.sub("linear1")),
+            linear2: Linear::new(&mut vs.sub("linear2")),
+            linear3: Linear::new(&mut vs.sub("linear3")),
+        }
+    }
+    fn forward(&self, input: &Nten2d<B, 784, f32>) -> Nten2d<B, 10, f32> {
+        let x: Nten4d<B, 1, 28, 28, f32> = input.reshape_4d();
+        let x: Nten4d<B, 6, 28, 28, f32> = self.conv1.forward(&x);
+        let x: Nten4d<B, 6, 14, 14, f32> = x.tanh().avg_pool2d(Pool2dConfig::new(2, 2));
+        let x: Nten4d<B, 16, 10, 10, f32> = self.conv2.forward(&x);
+        let x: Nten4d<B, 16, 5, 5, f32> = x.tanh().avg_pool2d(Pool2dConfig::new(2, 2));
+        // 16 * 5 * 5 = 400
+        let x: Nten2d<B, 400, f32> = x.flatten();
+        let x: Nten2d<B, 120, f32> = self.linear1.forward(&x).tanh();
+        let x: Nten2d<B, 84, f32> = self.linear2.forward(&x).tanh();
+        self.linear3.forward(&x)
+    }
+}
+pub fn lenet() {
+    const BATCH_SIZE: usize = 64;
+    let learning_rate: f32 = 0.05;
+    let num_epoch: usize = 1;
+    let print_interval: usize = 50;
+
+    let train_image_path = "./mnist_data/train-images.idx3-ubyte";
+    let train_label_path = "./mnist_data/train-labels.idx1-ubyte";
+    let (train_images, train_labels): (Vec<[[u8; 28]; 28]>, Vec<u8>)
+         = lantern_datasets::load_minst(train_image_path, train_label_path);
+    let train_images: Vec<[u8; 784]> = lantern_datasets::selialize_minst(&train_images);
+
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let mut optimizer = Sgd::new(learning_rate);
+
+    let model: LeNet<BATCH_SIZE> = LeNet::new(&mut vs);
+
+    println!("start learning");
+    for epoch in 0..num_epoch {
+        // labelはone-hotにせずclass indexのまま使う
+        let (train_image_batches,
+            train_label_batches): (Vec<Tensor2d<BATCH_SIZE, 784, f32>>, Vec<Tensor2d<BATCH_SIZE, 1, u32>>)
+             = lantern_datasets::shuffle_and_make_index_batch(&train_images, &train_labels);
+
+        for (i, (images, labels)) in train_image_batches.iter().zip(train_label_batches.iter()).enumerate() {
+            let images = Nten2d::new_from_val(images.clone())
+                .name("input")
+                .as_input(&mut vs);
+            let labels = Nten2d::new_from_val(labels.clone())
+                .name("labels")
+                .as_input(&mut vs);
+
+            let logits = model.forward(&images);
+            let loss: Nten2d<1, 1, f32> = loss_fn::cross_entropy(&logits, &labels, loss_fn::CrossEntropyConfig::new().label_smoothing(0.1));
+            let [logits, loss] = autograd.step_forward([logits.to_untyped(), loss.to_untyped()]);
+
+            let ctx: &mut Context = autograd.backward(&loss);
+            optimizer.update(ctx);
+            autograd.zero_grad();
+
+            if i % print_interval == 0 {
+                let predict_index = logits.val.unwrap().top_index_per_batch();
+                let label_index = labels.val.unwrap().to_untyped().to_vec_u32();
+                let acc = predict_index.iter().zip(label_index.iter()).filter(|(p, l)| **p == **l as usize).count();
+                println!("epoch {} loop {}, Loss: {}, acc: {:.2}%", epoch+1, i, loss.val.unwrap().to_vec_f32()[0], acc as f32/BATCH_SIZE as f32 * 100.0);
+            }
+        }
+    }
+}
+
+
+// 表形式データの回帰。深いMLPはBatchNorm1dがないと発散しやすい
+// D: 特徴数, H: hidden
+const TABULAR_DEPTH: usize = 6;
+struct DeepMlp<const D: usize, const H: usize> {
+    input: nn::Linear<D, H>,
+    hidden: Vec<(nn::Linear<H, H>, nn::BatchNorm1d<H>)>,
+    norm: nn::LayerNorm<H>,
+    output: nn::Linear<H, 1>,
+}
+impl<const D: usize, const H: usize> DeepMlp<D, H> {
+    fn new(vs: &mut VarStore) -> Self {
+        Self {
+            input: nn::Linear::new(&mut vs.sub("input")),
+            hidden: (0..TABULAR_DEPTH).map(|i| {
+                let mut vs = vs.sub(&format!("hidden{}", i));
+                (nn::Linear::new(&mut vs), nn::BatchNorm1d::new(&mut vs))
+            }).collect(),
+            norm: nn::LayerNorm::new(&mut vs.sub("norm")),
+            output: nn::Linear::new(&mut vs.sub("output")),
+        }
+    }
+    fn forward<const B: usize>(&self, input: &Nten2d<B, D, f32>) -> Nten2d<B, 1, f32> {
+        let mut x: Nten2d<B, H, f32> = self.input.forward(input).relu();
+        // 深いので各層の途中の値はforward後に捨てて，backwardで再計算する
+        for (linear, batch_norm) in self.hidden.iter() {
+            x = nten::checkpoint(&x, |x| batch_norm.forward(&linear.forward(x)).relu());
+        }
+        self.output.forward(&self.norm.forward(&x))
+    }
+}
+
+// y = sum_i sin(3 x_i) / D
+fn tabular_batch<const B: usize, const D: usize>() -> (Tensor2d<B, D, f32>, Tensor2d<B, 1, f32>) {
+    let x: Tensor2d<B, D, f32> = Tensor2d::new_uniform(-1.0, 1.0);
+    let y: Vec<f32> = x.to_untyped().to_vec_f32().chunks(D)
+        .map(|row| row.iter().map(|v| (3.0 * v).sin()).sum::<f32>() / D as f32)
+        .collect();
+    (x, Tensor2d::new_from_vec(y).unwrap())
+}
+
+pub fn tabular() {
+    const BATCH_SIZE: usize = 64;
+    const FEATURES: usize = 8;
+    let learning_rate: f32 = 0.05;
+    let num_step: usize = 300;
+    let print_interval: usize = 50;
+
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let mut optimizer = Sgd::new(learning_rate);
+    let model: DeepMlp<FEATURES, 32> = DeepMlp::new(&mut vs);
+
+    // 学習時はbatchの統計で正規化し，running statsを更新する
+    autograd.train();
+    for step in 0..num_step {
+        let (x, y) = tabular_batch::<BATCH_SIZE, FEATURES>();
+        let x = Nten2d::new_from_val(x).name("input").as_input(&mut vs);
+        let y = Nten2d::new_from_val(y).name("target").as_input(&mut vs);
+
+        let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&model.forward(&x), &y, Reduction::Mean);
+        let [loss] = autograd.step_forward([loss.to_untyped()]);
+        let ctx = autograd.backward(&loss);
+        optimizer.update(ctx);
+        autograd.zero_grad();
+
+        if step % print_interval == 0 {
+            println!("step {}, train loss: {}", step, loss.val.unwrap().to_vec_f32()[0]);
+        }
+    }
+
+    // 推論時はrunning statsで正規化する
+    autograd.eval();
+    let (x, y) = tabular_batch::<BATCH_SIZE, FEATURES>();
+    let x = Nten2d::new_from_val(x).name("input").as_input(&mut vs);
+    let y = Nten2d::new_from_val(y).name("target").as_input(&mut vs);
+    let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&model.forward(&x), &y, Reduction::Mean);
+    let [loss] = autograd.step_forward([loss.to_untyped()]);
+    println!("{:?} mode, test loss: {}", autograd.mode(), loss.val.unwrap().to_vec_f32()[0]);
+}
+
+
+// tabular()をdata parallelで学習する。batchをreplicaの数のshardに分け，各replicaが別のスレッドで1つずつ受け持つ
+// BatchNorm1dのrunning statsはreplica 0のものが残る
+pub fn tabular_data_parallel() {
+    const REPLICAS: usize = 4;
+    const SHARD_SIZE: usize = 16;
+    const FEATURES: usize = 8;
+    let learning_rate: f32 = 0.05;
+    let num_step: usize = 300;
+    let print_interval: usize = 50;
+
+    let mut dp: DataParallel<DeepMlp<FEATURES, 32>> = DataParallel::new(REPLICAS, DeepMlp::new);
+    let mut optimizer = Sgd::new(learning_rate);
+    dp.manual_seed(0);
+    dp.train();
+    println!("replicas: {}, batch size: {}", dp.n_replicas(), dp.n_replicas() * SHARD_SIZE);
+
+    for step in 0..num_step {
+        let shards: Vec<_> = (0..REPLICAS).map(|_| tabular_batch::<SHARD_SIZE, FEATURES>()).collect();
+        let loss = dp.step(&mut optimizer, |i, model, vs| {
+            let x = Nten2d::new_from_val(shards[i].0.clone()).name("input").as_input(vs);
+            let y = Nten2d::new_from_val(shards[i].1.clone()).name("target").as_input(vs);
+            loss_fn::mse_loss(&model.forward(&x), &y, Reduction::Mean)
+        });
+
+        if step % print_interval == 0 {
+            println!("step {}, train loss: {}", step, loss);
+        }
+    }
+
+    // 学習した値をstate_dictで別のAutogradのモデルに移して推論する
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let model: DeepMlp<FEATURES, 32> = DeepMlp::new(&mut vs);
+    vs.load_state_dict(&dp.varstore().state_dict());
+
+    autograd.eval();
+    let (x, y) = tabular_batch::<64, FEATURES>();
+    let x = Nten2d::new_from_val(x).name("input").as_input(&mut vs);
+    let y = Nten2d::new_from_val(y).name("target").as_input(&mut vs);
+    let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&model.forward(&x), &y, Reduction::Mean);
+    let [loss] = autograd.step_forward([loss.to_untyped()]);
+    println!("{:?} mode, test loss: {}", autograd.mode(), loss.val.unwrap().to_vec_f32()[0]);
+}
+
+// 行列分解による推薦。ユーザーとアイテムの埋め込みの内積を評価値とする
+// U: ユーザー数, I: アイテム数, D: 埋め込みの次元
+struct MatrixFactorization<const U: usize, const I: usize, const D: usize> {
+    user: nn::Embedding<U, D>,
+    item: nn::Embedding<I, D>,
+}
+impl<const U: usize, const I: usize, const D: usize> MatrixFactorization<U, I, D> {
+    fn new(vs: &mut VarStore) -> Self {
+        Self {
+            user: nn::Embedding::new(&mut vs.sub("user")),
+            item: nn::Embedding::new(&mut vs.sub("item")),
+        }
+    }
+    fn forward<const B: usize>(&self, vs: &mut VarStore, users: &Nten2d<B, 1, u32>, items: &Nten2d<B, 1, u32>) -> Nten2d<B, 1, f32> {
+        let product: Nten2d<B, D, f32> = self.user.forward(users).broadcast_mul(&self.item.forward(items));
+        // 行ごとの和
+        let ones = Nten2d::new_from_val(Tensor2d::<D, 1, f32>::new_ones()).name("ones").as_input(vs);
+        nten::matmul(&product, &ones)
+    }
+}
+
+pub fn recommend() {
+    const USERS: usize = 200;
+    const ITEMS: usize = 100;
+    const DIM: usize = 4;
+    const BATCH_SIZE: usize = 64;
+    let learning_rate: f32 = 0.5;
+    let num_step: usize = 2000;
+    let print_interval: usize = 200;
+
+    // 正解の評価値は隠れた埋め込みの内積
+    let true_users: Vec<f32> = Tensor2d::<USERS, DIM, f32>::new_normal(0.0, 1.0).to_untyped().to_vec_f32();
+    let true_items: Vec<f32> = Tensor2d::<ITEMS, DIM, f32>::new_normal(0.0, 1.0).to_untyped().to_vec_f32();
+    let mut rng = rand::thread_rng();
+
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    // Embeddingの勾配は触れた行だけなので，Sgdもbatchに出てきた行だけを更新する
+    let mut optimizer = Sgd::new(learning_rate);
+    let model: MatrixFactorization<USERS, ITEMS, DIM> = MatrixFactorization::new(&mut vs);
+    // userとitemの埋め込みはお互いに依存しないので，同時に実行する
+    autograd.set_parallel(true);
+
+    for step in 0..num_step {
+        let pairs: Vec<(usize, usize)> = (0..BATCH_SIZE).map(|_| (rng.gen_range(0..USERS), rng.gen_range(0..ITEMS))).collect();
+        let ratings: Vec<f32> = pairs.iter()
+            .map(|(u, i)| (0..DIM).map(|d| true_users[u * DIM + d] * true_items[i * DIM + d]).sum())
+            .collect();
+        let users = Tensor2d::<BATCH_SIZE, 1, u32>::new_from_indices(pairs.iter().map(|(u, _)| *u as u32).collect()).unwrap();
+        let items = Tensor2d::<BATCH_SIZE, 1, u32>::new_from_indices(pairs.iter().map(|(_, i)| *i as u32).collect()).unwrap();
+        let users = Nten2d::new_from_val(users).name("users").as_input(&mut vs);
+        let items = Nten2d::new_from_val(items).name("items").as_input(&mut vs);
+        let ratings = Nten2d::new_from_val(Tensor2d::new_from_vec(ratings).unwrap()).name("ratings").as_input(&mut vs);
+
+        let predict = model.forward(&mut vs, &users, &items);
+        let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&predict, &ratings, Reduction::Mean);
+        let [loss] = autograd.step_forward([loss.to_untyped()]);
+        let ctx = autograd.backward(&loss);
+        optimizer.update(ctx);
+        autograd.zero_grad();
+
+        if step % print_interval == 0 {
+            println!("step {}, loss: {}", step, loss.val.unwrap().to_vec_f32()[0]);
+        }
+    }
+}
+
+
+// 文字単位の小さなtransformer。次の文字を予測する
+// 文字は'a'..='z', ' ', '.'の28種類
+const CHARS: &str = "abcdefghijklmnopqrstuvwxyz .";
+const CHAR_VOCAB: usize = 28;
+
+fn encode_chars(text: &str) -> Vec<u32> {
+    text.chars().map(|c| CHARS.find(c).unwrap_or(26) as u32).collect()
+}
+
+// V: 文字の種類, T: 文脈の長さ, E: 埋め込みの次元, H: head数, DH: headごとの次元
+struct CharTransformer<const V: usize, const T: usize, const E: usize, const H: usize, const DH: usize> {
+    token: nn::Embedding<V, E>,
+    position: nn::Embedding<T, E>,
+    attention: nn::MultiHeadAttention<E, H, DH>,
+    norm1: nn::LayerNorm<E>,
+    ff1: nn::Linear<E, E>,
+    ff2: nn::Linear<E, E>,
+    norm2: nn::LayerNorm<E>,
+    head: nn::Linear<E, V>,
+}
+impl<const V: usize, const T: usize, const E: usize, const H: usize, const DH: usize> CharTransformer<V, T, E, H, DH> {
+    fn new(vs: &mut VarStore) -> Self {
+        Self {
+            token: nn::Embedding::new(&mut vs.sub("token")),
+            position: nn::Embedding::new(&mut vs.sub("position")),
+            attention: nn::MultiHeadAttention::new(&mut vs.sub("attention")),
+            norm1: nn::LayerNorm::new(&mut vs.sub("norm1")),
+            ff1: nn::Linear::new(&mut vs.sub("ff1")),
+            ff2: nn::Linear::new(&mut vs.sub("ff2")),
+            norm2: nn::LayerNorm::new(&mut vs.sub("norm2")),
+            head: nn::Linear::new(&mut vs.sub("head")),
+        }
+    }
+    // tokens: (B * T, 1) -> logits: (B * T, V)
+    fn forward<const B: usize, const BT: usize, const BH: usize>(&self, vs: &mut VarStore, tokens: &Nten2d<BT, 1, u32>) -> Nten2d<BT, V, f32> {
+        let positions: Vec<u32> = (0..BT).map(|i| (i % T) as u32).collect();
+        let positions = Nten2d::new_from_val(Tensor2d::<BT, 1, u32>::new_from_indices(positions).unwrap()).name("positions").as_input(vs);
+        // 後ろの文字を見ないようにする
+        let mask = Nten2d::new_from_val(Tensor2d::<T, T, bool>::new_causal_mask()).name("causal mask").as_input(vs);
+
+        let x: Nten2d<BT, E, f32> = self.token.forward(tokens).add(&self.position.forward(&positions));
+        let attended = self.attention.self_attention::<B, T, BT, BH>(&x.reshape_3d::<B, T, E>(), Some(&mask));
+        let x = self.norm1.forward(&x.add(&attended.reshape_2d::<BT, E>()));
+        let hidden = self.ff2.forward(&self.ff1.forward(&x).relu());
+        let x = self.norm2.forward(&x.add(&hidden));
+        self.head.forward(&x)
+    }
+}
+
+pub fn char_transformer() {
+    const CONTEXT: usize = 8;
+    const BATCH_SIZE: usize = 16;
+    const EMBED: usize = 16;
+    const HEADS: usize = 2;
+    let learning_rate: f32 = 0.1;
+    let num_step: usize = 400;
+    let print_interval: usize = 50;
+
+    let text = encode_chars(&"the quick brown fox jumps over the lazy dog. ".repeat(20));
+    let mut rng = rand::thread_rng();
+
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let mut optimizer = Sgd::new(learning_rate);
+    // attentionの重みなどの途中の値は使い終わったら捨てて，storageを使い回す
+    autograd.set_memory_planner(true);
+    let model: CharTransformer<CHAR_VOCAB, CONTEXT, EMBED, HEADS, { EMBED / HEADS }> = CharTransformer::new(&mut vs);
+
+    for step in 0..num_step {
+        // 文中のランダムな位置からCONTEXT文字を取り出し，1文字ずらしたものを正解にする
+        let (mut inputs, mut targets) = (Vec::new(), Vec::new());
+        for _ in 0..BATCH_SIZE {
+            let start = rng.gen_range(0..text.len() - CONTEXT - 1);
+            inputs.extend_from_slice(&text[start..start + CONTEXT]);
+            targets.extend_from_slice(&text[start + 1..start + CONTEXT + 1]);
+        }
+        let inputs = Nten2d::new_from_val(Tensor2d::<{ BATCH_SIZE * CONTEXT }, 1, u32>::new_from_indices(inputs).unwrap()).name("inputs").as_input(&mut vs);
+        let targets = Nten2d::new_from_val(Tensor2d::<{ BATCH_SIZE * CONTEXT }, 1, u32>::new_from_indices(targets).unwrap()).name("targets").as_input(&mut vs);
+
+        let logits = model.forward::<BATCH_SIZE, { BATCH_SIZE * CONTEXT }, { BATCH_SIZE * HEADS }>(&mut vs, &inputs);
+        let loss: Nten2d<1, 1, f32> = loss_fn::cross_entropy(&logits, &targets, loss_fn::CrossEntropyConfig::new());
+        let [loss] = autograd.step_forward([loss.to_untyped()]);
+        let ctx = autograd.backward(&loss);
+        optimizer.update(ctx);
+        autograd.zero_grad();
+
+        if step % print_interval == 0 {
+            println!("step {}, loss: {}", step, loss.val.unwrap().to_vec_f32()[0]);
+        }
+    }
+
+    // 書き出しに続けて1文字ずつ貪欲に生成する
+    autograd.eval();
+    let mut generated = encode_chars("the quic");
+    for _ in 0..40 {
+        let context = generated[generated.len() - CONTEXT..].to_vec();
+        let context = Nten2d::new_from_val(Tensor2d::<CONTEXT, 1, u32>::new_from_indices(context).unwrap()).name("context").as_input(&mut vs);
+        let logits = model.forward::<1, CONTEXT, HEADS>(&mut vs, &context);
+        let [logits] = autograd.step_forward([logits.to_untyped()]);
+        let next = logits.val.unwrap().top_index_per_batch()[CONTEXT - 1];
+        generated.push(next as u32);
+        autograd.zero_grad();
+    }
+    let generated: String = generated.iter().map(|&i| CHARS.as_bytes()[i as usize] as char).collect();
+    println!("generated: {}", generated);
+}
+
+
+// 時系列の予測。過去T点のsin波から次の1点を予測する
+struct Forecaster<const HD: usize, Cell: nn::RecurrentCell<1, HD>> {
+    recurrent: nn::Recurrent<1, HD, Cell>,
+    head: nn::Linear<HD, 1>,
+}
+impl<const HD: usize, Cell: nn::RecurrentCell<1, HD>> Forecaster<HD, Cell> {
+    fn new(vs: &mut VarStore, cell: impl FnOnce(&mut VarStore) -> Cell) -> Self {
+        Self {
+            recurrent: nn::Recurrent::new(cell(&mut vs.sub("cell"))),
+            head: nn::Linear::new(&mut vs.sub("head")),
+        }
+    }
+    // input (B, T, 1) -> 最後の隠れ状態から(B, 1)
+    fn forward<const B: usize, const T: usize>(&self, vs: &mut VarStore, input: &Nten3d<B, T, 1, f32>) -> Nten2d<B, 1, f32> {
+        let state = self.recurrent.cell.zero_state(vs);
+        let (_, state) = self.recurrent.forward(input, state);
+        self.head.forward(&Cell::hidden(&state))
+    }
+}
+
+// 位相と周期をランダムにしたsin波。returns (B, T, 1)の入力と(B, 1)の次の値
+fn sine_batch<const B: usize, const T: usize>() -> (Tensor3d<B, T, 1, f32>, Tensor2d<B, 1, f32>) {
+    let mut rng = rand::thread_rng();
+    let (mut inputs, mut targets) = (Vec::new(), Vec::new());
+    for _ in 0..B {
+        let phase: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+        let freq: f32 = rng.gen_range(0.2..0.4);
+        inputs.extend((0..T).map(|t| (phase + freq * t as f32).sin()));
+        targets.push((phase + freq * T as f32).sin());
+    }
+    (Tensor3d::new_from_vec(inputs).unwrap(), Tensor2d::new_from_vec(targets).unwrap())
+}
+
+fn train_forecaster<const HD: usize, Cell: nn::RecurrentCell<1, HD>>(name: &str, cell: impl FnOnce(&mut VarStore) -> Cell) {
+    const BATCH_SIZE: usize = 32;
+    const STEPS: usize = 12;
+    let learning_rate: f32 = 0.1;
+    let num_step: usize = 300;
+
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let mut optimizer = Sgd::new(learning_rate);
+    let model: Forecaster<HD, Cell> = Forecaster::new(&mut vs, cell);
+
+    let mut last_loss = 0.0;
+    for _ in 0..num_step {
+        let (x, y) = sine_batch::<BATCH_SIZE, STEPS>();
+        let x = Nten3d::new_from_val(x).name("input").as_input(&mut vs);
+        let y = Nten2d::new_from_val(y).name("target").as_input(&mut vs);
+
+        let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&model.forward(&mut vs, &x), &y, Reduction::Mean);
+        let [loss] = autograd.step_forward([loss.to_untyped()]);
+        let ctx = autograd.backward(&loss);
+        optimizer.update(ctx);
+        autograd.zero_grad();
+        last_loss = loss.val.unwrap().to_vec_f32()[0];
+    }
+    println!("{}: loss after {} steps: {}", name, num_step, last_loss);
+}
+
+pub fn forecast() {
+    train_forecaster::<16, _>("RNNCell", nn::RNNCell::new);
+    train_forecaster::<16, _>("GRUCell", nn::GRUCell::new);
+    train_forecaster::<16, _>("LSTMCell", nn::LSTMCell::new);
+}
@@ -0,0 +1,118 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, dtype::{BinaryOp, Dtype, Shape, UnaryOp}, nten::{Nten, NtenID}, tensor::{Storage, Tensor}};
+use super::{grad_graph, pure_cse_key, FnEdge, FnEdgeID};
+
+
+/*
+sigmoid, tanh
+backwardは出力だけを使うのでcacheはいらない
+*/
+
+// this FnEdge's front fn is implemented at Nten2d, Nten3d
+// fn sigmoid(), tanh() @Nten2d, @Nten3d
+
+#[derive(Clone)]
+pub struct Activation<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub shape: Shape,
+    pub op: UnaryOp,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Activation<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("{}<{}> {}", self.op.name(), T::type_name(), self.shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        let out = input.storage().unary_op(self.op);
+
+        ctx.insert_val(&self.output_id, Tensor {
+            name: self.op.name().to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(out)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let output = ctx.get_val(&self.output_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinput = Storage::unary_op_backward(self.op, &dout.storage(), &output.storage());
+
+        ctx.add_assign_grad(&self.input_id, &Tensor {
+            name: format!("d{}", self.op.name()),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(dinput)),
+        });
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        let output = &outputs[0];
+        let binary = grad_graph::binary::<T>;
+        // sigmoid: dy * y * (1 - y), tanh: dy * (1 - y * y)
+        let dout_y = binary(BinaryOp::Mul, dout, output);
+        let dout_y_y = binary(BinaryOp::Mul, &dout_y, output);
+        let dinput = match self.op {
+            UnaryOp::Sigmoid => binary(BinaryOp::Sub, &dout_y, &dout_y_y),
+            UnaryOp::Tanh => binary(BinaryOp::Sub, dout, &dout_y_y),
+        };
+        Some(vec![Some(dinput)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::Nten2d, test_utils::{assert_close, forward, gradcheck, gradgradcheck, sample}};
+
+    fn typed(ps: &[crate::nten::Nten]) -> Nten2d<3, 4, f32> {
+        ps[0].clone().to_typed2d().unwrap()
+    }
+
+    #[test]
+    fn forward_matches_formula() {
+        let x = sample(Shape::D2(3, 4), 1);
+        let sigmoid = forward(std::slice::from_ref(&x), |_, ps| typed(ps).sigmoid().to_untyped()).to_vec_f32();
+        let tanh = forward(std::slice::from_ref(&x), |_, ps| typed(ps).tanh().to_untyped()).to_vec_f32();
+        let x = x.to_vec_f32();
+        assert_close(&sigmoid, &x.iter().map(|v| 1.0 / (1.0 + (-v).exp())).collect::<Vec<f32>>(), 1e-6);
+        assert_close(&tanh, &x.iter().map(|v| v.tanh()).collect::<Vec<f32>>(), 1e-6);
+    }
+
+    #[test]
+    fn gradcheck_activation() {
+        gradcheck(&[sample(Shape::D2(3, 4), 1)], |_, ps| typed(ps).sigmoid().to_untyped());
+        gradcheck(&[sample(Shape::D2(3, 4), 1)], |_, ps| typed(ps).tanh().to_untyped());
+    }
+
+    #[test]
+    fn gradgradcheck_activation() {
+        gradgradcheck(&[sample(Shape::D2(3, 4), 1)], |_, ps| typed(ps).sigmoid().to_untyped());
+        gradgradcheck(&[sample(Shape::D2(3, 4), 1)], |_, ps| typed(ps).tanh().to_untyped());
+    }
+}
@@ -1,7 +1,7 @@
 
 use std::marker::PhantomData;
 use crate::{autograd::Context, dtype::{Dtype, Shape}, logger::LOGGER, nten::{get_new_nten_id, Nten, NtenID}, tensor::Tensor2d};
-use super::{get_new_fn_edge_id, FnEdge, FnEdgeID};
+use super::{get_new_fn_edge_id, pure_cse_key, shared, FnEdge, FnEdgeID};
 
 
 /*
@@ -18,7 +18,6 @@ pub fn add<const R: usize, const C: usize, T: Dtype>(lhs: &Nten, rhs: &Nten) ->
     let new_id: NtenID = get_new_nten_id();
     let add2d: Add2d<R, C, T> = Add2d::<R, C, T> {
         id: get_new_fn_edge_id(),
-        name: format!("Add2d<{}, {}, {}>", R, C, T::type_name()),
         sources: vec![lhs.creator.clone(), rhs.creator.clone()],
         input1_id: lhs.id,
         input2_id: rhs.id,
@@ -28,7 +27,7 @@ pub fn add<const R: usize, const C: usize, T: Dtype>(lhs: &Nten, rhs: &Nten) ->
     Nten {
         id: new_id,
         name: format!("auto created by Add2d<{}, {}, {}>", R, C, T::type_name()),
-        creator: Box::new(add2d),
+        creator: shared(Box::new(add2d)),
         shape: Shape::D2(R, C),
         val: None,
         grad: None,
@@ -38,7 +37,6 @@ pub fn add<const R: usize, const C: usize, T: Dtype>(lhs: &Nten, rhs: &Nten) ->
 #[derive(Clone)]
 pub struct Add2d<const R: usize, const C: usize, T> {
     pub id: FnEdgeID,
-    pub name: String,
     pub sources: Vec<Box<dyn FnEdge>>,
 
     pub input1_id: NtenID,
@@ -57,9 +55,22 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for Add2d<R, C, T> {
     fn sources(&self) -> Vec<Box<dyn FnEdge>> {
         self.sources.clone()
     }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input1_id, self.input2_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    // backwardは勾配をそのまま流すだけ
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        Vec::new()
+    }
     fn clone_box(&self) -> Box<dyn FnEdge> {
         Box::new(self.clone())
     }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
 
     fn forward(&self, ctx: &mut Context) {
         let input1: Tensor2d<R, C, T> = ctx.get_val_as_2d(&self.input1_id);
@@ -77,4 +88,30 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for Add2d<R, C, T> {
         ctx.add_assign_grad(&self.input1_id, &dout);
         ctx.add_assign_grad(&self.input2_id, &dout);
     }
-}
\ No newline at end of file
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        Some(vec![Some(dout.clone()), Some(dout.clone())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{Nten, Nten2d}, test_utils::{gradcheck, gradgradcheck, sample}};
+
+    fn add(ps: &[Nten]) -> Nten2d<3, 4, f32> {
+        let lhs: Nten2d<3, 4, f32> = ps[0].clone().to_typed2d().unwrap();
+        lhs.add(&ps[1].clone().to_typed2d().unwrap())
+    }
+
+    #[test]
+    fn gradcheck_add() {
+        gradcheck(&[sample(Shape::D2(3, 4), 1), sample(Shape::D2(3, 4), 2)], |_, ps| add(ps).to_untyped());
+    }
+
+    // addの勾配は定数なので，tanhをかけて2階の微分を0でなくする
+    #[test]
+    fn gradgradcheck_add() {
+        gradgradcheck(&[sample(Shape::D2(3, 4), 1), sample(Shape::D2(3, 4), 2)], |_, ps| add(ps).tanh().to_untyped());
+    }
+}
@@ -1,7 +1,7 @@
 
 use std::marker::PhantomData;
-use crate::{autograd::Context, dtype::{Dtype, Shape}, logger::LOGGER, nten::{get_new_nten_id, Nten, NtenID}, tensor::Tensor2d};
-use super::{get_new_fn_edge_id, FnEdge, FnEdgeID};
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::{Nten, NtenID}, tensor::Tensor2d};
+use super::{grad_graph, pure_cse_key, EdgePattern, FnEdge, FnEdgeID};
 
 
 /*
@@ -37,9 +37,25 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for AddBroadcast2d<R, C, T
     fn sources(&self) -> Vec<Box<dyn FnEdge>> {
         self.sources.clone()
     }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.weight_id, self.bias_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    // backwardは勾配だけで決まる
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        Vec::new()
+    }
     fn clone_box(&self) -> Box<dyn FnEdge> {
         Box::new(self.clone())
     }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+    fn pattern(&self) -> Option<EdgePattern> {
+        (T::type_name() == "f32").then_some(EdgePattern::AddBias { input_id: self.weight_id, bias_id: self.bias_id, output_id: self.output_id })
+    }
 
     fn forward(&self, ctx: &mut Context) {
         let input1: Tensor2d<R, C, T> = ctx.get_val_as_2d(&self.weight_id);
@@ -59,4 +75,26 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for AddBroadcast2d<R, C, T
         // weight側
         ctx.add_assign_grad(&self.weight_id, &din.to_untyped());
     }
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        // weightはそのまま，biasはbatch方向の和
+        Some(vec![Some(dout.clone()), Some(grad_graph::sum_to::<T>(dout, Shape::D2(1, C)))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nn::Linear, nten::Nten2d, test_utils::{gradgradcheck, sample}};
+
+    // Linearを通した2階の微分。tanhで2階の微分を0でなくする
+    #[test]
+    fn gradgradcheck_linear() {
+        let params = [sample(Shape::D2(4, 3), 1), sample(Shape::D2(3, 5), 2), sample(Shape::D2(1, 5), 3)];
+        gradgradcheck(&params, |_, ps| {
+            let x: Nten2d<4, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+            let linear: Linear<3, 5> = Linear { weight: ps[1].clone().to_typed2d().unwrap(), bias: ps[2].clone().to_typed2d().unwrap() };
+            linear.forward(&x).tanh().to_untyped()
+        });
+    }
 }
\ No newline at end of file
@@ -0,0 +1,259 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, dtype::{BinaryOp, Dtype, Shape}, logger::LOGGER, nten::{Nten, NtenID}, tensor::{Storage, Tensor}};
+use super::{grad_graph, pure_cse_key, FnEdge, FnEdgeID};
+
+
+/*
+NumPy style broadcastingの二項演算
+
+形状はNten2dなどのフロントの型でコンパイル時に検査済みなので，
+ここではランク共通で使えるようにShapeで持つ
+*/
+
+// this FnEdge's front fn is implemented at Nten2d
+// fn broadcast_add(), broadcast_sub(), broadcast_mul(), broadcast_div() @Nten2d
+
+#[derive(Clone)]
+pub struct Broadcast<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub op: BinaryOp,
+    pub lhs_id: NtenID,
+    pub rhs_id: NtenID,
+    pub output_id: NtenID,
+    pub lhs_shape: Shape,
+    pub rhs_shape: Shape,
+    pub output_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Broadcast<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Broadcast{}<{}> {} and {} to {}", self.op.name(), T::type_name(), self.lhs_shape, self.rhs_shape, self.output_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.lhs_id, self.rhs_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let lhs = ctx.get_val(&self.lhs_id);
+        let rhs = ctx.get_val(&self.rhs_id);
+
+        let output = match lhs.broadcast_op(&rhs, self.op) {
+            Ok(output) => output,
+            Err(e) => {
+                LOGGER.error(format!("{}::forward() >> {}", self.name(), e));
+                panic!("")
+            }
+        };
+
+        ctx.insert_val(&self.output_id, output);
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let lhs = ctx.get_val(&self.lhs_id);
+        let rhs = ctx.get_val(&self.rhs_id);
+
+        // broadcastされた軸の勾配はkernel内で足し合わされてlhs, rhsの形状で返ってくる
+        let (dlhs, drhs) = Storage::broadcast_op_backward(self.op, &dout.storage(), &lhs.storage(), self.lhs_shape, &rhs.storage(), self.rhs_shape);
+        let dlhs = Tensor { name: "broadcast dlhs".to_string(), shape: self.lhs_shape, storage: Arc::new(RwLock::new(dlhs)) };
+        let drhs = Tensor { name: "broadcast drhs".to_string(), shape: self.rhs_shape, storage: Arc::new(RwLock::new(drhs)) };
+
+        ctx.add_assign_grad(&self.lhs_id, &dlhs);
+        ctx.add_assign_grad(&self.rhs_id, &drhs);
+    }
+
+    fn backward_graph(&self, ctx: &mut Context, inputs: &[Nten], outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        let (lhs, rhs) = (&inputs[0], &inputs[1]);
+        let binary = grad_graph::binary::<T>;
+        let (dlhs, drhs) = match self.op {
+            BinaryOp::Add => (dout.clone(), dout.clone()),
+            BinaryOp::Sub => {
+                let minus_one = grad_graph::scalar(ctx, -1.0, self.output_shape);
+                (dout.clone(), binary(BinaryOp::Mul, dout, &minus_one))
+            },
+            BinaryOp::Mul => (binary(BinaryOp::Mul, dout, rhs), binary(BinaryOp::Mul, dout, lhs)),
+            // d(a / b)/db = -(a / b) / b
+            BinaryOp::Div => {
+                let minus_one = grad_graph::scalar(ctx, -1.0, self.output_shape);
+                let out_over_rhs = binary(BinaryOp::Div, &outputs[0], rhs);
+                (binary(BinaryOp::Div, dout, rhs), binary(BinaryOp::Mul, &binary(BinaryOp::Mul, dout, &out_over_rhs), &minus_one))
+            },
+            BinaryOp::Rem => return None,
+        };
+        Some(vec![
+            Some(grad_graph::sum_to::<T>(&dlhs, self.lhs_shape)),
+            Some(grad_graph::sum_to::<T>(&drhs, self.rhs_shape)),
+        ])
+    }
+}
+
+
+// broadcastで伸ばされた軸を足し合わせてoutput_shapeに戻す。create_graphでBroadcastの勾配に使う
+#[derive(Clone)]
+pub struct SumTo<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub input_shape: Shape,
+    pub output_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for SumTo<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("SumTo<{}> {} to {}", T::type_name(), self.input_shape, self.output_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        ctx.insert_val(&self.output_id, input.sum_to_shape(self.output_shape));
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        // 0にdoutを足してinput_shapeまで伸ばす
+        let dinput = Tensor::new_zeros::<T>(self.input_shape).broadcast_op(&dout, BinaryOp::Add).unwrap_or_else(|e| {
+            LOGGER.error(format!("{}::backward() >> {}", self.name(), e));
+            panic!("")
+        });
+
+        ctx.add_assign_grad(&self.input_id, &dinput);
+    }
+
+    fn backward_graph(&self, ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        let zeros = grad_graph::constant(ctx, Tensor::new_zeros::<T>(self.input_shape));
+        Some(vec![Some(grad_graph::binary::<T>(BinaryOp::Add, &zeros, dout))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::{BinaryOp, Shape}, nten::Nten2d, tensor::Tensor, test_utils::{assert_close, forward, gradcheck, gradgradcheck, sample}};
+
+    // 0に近い値で割らないように
+    fn away_from_zero(shape: Shape, seed: u64) -> Tensor {
+        let body = sample(shape, seed).to_vec_f32().iter().map(|x| x + x.signum() * 1.5).collect();
+        Tensor::new_from_vec(body, shape).unwrap()
+    }
+
+    fn check<const R: usize, const C: usize, const R2: usize, const C2: usize, const RO: usize, const CO: usize>(op: BinaryOp) {
+        let params = [sample(Shape::D2(R, C), 1), away_from_zero(Shape::D2(R2, C2), 2)];
+        gradcheck(&params, |_, ps| {
+            let lhs = ps[0].clone().to_typed2d::<R, C, f32>().unwrap();
+            let rhs = ps[1].clone().to_typed2d::<R2, C2, f32>().unwrap();
+            let out: Nten2d<RO, CO, f32> = match op {
+                BinaryOp::Add => lhs.broadcast_add(&rhs),
+                BinaryOp::Sub => lhs.broadcast_sub(&rhs),
+                BinaryOp::Mul => lhs.broadcast_mul(&rhs),
+                BinaryOp::Div => lhs.broadcast_div(&rhs),
+                BinaryOp::Rem => unreachable!(),
+            };
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn outer_product() {
+        let column = Tensor::new_from_vec(vec![1.0, 2.0], Shape::D2(2, 1)).unwrap();
+        let row = Tensor::new_from_vec(vec![1.0, 2.0, 3.0], Shape::D2(1, 3)).unwrap();
+        let out = forward(&[column, row], |_, ps| {
+            let column = ps[0].clone().to_typed2d::<2, 1, f32>().unwrap();
+            let row = ps[1].clone().to_typed2d::<1, 3, f32>().unwrap();
+            let out: Nten2d<2, 3, f32> = column.broadcast_mul(&row);
+            out.to_untyped()
+        });
+        assert_eq!(out.shape, Shape::D2(2, 3));
+        assert_close(&out.to_vec_f32(), &[1.0, 2.0, 3.0, 2.0, 4.0, 6.0], 1e-6);
+    }
+
+    #[test]
+    fn gradcheck_row_broadcast() {
+        for op in [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div] {
+            check::<3, 4, 1, 4, 3, 4>(op);
+        }
+    }
+
+    #[test]
+    fn gradcheck_column_broadcast() {
+        for op in [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div] {
+            check::<3, 4, 3, 1, 3, 4>(op);
+        }
+    }
+
+    #[test]
+    fn gradcheck_both_sides_broadcast() {
+        for op in [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div] {
+            check::<3, 1, 1, 4, 3, 4>(op);
+        }
+    }
+
+    #[test]
+    fn gradcheck_add_broadcast2d() {
+        let params = [sample(Shape::D2(3, 4), 1), sample(Shape::D2(1, 4), 2)];
+        gradcheck(&params, |_, ps| {
+            let input = ps[0].clone().to_typed2d::<3, 4, f32>().unwrap();
+            let bias = ps[1].clone().to_typed2d::<1, 4, f32>().unwrap();
+            input.add_broadcast(&bias).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradgradcheck_row_broadcast() {
+        for op in [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div] {
+            gradgradcheck(&[sample(Shape::D2(3, 4), 1), away_from_zero(Shape::D2(1, 4), 2)], |_, ps| {
+                let lhs = ps[0].clone().to_typed2d::<3, 4, f32>().unwrap();
+                let rhs = ps[1].clone().to_typed2d::<1, 4, f32>().unwrap();
+                let out: Nten2d<3, 4, f32> = match op {
+                    BinaryOp::Add => lhs.broadcast_add(&rhs),
+                    BinaryOp::Sub => lhs.broadcast_sub(&rhs),
+                    BinaryOp::Mul => lhs.broadcast_mul(&rhs),
+                    _ => lhs.broadcast_div(&rhs),
+                };
+                // Add, Subの勾配は定数なので，sigmoidをかけて2階の微分を0でなくする
+                out.sigmoid().to_untyped()
+            });
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::{Arc, Mutex, RwLock};
+use colored::Colorize;
+use rand::rngs::StdRng;
+use crate::{autograd::Context, logger::LOGGER, nten::NtenID, tensor::Tensor};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+gradient checkpointing。区間（segment）のFnEdgeをまとめて1つのFnEdgeとしてテープに積む
+forward: 区間を実行したあと，出力以外の途中の値とtemp tensor（Reluのmaskなど）を捨てる
+backward: 区間をもう一度forwardして値を作り直してから，区間の中をbackwardする
+区間の中のDropoutが同じmaskになるように，forward前の乱数の状態を保存して再計算で使う
+再計算でBatchNormのrunning statsが2回更新されないように，再計算の前にbufferの値を保存して戻す
+制限: backward_graph()はないので，区間を通るcreate_graph（grad_graph, functional::jvp）はできない
+*/
+
+// this FnEdge's front fn is implemented at nten_checkpoint.rs
+// fn checkpoint()
+
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    // 区間の外から読むnten（input, parameter, buffer, as_input()したnten, 外で計算した途中のnten）。inputが先頭
+    pub input_ids: Vec<NtenID>,
+    pub output_id: NtenID,
+    // 区間の中のFnEdge。sourcesが先になる順
+    pub segment: Vec<Box<dyn FnEdge>>,
+    pub rng_state: Arc<Mutex<Option<StdRng>>>,
+}
+impl Checkpoint {
+    fn run_segment_forward(&self, ctx: &mut Context) {
+        for fn_edge in self.segment.iter() {
+            fn_edge.forward(ctx);
+        }
+    }
+
+    // 区間の出力以外の値とgrad，区間で作られたtemp tensorを捨てる
+    fn free_segment(&self, ctx: &mut Context, temp_mark: usize) {
+        for fn_edge in self.segment.iter() {
+            for id in fn_edge.outputs() {
+                if id != self.output_id {
+                    ctx.remove_nten(&id);
+                }
+            }
+        }
+        for id in ctx.inserted_tensor_ids_since(temp_mark) {
+            ctx.remove_tensor(&id);
+        }
+    }
+}
+impl FnEdge for Checkpoint {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Checkpoint of {} FnEdges", self.segment.len())
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        self.input_ids.clone()
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        *self.rng_state.lock().unwrap() = Some(ctx.rng.clone());
+        let temp_mark = ctx.inserted_tensor_mark();
+
+        self.run_segment_forward(ctx);
+        self.free_segment(ctx, temp_mark);
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let temp_mark = ctx.inserted_tensor_mark();
+
+        // 再計算。乱数はforwardのときの状態に戻し，終わったら元に戻す
+        LOGGER.debug(format!("{}: {} {} FnEdges of Checkpoint id: {}", "Checkpoint".cyan(), "recompute".blue(), self.segment.len(), self.id));
+        let Some(rng) = self.rng_state.lock().unwrap().clone() else {
+            LOGGER.error(format!("Checkpoint::backward() >> FnEdge id: {} backward is called before forward", self.id));
+            panic!("")
+        };
+        let rng = std::mem::replace(&mut ctx.rng, rng);
+        // bufferはforwardで更新済みなので，再計算で変わった値を元に戻す
+        let buffer_ids = ctx.varstore.buffer_ids.lock().unwrap().clone();
+        let buffers: Vec<(NtenID, Tensor)> = self.input_ids.iter()
+            .filter(|id| buffer_ids.contains(id))
+            .map(|id| {
+                let val = ctx.get_val(id);
+                let snapshot = Tensor { name: val.name.clone(), shape: val.shape, storage: Arc::new(RwLock::new(val.storage().clone())) };
+                (*id, snapshot)
+            })
+            .collect();
+        self.run_segment_forward(ctx);
+        for (id, snapshot) in buffers {
+            ctx.get_val(&id).override_value(snapshot);
+        }
+        ctx.rng = rng;
+
+        for fn_edge in self.segment.iter().rev() {
+            if !fn_edge.outputs().iter().any(|id| ctx.try_get_grad(id).is_some()) {
+                continue;
+            }
+            fn_edge.backward(ctx);
+        }
+        self.free_segment(ctx, temp_mark);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Autograd, Mode, VarStore}, dtype::Shape, nn::{BatchNorm1d, Linear}, nten::{self, Nten, Nten2d}, tensor::{Tensor, Tensor2d}, test_utils::{as_parameters, assert_close, gradcheck_with, sample}};
+
+    // ps: input (4, 3), linear1 weight, bias, linear2 weight, bias
+    fn params() -> Vec<Tensor> {
+        vec![
+            sample(Shape::D2(4, 3), 1),
+            sample(Shape::D2(3, 5), 2), sample(Shape::D2(1, 5), 3),
+            sample(Shape::D2(5, 2), 4), sample(Shape::D2(1, 2), 5),
+        ]
+    }
+    fn linear<const I: usize, const O: usize>(ps: &[Nten], i: usize) -> Linear<I, O> {
+        Linear { weight: ps[i].clone().to_typed2d().unwrap(), bias: ps[i + 1].clone().to_typed2d().unwrap() }
+    }
+    // linear -> batch norm -> relu -> dropout -> linear
+    fn block(x: &Nten2d<4, 3, f32>, l1: &Linear<3, 5>, bn: &BatchNorm1d<5>, l2: &Linear<5, 2>, p: f32) -> Nten2d<4, 2, f32> {
+        l2.forward(&bn.forward(&l1.forward(x)).relu().dropout(p))
+    }
+    fn net(vs: &mut VarStore, ps: &[Nten], use_checkpoint: bool, p: f32) -> (Nten, BatchNorm1d<5>) {
+        let (l1, l2) = (linear(ps, 1), linear(ps, 3));
+        let bn = BatchNorm1d::new(vs);
+        let x: Nten2d<4, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+        let y = if use_checkpoint {
+            nten::checkpoint(&x, |x| block(x, &l1, &bn, &l2, p))
+        } else {
+            block(&x, &l1, &bn, &l2, p)
+        };
+        (y.tanh().to_untyped(), bn)
+    }
+
+    // returns (出力, parameterの勾配, backward後のrunning_mean, running_var)
+    fn run(use_checkpoint: bool, parallel: bool, p: f32) -> (Vec<f32>, Vec<Vec<f32>>, Vec<f32>, Vec<f32>) {
+        let mut ag = Autograd::new();
+        ag.manual_seed(0);
+        ag.set_parallel(parallel);
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &params());
+        let (y, bn) = net(&mut vs, &ps, use_checkpoint, p);
+        let [y] = ag.step_forward([y]);
+        let ctx = ag.backward_with(&y, sample(y.shape, 7));
+        let grads = ps.iter().map(|p| ctx.get_grad(&p.id).to_vec_f32()).collect();
+        let running_mean = ctx.get_val(&bn.running_mean.id).to_vec_f32();
+        let running_var = ctx.get_val(&bn.running_var.id).to_vec_f32();
+        (y.val.unwrap().to_vec_f32(), grads, running_mean, running_var)
+    }
+
+    #[test]
+    fn checkpoint_matches_plain_backward() {
+        // 並列実行ではwaveの組み方でDropoutのmaskが変わるので，Dropoutなしで比べる
+        for (parallel, p) in [(false, 0.5), (true, 0.0)] {
+            let (y, grads, running_mean, running_var) = run(false, parallel, p);
+            let (y_ckpt, grads_ckpt, running_mean_ckpt, running_var_ckpt) = run(true, parallel, p);
+            assert_close(&y_ckpt, &y, 1e-5);
+            for (g_ckpt, g) in grads_ckpt.iter().zip(grads.iter()) {
+                assert_close(g_ckpt, g, 1e-5);
+            }
+            // 再計算でrunning statsがもう一度更新されていない
+            assert_close(&running_mean_ckpt, &running_mean, 1e-6);
+            assert_close(&running_var_ckpt, &running_var, 1e-6);
+        }
+    }
+
+    #[test]
+    fn checkpoint_gradcheck() {
+        // reluの折れ目をまたがないようにepsを小さくする
+        gradcheck_with(&params(), 1e-3, 1e-2, Mode::Train, |vs, ps| net(vs, ps, true, 0.5).0);
+    }
+
+    #[test]
+    fn checkpoint_inputs_include_parameters_and_buffers() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &params());
+        let (y, bn) = net(&mut vs, &ps, true, 0.5);
+        let inputs = y.creator.sources()[0].inputs();
+        assert_eq!(inputs[0], ps[0].id);
+        for id in ps.iter().map(|p| p.id).chain([bn.weight.id, bn.bias.id, bn.running_mean.id, bn.running_var.id]) {
+            assert!(inputs.contains(&id), "nten id: {} is not in Checkpoint::inputs()", id);
+        }
+    }
+
+    // 区間の外で計算した途中のntenは区間に入れず，外から読む
+    #[test]
+    fn outside_intermediate_is_read_as_input() {
+        let run = |use_checkpoint: bool| {
+            let mut ag = Autograd::new();
+            let mut vs = ag.get_vs();
+            let ps = as_parameters(&mut vs, &params());
+            let l1: Linear<3, 5> = linear(&ps, 1);
+            let x: Nten2d<4, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+            let h = x.tanh();
+            let y = if use_checkpoint {
+                nten::checkpoint(&x, |x| l1.forward(&x.add(&h)))
+            } else {
+                l1.forward(&x.add(&h))
+            };
+            if use_checkpoint {
+                let inputs = y.creator.inputs();
+                assert!(inputs.contains(&h.id), "nten id: {} computed outside is not in Checkpoint::inputs()", h.id);
+            }
+            let [y] = ag.step_forward([y.to_untyped()]);
+            let ctx = ag.backward_with(&y, sample(y.shape, 7));
+            let grads: Vec<Vec<f32>> = ps[..3].iter().map(|p| ctx.get_grad(&p.id).to_vec_f32()).collect();
+            (y.val.unwrap().to_vec_f32(), grads)
+        };
+        let (y, grads) = run(false);
+        let (y_ckpt, grads_ckpt) = run(true);
+        assert_close(&y_ckpt, &y, 1e-6);
+        for (g_ckpt, g) in grads_ckpt.iter().zip(grads.iter()) {
+            assert_close(g_ckpt, g, 1e-6);
+        }
+    }
+
+    #[test]
+    fn grad_graph_through_checkpoint_names_checkpoint() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &params());
+        let (y, _) = net(&mut vs, &ps, true, 0.5);
+        let [y] = ag.step_forward([y]);
+        let grad = Nten2d::<4, 2, f32>::new_from_val(Tensor2d::new_ones()).as_input(&mut vs).to_untyped();
+        let Err(e) = ag.try_grad_graph_with(&y, &grad, [ps[1].id]) else {
+            panic!("grad_graph() through Checkpoint must fail");
+        };
+        assert!(e.contains("Checkpoint"), "{}", e);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+concat, stack (N inputs -> 1 output)とsplit, chunk (1 input -> N outputs)
+
+各テンソルを(outer, axis_len, inner)とみなしてaxisの方向につなげる，分ける
+stackは各入力にaxisの長さ1の軸を足してconcatするのと同じなので，
+ntenの形状（input_shapes, output_shapes）とは別にouter, axis_lens, innerを持つ
+*/
+
+// this FnEdge's front fn is implemented at nten_concat.rs
+// fn concat_n(), stack() @nten, fn concat(), split(), chunk() @Nten2d, fn concat_channels() @Nten4d
+
+#[derive(Clone)]
+pub struct Concat<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_ids: Vec<NtenID>,
+    pub input_shapes: Vec<Shape>,
+    pub output_id: NtenID,
+    pub output_shape: Shape,
+    pub outer: usize,
+    pub axis_lens: Vec<usize>,
+    pub inner: usize,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Concat<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Concat<{}> {} inputs to {}", T::type_name(), self.input_ids.len(), self.output_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        self.input_ids.clone()
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let inputs: Vec<Tensor> = self.input_ids.iter().map(|id| ctx.get_val(id)).collect();
+        let storages: Vec<_> = inputs.iter().map(|input| input.storage()).collect();
+        let refs: Vec<&Storage> = storages.iter().map(|storage| &**storage).collect();
+
+        let output = Storage::concat(&refs, self.outer, &self.axis_lens, self.inner);
+
+        ctx.insert_val(&self.output_id, Tensor { name: "concat".to_string(), shape: self.output_shape, storage: Arc::new(RwLock::new(output)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinputs = dout.storage().split(self.outer, &self.axis_lens, self.inner);
+
+        for ((id, shape), dinput) in self.input_ids.iter().zip(self.input_shapes.iter()).zip(dinputs) {
+            ctx.add_assign_grad(id, &Tensor { name: "concat dinput".to_string(), shape: *shape, storage: Arc::new(RwLock::new(dinput)) });
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Split<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub input_shape: Shape,
+    pub output_ids: Vec<NtenID>,
+    pub output_shapes: Vec<Shape>,
+    pub outer: usize,
+    pub axis_lens: Vec<usize>,
+    pub inner: usize,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Split<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Split<{}> {} to {} outputs", T::type_name(), self.input_shape, self.output_ids.len())
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        self.output_ids.clone()
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        let outputs = input.storage().split(self.outer, &self.axis_lens, self.inner);
+
+        for ((id, shape), output) in self.output_ids.iter().zip(self.output_shapes.iter()).zip(outputs) {
+            ctx.insert_val(id, Tensor { name: "split".to_string(), shape: *shape, storage: Arc::new(RwLock::new(output)) });
+        }
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        // 使われなかった出力には勾配がないので0とする
+        let douts: Vec<Tensor> = self.output_ids.iter().zip(self.output_shapes.iter())
+            .map(|(id, shape)| ctx.try_get_grad(id).unwrap_or_else(|| Tensor::new_zeros::<T>(*shape)))
+            .collect();
+        let storages: Vec<_> = douts.iter().map(|dout| dout.storage()).collect();
+        let refs: Vec<&Storage> = storages.iter().map(|storage| &**storage).collect();
+
+        let dinput = Storage::concat(&refs, self.outer, &self.axis_lens, self.inner);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "split dinput".to_string(), shape: self.input_shape, storage: Arc::new(RwLock::new(dinput)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{self, Nten, Nten2d, Nten3d}, test_utils::{forward, gradcheck, sample, typed4d}};
+
+    fn typed<const R: usize, const C: usize>(ps: &[Nten], i: usize) -> Nten2d<R, C, f32> {
+        ps[i].clone().to_typed2d().unwrap()
+    }
+
+    #[test]
+    fn concat_forward() {
+        let a = sample(Shape::D2(2, 2), 1);
+        let b = sample(Shape::D2(2, 1), 2);
+        let out = forward(&[a.clone(), b.clone()], |_, ps| {
+            let out: Nten2d<2, 3, f32> = typed::<2, 2>(ps, 0).concat::<1, 2, 1, 2, 3>(&typed::<2, 1>(ps, 1));
+            out.to_untyped()
+        }).to_vec_f32();
+        let (a, b) = (a.to_vec_f32(), b.to_vec_f32());
+        assert_eq!(out, vec![a[0], a[1], b[0], a[2], a[3], b[1]]);
+    }
+
+    #[test]
+    fn gradcheck_concat() {
+        gradcheck(&[sample(Shape::D2(2, 3), 3), sample(Shape::D2(1, 3), 4)], |_, ps| {
+            let out: Nten2d<3, 3, f32> = typed::<2, 3>(ps, 0).concat::<0, 1, 3, 3, 3>(&typed::<1, 3>(ps, 1));
+            out.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(2, 3), 5), sample(Shape::D2(2, 3), 6), sample(Shape::D2(2, 3), 7)], |_, ps| {
+            let out: Nten2d<2, 9, f32> = nten::concat_n::<1, 3, 2, 3, 2, 9, f32>([&typed(ps, 0), &typed(ps, 1), &typed(ps, 2)]);
+            out.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D4(2, 1, 2, 2), 8), sample(Shape::D4(2, 2, 2, 2), 9)], |_, ps| {
+            typed4d::<2, 1, 2, 2>(&ps[0]).concat_channels::<2, 3>(&typed4d::<2, 2, 2, 2>(&ps[1])).to_untyped()
+        });
+    }
+
+    // 出力の一部しか使わないときは，使わなかった出力の勾配は0として扱う
+    #[test]
+    fn gradcheck_split_with_unused_output() {
+        gradcheck(&[sample(Shape::D2(4, 3), 10)], |_, ps| {
+            let (top, _): (Nten2d<1, 3, f32>, Nten2d<3, 3, f32>) = typed::<4, 3>(ps, 0).split::<0, 1, 3, 3, 3>();
+            top.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(2, 5), 11)], |_, ps| {
+            let (left, right): (Nten2d<2, 2, f32>, Nten2d<2, 3, f32>) = typed::<2, 5>(ps, 0).split::<1, 2, 2, 2, 3>();
+            // 順番を入れ替えてつなげ直す
+            let out: Nten2d<2, 5, f32> = right.concat::<1, 2, 2, 2, 5>(&left);
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_chunk() {
+        gradcheck(&[sample(Shape::D2(6, 2), 12)], |_, ps| {
+            let [a, b, c]: [Nten2d<2, 2, f32>; 3] = typed::<6, 2>(ps, 0).chunk::<0, 3, 2, 2>();
+            let out: Nten2d<6, 2, f32> = nten::concat_n::<0, 3, 2, 2, 6, 2, f32>([&c, &a, &b]);
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn stack_and_unstack() {
+        let a = sample(Shape::D2(2, 3), 13);
+        let b = sample(Shape::D2(2, 3), 14);
+        let stacked = forward(&[a.clone(), b.clone()], |_, ps| nten::stack([&typed::<2, 3>(ps, 0), &typed::<2, 3>(ps, 1)]).to_untyped());
+        assert_eq!(stacked.shape, Shape::D3(2, 2, 3));
+        assert_eq!(stacked.to_vec_f32(), [a.to_vec_f32(), b.to_vec_f32()].concat());
+
+        gradcheck(&[sample(Shape::D2(2, 3), 15), sample(Shape::D2(2, 3), 16)], |_, ps| {
+            nten::stack([&typed::<2, 3>(ps, 1), &typed::<2, 3>(ps, 0)]).to_untyped()
+        });
+        gradcheck(&[sample(Shape::D3(3, 2, 2), 17)], |_, ps| {
+            let x: Nten3d<3, 2, 2, f32> = ps[0].clone().to_typed3d().unwrap();
+            let [a, _, c] = x.unstack();
+            nten::stack([&c, &a]).to_untyped()
+        });
+    }
+}
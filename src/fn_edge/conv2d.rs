@@ -0,0 +1,191 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, backend_cpu::Conv2dGeometry, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+// fnはnten convにある
+
+// stride, padding, dilationは(height, width)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conv2dConfig {
+    pub stride: (usize, usize),
+    pub padding: (usize, usize),
+    pub dilation: (usize, usize),
+    pub groups: usize,
+}
+impl Conv2dConfig {
+    // pytorchと同じデフォルト
+    pub fn new() -> Self {
+        Self {
+            stride: (1, 1),
+            padding: (0, 0),
+            dilation: (1, 1),
+            groups: 1,
+        }
+    }
+    pub fn stride(mut self, height: usize, width: usize) -> Self {
+        self.stride = (height, width);
+        self
+    }
+    pub fn padding(mut self, height: usize, width: usize) -> Self {
+        self.padding = (height, width);
+        self
+    }
+    pub fn dilation(mut self, height: usize, width: usize) -> Self {
+        self.dilation = (height, width);
+        self
+    }
+    pub fn groups(mut self, groups: usize) -> Self {
+        self.groups = groups;
+        self
+    }
+}
+
+// NCHW
+#[derive(Clone)]
+pub struct Conv2d<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    // (CO, C/groups, KH, KW)
+    pub weight_id: NtenID,
+    // (1, CO)
+    pub bias_id: Option<NtenID>,
+    pub output_id: NtenID,
+    pub geometry: Conv2dGeometry,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Conv2d<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        let g = &self.geometry;
+        format!("Conv2d<{}> input ({}, {}, {}, {}) kernel ({}, {}, {}, {}) to ({}, {}, {}, {})",
+            T::type_name(),
+            g.batch, g.in_channels, g.in_height, g.in_width,
+            g.out_channels, g.in_channels / g.groups, g.kernel_height, g.kernel_width,
+            g.batch, g.out_channels, g.out_height, g.out_width,
+        )
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        let mut inputs = vec![self.input_id, self.weight_id];
+        inputs.extend(self.bias_id);
+        inputs
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let weight = ctx.get_val(&self.weight_id);
+        let bias = self.bias_id.map(|id| ctx.get_val(&id));
+
+        let output = match &bias {
+            Some(bias) => Storage::conv2d(&input.storage(), &weight.storage(), Some(&bias.storage()), &self.geometry),
+            None => Storage::conv2d(&input.storage(), &weight.storage(), None, &self.geometry),
+        };
+        let g = &self.geometry;
+        let output = Tensor {
+            name: "conv2d".to_string(),
+            shape: Shape::D4(g.batch, g.out_channels, g.out_height, g.out_width),
+            storage: Arc::new(RwLock::new(output)),
+        };
+
+        ctx.insert_val(&self.output_id, output);
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let input = ctx.get_val(&self.input_id);
+        let weight = ctx.get_val(&self.weight_id);
+
+        let (dinput, dweight, dbias) = Storage::conv2d_backward(&dout.storage(), &input.storage(), &weight.storage(), &self.geometry);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "conv2d dinput".to_string(), shape: input.shape, storage: Arc::new(RwLock::new(dinput)) });
+        ctx.add_assign_grad(&self.weight_id, &Tensor { name: "conv2d dweight".to_string(), shape: weight.shape, storage: Arc::new(RwLock::new(dweight)) });
+        if let Some(bias_id) = &self.bias_id {
+            ctx.add_assign_grad(bias_id, &Tensor { name: "conv2d dbias".to_string(), shape: Shape::D2(1, self.geometry.out_channels), storage: Arc::new(RwLock::new(dbias)) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, fn_edge::Conv2dConfig, nten::{conv2d, Nten4d}, test_utils::{assert_close, forward, gradcheck, sample, typed4d}};
+
+    // 定義どおりの畳み込み
+    #[allow(clippy::too_many_arguments)]
+    fn naive_conv2d(x: &[f32], w: &[f32], b: &[f32], (n, c, h, wd): (usize, usize, usize, usize), (co, kh, kw): (usize, usize, usize), (ho, wo): (usize, usize), config: Conv2dConfig) -> Vec<f32> {
+        let (cg, cog) = (c / config.groups, co / config.groups);
+        let mut out = vec![0.0; n * co * ho * wo];
+        for ni in 0..n {
+            for o in 0..co {
+                let group = o / cog;
+                for oh in 0..ho {
+                    for ow in 0..wo {
+                        let mut acc = b[o];
+                        for ci in 0..cg {
+                            for a in 0..kh {
+                                for bb in 0..kw {
+                                    let ih = (oh * config.stride.0 + a * config.dilation.0) as isize - config.padding.0 as isize;
+                                    let iw = (ow * config.stride.1 + bb * config.dilation.1) as isize - config.padding.1 as isize;
+                                    if ih >= 0 && iw >= 0 && (ih as usize) < h && (iw as usize) < wd {
+                                        acc += x[((ni * c + group * cg + ci) * h + ih as usize) * wd + iw as usize] * w[((o * cg + ci) * kh + a) * kw + bb];
+                                    }
+                                }
+                            }
+                        }
+                        out[((ni * co + o) * ho + oh) * wo + ow] = acc;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn config() -> Conv2dConfig {
+        Conv2dConfig::new().stride(2, 1).padding(1, 2).dilation(2, 1).groups(2)
+    }
+
+    #[test]
+    fn forward_matches_naive() {
+        let params = [sample(Shape::D4(2, 4, 5, 6), 1), sample(Shape::D4(6, 2, 3, 2), 2), sample(Shape::D2(1, 6), 3)];
+        // HO = (5 + 2 - 5) / 2 + 1 = 2, WO = (6 + 4 - 2) / 1 + 1 = 9
+        let out = forward(&params, |_, ps| {
+            let bias = ps[2].clone().to_typed2d::<1, 6, f32>().unwrap();
+            let out: Nten4d<2, 6, 2, 9, f32> = conv2d(&typed4d::<2, 4, 5, 6>(&ps[0]), &typed4d::<6, 2, 3, 2>(&ps[1]), Some(&bias), config());
+            out.to_untyped()
+        });
+        let expected = naive_conv2d(&params[0].to_vec_f32(), &params[1].to_vec_f32(), &params[2].to_vec_f32(), (2, 4, 5, 6), (6, 3, 2), (2, 9), config());
+        assert_close(&out.to_vec_f32(), &expected, 1e-5);
+    }
+
+    #[test]
+    fn gradcheck_strided_dilated_grouped() {
+        let params = [sample(Shape::D4(2, 4, 5, 6), 1), sample(Shape::D4(6, 2, 3, 2), 2), sample(Shape::D2(1, 6), 3)];
+        gradcheck(&params, |_, ps| {
+            let bias = ps[2].clone().to_typed2d::<1, 6, f32>().unwrap();
+            let out: Nten4d<2, 6, 2, 9, f32> = conv2d(&typed4d::<2, 4, 5, 6>(&ps[0]), &typed4d::<6, 2, 3, 2>(&ps[1]), Some(&bias), config());
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_without_bias() {
+        let params = [sample(Shape::D4(1, 2, 4, 4), 4), sample(Shape::D4(3, 2, 3, 3), 5)];
+        gradcheck(&params, |_, ps| {
+            let out: Nten4d<1, 3, 2, 2, f32> = conv2d(&typed4d::<1, 2, 4, 4>(&ps[0]), &typed4d::<3, 2, 3, 3>(&ps[1]), None, Conv2dConfig::new());
+            out.to_untyped()
+        });
+    }
+}
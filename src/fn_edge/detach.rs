@@ -0,0 +1,129 @@
+use std::marker::PhantomData;
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::{Nten, NtenID}};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+detach (stop_gradient)。forwardは入力の値をそのまま出力にし（storageは共有），backwardでは勾配を流さない
+target network, straight-through estimator, EMA teacherなどで使う
+*/
+
+// this FnEdge's front fn is implemented at nten_detach.rs
+// fn detach() @Nten2d, Nten3d, Nten4d
+
+#[derive(Clone)]
+pub struct Detach<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Detach<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Detach<{}> {}", T::type_name(), self.shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        ctx.insert_val(&self.output_id, input);
+    }
+
+    fn backward(&self, _ctx: &mut Context) {
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], _output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        Some(vec![None])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Autograd, VarStore}, dtype::Shape, nten::{Nten, Nten2d}, optimizer::{Optimizer, Sgd}, test_utils::{as_parameters, assert_close, forward, sample}};
+
+    // y = x * detach(x)
+    fn square_with_detach(_: &mut VarStore, ps: &[Nten]) -> Nten {
+        let x = ps[0].clone().to_typed2d::<3, 4, f32>().unwrap();
+        let y: Nten2d<3, 4, f32> = x.broadcast_mul(&x.detach());
+        y.to_untyped()
+    }
+
+    #[test]
+    fn forward_is_identity() {
+        let x = sample(Shape::D3(2, 3, 4), 1);
+        let out = forward(std::slice::from_ref(&x), |_, ps| ps[0].clone().to_typed3d::<2, 3, 4, f32>().unwrap().detach().to_untyped());
+        assert_eq!(out.to_vec_f32(), x.to_vec_f32());
+    }
+
+    // d/dx (x * detach(x)) = detach(x)。detachしなければ2x
+    #[test]
+    fn backward_stops_gradient() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let x = sample(Shape::D2(3, 4), 2);
+        let ps = as_parameters(&mut vs, std::slice::from_ref(&x));
+        let [y] = ag.step_forward([square_with_detach(&mut vs, &ps)]);
+        let weight = sample(y.shape, 7);
+        let grad = ag.backward_with(&y, weight.clone()).get_grad(&ps[0].id).to_vec_f32();
+
+        let expected: Vec<f32> = x.to_vec_f32().iter().zip(weight.to_vec_f32()).map(|(x, w)| x * w).collect();
+        assert_close(&grad, &expected, 1e-6);
+    }
+
+    // grad_graph()でもdetach(x)は定数なので，勾配detach(x) * wをさらに微分しても0
+    #[test]
+    fn grad_graph_treats_detach_as_constant() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &[sample(Shape::D2(3, 4), 3)]);
+        let [y] = ag.step_forward([square_with_detach(&mut vs, &ps)]);
+        let y = Nten { grad: Some(sample(y.shape, 7)), ..y };
+        let [grad] = ag.grad_graph(&y, [ps[0].id]);
+        let [grad] = ag.step_forward([grad]);
+
+        let grad = Nten { grad: Some(sample(grad.shape, 11)), ..grad };
+        let ctx = ag.backward_roots([&grad]);
+        if let Some(second) = ctx.try_get_grad(&ps[0].id) {
+            assert!(second.to_vec_f32().iter().all(|g| *g == 0.0));
+        }
+    }
+
+    // detachの先にしかないparameterは勾配がなく，Sgdは更新しない
+    #[test]
+    fn sgd_skips_detached_parameter() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &[sample(Shape::D2(3, 4), 4), sample(Shape::D2(3, 4), 5)]);
+        let p = ps[0].clone().to_typed2d::<3, 4, f32>().unwrap();
+        let q = ps[1].clone().to_typed2d::<3, 4, f32>().unwrap();
+        let y: Nten2d<3, 4, f32> = p.broadcast_mul(&q.detach());
+        let [y] = ag.step_forward([y.to_untyped()]);
+        let ctx = ag.backward_with(&y, sample(y.shape, 7));
+        assert!(ctx.try_get_grad(&q.id).is_none());
+
+        let (p_before, q_before) = (ctx.get_val(&p.id).to_vec_f32(), ctx.get_val(&q.id).to_vec_f32());
+        Sgd::new(0.1).update(ctx);
+        assert_ne!(ctx.get_val(&p.id).to_vec_f32(), p_before);
+        assert_eq!(ctx.get_val(&q.id).to_vec_f32(), q_before);
+    }
+}
@@ -0,0 +1,125 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::{Context, Mode}, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+Mode::Train: 確率pで要素を0にし，残りを1 / (1 - p)倍する（inverted dropout）
+Mode::Inference: 恒等写像
+*/
+
+// this FnEdge's front fn is implemented at Nten2d, Nten4d
+// fn dropout() @Nten2d, @Nten4d
+
+#[derive(Clone)]
+pub struct Dropout<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub mask_cach_id: NtenID,
+    pub shape: Shape,
+    pub p: f32,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> Dropout<T> {
+    fn is_identity(&self, ctx: &Context) -> bool {
+        ctx.mode == Mode::Inference || self.p == 0.0
+    }
+}
+impl<T: Dtype> FnEdge for Dropout<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Dropout<{}> p: {} {}", T::type_name(), self.p, self.shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        if self.is_identity(ctx) {
+            ctx.insert_val(&self.output_id, input);
+            return;
+        }
+
+        let mask = Storage::new_bernoulli_mask(self.shape.numel(), self.p, &mut ctx.rng);
+        let output = input.storage().masked_scale(&mask, 1.0 / (1.0 - self.p));
+
+        // Relu2dと同じようにbackward用にmaskを保存しておく
+        ctx.insert_tensor(&self.mask_cach_id, Tensor { name: "dropout mask".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(mask)) });
+        ctx.insert_val(&self.output_id, Tensor { name: "dropout".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(output)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        // forwardとbackwardの間でmodeを切り替えないこと
+        if self.is_identity(ctx) {
+            ctx.add_assign_grad(&self.input_id, &dout);
+            return;
+        }
+
+        let mask = ctx.get_tensor(&self.mask_cach_id);
+        let dinput = dout.storage().masked_scale(&mask.storage(), 1.0 / (1.0 - self.p));
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "dropout dinput".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(dinput)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Mode, VarStore}, dtype::Shape, nten::Nten, test_utils::{forward, forward_with, gradcheck, gradcheck_with, sample, typed4d}};
+
+    fn dropout2d(p: f32) -> impl Fn(&mut VarStore, &[Nten]) -> Nten {
+        move |_, ps| ps[0].clone().to_typed2d::<4, 8, f32>().unwrap().dropout(p).to_untyped()
+    }
+
+    #[test]
+    fn train_zeroes_or_scales() {
+        let x = sample(Shape::D2(4, 8), 1);
+        let out = forward(std::slice::from_ref(&x), dropout2d(0.5)).to_vec_f32();
+        let mut dropped = 0;
+        for (y, x) in out.iter().zip(x.to_vec_f32()) {
+            if *y == 0.0 {
+                dropped += 1;
+            } else {
+                assert!((y - 2.0 * x).abs() < 1e-6);
+            }
+        }
+        assert!(0 < dropped && dropped < 32);
+    }
+
+    // forward()はmanual_seed(0)なので，何度やっても同じmaskになる
+    #[test]
+    fn seed_reproduces_mask() {
+        let x = sample(Shape::D2(4, 8), 2);
+        assert_eq!(forward(std::slice::from_ref(&x), dropout2d(0.3)).to_vec_f32(), forward(&[x], dropout2d(0.3)).to_vec_f32());
+    }
+
+    #[test]
+    fn gradcheck_train() {
+        gradcheck(&[sample(Shape::D2(4, 8), 3)], dropout2d(0.5));
+        gradcheck(&[sample(Shape::D4(2, 2, 3, 3), 4)], |_, ps| typed4d::<2, 2, 3, 3>(&ps[0]).dropout(0.3).to_untyped());
+    }
+
+    #[test]
+    fn eval_is_identity() {
+        gradcheck_with(&[sample(Shape::D2(4, 8), 5)], 1e-2, 1e-2, Mode::Inference, dropout2d(0.5));
+        let x = sample(Shape::D2(4, 8), 5);
+        assert_eq!(forward_with(std::slice::from_ref(&x), Mode::Inference, dropout2d(0.5)).to_vec_f32(), x.to_vec_f32());
+        assert_eq!(forward(std::slice::from_ref(&x), dropout2d(0.0)).to_vec_f32(), x.to_vec_f32());
+    }
+}
@@ -0,0 +1,141 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::NtenID, tensor::Tensor};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+weight (V, D)からindices (N, 1)の行を取り出して(N, D)にする
+backwardはweightの勾配の触れた行だけに足す。indicesには勾配は流れない
+*/
+
+// this FnEdge's front fn is implemented at nten_embedding.rs
+// fn embedding()
+
+#[derive(Clone)]
+pub struct Embedding<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub indices_id: NtenID,
+    pub weight_id: NtenID,
+    pub output_id: NtenID,
+    // V, D, N
+    pub num_embeddings: usize,
+    pub embedding_dim: usize,
+    pub num_indices: usize,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Embedding<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Embedding<{}> {} indices from ({}, {})", T::type_name(), self.num_indices, self.num_embeddings, self.embedding_dim)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.indices_id, self.weight_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let weight = ctx.get_val(&self.weight_id);
+        let indices = ctx.get_val(&self.indices_id);
+
+        let output = weight.storage().gather_rows(self.embedding_dim, &indices.storage());
+
+        ctx.insert_val(&self.output_id, Tensor { name: "embedding".to_string(), shape: Shape::D2(self.num_indices, self.embedding_dim), storage: Arc::new(RwLock::new(output)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let indices = ctx.get_val(&self.indices_id);
+
+        ctx.add_assign_grad_rows(&self.weight_id, Shape::D2(self.num_embeddings, self.embedding_dim), &indices, &dout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Autograd, VarStore}, dtype::Shape, nten::{self, Nten, Nten2d}, optimizer::{Optimizer, Sgd}, tensor::Tensor2d, test_utils::{assert_close, forward, gradcheck, sample}};
+
+    // 行1は2回，行3と4は使わない
+    fn indices(vs: &mut VarStore) -> Nten2d<4, 1, u32> {
+        Nten2d::new_from_val(Tensor2d::new_from_indices(vec![2, 1, 0, 1]).unwrap()).name("indices").as_input(vs)
+    }
+    fn lookup(vs: &mut VarStore, ps: &[Nten]) -> Nten {
+        nten::embedding(&indices(vs), &ps[0].clone().to_typed2d::<5, 3, f32>().unwrap()).to_untyped()
+    }
+
+    #[test]
+    fn forward_gathers_rows() {
+        let weight = sample(Shape::D2(5, 3), 1);
+        let rows = weight.to_vec_f32();
+        let expected: Vec<f32> = [2, 1, 0, 1].iter().flat_map(|&i| rows[i * 3..i * 3 + 3].to_vec()).collect();
+        assert_eq!(forward(&[weight], lookup).to_vec_f32(), expected);
+    }
+
+    #[test]
+    fn gradcheck_weight() {
+        gradcheck(&[sample(Shape::D2(5, 3), 2)], lookup);
+    }
+
+    // 勾配は触れた行だけを持ち，Sgdはその行だけを更新する
+    #[test]
+    fn sparse_grad_updates_touched_rows() {
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let init = sample(Shape::D2(5, 3), 3);
+        // Sgdはweightのstorageを書き換えるので先に値を取っておく
+        let init_vals = init.to_vec_f32();
+        let weight = Nten2d::new_from_val(init.to_typed2d::<5, 3, f32>().unwrap()).name("weight").as_parameter(&mut vs);
+        let out = nten::embedding(&indices(&mut vs), &weight);
+        let [out] = ag.step_forward([out.to_untyped()]);
+        let dout = sample(Shape::D2(4, 3), 4);
+        let ctx = ag.backward_with(&out, dout.clone());
+
+        let sparse = ctx.try_get_sparse_grad(&weight.id).expect("grad should stay sparse");
+        assert_eq!(sparse.rows.iter().map(|rows| rows.shape.numel()).sum::<usize>(), 12);
+        let d = dout.to_vec_f32();
+        let mut expected = vec![0.0; 15];
+        for (n, &i) in [2, 1, 0, 1].iter().enumerate() {
+            for c in 0..3 {
+                expected[i * 3 + c] += d[n * 3 + c];
+            }
+        }
+        assert_close(&ctx.get_grad(&weight.id).to_vec_f32(), &expected, 1e-6);
+
+        Sgd::new(0.5).update(ctx);
+        let updated = ctx.get_val(&weight.id).to_vec_f32();
+        let stepped: Vec<f32> = init_vals.iter().zip(expected.iter()).map(|(w, g)| w - 0.5 * g).collect();
+        assert_close(&updated, &stepped, 1e-6);
+        assert_eq!(&updated[9..], &init_vals[9..]);
+    }
+
+    // 同じweightをdenseにも使うと，行ごとの勾配はdenseの勾配にまとめられる
+    // backwardでdenseが先に来る順番と，行ごとの勾配が先に来る順番の両方
+    #[test]
+    fn gradcheck_tied_weight() {
+        gradcheck(&[sample(Shape::D2(5, 3), 5)], |vs, ps| {
+            let weight = ps[0].clone().to_typed2d::<5, 3, f32>().unwrap();
+            let looked_up: Nten2d<4, 3, f32> = nten::embedding(&indices(vs), &weight);
+            let scores: Nten2d<4, 5, f32> = nten::matmul(&looked_up, &weight.transpose());
+            scores.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(5, 3), 6)], |vs, ps| {
+            let weight = ps[0].clone().to_typed2d::<5, 3, f32>().unwrap();
+            let dense: Nten2d<3, 5, f32> = weight.tanh().transpose();
+            let looked_up: Nten2d<4, 3, f32> = nten::embedding(&indices(vs), &weight);
+            let scores: Nten2d<4, 5, f32> = nten::matmul(&looked_up, &dense);
+            scores.to_untyped()
+        });
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::{Arc, RwLock};
+use crate::{autograd::Context, backend_cpu::LinearGeometry, dtype::Shape, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+graph最適化（graph_opt.rs）が作るFnEdge。フロントはない
+FusedLinear: Matmul -> AddBroadcast2d (-> Relu2d) をまとめたもの
+Alias: CSEで重複したFnEdgeの代わり。同じ値の出力をstorageを共有して作り，gradは元の出力に足す
+*/
+
+// 最適化の対象になるFnEdgeの形。FnEdge::pattern()で返す
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgePattern {
+    // (n, m) x (m, o)
+    Matmul { lhs_id: NtenID, rhs_id: NtenID, output_id: NtenID, n: usize, m: usize, o: usize },
+    // (r, c) + (1, c)
+    AddBias { input_id: NtenID, bias_id: NtenID, output_id: NtenID },
+    Relu { input_id: NtenID, output_id: NtenID },
+}
+
+#[derive(Clone)]
+pub struct FusedLinear {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub weight_id: NtenID,
+    pub bias_id: NtenID,
+    pub output_id: NtenID,
+    pub geometry: LinearGeometry,
+}
+impl FnEdge for FusedLinear {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("FusedLinear {:?}", self.geometry)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.weight_id, self.bias_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let weight = ctx.get_val(&self.weight_id);
+        let bias = ctx.get_val(&self.bias_id);
+
+        let out = Storage::fused_linear(&input.storage(), &weight.storage(), &bias.storage(), &self.geometry);
+
+        ctx.insert_val(&self.output_id, Tensor {
+            name: "fused linear".to_string(),
+            shape: Shape::D2(self.geometry.n, self.geometry.o),
+            storage: Arc::new(RwLock::new(out)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let weight = ctx.get_val(&self.weight_id);
+        let output = ctx.get_val(&self.output_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let (dinput, dweight, dbias) = Storage::fused_linear_backward(&dout.storage(), &input.storage(), &weight.storage(), &output.storage(), &self.geometry);
+
+        let LinearGeometry { n, m, o, .. } = self.geometry;
+        let tensor = |storage, shape| Tensor { name: "d fused linear".to_string(), shape, storage: Arc::new(RwLock::new(storage)) };
+        ctx.add_assign_grad(&self.input_id, &tensor(dinput, Shape::D2(n, m)));
+        ctx.add_assign_grad(&self.weight_id, &tensor(dweight, Shape::D2(m, o)));
+        ctx.add_assign_grad(&self.bias_id, &tensor(dbias, Shape::D2(1, o)));
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Alias {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub original_id: NtenID,
+    pub output_id: NtenID,
+}
+impl FnEdge for Alias {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Alias of nten id: {}", self.original_id)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.original_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let original = ctx.get_val(&self.original_id);
+
+        ctx.insert_val(&self.output_id, original);
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        ctx.add_assign_grad(&self.original_id, &dout);
+    }
+}
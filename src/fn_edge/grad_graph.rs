@@ -0,0 +1,171 @@
+use std::marker::PhantomData;
+use crate::{autograd::Context, dtype::{is_broadcastable, BinaryOp, Dtype, Shape}, logger::LOGGER, nten::{get_new_nten_id, Nten, NtenID}, tensor::{self, Tensor}};
+use super::{get_new_fn_edge_id, shared, BatchedMatmul, Broadcast, FnEdge, HumanCreatedFnEdge, MaskedFill, Permute, Reshape, SumTo};
+
+
+/*
+create_graph用の部品。FnEdge::backward_graph()の中で勾配の計算をFnEdgeとして組み立てる
+形状は実行時のShapeで持つので型のないNtenを使う
+*/
+
+fn new_nten(id: NtenID, op_type: &str, creator: Box<dyn FnEdge>, shape: Shape) -> Nten {
+    Nten {
+        id,
+        name: format!("auto created by backward_graph {}", op_type),
+        creator: shared(creator),
+        shape,
+        val: None,
+        grad: None,
+    }
+}
+
+// 値が決まっている葉。ctxに直接入れる
+pub(crate) fn constant(ctx: &mut Context, val: Tensor) -> Nten {
+    let nten = Nten {
+        id: get_new_nten_id(),
+        name: "constant of backward_graph".to_string(),
+        creator: Box::new(HumanCreatedFnEdge::new()),
+        shape: val.shape,
+        val: Some(val),
+        grad: None,
+    };
+    ctx.insert_nten(nten.clone());
+    Nten { val: None, ..nten }
+}
+
+// likeと同じランクで要素1つの定数。broadcastしてかける
+pub(crate) fn scalar(ctx: &mut Context, value: f32, like: Shape) -> Nten {
+    let shape = Shape::from_dims(&vec![1; like.dims().len()]).unwrap();
+    constant(ctx, Tensor::new_from_vec(vec![value], shape).unwrap())
+}
+
+pub(crate) fn broadcast_shape(lhs: Shape, rhs: Shape) -> Result<Shape, String> {
+    let (lhs_dims, rhs_dims) = (lhs.dims(), rhs.dims());
+    if lhs_dims.len() != rhs_dims.len() {
+        return Err(format!("broadcast_shape() >> rank of {} and {} are unmatched", lhs, rhs));
+    }
+    let dims: Vec<usize> = lhs_dims.iter().zip(rhs_dims.iter()).map(|(l, r)| *l.max(r)).collect();
+    if !lhs_dims.iter().zip(rhs_dims.iter()).zip(dims.iter()).all(|((l, r), o)| is_broadcastable(*l, *r, *o)) {
+        return Err(format!("broadcast_shape() >> {} and {} can not be broadcasted", lhs, rhs));
+    }
+    Shape::from_dims(&dims)
+}
+
+pub(crate) fn binary<T: Dtype>(op: BinaryOp, lhs: &Nten, rhs: &Nten) -> Nten {
+    let output_shape = broadcast_shape(lhs.shape, rhs.shape).unwrap_or_else(|e| {
+        LOGGER.error(e);
+        panic!("")
+    });
+    let new_id = get_new_nten_id();
+    let edge = Broadcast::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![lhs.creator.clone(), rhs.creator.clone()],
+        op,
+        lhs_id: lhs.id,
+        rhs_id: rhs.id,
+        output_id: new_id,
+        lhs_shape: lhs.shape,
+        rhs_shape: rhs.shape,
+        output_shape,
+        _marker: PhantomData,
+    };
+    new_nten(new_id, &edge.name(), Box::new(edge), output_shape)
+}
+
+// broadcastで伸ばされた軸を足してshapeに戻す
+pub(crate) fn sum_to<T: Dtype>(input: &Nten, shape: Shape) -> Nten {
+    if input.shape == shape {
+        return input.clone();
+    }
+    let new_id = get_new_nten_id();
+    let edge = SumTo::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone()],
+        input_id: input.id,
+        output_id: new_id,
+        input_shape: input.shape,
+        output_shape: shape,
+        _marker: PhantomData,
+    };
+    new_nten(new_id, "SumTo", Box::new(edge), shape)
+}
+
+pub(crate) fn matmul_t<T: Dtype>(lhs: &Nten, rhs: &Nten, transpose_lhs: bool, transpose_rhs: bool) -> Nten {
+    let (_, output_shape) = tensor::matmul_geometry(lhs.shape, rhs.shape, transpose_lhs, transpose_rhs).unwrap_or_else(|e| {
+        LOGGER.error(e);
+        panic!("")
+    });
+    let new_id = get_new_nten_id();
+    let edge = BatchedMatmul::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![lhs.creator.clone(), rhs.creator.clone()],
+        lhs_id: lhs.id,
+        rhs_id: rhs.id,
+        output_id: new_id,
+        transpose_lhs,
+        transpose_rhs,
+        _marker: PhantomData,
+    };
+    new_nten(new_id, "BatchedMatmul", Box::new(edge), output_shape)
+}
+
+pub(crate) fn reshape<T: Dtype>(input: &Nten, shape: Shape) -> Nten {
+    let new_id = get_new_nten_id();
+    let edge = Reshape::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone()],
+        input_id: input.id,
+        output_id: new_id,
+        input_shape: input.shape,
+        output_shape: shape,
+        _marker: PhantomData,
+    };
+    new_nten(new_id, "Reshape", Box::new(edge), shape)
+}
+
+pub(crate) fn permute<T: Dtype>(input: &Nten, perm: &[usize]) -> Nten {
+    let dims = input.shape.dims();
+    let shape = Shape::from_dims(&perm.iter().map(|p| dims[*p]).collect::<Vec<_>>()).unwrap();
+    let new_id = get_new_nten_id();
+    let edge = Permute::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone()],
+        input_id: input.id,
+        output_id: new_id,
+        input_shape: input.shape,
+        perm: perm.to_vec(),
+        _marker: PhantomData,
+    };
+    new_nten(new_id, "Permute", Box::new(edge), shape)
+}
+
+// maskがtrueの要素をvalueにする
+pub(crate) fn masked_fill<T: Dtype>(input: &Nten, mask: &Nten, value: f32) -> Nten {
+    let new_id = get_new_nten_id();
+    let edge = MaskedFill::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone(), mask.creator.clone()],
+        input_id: input.id,
+        mask_id: mask.id,
+        output_id: new_id,
+        shape: input.shape,
+        value,
+        _marker: PhantomData,
+    };
+    new_nten(new_id, "MaskedFill", Box::new(edge), input.shape)
+}
+
+// op(lhs)・op(rhs)の勾配。転置は作らずtransposeフラグの組み合わせで表す
+pub(crate) fn matmul_t_backward<T: Dtype>(dout: &Nten, lhs: &Nten, rhs: &Nten, transpose_lhs: bool, transpose_rhs: bool) -> (Nten, Nten) {
+    let dlhs = if transpose_lhs {
+        matmul_t::<T>(rhs, dout, transpose_rhs, true)
+    } else {
+        matmul_t::<T>(dout, rhs, false, !transpose_rhs)
+    };
+    let drhs = if transpose_rhs {
+        matmul_t::<T>(dout, lhs, true, transpose_lhs)
+    } else {
+        matmul_t::<T>(lhs, dout, !transpose_lhs, false)
+    };
+    (dlhs, drhs)
+}
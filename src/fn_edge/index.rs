@@ -0,0 +1,320 @@
+use std::marker::PhantomData;
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::{Nten, NtenID}, tensor::Tensor};
+use super::{grad_graph, FnEdge, FnEdgeID};
+
+
+/*
+indexとmaskを使う演算。indexとmaskはas_input()でVarStoreに入れたntenで，勾配は流れない
+形状はフロントで検査済みなので，Tensorの演算のResultはunwrapする
+*/
+
+// this FnEdge's front fn is implemented at nten_index.rs
+// fn gather(), scatter_add(), index_select(), masked_fill() @Nten2d
+
+#[derive(Clone)]
+pub struct Gather<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub index_id: NtenID,
+    pub output_id: NtenID,
+    pub dim: usize,
+    pub input_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Gather<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Gather<{}> dim: {} from {}", T::type_name(), self.dim, self.input_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.index_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let index = ctx.get_val(&self.index_id);
+
+        ctx.insert_val(&self.output_id, input.gather(self.dim, &index).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let index = ctx.get_val(&self.index_id);
+
+        let dinput = Tensor::new_zeros::<T>(self.input_shape).scatter_add(self.dim, &index, &dout).unwrap();
+
+        ctx.add_assign_grad(&self.input_id, &dinput);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct ScatterAdd<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub index_id: NtenID,
+    pub src_id: NtenID,
+    pub output_id: NtenID,
+    pub dim: usize,
+    pub input_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for ScatterAdd<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("ScatterAdd<{}> dim: {} to {}", T::type_name(), self.dim, self.input_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.index_id, self.src_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let index = ctx.get_val(&self.index_id);
+        let src = ctx.get_val(&self.src_id);
+
+        ctx.insert_val(&self.output_id, input.scatter_add(self.dim, &index, &src).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let index = ctx.get_val(&self.index_id);
+
+        // 足されたsrcの勾配はその位置のdout
+        let dsrc = dout.gather(self.dim, &index).unwrap();
+
+        ctx.add_assign_grad(&self.input_id, &dout);
+        ctx.add_assign_grad(&self.src_id, &dsrc);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct IndexSelect<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub indices_id: NtenID,
+    pub output_id: NtenID,
+    pub dim: usize,
+    pub input_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for IndexSelect<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("IndexSelect<{}> dim: {} from {}", T::type_name(), self.dim, self.input_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.indices_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let indices = ctx.get_val(&self.indices_id);
+
+        ctx.insert_val(&self.output_id, input.index_select(self.dim, &indices).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let indices = ctx.get_val(&self.indices_id);
+
+        let dinput = Tensor::new_zeros::<T>(self.input_shape).index_add(self.dim, &indices, &dout).unwrap();
+
+        ctx.add_assign_grad(&self.input_id, &dinput);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct MaskedFill<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub mask_id: NtenID,
+    pub output_id: NtenID,
+    pub shape: Shape,
+    pub value: f32,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for MaskedFill<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("MaskedFill<{}> value: {} {}", T::type_name(), self.value, self.shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.mask_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let mask = ctx.get_val(&self.mask_id);
+
+        ctx.insert_val(&self.output_id, input.masked_fill(&mask, self.value).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let mask = ctx.get_val(&self.mask_id);
+
+        // 埋められた要素には勾配が流れない
+        let dinput = dout.masked_fill(&mask, 0.0).unwrap();
+
+        ctx.add_assign_grad(&self.input_id, &dinput);
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        Some(vec![Some(grad_graph::masked_fill::<T>(dout, &inputs[1], 0.0)), None])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::VarStore, dtype::Shape, nten::{Nten, Nten2d}, tensor::{Tensor, Tensor2d}, test_utils::{forward, gradcheck, gradgradcheck, sample}};
+
+    fn index<const R: usize, const C: usize>(vs: &mut VarStore, data: Vec<u32>) -> Nten2d<R, C, u32> {
+        Nten2d::new_from_val(Tensor2d::new_from_indices(data).unwrap()).name("index").as_input(vs)
+    }
+    fn typed(ps: &[Nten], i: usize) -> Nten2d<3, 4, f32> {
+        ps[i].clone().to_typed2d().unwrap()
+    }
+
+    #[test]
+    fn tensor_ops() {
+        let x = Tensor::new_from_vec((0..12).map(|v| v as f32).collect(), Shape::D2(3, 4)).unwrap();
+        let index = Tensor2d::<2, 2, u32>::new_from_indices(vec![2, 0, 1, 1]).unwrap().to_untyped();
+        assert_eq!(x.gather(0, &index).unwrap().to_vec_f32(), vec![8.0, 1.0, 4.0, 5.0]);
+        assert_eq!(x.gather(1, &index).unwrap().to_vec_f32(), vec![2.0, 0.0, 5.0, 5.0]);
+
+        let src = Tensor::new_ones::<f32>(Shape::D2(2, 2));
+        let zeros = Tensor::new_zeros::<f32>(Shape::D2(3, 4));
+        assert_eq!(zeros.scatter_add(1, &index, &src).unwrap().to_vec_f32(), vec![1.0, 0.0, 1.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        let indices = Tensor2d::<1, 2, u32>::new_from_indices(vec![2, 2]).unwrap().to_untyped();
+        assert_eq!(x.index_select(0, &indices).unwrap().to_vec_f32(), vec![8.0, 9.0, 10.0, 11.0, 8.0, 9.0, 10.0, 11.0]);
+        assert_eq!(x.index_select(1, &indices).unwrap().to_vec_f32(), vec![2.0, 2.0, 6.0, 6.0, 10.0, 10.0]);
+
+        let mask = Tensor2d::<3, 4, f32>::new_from_vec((0..12).map(|v| v as f32).collect()).unwrap().select_larger_than(8.5).to_untyped();
+        assert_eq!(x.masked_select(&mask).unwrap().to_vec_f32(), vec![9.0, 10.0, 11.0]);
+        assert_eq!(x.masked_select(&mask).unwrap().shape, Shape::D1(3));
+        assert_eq!(&x.masked_fill(&mask, -1.0).unwrap().to_vec_f32()[8..], &[8.0, -1.0, -1.0, -1.0]);
+        assert!(x.gather(2, &index).is_err());
+    }
+
+    #[test]
+    fn gradcheck_gather() {
+        // 同じ要素を2回取り出すと勾配は足される
+        gradcheck(&[sample(Shape::D2(3, 4), 1)], |vs, ps| {
+            typed(ps, 0).gather::<0, 2, 3>(&index(vs, vec![2, 0, 2, 2, 1, 1])).to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(3, 4), 2)], |vs, ps| {
+            typed(ps, 0).gather::<1, 3, 2>(&index(vs, vec![3, 3, 0, 1, 2, 0])).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_scatter_add() {
+        gradcheck(&[sample(Shape::D2(3, 4), 3), sample(Shape::D2(2, 3), 4)], |vs, ps| {
+            let src: Nten2d<2, 3, f32> = ps[1].clone().to_typed2d().unwrap();
+            typed(ps, 0).scatter_add::<0, 2, 3>(&index(vs, vec![2, 0, 2, 2, 1, 1]), &src).to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(3, 4), 5), sample(Shape::D2(3, 2), 6)], |vs, ps| {
+            let src: Nten2d<3, 2, f32> = ps[1].clone().to_typed2d().unwrap();
+            typed(ps, 0).scatter_add::<1, 3, 2>(&index(vs, vec![3, 3, 0, 1, 2, 0]), &src).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_index_select() {
+        gradcheck(&[sample(Shape::D2(3, 4), 7)], |vs, ps| {
+            let out: Nten2d<4, 4, f32> = typed(ps, 0).index_select::<0, 4, 4, 4>(&index(vs, vec![1, 0, 1, 1]));
+            out.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(3, 4), 8)], |vs, ps| {
+            let out: Nten2d<3, 2, f32> = typed(ps, 0).index_select::<1, 2, 3, 2>(&index(vs, vec![3, 3]));
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn masked_fill_blocks_grad() {
+        let x = sample(Shape::D2(3, 4), 9);
+        let fill = |vs: &mut VarStore, ps: &[Nten]| {
+            let mask = Nten2d::new_from_val(Tensor2d::<3, 4, bool>::new_causal_mask()).name("mask").as_input(vs);
+            typed(ps, 0).masked_fill(&mask, 0.5).to_untyped()
+        };
+        let out = forward(std::slice::from_ref(&x), fill).to_vec_f32();
+        let x = x.to_vec_f32();
+        // causal maskはj <= iでtrue
+        for i in 0..3 {
+            for j in 0..4 {
+                assert_eq!(out[i * 4 + j], if j <= i { 0.5 } else { x[i * 4 + j] });
+            }
+        }
+        gradcheck(&[sample(Shape::D2(3, 4), 9)], fill);
+    }
+
+    #[test]
+    fn gradgradcheck_masked_fill() {
+        gradgradcheck(&[sample(Shape::D2(3, 4), 9)], |vs, ps| {
+            let mask = Nten2d::new_from_val(Tensor2d::<3, 4, bool>::new_causal_mask()).name("mask").as_input(vs);
+            typed(ps, 0).masked_fill(&mask, 0.5).sigmoid().to_untyped()
+        });
+    }
+}
@@ -0,0 +1,482 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, backend_cpu::CrossEntropyGeometry, dtype::{BinaryOp, Dtype, PointwiseLoss, Reduction, Shape}, nten::{Nten, NtenID}, tensor::{Storage, Tensor}};
+use super::{grad_graph, FnEdge, FnEdgeID};
+
+
+/*
+lossをグラフの中で計算する。Reduction::Mean, Sumの出力は(1, 1)，Noneは入力と同じ形状
+targetは定数として扱い勾配を流さない
+*/
+
+// this FnEdge's front fn is implemented at loss_fn
+// fn mse_loss(), l1_loss(), huber_loss(), bce_loss(), bce_with_logits_loss(), nll_loss(), cross_entropy()
+
+fn output_shape(shape: Shape, reduction: Reduction) -> Shape {
+    match reduction {
+        Reduction::None => shape,
+        Reduction::Mean | Reduction::Sum => Shape::D2(1, 1),
+    }
+}
+
+#[derive(Clone)]
+pub struct Loss<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub predict_id: NtenID,
+    pub target_id: NtenID,
+    pub output_id: NtenID,
+    pub shape: Shape,
+    pub loss: PointwiseLoss,
+    pub reduction: Reduction,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Loss<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("{}<{}> {} reduction: {:?}", self.loss.name(), T::type_name(), self.shape, self.reduction)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.predict_id, self.target_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let predict = ctx.get_val(&self.predict_id);
+        let target = ctx.get_val(&self.target_id);
+
+        let out = predict.storage().pointwise_loss(&target.storage(), self.loss, self.reduction);
+
+        ctx.insert_val(&self.output_id, Tensor {
+            name: self.loss.name().to_string(),
+            shape: output_shape(self.shape, self.reduction),
+            storage: Arc::new(RwLock::new(out)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let predict = ctx.get_val(&self.predict_id);
+        let target = ctx.get_val(&self.target_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dpredict = Storage::pointwise_loss_backward(&dout.storage(), &predict.storage(), &target.storage(), self.loss, self.reduction);
+
+        ctx.add_assign_grad(&self.predict_id, &Tensor {
+            name: format!("d{}", self.loss.name()),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(dpredict)),
+        });
+    }
+
+    // MSEのみ対応: d/dx = 2 * (x - y) (* 1 / n for Mean)
+    fn backward_graph(&self, ctx: &mut Context, inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        if self.loss != PointwiseLoss::Mse {
+            return None;
+        }
+        let dout = output_grads[0].as_ref()?;
+        let binary = grad_graph::binary::<T>;
+        let coef = match self.reduction {
+            Reduction::Mean => 2.0 / self.shape.dims().iter().product::<usize>() as f32,
+            Reduction::None | Reduction::Sum => 2.0,
+        };
+        let coef = grad_graph::scalar(ctx, coef, self.shape);
+        // (1, 1)の出力はpredictと同じランクにしてからbroadcastする
+        let dout = match self.reduction {
+            Reduction::None => dout.clone(),
+            Reduction::Mean | Reduction::Sum => grad_graph::reshape::<T>(dout, coef.shape),
+        };
+        let diff = binary(BinaryOp::Sub, &inputs[0], &inputs[1]);
+        let dpredict = binary(BinaryOp::Mul, &binary(BinaryOp::Mul, &dout, &coef), &diff);
+        Some(vec![Some(dpredict), None])
+    }
+}
+
+
+// inputは(rows, cols)のlog確率，targetは(rows, 1)のu32のclass index
+#[derive(Clone)]
+pub struct NllLoss<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub target_id: NtenID,
+    pub output_id: NtenID,
+    pub rows: usize,
+    pub cols: usize,
+    pub reduction: Reduction,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for NllLoss<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("NllLoss<{}> ({}, {}) reduction: {:?}", T::type_name(), self.rows, self.cols, self.reduction)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.target_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let target = ctx.get_val(&self.target_id);
+
+        let out = input.storage().nll_loss(self.cols, &target.storage(), self.reduction);
+
+        ctx.insert_val(&self.output_id, Tensor {
+            name: "NllLoss".to_string(),
+            shape: output_shape(Shape::D2(self.rows, 1), self.reduction),
+            storage: Arc::new(RwLock::new(out)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let target = ctx.get_val(&self.target_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinput = Storage::nll_loss_backward(&dout.storage(), (self.rows, self.cols), &target.storage(), self.reduction);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor {
+            name: "dNllLoss".to_string(),
+            shape: Shape::D2(self.rows, self.cols),
+            storage: Arc::new(RwLock::new(dinput)),
+        });
+    }
+}
+
+
+// pytorchと同じデフォルト。weightはclassごとの重みでlenはclass数
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossEntropyConfig {
+    pub weight: Option<Vec<f32>>,
+    pub label_smoothing: f32,
+    pub ignore_index: Option<u32>,
+    pub reduction: Reduction,
+}
+impl CrossEntropyConfig {
+    pub fn new() -> Self {
+        Self {
+            weight: None,
+            label_smoothing: 0.0,
+            ignore_index: None,
+            reduction: Reduction::Mean,
+        }
+    }
+    pub fn weight(mut self, weight: Vec<f32>) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+    pub fn label_smoothing(mut self, label_smoothing: f32) -> Self {
+        self.label_smoothing = label_smoothing;
+        self
+    }
+    pub fn ignore_index(mut self, ignore_index: u32) -> Self {
+        self.ignore_index = Some(ignore_index);
+        self
+    }
+    pub fn reduction(mut self, reduction: Reduction) -> Self {
+        self.reduction = reduction;
+        self
+    }
+}
+
+// log_softmaxとNLLをまとめたもの。inputは(rows, cols)のlogit，targetは(rows, 1)のu32のclass index
+#[derive(Clone)]
+pub struct CrossEntropy<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub target_id: NtenID,
+    pub output_id: NtenID,
+    pub softmax_cach_id: NtenID,
+    pub weight: Option<Vec<f32>>,
+    pub geometry: CrossEntropyGeometry,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for CrossEntropy<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("CrossEntropy<{}> {:?} weighted: {}", T::type_name(), self.geometry, self.weight.is_some())
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id, self.target_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let target = ctx.get_val(&self.target_id);
+
+        let (loss, softmax) = input.storage().cross_entropy(&target.storage(), self.weight.as_deref(), &self.geometry);
+
+        ctx.insert_tensor(&self.softmax_cach_id, Tensor {
+            name: "CrossEntropy softmax cach".to_string(),
+            shape: Shape::D2(self.geometry.rows, self.geometry.cols),
+            storage: Arc::new(RwLock::new(softmax)),
+        });
+        ctx.insert_val(&self.output_id, Tensor {
+            name: "CrossEntropy".to_string(),
+            shape: output_shape(Shape::D2(self.geometry.rows, 1), self.geometry.reduction),
+            storage: Arc::new(RwLock::new(loss)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let target = ctx.get_val(&self.target_id);
+        let softmax = ctx.get_tensor(&self.softmax_cach_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinput = Storage::cross_entropy_backward(&dout.storage(), &softmax.storage(), &target.storage(), self.weight.as_deref(), &self.geometry);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor {
+            name: "dCrossEntropy".to_string(),
+            shape: Shape::D2(self.geometry.rows, self.geometry.cols),
+            storage: Arc::new(RwLock::new(dinput)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Autograd, VarStore}, dtype::{Reduction, Shape}, loss_fn::{self, CrossEntropyConfig}, nten::{Nten, Nten2d}, tensor::{Tensor, Tensor2d}, test_utils::{assert_close, forward, gradcheck, gradgradcheck, sample}};
+
+    fn typed(ps: &[Nten], i: usize) -> Nten2d<3, 4, f32> {
+        ps[i].clone().to_typed2d().unwrap()
+    }
+    // targetは定数なのでparameterにしない
+    fn target(vs: &mut VarStore, val: Tensor) -> Nten2d<3, 4, f32> {
+        Nten2d::new_from_val(val.to_typed2d().unwrap()).name("target").as_input(vs)
+    }
+    fn class_target(vs: &mut VarStore, classes: Vec<u32>) -> Nten2d<3, 1, u32> {
+        Nten2d::new_from_val(Tensor2d::new_from_indices(classes).unwrap()).name("target").as_input(vs)
+    }
+    // [0, 1]のtarget
+    fn probabilities(seed: u64) -> Tensor {
+        let body = sample(Shape::D2(3, 4), seed).to_vec_f32().iter().map(|x| (x + 1.0) / 2.0).collect();
+        Tensor::new_from_vec(body, Shape::D2(3, 4)).unwrap()
+    }
+
+    // 要素ごとのlossをReduction::Noneで計算し，手で計算した値と比べる
+    #[test]
+    fn pointwise_losses_match_formula() {
+        let predict = sample(Shape::D2(3, 4), 1);
+        let y = sample(Shape::D2(3, 4), 2);
+        let (p, t) = (predict.to_vec_f32(), y.to_vec_f32());
+        let none = |f: fn(&Nten2d<3, 4, f32>, &Nten2d<3, 4, f32>) -> Nten2d<3, 4, f32>| {
+            let y = y.clone();
+            forward(std::slice::from_ref(&predict), move |vs, ps| f(&typed(ps, 0), &target(vs, y.clone())).to_untyped()).to_vec_f32()
+        };
+        let expected = |f: fn(f32, f32) -> f32| p.iter().zip(t.iter()).map(|(p, t)| f(*p, *t)).collect::<Vec<f32>>();
+
+        assert_close(&none(|p, t| loss_fn::mse_loss(p, t, Reduction::None)), &expected(|p, t| (p - t) * (p - t)), 1e-5);
+        assert_close(&none(|p, t| loss_fn::l1_loss(p, t, Reduction::None)), &expected(|p, t| (p - t).abs()), 1e-5);
+        assert_close(&none(|p, t| loss_fn::huber_loss(p, t, 0.5, Reduction::None)),
+            &expected(|p, t| if (p - t).abs() <= 0.5 { 0.5 * (p - t) * (p - t) } else { 0.5 * ((p - t).abs() - 0.25) }), 1e-5);
+        assert_close(&none(|p, t| loss_fn::bce_with_logits_loss(p, t, Reduction::None)),
+            &expected(|p, t| {
+                let s = 1.0 / (1.0 + (-p).exp());
+                -(t * s.ln() + (1.0 - t) * (1.0 - s).ln())
+            }), 1e-5);
+    }
+
+    #[test]
+    fn reductions() {
+        let params = [sample(Shape::D2(3, 4), 1), sample(Shape::D2(3, 4), 2)];
+        let reduced = |reduction: Reduction| forward(&params, move |_, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&typed(ps, 0), &typed(ps, 1), reduction);
+            loss.to_untyped()
+        }).to_vec_f32()[0];
+        let none = forward(&params, |_, ps| {
+            let loss: Nten2d<3, 4, f32> = loss_fn::mse_loss(&typed(ps, 0), &typed(ps, 1), Reduction::None);
+            loss.to_untyped()
+        }).to_vec_f32();
+        let sum: f32 = none.iter().sum();
+        assert_close(&[reduced(Reduction::Sum)], &[sum], 1e-5);
+        assert_close(&[reduced(Reduction::Mean)], &[sum / 12.0], 1e-5);
+    }
+
+    #[test]
+    fn gradcheck_mse_l1_huber() {
+        let params = [sample(Shape::D2(3, 4), 1)];
+        let y = sample(Shape::D2(3, 4), 2);
+        gradcheck(&params, |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&typed(ps, 0), &target(vs, y.clone()), Reduction::Mean);
+            loss.to_untyped()
+        });
+        gradcheck(&params, |vs, ps| {
+            let loss: Nten2d<3, 4, f32> = loss_fn::l1_loss(&typed(ps, 0), &target(vs, y.clone()), Reduction::None);
+            loss.to_untyped()
+        });
+        gradcheck(&params, |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::huber_loss(&typed(ps, 0), &target(vs, y.clone()), 0.5, Reduction::Sum);
+            loss.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_bce() {
+        let params = [sample(Shape::D2(3, 4), 1)];
+        gradcheck(&params, |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::bce_loss(&typed(ps, 0).sigmoid(), &target(vs, probabilities(3)), Reduction::Mean);
+            loss.to_untyped()
+        });
+        gradcheck(&params, |vs, ps| {
+            let loss: Nten2d<3, 4, f32> = loss_fn::bce_with_logits_loss(&typed(ps, 0), &target(vs, probabilities(3)), Reduction::None);
+            loss.to_untyped()
+        });
+    }
+
+    // bce(sigmoid(x))とbce_with_logits(x)は同じ値
+    #[test]
+    fn bce_with_logits_matches_bce() {
+        let params = [sample(Shape::D2(3, 4), 1)];
+        let bce = forward(&params, |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::bce_loss(&typed(ps, 0).sigmoid(), &target(vs, probabilities(3)), Reduction::Mean);
+            loss.to_untyped()
+        }).to_vec_f32();
+        let with_logits = forward(&params, |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::bce_with_logits_loss(&typed(ps, 0), &target(vs, probabilities(3)), Reduction::Mean);
+            loss.to_untyped()
+        }).to_vec_f32();
+        assert_close(&with_logits, &bce, 1e-5);
+    }
+
+    #[test]
+    fn nll_loss_picks_target() {
+        let input = sample(Shape::D2(3, 4), 1);
+        let out = forward(std::slice::from_ref(&input), |vs, ps| {
+            let loss: Nten2d<3, 1, f32> = loss_fn::nll_loss(&typed(ps, 0), &class_target(vs, vec![2, 0, 3]), Reduction::None);
+            loss.to_untyped()
+        }).to_vec_f32();
+        let input = input.to_vec_f32();
+        assert_close(&out, &[-input[2], -input[4], -input[11]], 1e-6);
+
+        gradcheck(&[sample(Shape::D2(3, 4), 1)], |vs, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::nll_loss(&typed(ps, 0), &class_target(vs, vec![2, 0, 3]), Reduction::Mean);
+            loss.to_untyped()
+        });
+    }
+
+    fn cross_entropy(vs: &mut VarStore, ps: &[Nten], classes: Vec<u32>, config: CrossEntropyConfig) -> Nten {
+        let loss: Nten2d<1, 1, f32> = loss_fn::cross_entropy(&typed(ps, 0), &class_target(vs, classes), config);
+        loss.to_untyped()
+    }
+
+    // pytorchと同じ定義: q = (1 - ls) onehot + ls / C, loss_i = -sum_c w_c q_c log_softmax_c, Meanは無視しない行のw_tの合計で割る
+    #[test]
+    fn cross_entropy_matches_formula() {
+        let input = sample(Shape::D2(3, 4), 1);
+        let weight = vec![0.5, 1.0, 2.0, 1.5];
+        let classes = vec![2, 1, 3];
+        let config = CrossEntropyConfig::new().weight(weight.clone()).label_smoothing(0.2).ignore_index(1);
+        let out = forward(std::slice::from_ref(&input), |vs, ps| cross_entropy(vs, ps, classes.clone(), config.clone())).to_vec_f32();
+
+        let x = input.to_vec_f32();
+        let (mut total, mut denominator) = (0.0, 0.0);
+        for (i, t) in classes.iter().enumerate() {
+            if *t == 1 {
+                continue;
+            }
+            let row = &x[i * 4..i * 4 + 4];
+            let log_sum = row.iter().map(|v| v.exp()).sum::<f32>().ln();
+            for c in 0..4 {
+                let onehot = if c == *t as usize { 0.8 } else { 0.0 };
+                total -= (onehot + 0.2 / 4.0) * weight[c] * (row[c] - log_sum);
+            }
+            denominator += weight[*t as usize];
+        }
+        assert_close(&out, &[total / denominator], 1e-5);
+    }
+
+    // one-hotのteacherを使う従来のsoftmax_cross_entropy_f32と同じ値
+    #[test]
+    fn cross_entropy_matches_one_hot() {
+        let input = sample(Shape::D2(3, 4), 1);
+        let out = forward(std::slice::from_ref(&input), |vs, ps| cross_entropy(vs, ps, vec![2, 0, 3], CrossEntropyConfig::new())).to_vec_f32();
+
+        let mut one_hot = vec![0.0; 12];
+        for (i, t) in [2, 0, 3].iter().enumerate() {
+            one_hot[i * 4 + t] = 1.0;
+        }
+        let mut predict = Nten2d::<3, 4, f32>::new_from_val(input.to_typed2d().unwrap()).to_untyped();
+        let expected = loss_fn::softmax_cross_entropy_f32(&mut predict, Tensor::new_from_vec(one_hot, Shape::D2(3, 4)).unwrap());
+        assert_close(&out, &[expected], 1e-4);
+    }
+
+    #[test]
+    fn gradcheck_cross_entropy() {
+        let params = [sample(Shape::D2(3, 4), 1)];
+        gradcheck(&params, |vs, ps| cross_entropy(vs, ps, vec![2, 0, 3], CrossEntropyConfig::new()));
+        gradcheck(&params, |vs, ps| cross_entropy(vs, ps, vec![2, 0, 3],
+            CrossEntropyConfig::new().weight(vec![0.5, 1.0, 2.0, 1.5]).label_smoothing(0.2).ignore_index(0)));
+        gradcheck(&params, |vs, ps| {
+            let config = CrossEntropyConfig::new().label_smoothing(0.1).reduction(Reduction::None);
+            let loss: Nten2d<3, 1, f32> = loss_fn::cross_entropy(&typed(ps, 0), &class_target(vs, vec![2, 0, 3]), config);
+            loss.to_untyped()
+        });
+    }
+
+    // ignore_indexの行はlossも勾配も0。全部無視したときのMeanは0
+    #[test]
+    fn cross_entropy_ignores_rows() {
+        let input = sample(Shape::D2(3, 4), 1);
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let logits: Nten2d<3, 4, f32> = Nten2d::new_from_val(input.to_typed2d().unwrap()).name("logits").as_parameter(&mut vs);
+        let config = CrossEntropyConfig::new().ignore_index(0).reduction(Reduction::None);
+        let loss: Nten2d<3, 1, f32> = loss_fn::cross_entropy(&logits, &class_target(&mut vs, vec![2, 0, 3]), config);
+        let [loss] = ag.step_forward([loss.to_untyped()]);
+        assert_eq!(loss.val.as_ref().unwrap().to_vec_f32()[1], 0.0);
+        let ctx = ag.backward_with(&loss, Tensor::new_ones::<f32>(loss.shape));
+        assert_eq!(ctx.get_grad(&logits.id).to_vec_f32()[4..8], [0.0; 4]);
+
+        let out = forward(&[input], |vs, ps| cross_entropy(vs, ps, vec![0, 0, 0], CrossEntropyConfig::new().ignore_index(0))).to_vec_f32();
+        assert_eq!(out, [0.0]);
+    }
+
+    // backward_graphはMSEだけ対応
+    #[test]
+    fn gradgradcheck_mse() {
+        gradgradcheck(&[sample(Shape::D2(3, 4), 1), sample(Shape::D2(3, 4), 2)], |_, ps| {
+            let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&typed(ps, 0).tanh(), &typed(ps, 1), Reduction::Mean);
+            loss.to_untyped()
+        });
+        gradgradcheck(&[sample(Shape::D2(3, 4), 1), sample(Shape::D2(3, 4), 2)], |_, ps| {
+            let loss: Nten2d<3, 4, f32> = loss_fn::mse_loss(&typed(ps, 0).tanh(), &typed(ps, 1), Reduction::None);
+            loss.to_untyped()
+        });
+    }
+}
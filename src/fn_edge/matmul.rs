@@ -1,7 +1,7 @@
 
-use std::{fmt::format, marker::PhantomData};
-use crate::{autograd::Context, dtype::{Dtype, Shape}, logger::LOGGER, nten::{get_new_nten_id, Nten, Nten2d, NtenID}, tensor::{self, Tensor2d}};
-use super::{get_new_fn_edge_id, FnEdge, FnEdgeID};
+use std::marker::PhantomData;
+use crate::{autograd::Context, dtype::Dtype, logger::LOGGER, nten::{Nten, NtenID}, tensor::{self, Tensor2d}};
+use super::{grad_graph, pure_cse_key, EdgePattern, FnEdge, FnEdgeID};
 
 
 
@@ -34,9 +34,21 @@ impl<const N: usize, const M: usize, const O: usize, T: Dtype> FnEdge for Matmul
     fn sources(&self) -> Vec<Box<dyn FnEdge>> {
         self.sources.clone()
     }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.lhs_id, self.rhs_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
     fn clone_box(&self) -> Box<dyn FnEdge> {
         Box::new(self.clone())
     }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+    fn pattern(&self) -> Option<EdgePattern> {
+        (T::type_name() == "f32").then_some(EdgePattern::Matmul { lhs_id: self.lhs_id, rhs_id: self.rhs_id, output_id: self.output_id, n: N, m: M, o: O })
+    }
 
 
     fn forward(&self, ctx: &mut Context) {
@@ -50,15 +62,202 @@ impl<const N: usize, const M: usize, const O: usize, T: Dtype> FnEdge for Matmul
     }
 
     fn backward(&self, ctx: &mut Context) {
-        let lhs: Tensor2d<N, M, T> = ctx.get_val_as_2d(&self.lhs_id);
-        let rhs: Tensor2d<M, O, T> = ctx.get_val_as_2d(&self.rhs_id);
+        let lhs = ctx.get_val(&self.lhs_id);
+        let rhs = ctx.get_val(&self.rhs_id);
+
+        let din = ctx.get_grad(&self.output_id);
+
+        // 転置は作らずtransposed-kernelで計算する
+        let (dlhs, drhs) = tensor::matmul_t_backward(&din, &lhs, &rhs, false, false).unwrap();
+
+        ctx.add_assign_grad(&self.lhs_id, &dlhs);
+        ctx.add_assign_grad(&self.rhs_id, &drhs);
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        let (dlhs, drhs) = grad_graph::matmul_t_backward::<T>(dout, &inputs[0], &inputs[1], false, false);
+        Some(vec![Some(dlhs), Some(drhs)])
+    }
+}
+
+
+// bmm, matmul_tの共通edge。lhs, rhsはD2同士かD3同士
+// fnはnten matmulにある
+#[derive(Clone)]
+pub struct BatchedMatmul<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub lhs_id: NtenID,
+    pub rhs_id: NtenID,
+    pub output_id: NtenID,
+    pub transpose_lhs: bool,
+    pub transpose_rhs: bool,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for BatchedMatmul<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("BatchedMatmul<{}> transpose_lhs: {}, transpose_rhs: {}", T::type_name(), self.transpose_lhs, self.transpose_rhs)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.lhs_id, self.rhs_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let lhs = ctx.get_val(&self.lhs_id);
+        let rhs = ctx.get_val(&self.rhs_id);
+
+        let out = tensor::matmul_t(&lhs, &rhs, self.transpose_lhs, self.transpose_rhs).unwrap_or_else(|e| {
+            LOGGER.error(format!("BatchedMatmul::forward() >> {}", e));
+            panic!("")
+        });
+
+        ctx.insert_val(&self.output_id, out);
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let lhs = ctx.get_val(&self.lhs_id);
+        let rhs = ctx.get_val(&self.rhs_id);
+
+        let dout = ctx.get_grad(&self.output_id);
+
+        let (dlhs, drhs) = tensor::matmul_t_backward(&dout, &lhs, &rhs, self.transpose_lhs, self.transpose_rhs).unwrap_or_else(|e| {
+            LOGGER.error(format!("BatchedMatmul::backward() >> {}", e));
+            panic!("")
+        });
 
-        let din: Tensor2d<N, O, T> = ctx.get_grad_as_2d(&self.output_id);
+        ctx.add_assign_grad(&self.lhs_id, &dlhs);
+        ctx.add_assign_grad(&self.rhs_id, &drhs);
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        let (dlhs, drhs) = grad_graph::matmul_t_backward::<T>(dout, &inputs[0], &inputs[1], self.transpose_lhs, self.transpose_rhs);
+        Some(vec![Some(dlhs), Some(drhs)])
+    }
+}
 
-        let dlhs = tensor::matmul(&din, &rhs.transpose());
-        let drhs = tensor::matmul(&lhs.transpose(), &din);
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{self, Nten, Nten2d, Nten3d}, tensor::Tensor, test_utils::{assert_close, forward, gradcheck, gradgradcheck, sample}};
 
-        ctx.add_assign_grad(&self.lhs_id, &dlhs.to_untyped());
-        ctx.add_assign_grad(&self.rhs_id, &drhs.to_untyped());
+    fn typed<const R: usize, const C: usize>(ps: &[Nten], i: usize) -> Nten2d<R, C, f32> {
+        ps[i].clone().to_typed2d().unwrap()
     }
-}
\ No newline at end of file
+    fn typed3d<const B: usize, const R: usize, const C: usize>(ps: &[Nten], i: usize) -> Nten3d<B, R, C, f32> {
+        ps[i].clone().to_typed3d().unwrap()
+    }
+
+    #[test]
+    fn gradcheck_matmul() {
+        gradcheck(&[sample(Shape::D2(2, 3), 1), sample(Shape::D2(3, 4), 2)], |_, ps| {
+            nten::matmul(&typed::<2, 3>(ps, 0), &typed::<3, 4>(ps, 1)).to_untyped()
+        });
+    }
+
+    // 転置フラグの組み合わせはすべて，転置してからmatmulしたものと同じ
+    #[test]
+    fn matmul_t_matches_explicit_transpose() {
+        let params = [sample(Shape::D2(2, 3), 3), sample(Shape::D2(3, 4), 4)];
+        let expected = forward(&params, |_, ps| nten::matmul(&typed::<2, 3>(ps, 0), &typed::<3, 4>(ps, 1)).to_untyped()).to_vec_f32();
+
+        let params_t = [
+            forward(&params[..1], |_, ps| typed::<2, 3>(ps, 0).transpose().to_untyped()),
+            forward(&params[1..], |_, ps| typed::<3, 4>(ps, 0).transpose().to_untyped()),
+        ];
+        let cases: [(bool, bool, [_; 2]); 4] = [
+            (false, false, [params[0].clone(), params[1].clone()]),
+            (true, false, [params_t[0].clone(), params[1].clone()]),
+            (false, true, [params[0].clone(), params_t[1].clone()]),
+            (true, true, [params_t[0].clone(), params_t[1].clone()]),
+        ];
+        for (transpose_lhs, transpose_rhs, operands) in cases {
+            let shapes = (operands[0].shape, operands[1].shape);
+            let out = forward(&operands, |_, ps| {
+                let out: Nten2d<2, 4, f32> = match shapes {
+                    (Shape::D2(2, 3), Shape::D2(3, 4)) => nten::matmul_t(&typed::<2, 3>(ps, 0), &typed::<3, 4>(ps, 1), transpose_lhs, transpose_rhs),
+                    (Shape::D2(3, 2), Shape::D2(3, 4)) => nten::matmul_t(&typed::<3, 2>(ps, 0), &typed::<3, 4>(ps, 1), transpose_lhs, transpose_rhs),
+                    (Shape::D2(2, 3), Shape::D2(4, 3)) => nten::matmul_t(&typed::<2, 3>(ps, 0), &typed::<4, 3>(ps, 1), transpose_lhs, transpose_rhs),
+                    _ => nten::matmul_t(&typed::<3, 2>(ps, 0), &typed::<4, 3>(ps, 1), transpose_lhs, transpose_rhs),
+                };
+                out.to_untyped()
+            });
+            assert_close(&out.to_vec_f32(), &expected, 1e-6);
+        }
+    }
+
+    #[test]
+    fn gradcheck_matmul_t() {
+        gradcheck(&[sample(Shape::D2(3, 2), 5), sample(Shape::D2(4, 3), 6)], |_, ps| {
+            let out: Nten2d<2, 4, f32> = nten::matmul_t(&typed::<3, 2>(ps, 0), &typed::<4, 3>(ps, 1), true, true);
+            out.to_untyped()
+        });
+        gradcheck(&[sample(Shape::D2(2, 3), 7), sample(Shape::D2(4, 3), 8)], |_, ps| {
+            let out: Nten2d<2, 4, f32> = nten::matmul_t(&typed::<2, 3>(ps, 0), &typed::<4, 3>(ps, 1), false, true);
+            out.to_untyped()
+        });
+    }
+
+    // batchごとに2次元のmatmulをしたものと同じ
+    #[test]
+    fn bmm_matches_matmul_per_batch() {
+        let params = [sample(Shape::D3(2, 2, 3), 9), sample(Shape::D3(2, 3, 4), 10)];
+        let out = forward(&params, |_, ps| nten::bmm(&typed3d::<2, 2, 3>(ps, 0), &typed3d::<2, 3, 4>(ps, 1)).to_untyped()).to_vec_f32();
+        let (lhs, rhs) = (params[0].to_vec_f32(), params[1].to_vec_f32());
+        for b in 0..2 {
+            let batch = [
+                Tensor::new_from_vec(lhs[b * 6..(b + 1) * 6].to_vec(), Shape::D2(2, 3)).unwrap(),
+                Tensor::new_from_vec(rhs[b * 12..(b + 1) * 12].to_vec(), Shape::D2(3, 4)).unwrap(),
+            ];
+            let expected = forward(&batch, |_, ps| nten::matmul(&typed::<2, 3>(ps, 0), &typed::<3, 4>(ps, 1)).to_untyped());
+            assert_close(&out[b * 8..(b + 1) * 8], &expected.to_vec_f32(), 1e-6);
+        }
+    }
+
+    #[test]
+    fn gradcheck_bmm() {
+        gradcheck(&[sample(Shape::D3(2, 2, 3), 11), sample(Shape::D3(2, 3, 4), 12)], |_, ps| {
+            nten::bmm(&typed3d::<2, 2, 3>(ps, 0), &typed3d::<2, 3, 4>(ps, 1)).to_untyped()
+        });
+        gradcheck(&[sample(Shape::D3(2, 3, 2), 13), sample(Shape::D3(2, 4, 3), 14)], |_, ps| {
+            let out: Nten3d<2, 2, 4, f32> = nten::bmm_t(&typed3d::<2, 3, 2>(ps, 0), &typed3d::<2, 4, 3>(ps, 1), true, true);
+            out.to_untyped()
+        });
+    }
+
+    // 勾配はもう一方の入力について線形なので，2階の微分は入力どうしの交差項になる
+    #[test]
+    fn gradgradcheck_matmul() {
+        gradgradcheck(&[sample(Shape::D2(2, 3), 1), sample(Shape::D2(3, 4), 2)], |_, ps| {
+            nten::matmul(&typed::<2, 3>(ps, 0), &typed::<3, 4>(ps, 1)).to_untyped()
+        });
+        gradgradcheck(&[sample(Shape::D2(3, 2), 1), sample(Shape::D2(4, 3), 2)], |_, ps| {
+            let out: Nten2d<2, 4, f32> = nten::matmul_t(&typed::<3, 2>(ps, 0), &typed::<4, 3>(ps, 1), true, true);
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradgradcheck_bmm() {
+        gradgradcheck(&[sample(Shape::D3(2, 2, 3), 1), sample(Shape::D3(2, 3, 4), 2)], |_, ps| {
+            nten::bmm(&typed3d::<2, 2, 3>(ps, 0), &typed3d::<2, 3, 4>(ps, 1)).to_untyped()
+        });
+    }
+}
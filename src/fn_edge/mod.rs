@@ -10,8 +10,41 @@ pub use add::Add2d;
 
 mod add_broadcast;
 pub use add_broadcast::AddBroadcast2d;
+mod broadcast;
+pub use broadcast::{Broadcast, SumTo};
 mod matmul;
-pub use matmul::Matmul;
+pub use matmul::{BatchedMatmul, Matmul};
+mod conv2d;
+pub use conv2d::{Conv2d, Conv2dConfig};
+mod pool;
+pub use pool::{AvgPool2d, MaxPool2d, Pool2dConfig};
+mod norm;
+pub use norm::{Normalize, RunningStats};
+mod dropout;
+pub use dropout::Dropout;
+mod embedding;
+pub use embedding::Embedding;
+mod index;
+pub use index::{Gather, IndexSelect, MaskedFill, ScatterAdd};
+mod concat;
+pub use concat::{Concat, Split};
+mod reshape;
+pub use reshape::{Permute, Reshape};
+mod softmax;
+pub use softmax::Softmax;
+mod activation;
+pub use activation::Activation;
+mod loss;
+pub use loss::{CrossEntropy, CrossEntropyConfig, Loss, NllLoss};
+mod detach;
+pub use detach::Detach;
+mod fused;
+pub use fused::{Alias, EdgePattern, FusedLinear};
+mod checkpoint;
+pub use checkpoint::Checkpoint;
+pub(crate) mod grad_graph;
+mod shared;
+pub(crate) use shared::shared;
 pub mod relu;
 pub use relu::Relu2d;
 
@@ -19,19 +52,50 @@ pub use relu::Relu2d;
 
 /*-------------Level 2, graph node & system-----------------------------------
 */
-pub trait FnEdge {
+pub trait FnEdge: Send + Sync {
     //fn name(&self) -> String;
     // 計算グラフ構築用
     fn get_id(&self) -> FnEdgeID;
     fn sources(&self) -> Vec<Box<dyn FnEdge>>;
+    // forwardで読むntenと書くnten（backward用のcacheは含まない）
+    fn inputs(&self) -> Vec<NtenID>;
+    fn outputs(&self) -> Vec<NtenID>;
+    // backwardで値を読むnten。memory plannerはこれ以外の途中の値をforwardで最後に使われた時点で捨てる
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        self.inputs().into_iter().chain(self.outputs()).collect()
+    }
     fn clone_box(&self) -> Box<dyn FnEdge>;
     // 計算実行用
     fn forward(&self, ctx: &mut Context);
     fn backward(&self, ctx: &mut Context);
+    /*
+    create_graph用。backwardと同じ勾配をFnEdgeの組み合わせとして組み立て，入力の勾配Ntenを返す
+    inputs, outputs, output_gradsはinputs(), outputs()と同じ順。勾配の流れない入力はNone
+    対応していないFnEdgeはNoneを返す
+    */
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], _output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        None
+    }
+    /*
+    graph最適化用（graph_opt.rs）
+    cse_key: 同じ計算（種類，形状，属性）をするFnEdgeで同じになるkey。入力のidは含めない
+        乱数を使うなど重複を消せないFnEdgeはNone
+    pattern: op fusionの対象ならその形
+    */
+    fn cse_key(&self) -> Option<String> {
+        None
+    }
+    fn pattern(&self) -> Option<EdgePattern> {
+        None
+    }
 
     // デバック
     fn name(&self) -> String;
 }
+// 乱数を使わず，名前（形状と属性を含む）と入力で出力が決まるFnEdgeのcse_key
+pub(crate) fn pure_cse_key(fn_edge: &dyn FnEdge) -> Option<String> {
+    Some(fn_edge.name())
+}
 // Implement Clone for Box<dyn FnEdge> using the clone_box method
 impl Clone for Box<dyn FnEdge> {
     fn clone(&self) -> Box<dyn FnEdge> {
@@ -45,6 +109,8 @@ pub fn get_new_fn_edge_id() -> FnEdgeID {
     use std::sync::atomic;
     static COUNTER: atomic::AtomicU32 = atomic::AtomicU32::new(1);
     // get unique id
+    // Autogradごとではなくプロセス全体のカウンタ。FnEdgeはAutogradを持たずに作るので，別スレッドで並行に作ると番号は決まらない
+    // 番号はtape内の識別にだけ使い，保存には使わない
     let u32 = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
     FnEdgeID(u32)
 }
@@ -77,6 +143,12 @@ impl FnEdge for DummyFnEdge {
         // this must return vec of nothing for stop graph walk in making tape stage
         vec![]
     }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![]
+    }
     fn forward(&self, ctx: &mut Context) {
     }
     fn backward(&self, ctx: &mut Context) {
@@ -107,6 +179,12 @@ impl FnEdge for HumanCreatedFnEdge {
     fn sources(&self) -> Vec<Box<dyn FnEdge>> {
         vec![]
     }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![]
+    }
     fn forward(&self, ctx: &mut Context) {
         //ctx.varstore.print_all_contents_id();
     }
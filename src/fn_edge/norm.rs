@@ -0,0 +1,186 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::{Context, Mode}, backend_cpu::NormGeometry, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+/*
+BatchNorm, LayerNormの正規化部分 x_hat = (x - mean) / sqrt(var + eps)
+gammaとbetaのaffineはフロント側でbroadcast_mul, broadcast_addをつなげて行う
+
+running statsを持つ（BatchNorm）場合
+- Mode::Train: batchの平均と分散で正規化し，running statsを更新する
+- Mode::Inference: running statsで正規化する
+running statsを持たない（LayerNorm）場合は常にその入力の平均と分散で正規化する
+*/
+
+// this FnEdge's front fn is implemented at nten_norm.rs
+// fn batch_norm1d(), batch_norm2d(), layer_norm()
+
+// BatchNormのrunning statsはVarStoreにbufferとして登録されたntenで，形状はD2(1, channels)
+#[derive(Clone, Copy, Debug)]
+pub struct RunningStats {
+    pub mean_id: NtenID,
+    pub var_id: NtenID,
+    pub momentum: f32,
+}
+
+#[derive(Clone)]
+pub struct Normalize<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub x_hat_cach_id: NtenID,
+    pub inv_std_cach_id: NtenID,
+    pub shape: Shape,
+    pub geometry: NormGeometry,
+    pub eps: f32,
+    pub running: Option<RunningStats>,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> Normalize<T> {
+    // 平均と分散をこの入力から計算するか
+    fn use_batch_stats(&self, ctx: &Context) -> bool {
+        self.running.is_none() || ctx.mode == Mode::Train
+    }
+}
+impl<T: Dtype> FnEdge for Normalize<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        let kind = if self.running.is_some() { "BatchNorm" } else { "LayerNorm" };
+        format!("Normalize<{}> ({}) {}", T::type_name(), kind, self.shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        let mut inputs = vec![self.input_id];
+        if let Some(running) = &self.running {
+            inputs.push(running.mean_id);
+            inputs.push(running.var_id);
+        }
+        inputs
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        let (mean, var) = if self.use_batch_stats(ctx) {
+            let (mean, var) = input.storage().channel_mean_var(&self.geometry);
+            if let Some(running) = &self.running {
+                // runningの分散は不偏分散で更新する（pytorchと同じ）
+                let count = self.geometry.reduce_size() as f32;
+                let correction = if count > 1.0 { count / (count - 1.0) } else { 1.0 };
+                let running_mean = ctx.get_val(&running.mean_id);
+                let running_var = ctx.get_val(&running.var_id);
+                let new_mean = running_mean.storage().momentum_update(&mean, running.momentum, 1.0);
+                let new_var = running_var.storage().momentum_update(&var, running.momentum, correction);
+                // bufferはArcを共有しているのでモデル側の値も更新される
+                running_mean.override_value(Tensor { name: running_mean.name.clone(), shape: running_mean.shape, storage: Arc::new(RwLock::new(new_mean)) });
+                running_var.override_value(Tensor { name: running_var.name.clone(), shape: running_var.shape, storage: Arc::new(RwLock::new(new_var)) });
+            }
+            (mean, var)
+        } else {
+            let running = self.running.as_ref().unwrap();
+            let mean = ctx.get_val(&running.mean_id).storage().clone();
+            let var = ctx.get_val(&running.var_id).storage().clone();
+            (mean, var)
+        };
+
+        let (x_hat, inv_std) = input.storage().normalize(&self.geometry, &mean, &var, self.eps);
+
+        // backward用に保存しておく
+        ctx.insert_tensor(&self.inv_std_cach_id, Tensor { name: "normalize inv_std".to_string(), shape: Shape::D1(self.geometry.channels), storage: Arc::new(RwLock::new(inv_std)) });
+        ctx.insert_tensor(&self.x_hat_cach_id, Tensor { name: "normalize x_hat".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(x_hat.clone())) });
+        ctx.insert_val(&self.output_id, Tensor { name: "normalize".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(x_hat)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let x_hat = ctx.get_tensor(&self.x_hat_cach_id);
+        let inv_std = ctx.get_tensor(&self.inv_std_cach_id);
+
+        // forwardとbackwardの間でmodeを切り替えないこと
+        let dinput = Storage::normalize_backward(&dout.storage(), &x_hat.storage(), &inv_std.storage(), &self.geometry, self.use_batch_stats(ctx));
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "normalize dinput".to_string(), shape: self.shape, storage: Arc::new(RwLock::new(dinput)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::{Autograd, Mode, VarStore}, dtype::Shape, nten::{batch_norm1d, batch_norm2d, layer_norm, Nten2d}, tensor::{Tensor, Tensor2d}, test_utils::{assert_close, gradcheck, gradcheck_with, sample, typed4d}};
+
+    fn running_stats<const F: usize>(vs: &mut VarStore) -> (Nten2d<1, F, f32>, Nten2d<1, F, f32>) {
+        let mean = Nten2d::new_from_val(Tensor2d::new_from_vec(vec![0.5; F]).unwrap()).name("running_mean").as_buffer(vs);
+        let var = Nten2d::new_from_val(Tensor2d::new_from_vec(vec![2.0; F]).unwrap()).name("running_var").as_buffer(vs);
+        (mean, var)
+    }
+
+    #[test]
+    fn gradcheck_batch_norm1d_train() {
+        gradcheck(&[sample(Shape::D2(5, 3), 1)], |vs, ps| {
+            let (mean, var) = running_stats::<3>(vs);
+            batch_norm1d(&ps[0].clone().to_typed2d::<5, 3, f32>().unwrap(), &mean, &var, 0.1, 1e-5).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_batch_norm1d_eval() {
+        gradcheck_with(&[sample(Shape::D2(5, 3), 2)], 1e-2, 1e-2, Mode::Inference, |vs, ps| {
+            let (mean, var) = running_stats::<3>(vs);
+            batch_norm1d(&ps[0].clone().to_typed2d::<5, 3, f32>().unwrap(), &mean, &var, 0.1, 1e-5).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_batch_norm2d_train() {
+        gradcheck(&[sample(Shape::D4(3, 2, 2, 3), 3)], |vs, ps| {
+            let (mean, var) = running_stats::<2>(vs);
+            batch_norm2d(&typed4d::<3, 2, 2, 3>(&ps[0]), &mean, &var, 0.1, 1e-5).to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_layer_norm() {
+        gradcheck(&[sample(Shape::D2(3, 5), 4)], |_, ps| {
+            layer_norm(&ps[0].clone().to_typed2d::<3, 5, f32>().unwrap(), 1e-5).to_untyped()
+        });
+    }
+
+    // train: batch統計で正規化し，running statsを更新する。eval: running statsで正規化し，更新しない
+    #[test]
+    fn running_stats_follow_mode() {
+        let input = [1.0, 2.0, 3.0, 5.0];
+        let mut ag = Autograd::new();
+        let mut vs = ag.get_vs();
+        let (mean, var) = running_stats::<1>(&mut vs);
+        let x = Nten2d::new_from_val(Tensor2d::<4, 1, f32>::new_from_vec(input.to_vec()).unwrap()).name("input").as_input(&mut vs);
+        let [out] = ag.step_forward([batch_norm1d(&x, &mean, &var, 0.1, 0.0).to_untyped()]);
+        // 平均2.75，分散2.1875，不偏分散2.9166...
+        let std = 2.1875f32.sqrt();
+        assert_close(&out.val.as_ref().unwrap().to_vec_f32(), &input.map(|v| (v - 2.75) / std), 1e-5);
+        let ctx = ag.backward_with(&out, Tensor::new_ones::<f32>(Shape::D2(4, 1)));
+        assert_close(&ctx.get_val(&mean.id).to_vec_f32(), &[0.9 * 0.5 + 0.1 * 2.75], 1e-6);
+        assert_close(&ctx.get_val(&var.id).to_vec_f32(), &[0.9 * 2.0 + 0.1 * 2.1875 * 4.0 / 3.0], 1e-6);
+        ag.zero_grad();
+
+        ag.eval();
+        let (running_mean, running_var) = (0.725f32, 2.0916667f32);
+        let x = Nten2d::new_from_val(Tensor2d::<4, 1, f32>::new_from_vec(input.to_vec()).unwrap()).name("input").as_input(&mut vs);
+        let [out] = ag.step_forward([batch_norm1d(&x, &mean, &var, 0.1, 0.0).to_untyped()]);
+        assert_close(&out.val.as_ref().unwrap().to_vec_f32(), &input.map(|v| (v - running_mean) / running_var.sqrt()), 1e-5);
+        let ctx = ag.backward_with(&out, Tensor::new_ones::<f32>(Shape::D2(4, 1)));
+        assert_close(&ctx.get_val(&mean.id).to_vec_f32(), &[running_mean], 1e-6);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, backend_cpu::{Pool2dGeometry, PoolWindow}, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{FnEdge, FnEdgeID};
+
+
+// this FnEdge's front fn is implemented at Nten4d
+// fn max_pool2d(), avg_pool2d(), adaptive_avg_pool2d(), global_avg_pool2d() @Nten4d
+
+// kernel, stride, paddingは(height, width)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pool2dConfig {
+    pub kernel: (usize, usize),
+    pub stride: (usize, usize),
+    pub padding: (usize, usize),
+}
+impl Pool2dConfig {
+    // pytorchと同じくstrideのデフォルトはkernelと同じ
+    pub fn new(kernel_height: usize, kernel_width: usize) -> Self {
+        Self {
+            kernel: (kernel_height, kernel_width),
+            stride: (kernel_height, kernel_width),
+            padding: (0, 0),
+        }
+    }
+    pub fn stride(mut self, height: usize, width: usize) -> Self {
+        self.stride = (height, width);
+        self
+    }
+    pub fn padding(mut self, height: usize, width: usize) -> Self {
+        self.padding = (height, width);
+        self
+    }
+}
+
+fn output_shape(geometry: &Pool2dGeometry) -> Shape {
+    Shape::D4(geometry.batch, geometry.channels, geometry.out_height, geometry.out_width)
+}
+fn input_shape(geometry: &Pool2dGeometry) -> Shape {
+    Shape::D4(geometry.batch, geometry.channels, geometry.in_height, geometry.in_width)
+}
+
+
+#[derive(Clone)]
+pub struct MaxPool2d<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub argmax_cach_id: NtenID,
+    pub geometry: Pool2dGeometry,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for MaxPool2d<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("MaxPool2d<{}> {} to {}", T::type_name(), input_shape(&self.geometry), output_shape(&self.geometry))
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        let (output, argmax) = input.storage().max_pool2d(&self.geometry);
+
+        // Relu2dのmaskと同じように，backward用にargmaxを保存しておく
+        ctx.insert_tensor(&self.argmax_cach_id, Tensor { name: "max_pool2d argmax".to_string(), shape: output_shape(&self.geometry), storage: Arc::new(RwLock::new(argmax)) });
+        ctx.insert_val(&self.output_id, Tensor { name: "max_pool2d".to_string(), shape: output_shape(&self.geometry), storage: Arc::new(RwLock::new(output)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+        let argmax = ctx.get_tensor(&self.argmax_cach_id);
+
+        let dinput = Storage::max_pool2d_backward(&dout.storage(), &argmax.storage(), &self.geometry);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "max_pool2d dinput".to_string(), shape: input_shape(&self.geometry), storage: Arc::new(RwLock::new(dinput)) });
+    }
+}
+
+
+// AdaptiveAvgPool2dはgeometry.windowがPoolWindow::AdaptiveのAvgPool2d
+#[derive(Clone)]
+pub struct AvgPool2d<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub geometry: Pool2dGeometry,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for AvgPool2d<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        let kind = if self.geometry.window == PoolWindow::Adaptive { "AdaptiveAvgPool2d" } else { "AvgPool2d" };
+        format!("{}<{}> {} to {}", kind, T::type_name(), input_shape(&self.geometry), output_shape(&self.geometry))
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        let output = input.storage().avg_pool2d(&self.geometry);
+
+        ctx.insert_val(&self.output_id, Tensor { name: "avg_pool2d".to_string(), shape: output_shape(&self.geometry), storage: Arc::new(RwLock::new(output)) });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinput = Storage::avg_pool2d_backward(&dout.storage(), &self.geometry);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor { name: "avg_pool2d dinput".to_string(), shape: input_shape(&self.geometry), storage: Arc::new(RwLock::new(dinput)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, fn_edge::Pool2dConfig, nten::Nten4d, tensor::Tensor, test_utils::{assert_close, forward, gradcheck, sample, typed4d}};
+
+    #[test]
+    fn max_pool_forward() {
+        let input = Tensor::new_from_vec((0..16).map(|i| ((i * 7) % 16) as f32).collect(), Shape::D4(1, 1, 4, 4)).unwrap();
+        // [[0, 7, 14, 5], [12, 3, 10, 1], [8, 15, 6, 13], [4, 11, 2, 9]]
+        let out = forward(&[input], |_, ps| {
+            let out: Nten4d<1, 1, 2, 2, f32> = typed4d::<1, 1, 4, 4>(&ps[0]).max_pool2d(Pool2dConfig::new(2, 2));
+            out.to_untyped()
+        });
+        assert_close(&out.to_vec_f32(), &[12.0, 14.0, 15.0, 13.0], 0.0);
+    }
+
+    #[test]
+    fn gradcheck_max_pool_overlapping_and_padded() {
+        // 乱数なので窓の中で値が重ならない
+        gradcheck(&[sample(Shape::D4(2, 2, 5, 5), 1)], |_, ps| {
+            let out: Nten4d<2, 2, 3, 3, f32> = typed4d::<2, 2, 5, 5>(&ps[0]).max_pool2d(Pool2dConfig::new(3, 3).stride(2, 2).padding(1, 1));
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_avg_pool_padded() {
+        gradcheck(&[sample(Shape::D4(2, 2, 5, 5), 2)], |_, ps| {
+            let out: Nten4d<2, 2, 3, 3, f32> = typed4d::<2, 2, 5, 5>(&ps[0]).avg_pool2d(Pool2dConfig::new(3, 3).stride(2, 2).padding(1, 1));
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_adaptive_avg_pool() {
+        gradcheck(&[sample(Shape::D4(1, 2, 5, 7), 3)], |_, ps| {
+            let out: Nten4d<1, 2, 2, 3, f32> = typed4d::<1, 2, 5, 7>(&ps[0]).adaptive_avg_pool2d();
+            out.to_untyped()
+        });
+    }
+
+    #[test]
+    fn global_avg_pool_is_channel_mean() {
+        let input = sample(Shape::D4(2, 3, 4, 4), 4);
+        let out = forward(std::slice::from_ref(&input), |_, ps| typed4d::<2, 3, 4, 4>(&ps[0]).global_avg_pool2d().to_untyped());
+        let expected: Vec<f32> = input.to_vec_f32().chunks(16).map(|plane| plane.iter().sum::<f32>() / 16.0).collect();
+        assert_eq!(out.shape, Shape::D4(2, 3, 1, 1));
+        assert_close(&out.to_vec_f32(), &expected, 1e-5);
+    }
+}
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
-use crate::{autograd::Context, dtype::{Dtype, Shape}, logger::LOGGER, nten::{get_new_nten_id, Nten, Nten2d, NtenID}, tensor::Tensor2d};
-use super::{get_new_fn_edge_id, FnEdge, FnEdgeID};
+use crate::{autograd::Context, dtype::Dtype, nten::{Nten, NtenID}, tensor::Tensor2d};
+use super::{grad_graph, pure_cse_key, EdgePattern, FnEdge, FnEdgeID};
 
 
 
@@ -30,9 +30,23 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for Relu2d<R, C, T> {
         self.sources.clone()
     }
 
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+
     fn clone_box(&self) -> Box<dyn FnEdge> {
         Box::new(self.clone())
     }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+    fn pattern(&self) -> Option<EdgePattern> {
+        (T::type_name() == "f32").then_some(EdgePattern::Relu { input_id: self.input_id, output_id: self.output_id })
+    }
 
     fn forward(&self, ctx: &mut crate::autograd::Context) {
         let input: Tensor2d<R, C, T> = ctx.get_val_as_2d(&self.input_id);
@@ -56,4 +70,48 @@ impl<const R: usize, const C: usize, T: Dtype> FnEdge for Relu2d<R, C, T> {
 
         ctx.add_assign_grad(&self.input_id, &dout.to_untyped());
     }
-}
\ No newline at end of file
+
+    fn backward_graph(&self, ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        // forwardで作ったmaskは定数として使う
+        let mask = ctx.get_tensor(&self.mask_cach_id);
+        let mask = grad_graph::constant(ctx, mask);
+        Some(vec![Some(grad_graph::masked_fill::<T>(dout, &mask, 0.0))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{Nten, Nten2d}, tensor::Tensor, test_utils::{forward, gradcheck, gradgradcheck, sample}};
+
+    fn typed(ps: &[Nten]) -> Nten2d<3, 4, f32> {
+        ps[0].clone().to_typed2d().unwrap()
+    }
+    // 0の近くの折れ目を有限差分がまたがないように
+    fn away_from_zero(seed: u64) -> Tensor {
+        let body = sample(Shape::D2(3, 4), seed).to_vec_f32().iter().map(|x| x + x.signum() * 0.1).collect();
+        Tensor::new_from_vec(body, Shape::D2(3, 4)).unwrap()
+    }
+
+    #[test]
+    fn relu_clamps_negative() {
+        let x = sample(Shape::D2(3, 4), 1);
+        let out = forward(std::slice::from_ref(&x), |_, ps| typed(ps).relu().to_untyped()).to_vec_f32();
+        assert_eq!(out, x.to_vec_f32().iter().map(|v| v.max(0.0)).collect::<Vec<f32>>());
+    }
+
+    #[test]
+    fn gradcheck_relu() {
+        gradcheck(&[away_from_zero(1)], |_, ps| typed(ps).relu().to_untyped());
+    }
+
+    // x * relu(x)の2階の微分は正の要素で2
+    #[test]
+    fn gradgradcheck_relu() {
+        gradgradcheck(&[away_from_zero(1)], |_, ps| {
+            let x = typed(ps);
+            let out: Nten2d<3, 4, f32> = x.relu().broadcast_mul(&x);
+            out.to_untyped()
+        });
+    }
+}
@@ -0,0 +1,226 @@
+use std::marker::PhantomData;
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::{Nten, NtenID}};
+use super::{grad_graph, pure_cse_key, FnEdge, FnEdgeID};
+
+
+/*
+reshape (flatten, squeeze, unsqueeze)とpermute (transpose)
+要素数や軸の対応はフロントの型でコンパイル時に検査済み
+*/
+
+// this FnEdge's front fn is implemented at nten_shape.rs
+// fn reshape(), flatten(), squeeze(), unsqueeze(), permute(), transpose() @Nten2d, Nten3d, Nten4d
+
+// 要素の並びは変わらないのでstorageは入力と共有する
+#[derive(Clone)]
+pub struct Reshape<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub input_shape: Shape,
+    pub output_shape: Shape,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> FnEdge for Reshape<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Reshape<{}> {} to {}", T::type_name(), self.input_shape, self.output_shape)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    // backwardは形だけ使う
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        Vec::new()
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        ctx.insert_val(&self.output_id, input.reshape(self.output_shape).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        ctx.add_assign_grad(&self.input_id, &dout.reshape(self.input_shape).unwrap());
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        Some(vec![Some(grad_graph::reshape::<T>(dout, self.input_shape))])
+    }
+}
+
+
+// 出力のi番目の軸は入力のperm[i]番目の軸
+#[derive(Clone)]
+pub struct Permute<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub output_id: NtenID,
+    pub input_shape: Shape,
+    pub perm: Vec<usize>,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T> Permute<T> {
+    // 逆の並べ替え
+    fn inverse_perm(&self) -> Vec<usize> {
+        let mut inverse = vec![0; self.perm.len()];
+        for (i, p) in self.perm.iter().enumerate() {
+            inverse[*p] = i;
+        }
+        inverse
+    }
+}
+impl<T: Dtype> FnEdge for Permute<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Permute<{}> {} by {:?}", T::type_name(), self.input_shape, self.perm)
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        vec![self.input_id]
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    // backwardは形だけ使う
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        Vec::new()
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+
+        ctx.insert_val(&self.output_id, input.permute(&self.perm).unwrap());
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let dout = ctx.get_grad(&self.output_id);
+
+        ctx.add_assign_grad(&self.input_id, &dout.permute(&self.inverse_perm()).unwrap());
+    }
+
+    fn backward_graph(&self, _ctx: &mut Context, _inputs: &[Nten], _outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        let dout = output_grads[0].as_ref()?;
+        Some(vec![Some(grad_graph::permute::<T>(dout, &self.inverse_perm()))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::{is_permutation, Shape}, nten::{Nten, Nten2d, Nten3d}, test_utils::{forward, gradcheck, gradgradcheck, sample, typed4d}};
+
+    fn typed3d<const B: usize, const R: usize, const C: usize>(ps: &[Nten]) -> Nten3d<B, R, C, f32> {
+        ps[0].clone().to_typed3d().unwrap()
+    }
+
+    #[test]
+    fn permutation_check() {
+        assert!(is_permutation(&[2, 0, 1]));
+        assert!(!is_permutation(&[0, 0, 1]));
+        assert!(!is_permutation(&[0, 3, 1]));
+    }
+
+    // reshapeは要素の並びを変えない
+    #[test]
+    fn reshape_keeps_order() {
+        let x = sample(Shape::D2(2, 6), 1);
+        let out = forward(std::slice::from_ref(&x), |_, ps| {
+            ps[0].clone().to_typed2d::<2, 6, f32>().unwrap().reshape_3d::<3, 2, 2>().to_untyped()
+        });
+        assert_eq!(out.shape, Shape::D3(3, 2, 2));
+        assert_eq!(out.to_vec_f32(), x.to_vec_f32());
+    }
+
+    #[test]
+    fn permute_forward() {
+        let x = sample(Shape::D3(2, 3, 4), 2);
+        let out = forward(std::slice::from_ref(&x), |_, ps| typed3d::<2, 3, 4>(ps).permute::<2, 0, 1, 4, 2, 3>().to_untyped()).to_vec_f32();
+        let x = x.to_vec_f32();
+        // out[k][b][r] = x[b][r][k]
+        for b in 0..2 {
+            for r in 0..3 {
+                for k in 0..4 {
+                    assert_eq!(out[(k * 2 + b) * 3 + r], x[(b * 3 + r) * 4 + k]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gradcheck_reshape() {
+        gradcheck(&[sample(Shape::D2(2, 6), 3)], |_, ps| {
+            let x: Nten2d<2, 6, f32> = ps[0].clone().to_typed2d().unwrap();
+            let y: Nten2d<4, 3, f32> = x.reshape();
+            y.transpose().to_untyped()
+        });
+        gradcheck(&[sample(Shape::D4(2, 2, 2, 3), 4)], |_, ps| {
+            let flat: Nten2d<2, 12, f32> = typed4d::<2, 2, 2, 3>(&ps[0]).flatten();
+            flat.transpose().to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_squeeze_unsqueeze() {
+        gradcheck(&[sample(Shape::D2(3, 2), 5)], |_, ps| {
+            let x: Nten2d<3, 2, f32> = ps[0].clone().to_typed2d().unwrap();
+            let y: Nten3d<3, 1, 2, f32> = x.unsqueeze::<1, 3, 1, 2>();
+            let z: Nten2d<3, 2, f32> = y.permute::<1, 0, 2, 1, 3, 2>().squeeze::<0, 3, 2>();
+            z.transpose().to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradcheck_permute() {
+        gradcheck(&[sample(Shape::D3(2, 3, 4), 6)], |_, ps| typed3d::<2, 3, 4>(ps).permute::<1, 2, 0, 3, 4, 2>().to_untyped());
+        gradcheck(&[sample(Shape::D3(2, 3, 4), 7)], |_, ps| typed3d::<2, 3, 4>(ps).transpose().to_untyped());
+        gradcheck(&[sample(Shape::D4(2, 3, 2, 2), 8)], |_, ps| {
+            typed4d::<2, 3, 2, 2>(&ps[0]).permute::<0, 2, 3, 1, 2, 2, 2, 3>().to_untyped()
+        });
+    }
+
+    #[test]
+    fn gradgradcheck_reshape_permute() {
+        gradgradcheck(&[sample(Shape::D2(2, 6), 3)], |_, ps| {
+            let x: Nten2d<2, 6, f32> = ps[0].clone().to_typed2d().unwrap();
+            let y: Nten2d<4, 3, f32> = x.reshape();
+            y.tanh().to_untyped()
+        });
+        gradgradcheck(&[sample(Shape::D3(2, 3, 4), 4)], |_, ps| {
+            let x: Nten3d<2, 3, 4, f32> = ps[0].clone().to_typed3d().unwrap();
+            x.permute::<2, 0, 1, 4, 2, 3>().tanh().to_untyped()
+        });
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+use crate::{autograd::Context, nten::{Nten, NtenID}};
+use super::{EdgePattern, FnEdge, FnEdgeID};
+
+
+/*
+ntenのcreatorをArcで共有するFnEdge。ほかのメソッドは中身にそのまま渡す
+FnEdgeはsourcesに入力のcreatorをcloneして持つので，creatorをそのまま持つとcloneのたびにグラフ全体をコピーする
+RNNのように同じntenを何回も使うグラフでは，コピーの量が系列の長さに対して指数的に増える
+shared()で包んでおけばcloneはArcのcloneだけになる
+*/
+
+#[derive(Clone)]
+pub struct Shared(Arc<dyn FnEdge>);
+
+pub(crate) fn shared(creator: Box<dyn FnEdge>) -> Box<dyn FnEdge> {
+    Box::new(Shared(Arc::from(creator)))
+}
+
+impl FnEdge for Shared {
+    fn get_id(&self) -> FnEdgeID {
+        self.0.get_id()
+    }
+    fn name(&self) -> String {
+        self.0.name()
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.0.sources()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        self.0.inputs()
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        self.0.outputs()
+    }
+    fn saved_for_backward(&self) -> Vec<NtenID> {
+        self.0.saved_for_backward()
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        self.0.forward(ctx)
+    }
+    fn backward(&self, ctx: &mut Context) {
+        self.0.backward(ctx)
+    }
+    fn backward_graph(&self, ctx: &mut Context, inputs: &[Nten], outputs: &[Nten], output_grads: &[Option<Nten>]) -> Option<Vec<Option<Nten>>> {
+        self.0.backward_graph(ctx, inputs, outputs, output_grads)
+    }
+    fn cse_key(&self) -> Option<String> {
+        self.0.cse_key()
+    }
+    fn pattern(&self) -> Option<EdgePattern> {
+        self.0.pattern()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{Nten, Nten2d}, test_utils::{assert_close, forward, sample}};
+
+    // x = x + xを繰り返す。creatorを共有しないとaddのたびにグラフのコピーが2倍になる
+    #[test]
+    fn reused_nten_shares_creator() {
+        const DEPTH: i32 = 40;
+        let x = sample(Shape::D2(2, 3), 1);
+        let out = forward(std::slice::from_ref(&x), |_, ps: &[Nten]| {
+            let mut y: Nten2d<2, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+            for _ in 0..DEPTH {
+                y = y.add(&y);
+            }
+            y.to_untyped()
+        });
+        let expected: Vec<f32> = x.to_vec_f32().iter().map(|v| v * 2f32.powi(DEPTH)).collect();
+        assert_close(&out.to_vec_f32(), &expected, 1e-6);
+    }
+}
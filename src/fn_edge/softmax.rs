@@ -0,0 +1,149 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock}};
+use crate::{autograd::Context, dtype::{Dtype, Shape}, nten::NtenID, tensor::{Storage, Tensor}};
+use super::{pure_cse_key, FnEdge, FnEdgeID};
+
+
+/*
+最後の軸でのsoftmax(scale * x)
+maskはboolのNtenで，最後の2軸(または最後の軸)と同じ形状。先頭の軸にはbroadcastする
+maskがfalseの要素は確率0になる
+*/
+
+// this FnEdge's front fn is implemented at Nten2d, Nten3d and nten_attention.rs
+// fn softmax() @Nten2d, @Nten3d, fn scaled_dot_product_attention()
+
+#[derive(Clone)]
+pub struct Softmax<T> {
+    pub id: FnEdgeID,
+    pub sources: Vec<Box<dyn FnEdge>>,
+
+    pub input_id: NtenID,
+    pub mask_id: Option<NtenID>,
+    pub output_id: NtenID,
+    pub shape: Shape,
+    pub scale: f32,
+
+    pub _marker: PhantomData<T>,
+}
+impl<T: Dtype> Softmax<T> {
+    fn cols(&self) -> usize {
+        *self.shape.dims().last().unwrap()
+    }
+}
+impl<T: Dtype> FnEdge for Softmax<T> {
+    fn get_id(&self) -> FnEdgeID {
+        self.id
+    }
+    fn name(&self) -> String {
+        format!("Softmax<{}> {} scale: {}, masked: {}", T::type_name(), self.shape, self.scale, self.mask_id.is_some())
+    }
+    fn sources(&self) -> Vec<Box<dyn FnEdge>> {
+        self.sources.clone()
+    }
+    fn inputs(&self) -> Vec<NtenID> {
+        let mut inputs = vec![self.input_id];
+        inputs.extend(self.mask_id);
+        inputs
+    }
+    fn outputs(&self) -> Vec<NtenID> {
+        vec![self.output_id]
+    }
+    fn clone_box(&self) -> Box<dyn FnEdge> {
+        Box::new(self.clone())
+    }
+    fn cse_key(&self) -> Option<String> {
+        pure_cse_key(self)
+    }
+
+    fn forward(&self, ctx: &mut Context) {
+        let input = ctx.get_val(&self.input_id);
+        let mask = self.mask_id.map(|id| ctx.get_val(&id));
+
+        let out = input.storage().softmax(self.cols(), self.scale, mask.as_ref().map(|m| m.storage()).as_deref());
+
+        ctx.insert_val(&self.output_id, Tensor {
+            name: "softmax".to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(out)),
+        });
+    }
+
+    fn backward(&self, ctx: &mut Context) {
+        let output = ctx.get_val(&self.output_id);
+        let dout = ctx.get_grad(&self.output_id);
+
+        let dinput = Storage::softmax_backward(&dout.storage(), &output.storage(), self.cols(), self.scale);
+
+        ctx.add_assign_grad(&self.input_id, &Tensor {
+            name: "dsoftmax".to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(dinput)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dtype::Shape, nten::{self, Nten, Nten2d, Nten3d}, tensor::Tensor2d, test_utils::{assert_close, forward, gradcheck, sample}};
+
+    fn typed3d<const B: usize, const R: usize, const C: usize>(ps: &[Nten], i: usize) -> Nten3d<B, R, C, f32> {
+        ps[i].clone().to_typed3d().unwrap()
+    }
+
+    #[test]
+    fn softmax_rows_sum_to_one() {
+        let x = sample(Shape::D2(3, 4), 1);
+        let out = forward(std::slice::from_ref(&x), |_, ps| ps[0].clone().to_typed2d::<3, 4, f32>().unwrap().softmax().to_untyped()).to_vec_f32();
+        let x = x.to_vec_f32();
+        for r in 0..3 {
+            let exps: Vec<f32> = x[r * 4..r * 4 + 4].iter().map(|v| v.exp()).collect();
+            let sum: f32 = exps.iter().sum();
+            assert_close(&out[r * 4..r * 4 + 4], &exps.iter().map(|e| e / sum).collect::<Vec<_>>(), 1e-5);
+        }
+    }
+
+    #[test]
+    fn gradcheck_softmax() {
+        gradcheck(&[sample(Shape::D2(3, 4), 2)], |_, ps| ps[0].clone().to_typed2d::<3, 4, f32>().unwrap().softmax().to_untyped());
+        gradcheck(&[sample(Shape::D3(2, 3, 4), 3)], |_, ps| typed3d::<2, 3, 4>(ps, 0).softmax().to_untyped());
+    }
+
+    // softmax(q k^T / sqrt(D)) vをそのまま計算したものと同じ
+    #[test]
+    fn attention_matches_naive() {
+        let params = [sample(Shape::D3(1, 2, 4), 4), sample(Shape::D3(1, 3, 4), 5), sample(Shape::D3(1, 3, 2), 6)];
+        let out = forward(&params, |_, ps| {
+            nten::scaled_dot_product_attention(&typed3d::<1, 2, 4>(ps, 0), &typed3d::<1, 3, 4>(ps, 1), &typed3d::<1, 3, 2>(ps, 2), None).to_untyped()
+        }).to_vec_f32();
+        let (q, k, v) = (params[0].to_vec_f32(), params[1].to_vec_f32(), params[2].to_vec_f32());
+        for i in 0..2 {
+            let scores: Vec<f32> = (0..3).map(|j| (0..4).map(|d| q[i * 4 + d] * k[j * 4 + d]).sum::<f32>() / 2.0).collect();
+            let sum: f32 = scores.iter().map(|s| s.exp()).sum();
+            let expected: Vec<f32> = (0..2).map(|c| (0..3).map(|j| scores[j].exp() / sum * v[j * 2 + c]).sum()).collect();
+            assert_close(&out[i * 2..i * 2 + 2], &expected, 1e-5);
+        }
+    }
+
+    #[test]
+    fn gradcheck_attention() {
+        let params = [sample(Shape::D3(2, 3, 4), 7), sample(Shape::D3(2, 3, 4), 8), sample(Shape::D3(2, 3, 2), 9)];
+        gradcheck(&params, |_, ps| {
+            nten::scaled_dot_product_attention(&typed3d::<2, 3, 4>(ps, 0), &typed3d::<2, 3, 4>(ps, 1), &typed3d::<2, 3, 2>(ps, 2), None).to_untyped()
+        });
+        gradcheck(&params, |vs, ps| {
+            let mask = Nten2d::new_from_val(Tensor2d::<3, 3, bool>::new_causal_mask()).name("mask").as_input(vs);
+            nten::scaled_dot_product_attention(&typed3d::<2, 3, 4>(ps, 0), &typed3d::<2, 3, 4>(ps, 1), &typed3d::<2, 3, 2>(ps, 2), Some(&mask)).to_untyped()
+        });
+    }
+
+    // causal maskでは最初のqueryは最初のvalueだけを見る
+    #[test]
+    fn causal_mask_hides_future() {
+        let params = [sample(Shape::D3(1, 3, 4), 10), sample(Shape::D3(1, 3, 4), 11), sample(Shape::D3(1, 3, 2), 12)];
+        let out = forward(&params, |vs, ps| {
+            let mask = Nten2d::new_from_val(Tensor2d::<3, 3, bool>::new_causal_mask()).name("mask").as_input(vs);
+            nten::scaled_dot_product_attention(&typed3d::<1, 3, 4>(ps, 0), &typed3d::<1, 3, 4>(ps, 1), &typed3d::<1, 3, 2>(ps, 2), Some(&mask)).to_untyped()
+        }).to_vec_f32();
+        assert_close(&out[..2], &params[2].to_vec_f32()[..2], 1e-6);
+    }
+}
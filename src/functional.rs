@@ -0,0 +1,201 @@
+use crate::{
+    autograd::{Autograd, VarStore}, dtype::Shape, logger::LOGGER, nten::{Nten, Nten2d}, tensor::{Tensor, Tensor2d}
+};
+
+/*
+Autograd, VarStoreを意識せずに使う関数形式の自動微分
+fは入力のntenから出力のntenを作るクロージャ。定数はvsにas_input()で入れる
+呼ぶたびにAutogradを作ってfのグラフを組み直す
+
+ex) let g = functional::grad(|x, _| loss_fn::mse_loss(x, ...), x0);
+*/
+
+// fを一度forwardした状態。outputはstep_forward済み
+struct Traced<const R: usize, const C: usize> {
+    ag: Autograd,
+    vs: VarStore,
+    input: Nten2d<R, C, f32>,
+    output: Nten,
+}
+
+fn trace<const R: usize, const C: usize, const RO: usize, const CO: usize, F>(f: &F, x: &Tensor2d<R, C, f32>) -> Traced<R, C>
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<RO, CO, f32> {
+    let mut ag = Autograd::new();
+    let mut vs = ag.get_vs();
+    let input = Nten2d::new_from_val(x.clone()).name("functional input").as_input(&mut vs);
+    let output = f(&input, &mut vs);
+    let [output] = ag.step_forward([output.to_untyped()]);
+    Traced { ag, vs, input, output }
+}
+
+fn to_typed<const R: usize, const C: usize>(tensor: Tensor, fn_name: &str) -> Tensor2d<R, C, f32> {
+    tensor.to_typed2d().unwrap_or_else(|e| {
+        LOGGER.error(format!("functional::{}() >> {}", fn_name, e));
+        panic!("")
+    })
+}
+
+// 出力は要素1つのnten。入力に関する勾配を返す
+pub fn grad<const R: usize, const C: usize, F>(f: F, x: Tensor2d<R, C, f32>) -> Tensor2d<R, C, f32>
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<1, 1, f32> {
+    value_and_grad(f, x).1
+}
+
+pub fn value_and_grad<const R: usize, const C: usize, F>(f: F, x: Tensor2d<R, C, f32>) -> (f32, Tensor2d<R, C, f32>)
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<1, 1, f32> {
+    let (value, dx) = vjp(f, x, Tensor2d::<1, 1, f32>::new_ones());
+    (value.to_untyped().to_vec_f32()[0], dx)
+}
+
+// vector-Jacobian product v^T J。出力の値と入力の勾配を返す
+pub fn vjp<const R: usize, const C: usize, const RO: usize, const CO: usize, F>
+    (f: F, x: Tensor2d<R, C, f32>, v: Tensor2d<RO, CO, f32>) -> (Tensor2d<RO, CO, f32>, Tensor2d<R, C, f32>)
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<RO, CO, f32> {
+    let mut traced = trace(&f, &x);
+    let output = traced.output.val.clone().unwrap();
+
+    let ctx = traced.ag.backward_with(&traced.output, v.to_untyped());
+    // 出力につながっていない入力の勾配は0
+    let dx = ctx.try_get_grad(&traced.input.id).unwrap_or_else(|| Tensor::new_zeros::<f32>(Shape::D2(R, C)));
+
+    (to_typed(output, "vjp"), to_typed(dx, "vjp"))
+}
+
+/*
+Jacobian-vector product J t。出力の値とJ tを返す
+double-vjp: g(u) = J^T uをgrad_graph_withで組み立てると，gはuについて線形なので
+d<g(u), t>/du = J tになる（uの値は何でもよい）
+制限: fの中のFnEdgeはすべてbackward_graph()に対応している必要がある
+    対応: add, add_broadcast（Linear）, broadcast_add/sub/mul/div, matmul, bmm, reshape, permute, relu, sigmoid, tanh, masked_fill, detach, mse_loss
+    対応していないFnEdge（conv2d, softmax, cross_entropyなど）があれば，その名前を入れたErrを返す
+*/
+pub fn jvp<const R: usize, const C: usize, const RO: usize, const CO: usize, F>
+    (f: F, x: Tensor2d<R, C, f32>, t: Tensor2d<R, C, f32>) -> Result<(Tensor2d<RO, CO, f32>, Tensor2d<RO, CO, f32>), String>
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<RO, CO, f32> {
+    let mut traced = trace(&f, &x);
+    let output = traced.output.val.clone().unwrap();
+
+    let u = Nten2d::new_from_val(Tensor2d::<RO, CO, f32>::new_zeros()).name("jvp dummy cotangent").as_input(&mut traced.vs);
+    let [jtu] = traced.ag.try_grad_graph_with(&traced.output, &u.clone().to_untyped(), [traced.input.id])
+        .map_err(|e| format!("functional::jvp() >> forward mode is not available: {}", e))?;
+    let [jtu] = traced.ag.step_forward([jtu]);
+
+    let ctx = traced.ag.backward_with(&jtu, t.to_untyped());
+    let jt = ctx.try_get_grad(&u.id).unwrap_or_else(|| Tensor::new_zeros::<f32>(output.shape));
+
+    Ok((to_typed(output, "jvp"), to_typed(jt, "jvp")))
+}
+
+/*
+小さい関数用のヤコビアン。出力の要素ごとにvjpをするのでRO * CO回fを組み直す
+M = RO * CO, N = R * C。J[i][j] = d(出力のi番目)/d(入力のj番目)（どちらもrow major）
+*/
+pub fn jacobian<const R: usize, const C: usize, const RO: usize, const CO: usize, const M: usize, const N: usize, F>
+    (f: F, x: Tensor2d<R, C, f32>) -> Tensor2d<M, N, f32>
+where F: Fn(&Nten2d<R, C, f32>, &mut VarStore) -> Nten2d<RO, CO, f32> {
+    const {
+        assert!(M == RO * CO && N == R * C, "functional::jacobian() >> M must be RO * CO and N must be R * C");
+    }
+    let mut body = Vec::with_capacity(M * N);
+    for i in 0..M {
+        let mut one_hot = vec![0.0; M];
+        one_hot[i] = 1.0;
+        let v = Tensor2d::<RO, CO, f32>::new_from_vec(one_hot).unwrap();
+        let (_, row) = vjp(&f, x.clone(), v);
+        body.extend(row.to_untyped().to_vec_f32());
+    }
+    Tensor2d::new_from_vec(body).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::VarStore, dtype::{Reduction, Shape}, loss_fn, nn::Linear, nten::{self, Nten2d}, tensor::Tensor2d, test_utils::{assert_close, sample}};
+
+    use super::{grad, jacobian, jvp, value_and_grad, vjp};
+
+    fn x() -> Tensor2d<2, 3, f32> {
+        sample(Shape::D2(2, 3), 1).to_typed2d().unwrap()
+    }
+    // f(x) = tanh(x W)。Wは定数
+    fn f(x: &Nten2d<2, 3, f32>, vs: &mut VarStore) -> Nten2d<2, 2, f32> {
+        let weight = Nten2d::new_from_val(sample(Shape::D2(3, 2), 2).to_typed2d::<3, 2, f32>().unwrap()).name("weight").as_input(vs);
+        let y: Nten2d<2, 2, f32> = nten::matmul(x, &weight);
+        y.tanh()
+    }
+
+    #[test]
+    fn grad_of_sum_of_squares() {
+        let sum_of_squares = |x: &Nten2d<2, 3, f32>, vs: &mut VarStore| {
+            let zeros = Nten2d::new_from_val(Tensor2d::<2, 3, f32>::new_zeros()).name("zeros").as_input(vs);
+            loss_fn::mse_loss(x, &zeros, Reduction::Sum)
+        };
+        let x0 = x().to_untyped().to_vec_f32();
+        let (value, dx) = value_and_grad(sum_of_squares, x());
+        assert_close(&[value], &[x0.iter().map(|v| v * v).sum()], 1e-6);
+        assert_close(&dx.to_untyped().to_vec_f32(), &x0.iter().map(|v| 2.0 * v).collect::<Vec<f32>>(), 1e-6);
+        assert_close(&grad(sum_of_squares, x()).to_untyped().to_vec_f32(), &dx.to_untyped().to_vec_f32(), 0.0);
+    }
+
+    // vjpはv^T J，jvpはJ t。jacobianの行と列でそれぞれ確かめる
+    #[test]
+    fn vjp_and_jvp_match_jacobian() {
+        let j = jacobian::<2, 3, 2, 2, 4, 6, _>(f, x()).to_untyped().to_vec_f32();
+        let v = sample(Shape::D2(2, 2), 3);
+        let t = sample(Shape::D2(2, 3), 4);
+
+        let (_, vj) = vjp(f, x(), v.to_typed2d().unwrap());
+        let expected: Vec<f32> = (0..6).map(|n| (0..4).map(|m| v.to_vec_f32()[m] * j[m * 6 + n]).sum()).collect();
+        assert_close(&vj.to_untyped().to_vec_f32(), &expected, 1e-5);
+
+        let (_, jt) = jvp(f, x(), t.to_typed2d().unwrap()).unwrap();
+        let expected: Vec<f32> = (0..4).map(|m| (0..6).map(|n| j[m * 6 + n] * t.to_vec_f32()[n]).sum()).collect();
+        assert_close(&jt.to_untyped().to_vec_f32(), &expected, 1e-5);
+    }
+
+    // jacobianを有限差分と比べる
+    #[test]
+    fn jacobian_matches_finite_difference() {
+        let j = jacobian::<2, 3, 2, 2, 4, 6, _>(f, x()).to_untyped().to_vec_f32();
+        let eps = 1e-2;
+        for n in 0..6 {
+            let shifted = |delta: f32| {
+                let mut body = x().to_untyped().to_vec_f32();
+                body[n] += delta;
+                let (y, _) = vjp(f, Tensor2d::new_from_vec(body).unwrap(), Tensor2d::new_zeros());
+                y.to_untyped().to_vec_f32()
+            };
+            let (plus, minus) = (shifted(eps), shifted(-eps));
+            for m in 0..4 {
+                let numerical = (plus[m] - minus[m]) / (2.0 * eps);
+                assert!((numerical - j[m * 6 + n]).abs() < 1e-3, "J[{}][{}]: numerical {} but {}", m, n, numerical, j[m * 6 + n]);
+            }
+        }
+    }
+
+    // backward_graph()のないFnEdgeがあれば，その名前を入れたErrになる
+    // Linearは x W + bなので J t = t W
+    #[test]
+    fn jvp_through_linear() {
+        let (weight, bias) = (sample(Shape::D2(3, 4), 5), sample(Shape::D2(1, 4), 6));
+        let linear = |x: &Nten2d<2, 3, f32>, vs: &mut VarStore| {
+            let linear: Linear<3, 4> = Linear {
+                weight: Nten2d::new_from_val(weight.to_typed2d().unwrap()).name("Linear weight").as_input(vs),
+                bias: Nten2d::new_from_val(bias.to_typed2d().unwrap()).name("Linear bias").as_input(vs),
+            };
+            linear.forward(x)
+        };
+        let t = sample(Shape::D2(2, 3), 4);
+        let (w, t_body) = (weight.to_vec_f32(), t.to_vec_f32());
+        let expected: Vec<f32> = (0..8).map(|i| (0..3).map(|k| t_body[i / 4 * 3 + k] * w[k * 4 + i % 4]).sum()).collect();
+
+        let (_, jt) = jvp(linear, x(), t.to_typed2d().unwrap()).unwrap();
+        assert_close(&jt.to_untyped().to_vec_f32(), &expected, 1e-5);
+    }
+
+    #[test]
+    fn jvp_names_unsupported_op() {
+        let softmax = |x: &Nten2d<2, 3, f32>, _: &mut VarStore| x.softmax();
+        let Err(e) = jvp(softmax, x(), x()) else { panic!("jvp through softmax must fail") };
+        assert!(e.contains("Softmax"), "{}", e);
+    }
+}
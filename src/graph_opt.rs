@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+
+use colored::Colorize;
+
+use crate::{
+    backend_cpu::LinearGeometry, fn_edge::{get_new_fn_edge_id, Alias, EdgePattern, FnEdge, FnEdgeID, FusedLinear}, logger::LOGGER, nten::NtenID
+};
+
+/*
+テープの最適化。Autograd::step_forward()でテープを作ってから実行するまでの間に行う
+1, CSE: 同じ入力に同じ計算をするFnEdgeを，先にあるFnEdgeの出力を共有するAliasに置き換える
+2, op fusion: Matmul -> AddBroadcast2d (-> Relu2d) をFusedLinearにまとめる
+3, DCE: 出力がどこからも使われないFnEdgeを消す
+
+keep_ids: 値がctxに残っている必要があるnten（step_forwardの結果，hookのついたntenなど）
+pinned: 置き換えてはいけないFnEdge（forward hookのついたFnEdgeなど）
+*/
+
+pub(crate) struct Optimized {
+    pub tape: Vec<Box<dyn FnEdge>>,
+    // 出力を別のFnEdgeが作るようになったFnEdge。実行済みとして扱う
+    pub replaced: Vec<FnEdgeID>,
+}
+
+pub(crate) fn optimize(tape: Vec<Box<dyn FnEdge>>, keep_ids: &HashSet<NtenID>, pinned: &HashSet<FnEdgeID>) -> Optimized {
+    let before = tape.len();
+    let mut replaced = Vec::new();
+    let tape = eliminate_common_subexpressions(tape, pinned, &mut replaced);
+    let tape = fuse_linear(tape, keep_ids, pinned, &mut replaced);
+    let tape = eliminate_dead_code(tape, keep_ids, pinned);
+    LOGGER.debug(format!("{}: optimized tape {} -> {} FnEdges", "graph_opt".cyan(), before, tape.len()));
+    Optimized { tape, replaced }
+}
+
+// (cse_key, 入力) -> その出力と作ったFnEdge
+type SeenEdges = HashMap<(String, Vec<NtenID>), (NtenID, Box<dyn FnEdge>)>;
+
+fn eliminate_common_subexpressions(tape: Vec<Box<dyn FnEdge>>, pinned: &HashSet<FnEdgeID>, replaced: &mut Vec<FnEdgeID>) -> Vec<Box<dyn FnEdge>> {
+    // 重複していた出力 -> 先にある同じ値の出力
+    let mut rename: HashMap<NtenID, NtenID> = HashMap::new();
+    let mut seen: SeenEdges = HashMap::new();
+    let mut optimized = Vec::with_capacity(tape.len());
+
+    for fn_edge in tape {
+        let outputs = fn_edge.outputs();
+        let key = match fn_edge.cse_key() {
+            Some(key) if outputs.len() == 1 && !pinned.contains(&fn_edge.get_id()) => key,
+            _ => {
+                optimized.push(fn_edge);
+                continue;
+            },
+        };
+        let inputs: Vec<NtenID> = fn_edge.inputs().iter().map(|id| *rename.get(id).unwrap_or(id)).collect();
+        match seen.get(&(key.clone(), inputs.clone())) {
+            Some((original_id, original_creator)) => {
+                rename.insert(outputs[0], *original_id);
+                replaced.push(fn_edge.get_id());
+                optimized.push(Box::new(Alias {
+                    id: get_new_fn_edge_id(),
+                    sources: vec![original_creator.clone()],
+                    original_id: *original_id,
+                    output_id: outputs[0],
+                }));
+            },
+            None => {
+                seen.insert((key, inputs), (outputs[0], fn_edge.clone_box()));
+                optimized.push(fn_edge);
+            },
+        }
+    }
+    optimized
+}
+
+fn fuse_linear(tape: Vec<Box<dyn FnEdge>>, keep_ids: &HashSet<NtenID>, pinned: &HashSet<FnEdgeID>, replaced: &mut Vec<FnEdgeID>) -> Vec<Box<dyn FnEdge>> {
+    let mut consumers: HashMap<NtenID, usize> = HashMap::new();
+    for fn_edge in tape.iter() {
+        for id in fn_edge.inputs() {
+            *consumers.entry(id).or_default() += 1;
+        }
+    }
+    // 途中の値を他で使わないときだけまとめられる
+    let fusable = |id: &NtenID| consumers.get(id) == Some(&1) && !keep_ids.contains(id);
+    let pattern = |fn_edge: &dyn FnEdge| if pinned.contains(&fn_edge.get_id()) { None } else { fn_edge.pattern() };
+
+    // 入力のid -> それを使うFnEdgeのindex
+    let mut consumer_index: HashMap<NtenID, usize> = HashMap::new();
+    for (i, fn_edge) in tape.iter().enumerate() {
+        for id in fn_edge.inputs() {
+            consumer_index.insert(id, i);
+        }
+    }
+
+    let mut removed: HashSet<usize> = HashSet::new();
+    let mut fused: HashMap<usize, Box<dyn FnEdge>> = HashMap::new();
+    for (i, fn_edge) in tape.iter().enumerate() {
+        let Some(EdgePattern::Matmul { lhs_id, rhs_id, output_id: matmul_out, n, m, o }) = pattern(fn_edge.as_ref()) else { continue };
+        if !fusable(&matmul_out) {
+            continue;
+        }
+        let Some(&add_index) = consumer_index.get(&matmul_out) else { continue };
+        let Some(EdgePattern::AddBias { input_id, bias_id, output_id: add_out }) = pattern(tape[add_index].as_ref()) else { continue };
+        if input_id != matmul_out {
+            continue;
+        }
+        // reluがあれば一緒にまとめる
+        let relu = consumer_index.get(&add_out).copied().filter(|_| fusable(&add_out)).and_then(|relu_index| {
+            match pattern(tape[relu_index].as_ref()) {
+                Some(EdgePattern::Relu { output_id, .. }) => Some((relu_index, output_id)),
+                _ => None,
+            }
+        });
+        let (last_index, output_id) = relu.unwrap_or((add_index, add_out));
+
+        let mut sources = fn_edge.sources();
+        sources.extend(tape[add_index].sources().into_iter().filter(|source| source.get_id() != fn_edge.get_id()));
+        fused.insert(last_index, Box::new(FusedLinear {
+            id: get_new_fn_edge_id(),
+            sources,
+            input_id: lhs_id,
+            weight_id: rhs_id,
+            bias_id,
+            output_id,
+            geometry: LinearGeometry { n, m, o, relu: relu.is_some() },
+        }));
+        // まとめたFnEdgeはどれも実行済みとして扱う。後のstep_forwardでsourcesをたどって再実行しない
+        let fused_indices: Vec<usize> = [i, add_index].into_iter().chain(relu.map(|(relu_index, _)| relu_index)).collect();
+        replaced.extend(fused_indices.iter().map(|&index| tape[index].get_id()));
+        removed.extend(fused_indices);
+    }
+
+    tape.into_iter().enumerate().filter_map(|(i, fn_edge)| {
+        match fused.remove(&i) {
+            Some(fused_edge) => Some(fused_edge),
+            None if removed.contains(&i) => None,
+            None => Some(fn_edge),
+        }
+    }).collect()
+}
+
+fn eliminate_dead_code(tape: Vec<Box<dyn FnEdge>>, keep_ids: &HashSet<NtenID>, pinned: &HashSet<FnEdgeID>) -> Vec<Box<dyn FnEdge>> {
+    let mut live: HashSet<NtenID> = keep_ids.clone();
+    let mut is_live = vec![false; tape.len()];
+    for (i, fn_edge) in tape.iter().enumerate().rev() {
+        if pinned.contains(&fn_edge.get_id()) || fn_edge.outputs().iter().any(|id| live.contains(id)) {
+            is_live[i] = true;
+            live.extend(fn_edge.inputs());
+        }
+    }
+    tape.into_iter().zip(is_live).filter_map(|(fn_edge, is_live)| is_live.then_some(fn_edge)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{autograd::{Autograd, VarStore}, dtype::Shape, nten::{self, Nten, Nten2d}, tensor::Tensor, test_utils::{as_parameters, assert_close, sample, tape_of}};
+
+    use super::{optimize, Optimized};
+
+    // ps: input (4, 3), weight (3, 5), bias (1, 5)
+    fn params() -> Vec<Tensor> {
+        vec![sample(Shape::D2(4, 3), 1), sample(Shape::D2(3, 5), 2), sample(Shape::D2(1, 5), 3)]
+    }
+    // (matmulの出力, biasを足した出力, reluの出力)
+    fn linear_relu(ps: &[Nten]) -> (Nten2d<4, 5, f32>, Nten2d<4, 5, f32>, Nten2d<4, 5, f32>) {
+        let x: Nten2d<4, 3, f32> = ps[0].clone().to_typed2d().unwrap();
+        let h: Nten2d<4, 5, f32> = nten::matmul(&x, &ps[1].clone().to_typed2d::<3, 5, f32>().unwrap());
+        let a = h.add_broadcast(&ps[2].clone().to_typed2d::<1, 5, f32>().unwrap());
+        let y = a.relu();
+        (h, a, y)
+    }
+
+    fn optimize_for(results: &[Nten]) -> Optimized {
+        let keep_ids = results.iter().map(|result| result.id).collect();
+        optimize(tape_of(results), &keep_ids, &HashSet::new())
+    }
+    fn count_fused(optimized: &Optimized) -> usize {
+        optimized.tape.iter().filter(|fn_edge| fn_edge.name().starts_with("FusedLinear")).count()
+    }
+    fn in_tape(optimized: &Optimized, nten: &Nten2d<4, 5, f32>) -> bool {
+        optimized.tape.iter().any(|fn_edge| fn_edge.get_id() == nten.creator.get_id())
+    }
+
+    #[test]
+    fn fuse_linear_replaces_every_fused_edge() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let (h, a, y) = linear_relu(&ps);
+        let optimized = optimize_for(&[y.clone().to_untyped()]);
+
+        assert_eq!(count_fused(&optimized), 1);
+        for nten in [&h, &a, &y] {
+            assert!(!in_tape(&optimized, nten));
+            assert!(optimized.replaced.contains(&nten.creator.get_id()));
+        }
+    }
+
+    // matmulの出力を他でも使うとまとめない
+    #[test]
+    fn fuse_linear_skips_shared_intermediate() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let (h, _, y) = linear_relu(&ps);
+        let z = h.tanh();
+        let optimized = optimize_for(&[y.clone().to_untyped(), z.clone().to_untyped()]);
+
+        assert_eq!(count_fused(&optimized), 0);
+        assert!(in_tape(&optimized, &h));
+    }
+
+    // step_forwardで求める途中の値はまとめずに作る
+    #[test]
+    fn fuse_linear_keeps_requested_intermediate() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let (h, a, y) = linear_relu(&ps);
+
+        let optimized = optimize_for(&[y.clone().to_untyped(), h.clone().to_untyped()]);
+        assert_eq!(count_fused(&optimized), 0);
+
+        // biasを足した出力を求めるときはreluだけ残す
+        let optimized = optimize_for(&[y.clone().to_untyped(), a.clone().to_untyped()]);
+        assert_eq!(count_fused(&optimized), 1);
+        assert!(in_tape(&optimized, &y));
+        assert!(!in_tape(&optimized, &h));
+    }
+
+    // (結果の値, parameterの勾配)
+    fn run(graph_optimization: bool) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let mut ag = Autograd::new();
+        ag.set_graph_optimization(graph_optimization);
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &params());
+        let (h, _, y) = linear_relu(&ps);
+        // 同じ計算（CSE）と使われない枝（DCE）
+        let (_, _, y_again) = linear_relu(&ps);
+        let _unused = y.tanh();
+        let out: Nten2d<4, 5, f32> = y.broadcast_mul(&y_again);
+        let results = ag.step_forward([out.to_untyped(), h.to_untyped()]);
+        let values = results.iter().map(|result| result.val.as_ref().unwrap().to_vec_f32()).collect();
+
+        let ctx = ag.backward_with(&results[0], sample(results[0].shape, 7));
+        let grads = ps.iter().map(|p| ctx.get_grad(&p.id).to_vec_f32()).collect();
+        (values, grads)
+    }
+
+    #[test]
+    fn optimized_tape_matches_plain() {
+        let (values, grads) = run(false);
+        let (values_optimized, grads_optimized) = run(true);
+        for (optimized, plain) in values_optimized.iter().zip(values.iter()).chain(grads_optimized.iter().zip(grads.iter())) {
+            assert_close(optimized, plain, 1e-5);
+        }
+    }
+}
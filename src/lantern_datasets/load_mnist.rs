@@ -141,4 +141,35 @@ pub fn shuffle_and_make_batch<const B: usize>(
     }
 
     (data_batches, label_batches)
-}
\ No newline at end of file
+}
+// labelをone-hotにせずclass indexのまま返す。loss_fn::cross_entropy用
+pub fn shuffle_and_make_index_batch<const B: usize>(
+    data: &[[u8; 784]],
+    labels: &[u8],
+) -> (Vec<Tensor2d<B, 784, f32>>, Vec<Tensor2d<B, 1, u32>>) {
+    let mut rng = rand::thread_rng();
+    let mut indices: Vec<usize> = (0..data.len()).collect();
+    indices.shuffle(&mut rng);
+
+    let num_batches: usize = indices.len() / B;
+
+    let mut data_batches: Vec<Tensor2d<B, 784, f32>> = Vec::with_capacity(num_batches);
+    let mut label_batches: Vec<Tensor2d<B, 1, u32>> = Vec::with_capacity(num_batches);
+
+    for batch in indices.chunks_exact(B) {
+        let mut batch_data: Vec<f32> = Vec::with_capacity(B * 784);
+        let mut batch_labels: Vec<u32> = Vec::with_capacity(B);
+
+        for &original_idx in batch {
+            for &byte in &data[original_idx] {
+                batch_data.push(byte as f32/ u8::MAX as f32);
+            }
+            batch_labels.push(labels[original_idx] as u32);
+        }
+
+        data_batches.push(Tensor2d::new_from_vec(batch_data).unwrap());
+        label_batches.push(Tensor2d::new_from_indices(batch_labels).unwrap());
+    }
+
+    (data_batches, label_batches)
+}
@@ -0,0 +1,126 @@
+use std::marker::PhantomData;
+
+use crate::{backend_cpu::CrossEntropyGeometry, dtype::{Dtype, PointwiseLoss, Reduction, Shape}, fn_edge::{get_new_fn_edge_id, CrossEntropy, CrossEntropyConfig, Loss, NllLoss}, logger::LOGGER, nten::{get_new_nten_id, Nten2d}};
+
+
+/*
+lossをFnEdgeとしてグラフに積む
+出力はReduction::Mean, Sumなら(1, 1)，Noneなら(R, C)。(RO, CO)は構築時に検査する
+ex) let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&predict, &target, Reduction::Mean);
+*/
+
+fn check_output_shape(op_type: &str, shape: (usize, usize), output: (usize, usize), reduction: Reduction) {
+    let expected = if reduction == Reduction::None { shape } else { (1, 1) };
+    if output != expected {
+        LOGGER.error(format!("{}() >> output shape {:?} is unmatched with {:?} for {:?}", op_type, output, expected, reduction));
+        panic!("")
+    }
+}
+
+fn pointwise_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, loss: PointwiseLoss, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    check_output_shape(loss.name(), (R, C), (RO, CO), reduction);
+    let new_id = get_new_nten_id();
+    let edge = Loss::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![predict.creator.clone(), target.creator.clone()],
+        predict_id: predict.id,
+        target_id: target.id,
+        output_id: new_id,
+        shape: Shape::D2(R, C),
+        loss,
+        reduction,
+        _marker: PhantomData,
+    };
+    Nten2d::new_from_creator(new_id, format!("auto created by {}<{}, {}, {}>", loss.name(), R, C, T::type_name()), Box::new(edge))
+}
+
+// (predict - target)^2
+pub fn mse_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    pointwise_loss(predict, target, PointwiseLoss::Mse, reduction)
+}
+
+// |predict - target|
+pub fn l1_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    pointwise_loss(predict, target, PointwiseLoss::L1, reduction)
+}
+
+// |d| <= deltaなら0.5 d^2，それ以外はdelta (|d| - 0.5 delta)
+pub fn huber_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, delta: f32, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    if delta <= 0.0 {
+        LOGGER.error(format!("huber_loss() >> delta must be positive, found {}", delta));
+        panic!("")
+    }
+    pointwise_loss(predict, target, PointwiseLoss::Huber(delta), reduction)
+}
+
+// predictは(0, 1)の確率
+pub fn bce_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    pointwise_loss(predict, target, PointwiseLoss::Bce, reduction)
+}
+
+// predictはlogit。sigmoidとBCEをまとめて数値的に安定に計算する
+pub fn bce_with_logits_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (predict: &Nten2d<R, C, T>, target: &Nten2d<R, C, T>, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    pointwise_loss(predict, target, PointwiseLoss::BceWithLogits, reduction)
+}
+
+// inputは(R, C)のlog確率，targetはclass index。Reduction::Noneの出力は(R, 1)
+pub fn nll_loss<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (input: &Nten2d<R, C, T>, target: &Nten2d<R, 1, u32>, reduction: Reduction) -> Nten2d<RO, CO, T> {
+    check_output_shape("nll_loss", (R, 1), (RO, CO), reduction);
+    let new_id = get_new_nten_id();
+    let edge = NllLoss::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone(), target.creator.clone()],
+        input_id: input.id,
+        target_id: target.id,
+        output_id: new_id,
+        rows: R,
+        cols: C,
+        reduction,
+        _marker: PhantomData,
+    };
+    Nten2d::new_from_creator(new_id, format!("auto created by NllLoss<{}, {}, {}>", R, C, T::type_name()), Box::new(edge))
+}
+
+/*
+inputは(R, C)のlogit，targetはclass index。one-hotのteacherはいらない
+class weight, label smoothing, ignore_indexはconfigで指定する。Reduction::Noneの出力は(R, 1)
+ex) let loss: Nten2d<1, 1, f32> = loss_fn::cross_entropy(&logits, &target, CrossEntropyConfig::new().ignore_index(0));
+*/
+pub fn cross_entropy<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (input: &Nten2d<R, C, T>, target: &Nten2d<R, 1, u32>, config: CrossEntropyConfig) -> Nten2d<RO, CO, T> {
+    check_output_shape("cross_entropy", (R, 1), (RO, CO), config.reduction);
+    if config.weight.as_ref().is_some_and(|w| w.len() != C) {
+        LOGGER.error(format!("cross_entropy() >> weight length {:?} is unmatched with {} classes", config.weight.as_ref().map(|w| w.len()), C));
+        panic!("")
+    }
+    if !(0.0..=1.0).contains(&config.label_smoothing) {
+        LOGGER.error(format!("cross_entropy() >> label_smoothing must be in [0, 1], found {}", config.label_smoothing));
+        panic!("")
+    }
+    let new_id = get_new_nten_id();
+    let edge = CrossEntropy::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![input.creator.clone(), target.creator.clone()],
+        input_id: input.id,
+        target_id: target.id,
+        output_id: new_id,
+        softmax_cach_id: get_new_nten_id(),
+        weight: config.weight,
+        geometry: CrossEntropyGeometry {
+            rows: R,
+            cols: C,
+            label_smoothing: config.label_smoothing,
+            ignore_index: config.ignore_index,
+            reduction: config.reduction,
+        },
+        _marker: PhantomData,
+    };
+    Nten2d::new_from_creator(new_id, format!("auto created by CrossEntropy<{}, {}, {}>", R, C, T::type_name()), Box::new(edge))
+}
@@ -4,6 +4,10 @@ use colored::Colorize;
 
 use crate::{backend_cpu::RawDense, dtype::{Dtype, Shape}, nten::Nten, tensor::{Storage, Tensor, Tensor2d}};
 
+mod loss;
+pub use loss::{bce_loss, bce_with_logits_loss, cross_entropy, huber_loss, l1_loss, mse_loss, nll_loss};
+pub use crate::{dtype::Reduction, fn_edge::CrossEntropyConfig};
+
 // implaceは危険なので制限する
 impl Tensor {
     pub(in crate::loss_fn) fn storage_mut(&self) -> RwLockWriteGuard<'_, Storage> {
@@ -34,6 +38,7 @@ pub fn softmax_cross_entropy_f32(predict: &mut Nten, teacher: Tensor) -> f32 {
     let one_data_len = match predict.shape {
         Shape::D1(n) => n,
         Shape::D2(_r, c) => c,
+        _ => panic!("softmax_cross_entropy_f32>> {} is not supported", predict.shape.to_string()),
     };
 
 
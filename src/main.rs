@@ -5,10 +5,10 @@ use colored::Colorize;
 use dtype::Dtype;
 use lantern_datasets::{load_minst, shuffle_and_make_batch};
 //use optimizer::Sgd;
-use tensor::{Tensor, Tensor2d};
-use nten::{Nten, Nten2d};
+use tensor::{Tensor, Tensor2d, Tensor4d};
+use nten::{Nten2d, Nten3d, Nten4d};
 
-use crate::{autograd::Context, lantern_datasets::selialize_minst, optimizer::{Optimizer, Sgd}};
+use crate::{autograd::Context, fn_edge::Conv2dConfig, lantern_datasets::selialize_minst, loss_fn::{CrossEntropyConfig, Reduction}, optimizer::{Optimizer, Sgd}};
 
 
 mod tensor;
@@ -16,8 +16,14 @@ mod backend_cpu;
 mod fn_edge;
 mod loss_fn;
 mod nten;
+mod nn;
 mod optimizer;
 mod autograd;
+mod functional;
+mod graph_opt;
+mod scheduler;
+mod state_dict;
+mod data_parallel;
 mod dtype;
 mod logger;
 mod machine_config;
@@ -25,6 +31,9 @@ mod machine_config;
 mod lantern_datasets;
 mod example;
 
+#[cfg(test)]
+mod test_utils;
+
 /*
 MNISTの学習デモは./example.rsを見てください。
 
@@ -42,6 +51,37 @@ fn nten_add()
 fn matmul()
 行列積の自動微分です。
 
+fn matmul_t()
+転置したオペランドとの行列積です。転置したTensorは作らずにtransposed-kernelで計算します。
+
+fn broadcast()
+NumPy方式のbroadcastを使った演算の自動微分です。
+
+fn conv()
+stride, padding, dilation, groupsを指定した畳み込みの自動微分です。LeNetは./example.rsにあります。
+
+fn norm()
+BatchNorm2dの学習時と推論時の動作の違いです。深いMLPの例は./example.rsのtabular()に，それをdata parallelで学習する例はtabular_data_parallel()にあります。
+
+fn index()
+gatherとindex_selectで値を取り出す演算の自動微分です。
+
+fn concat()
+concat, split, stackでつなげる，分ける演算の自動微分です。
+
+fn loss()
+MSE, L1, Huber, BCE, NLLのlossをグラフに積み，lossからbackwardします。
+class indexを正解にするcross entropyのclass weight, ignore_indexも試します。
+
+fn double_backward()
+勾配を計算グラフとして組み立て，それをさらに微分します（gradient penalty）。
+
+fn functional()
+Autograd, VarStoreを使わずにgrad, vjp, jvp, jacobianで関数を微分します。
+
+fn hooks()
+forwardの出力とbackwardの勾配をhookで見たり，勾配を書き換えたりします。
+
 fn mnist()
 デバッグ用なのでMNISTの学習デモは./example.rsを見てください。
 実際のデータセットを使って学習ができることを示しました。ここでは，データセットの作成，
@@ -62,10 +102,6 @@ fn raw_add() {
     println!("{:?}", output.storage);
 }
 
-fn dummy_loss_fn(result: &mut Nten) {
-    result.grad = result.val.clone();
-}
-
 fn nten_add() {
     let input1: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
         [1.0, 2.0],
@@ -79,22 +115,22 @@ fn nten_add() {
 
     let mut autograd = Autograd::new();
     let mut vs = autograd.get_vs();
-    let nten1 = Nten2d::new_from_val(input1).as_parameter(&mut vs);
+    let nten1 = Nten2d::new_from_val(input1).name("nten1").as_parameter(&mut vs);
     println!("{}", nten1.id);
-    let nten2 = Nten2d::new_from_val(input2).as_parameter(&mut vs);
+    let nten2 = Nten2d::new_from_val(input2).name("nten2").as_parameter(&mut vs);
     println!("{}", nten2.id);
 
-    let output = nten1.add(&nten2).as_parameter(&mut vs);
+    let output = nten1.add(&nten2);
     println!("{}", output.id);
 
     // forwardだけexecute
-    let mut result = autograd.step_forward([output.to_untyped()]);
+    let result = autograd.step_forward([output.to_untyped()]);
 
     println!("result {:?}", result[0].val);
 
     // backward
-    dummy_loss_fn(&mut result[0]);
-    let ctx = autograd.backward(&result[0]);
+    // 結果の値をそのまま勾配として流す
+    let ctx = autograd.backward_with(&result[0], result[0].val.clone().unwrap());
     
     println!("grad {:?}", ctx.get_grad(&nten1.id))
     /*
@@ -133,9 +169,6 @@ impl<const N: usize, const M: usize> Matmul<N, M> {
         result
     }
 }
-fn all_one_loss_fn(result: &mut Nten) {
-    result.grad = Some(Tensor::new_ones::<f32>(result.shape));
-}
 fn matmul() {
     
     let mut autograd = Autograd::new();
@@ -161,15 +194,14 @@ fn matmul() {
     
 
     vs.print_all_contents_id();
-    let mut result = autograd.step_forward([result.to_untyped()]);
+    let result = autograd.step_forward([result.to_untyped()]);
     println!("{:?}", result[0].val);
     /* 外部で計算した正解の値
     7	10	13
     15	22	29
     */
     
-    all_one_loss_fn(&mut result[0]);
-    let ctx = autograd.backward(&result[0]);
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
 
     let param_diff = ctx.get_grad(&linear.parameter.id);
     println!("{:?}", param_diff);
@@ -186,6 +218,426 @@ fn matmul() {
      */
 }
 
+// matmul()と同じ計算を，pytorchのLinearのように(O, I)で持ったweightの転置で行う
+fn matmul_t() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    let input: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
+        [1.0, 2.0],
+        [3.0, 4.0]
+    ]);
+    let weight: Tensor2d<3, 2, f32> = Tensor2d::new_from_martix([
+        [1.0, 3.0],
+        [2.0, 4.0],
+        [3.0, 5.0]
+    ]);
+    let input = Nten2d::new_from_val(input).name("input").as_input(&mut vs);
+    let weight = Nten2d::new_from_val(weight).name("weight").as_parameter(&mut vs);
+
+    // input x weight^T。転置したTensorは作らない
+    let out: Nten2d<2, 3, f32> = nten::matmul_t(&input, &weight, false, true);
+
+    let result = autograd.step_forward([out.to_untyped()]);
+    println!("{:?}", result[0].val);
+    /* matmul()と同じ
+    7	10	13
+    15	22	29
+    */
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+    println!("{:?}", ctx.get_grad(&weight.id));
+    /* matmul()のparameterの勾配の転置
+    [[4. 6.]
+     [4. 6.]
+     [4. 6.]]
+     */
+}
+
+fn broadcast() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    // 外積 Nten2d<2, 1> * Nten2d<1, 3> -> Nten2d<2, 3>
+    let column: Tensor2d<2, 1, f32> = Tensor2d::new_from_martix([
+        [1.0],
+        [2.0]
+    ]);
+    let row: Tensor2d<1, 3, f32> = Tensor2d::new_from_martix([
+        [1.0, 2.0, 3.0]
+    ]);
+    let column = Nten2d::new_from_val(column).name("column").as_parameter(&mut vs);
+    let row = Nten2d::new_from_val(row).name("row").as_parameter(&mut vs);
+
+    // 出力の形状は型注釈から決まり，broadcastできない組み合わせはコンパイルエラーになる
+    let outer: Nten2d<2, 3, f32> = column.broadcast_mul(&row);
+
+    let result = autograd.step_forward([outer.to_untyped()]);
+    println!("{:?}", result[0].val);
+    /* numpyで計算した正解
+    [[1. 2. 3.]
+     [2. 4. 6.]]
+     */
+
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+
+    println!("{:?}", ctx.get_grad(&column.id));
+    /* numpyで計算した正解
+    [[6.]
+     [6.]]
+     */
+    println!("{:?}", ctx.get_grad(&row.id));
+    /* numpyで計算した正解
+    [[3. 3. 3.]]
+     */
+}
+
+
+fn conv() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    // 2グループ，stride 2, dilation 2の畳み込み。出力の大きさは型注釈と一致しなければエラーになる
+    // HO = (6 + 2 * 1 - 2 * (3 - 1) - 1) / 2 + 1 = 2
+    let input: Tensor4d<1, 4, 6, 6, f32> = Tensor4d::new_uniform(-1.0, 1.0);
+    let weight: Tensor4d<2, 2, 3, 3, f32> = Tensor4d::new_uniform(-1.0, 1.0);
+    let input = Nten4d::new_from_val(input).name("input").as_parameter(&mut vs);
+    let weight = Nten4d::new_from_val(weight).name("weight").as_parameter(&mut vs);
+
+    let config = Conv2dConfig::new().stride(2, 2).padding(1, 1).dilation(2, 2).groups(2);
+    let output: Nten4d<1, 2, 2, 2, f32> = nten::conv2d(&input, &weight, None, config);
+
+    let result = autograd.step_forward([output.to_untyped()]);
+    println!("{:?}", result[0].val);
+
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+    println!("{:?}", ctx.get_grad(&input.id));
+    println!("{:?}", ctx.get_grad(&weight.id));
+}
+
+fn norm() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let batch_norm: nn::BatchNorm2d<2> = nn::BatchNorm2d::new(&mut vs);
+
+    // 学習時はbatchの統計で正規化し，running statsをmomentum 0.1で更新する
+    autograd.train();
+    let input: Tensor4d<4, 2, 3, 3, f32> = Tensor4d::new_uniform(1.0, 3.0);
+    let input = Nten4d::new_from_val(input).name("input").as_input(&mut vs);
+    let result = autograd.step_forward([batch_norm.forward(&input).to_untyped()]);
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+    // 平均は2付近なので0.2付近
+    println!("running_mean {:?}", ctx.get_val(&batch_norm.running_mean.id));
+    println!("running_var {:?}", ctx.get_val(&batch_norm.running_var.id));
+    autograd.zero_grad();
+
+    // 推論時はrunning statsで正規化し，running statsは変わらない
+    autograd.eval();
+    let input: Tensor4d<4, 2, 3, 3, f32> = Tensor4d::new_uniform(1.0, 3.0);
+    let input = Nten4d::new_from_val(input).name("input").as_input(&mut vs);
+    let result = autograd.step_forward([batch_norm.forward(&input).to_untyped()]);
+    println!("{:?}", result[0].val);
+}
+
+fn index() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    // 3サンプル，4クラスのスコア
+    let scores: Tensor2d<3, 4, f32> = Tensor2d::new_from_martix([
+        [0.1, 0.7, 0.1, 0.1],
+        [0.5, 0.2, 0.2, 0.1],
+        [0.3, 0.3, 0.3, 0.1],
+    ]);
+    let scores = Nten2d::new_from_val(scores).name("scores").as_parameter(&mut vs);
+    let labels = Nten2d::new_from_val(Tensor2d::<3, 1, u32>::new_from_indices(vec![1, 0, 3]).unwrap()).name("labels").as_input(&mut vs);
+    let rows = Nten2d::new_from_val(Tensor2d::<1, 2, u32>::new_from_indices(vec![2, 0]).unwrap()).name("rows").as_input(&mut vs);
+
+    // 正解ラベルのスコアを取り出す（自作のlossなどに使う）
+    let picked: Nten2d<3, 1, f32> = scores.gather::<1, 3, 1>(&labels);
+    // 2行目と0行目を並べる
+    let selected: Nten2d<2, 4, f32> = scores.index_select::<0, 2, 2, 4>(&rows);
+
+    let result = autograd.step_forward([picked.to_untyped(), selected.to_untyped()]);
+    println!("{:?}", result[0].val);
+    /* 正解
+    [[0.7]
+     [0.5]
+     [0.1]]
+     */
+    println!("{:?}", result[1].val);
+    /* 正解
+    [[0.3 0.3 0.3 0.1]
+     [0.1 0.7 0.1 0.1]]
+     */
+
+    // gatherの勾配は取り出した位置だけに1が入る
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+    println!("{:?}", ctx.get_grad(&scores.id));
+    /* 正解
+    [[0. 1. 0. 0.]
+     [1. 0. 0. 0.]
+     [0. 0. 0. 1.]]
+     */
+}
+
+fn concat() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    let a: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
+        [1.0, 2.0],
+        [3.0, 4.0]
+    ]);
+    let b: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
+        [5.0, 6.0],
+        [7.0, 8.0]
+    ]);
+    let a = Nten2d::new_from_val(a).name("a").as_parameter(&mut vs);
+    let b = Nten2d::new_from_val(b).name("b").as_parameter(&mut vs);
+
+    // 列方向に3つつなげる
+    let joined: Nten2d<2, 6, f32> = nten::concat_n::<1, 3, 2, 2, 2, 6, f32>([&a, &b, &a]);
+    // splitの2つの出力のうち片方だけを使う。使わなかった出力の勾配は0として扱う
+    let (left, _): (Nten2d<2, 1, f32>, Nten2d<2, 5, f32>) = joined.split::<1, 2, 1, 2, 5>();
+    let stacked: Nten3d<2, 2, 2, f32> = nten::stack([&b, &a]);
+
+    let result = autograd.step_forward([left.to_untyped(), stacked.to_untyped()]);
+    println!("{:?}", result[0].val);
+    /* 正解
+    [[1.]
+     [3.]]
+     */
+    println!("{:?}", result[1].val);
+    /* 正解
+    [[[5. 6.] [7. 8.]]
+     [[1. 2.] [3. 4.]]]
+     */
+
+    let ctx = autograd.backward_with(&result[0], Tensor::new_ones::<f32>(result[0].shape));
+    println!("{:?}", ctx.get_grad(&a.id));
+    /* 正解
+    [[1. 0.]
+     [1. 0.]]
+     */
+}
+
+fn loss() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    let predict: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
+        [0.5, 2.0],
+        [-1.0, 0.0]
+    ]);
+    let target: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([
+        [1.0, 0.0],
+        [0.0, 0.0]
+    ]);
+    let predict = Nten2d::new_from_val(predict).name("predict").as_parameter(&mut vs);
+    let target = Nten2d::new_from_val(target).name("target").as_input(&mut vs);
+
+    // lossはグラフに積まれるので，backwardはlossから始められる
+    let mse: Nten2d<1, 1, f32> = loss_fn::mse_loss(&predict, &target, Reduction::Mean);
+    let l1: Nten2d<1, 1, f32> = loss_fn::l1_loss(&predict, &target, Reduction::Mean);
+    let huber: Nten2d<1, 1, f32> = loss_fn::huber_loss(&predict, &target, 1.0, Reduction::Sum);
+    // multi-labelの分類はlogitのままbce_with_logits_lossに渡す
+    let bce: Nten2d<1, 1, f32> = loss_fn::bce_with_logits_loss(&predict, &target, Reduction::Mean);
+    let bce_prob: Nten2d<1, 1, f32> = loss_fn::bce_loss(&predict.sigmoid(), &target, Reduction::Mean);
+    let per_element: Nten2d<2, 2, f32> = loss_fn::mse_loss(&predict, &target, Reduction::None);
+
+    let result = autograd.step_forward([mse.to_untyped(), l1.to_untyped(), huber.to_untyped(), bce.to_untyped(), bce_prob.to_untyped(), per_element.to_untyped()]);
+    for loss in result.iter() {
+        println!("{:?}", loss.val);
+    }
+    /* 正解
+    mse: 1.3125, l1: 0.875, huber: 2.125, bce: 0.9019, bce_prob: 0.9019
+    per_element:
+    [[0.25 4.]
+     [1.   0.]]
+     */
+
+    // d mse / d predict = 2 (predict - target) / 4
+    let ctx = autograd.backward(&result[0]);
+    println!("{:?}", ctx.get_grad(&predict.id));
+    /* 正解
+    [[-0.25 1.]
+     [-0.5  0.]]
+     */
+
+    // nll_lossにはlog確率とclass indexを渡す
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let log_probs: Tensor2d<2, 3, f32> = Tensor2d::new_from_martix([
+        [0.2f32.ln(), 0.5f32.ln(), 0.3f32.ln()],
+        [0.6f32.ln(), 0.1f32.ln(), 0.3f32.ln()]
+    ]);
+    let log_probs = Nten2d::new_from_val(log_probs).name("log_probs").as_parameter(&mut vs);
+    let labels = Nten2d::new_from_val(Tensor2d::<2, 1, u32>::new_from_indices(vec![1, 0]).unwrap()).name("labels").as_input(&mut vs);
+    let nll: Nten2d<1, 1, f32> = loss_fn::nll_loss(&log_probs, &labels, Reduction::Mean);
+    let [nll] = autograd.step_forward([nll.to_untyped()]);
+    println!("{:?}", nll.val);
+    /* 正解
+    -(ln 0.5 + ln 0.6) / 2 = 0.6020
+     */
+
+    // cross_entropyはlogitとclass indexを渡す。one-hotのteacherはいらない
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+    let logits: Tensor2d<3, 3, f32> = Tensor2d::new_from_martix([
+        [0.0, 2.0, 1.0],
+        [1.0, 1.0, 1.0],
+        [3.0, 1.0, 0.0]
+    ]);
+    let logits = Nten2d::new_from_val(logits).name("logits").as_parameter(&mut vs);
+    // class 0はpadding
+    let labels = Nten2d::new_from_val(Tensor2d::<3, 1, u32>::new_from_indices(vec![1, 0, 2]).unwrap()).name("labels").as_input(&mut vs);
+    // 少ないclass 2の重みを大きくし，paddingの行は無視する
+    let config = CrossEntropyConfig::new()
+        .weight(vec![1.0, 1.0, 2.0])
+        .ignore_index(0)
+        .reduction(Reduction::None);
+    let per_sample: Nten2d<3, 1, f32> = loss_fn::cross_entropy(&logits, &labels, config);
+    let [per_sample] = autograd.step_forward([per_sample.to_untyped()]);
+    println!("{:?}", per_sample.val);
+    /* 正解
+    [[0.4076]
+     [0.    ]
+     [6.3397]]
+     */
+    let ctx = autograd.backward_with(&per_sample, Tensor::new_ones::<f32>(per_sample.shape));
+    println!("{:?}", ctx.get_grad(&logits.id));
+    /* 正解
+    paddingの行の勾配は0
+    [[ 0.0900 -0.3348  0.2447]
+     [ 0.      0.      0.    ]
+     [ 1.6876  0.2284 -1.9160]]
+     */
+}
+
+fn double_backward() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    let x: Tensor2d<1, 3, f32> = Tensor2d::new_from_martix([[0.5, -1.0, 2.0]]);
+    let x = Nten2d::new_from_val(x).name("x").as_parameter(&mut vs);
+    let zeros = Nten2d::new_from_val(Tensor2d::<1, 3, f32>::new_zeros()).name("zeros").as_input(&mut vs);
+
+    // y = sum(tanh(x)^2)
+    let y: Nten2d<1, 1, f32> = loss_fn::mse_loss(&x.tanh(), &zeros, Reduction::Sum);
+    let [y] = autograd.step_forward([y.to_untyped()]);
+
+    // dy/dxをFnEdgeのグラフとして組み立てる。backward()と違って，これ自体を微分できる
+    let [dy_dx] = autograd.grad_graph(&y, [x.id]);
+    let dy_dx: Nten2d<1, 3, f32> = dy_dx.to_typed2d().unwrap();
+    // gradient penalty: |dy/dx|^2
+    let penalty: Nten2d<1, 1, f32> = loss_fn::mse_loss(&dy_dx, &zeros, Reduction::Sum);
+    let [dy_dx, penalty] = autograd.step_forward([dy_dx.to_untyped(), penalty.to_untyped()]);
+    println!("{:?}", dy_dx.val);
+    /* 正解
+    2 tanh(x) (1 - tanh(x)^2)
+    [[0.7269 -0.6397 0.1362]]
+     */
+
+    let ctx = autograd.backward(&penalty);
+    println!("{:?}", ctx.get_grad(&x.id));
+    /* 正解
+    2 dy/dx d^2y/dx^2
+    [[0.8217 0.7953 -0.0688]]
+     */
+}
+
+fn functional() {
+    // Autograd, VarStoreを作らずに関数の微分をとる
+    let x: Tensor2d<1, 2, f32> = Tensor2d::new_from_martix([[1.0, -2.0]]);
+    // f(x) = x * x（要素ごと）
+    let f = |x: &Nten2d<1, 2, f32>, _: &mut VarStore| -> Nten2d<1, 2, f32> { x.broadcast_mul(x) };
+    println!("{}", "jacobian".green());
+    println!("{:?}", functional::jacobian::<1, 2, 1, 2, 2, 2, _>(f, x.clone()));
+    /* 正解
+    diag(2x)
+    [[2.  0.]
+     [0. -4.]]
+     */
+
+    println!("{}", "vjp".green());
+    let (y, vj) = functional::vjp(f, x.clone(), Tensor2d::new_from_martix([[1.0, 10.0]]));
+    println!("{:?}\n{:?}", y, vj);
+    /* 正解
+    f(x) = [[1. 4.]]
+    [1, 10] J = [[2. -40.]]
+     */
+
+    println!("{}", "jvp".green());
+    match functional::jvp(f, x.clone(), Tensor2d::new_from_martix([[1.0, 10.0]])) {
+        Ok((_, jt)) => println!("{:?}", jt),
+        Err(e) => println!("{}", e),
+    }
+    /* 正解
+    J [1, 10]^T = [[2. -40.]]
+     */
+
+    // softmaxはbackward_graph()がないので，jvpは対応していないFnEdgeの名前を返す
+    match functional::jvp(|x: &Nten2d<1, 2, f32>, _: &mut VarStore| x.softmax(), x.clone(), x.clone()) {
+        Ok((_, jt)) => println!("{:?}", jt),
+        Err(e) => println!("{}", e),
+    }
+
+    println!("{}", "grad".green());
+    let sum_of_squares = |x: &Nten2d<1, 2, f32>, vs: &mut VarStore| {
+        let zeros = Nten2d::new_from_val(Tensor2d::<1, 2, f32>::new_zeros()).name("zeros").as_input(vs);
+        loss_fn::mse_loss(x, &zeros, Reduction::Sum)
+    };
+    println!("{:?}", functional::grad(sum_of_squares, x.clone()));
+    let (value, _) = functional::value_and_grad(sum_of_squares, x);
+    println!("{}", value);
+    /* 正解
+    [[2. -4.]]
+    5
+     */
+}
+
+fn hooks() {
+    let mut autograd = Autograd::new();
+    let mut vs = autograd.get_vs();
+
+    let w: Tensor2d<2, 2, f32> = Tensor2d::new_from_martix([[1.0, 2.0], [3.0, -1.0]]);
+    let w = Nten2d::new_from_val(w).name("w").as_parameter(&mut vs);
+    // parameterのgrad hookはzero_gradでは消えず，毎iteration呼ばれる。ここでは勾配を半分にする
+    let halve = autograd.register_grad_hook(w.id, |grad| Some(grad.mul_scalar(0.5)));
+
+    for step in 0..2 {
+        println!("{}", format!("step {}", step).green());
+        let x: Tensor2d<1, 2, f32> = Tensor2d::new_from_martix([[1.0, -2.0]]);
+        let x = Nten2d::new_from_val(x).name("x").as_input(&mut vs);
+        let zeros = Nten2d::new_from_val(Tensor2d::<1, 2, f32>::new_zeros()).name("zeros").as_input(&mut vs);
+        let h: Nten2d<1, 2, f32> = nten::matmul(&x, &w);
+        let y = h.relu();
+        // forward hookはFnEdgeに登録して，forwardの出力を見る
+        let watch_relu = autograd.register_forward_hook(y.creator.get_id(), |fn_edge, outputs| println!("{}: {:?}", fn_edge.name(), outputs[0]));
+        // Noneを返すhookは勾配を見るだけ
+        let watch_h = autograd.register_grad_hook(h.id, |grad| {
+            println!("grad of h: {:?}", grad);
+            None
+        });
+
+        let loss: Nten2d<1, 1, f32> = loss_fn::mse_loss(&y, &zeros, Reduction::Sum);
+        let [loss] = autograd.step_forward([loss.to_untyped()]);
+        let ctx = autograd.backward(&loss);
+        println!("grad of w: {:?}", ctx.get_grad(&w.id));
+        autograd.zero_grad();
+
+        autograd.remove_hook(watch_relu);
+        autograd.remove_hook(watch_h);
+        // 2回目は勾配を半分にしない
+        autograd.remove_hook(halve);
+    }
+    /* 正解
+    h = [[-5. 4.]], relu(h) = [[0. 4.]]
+    grad of h: [[0. 8.]]
+    grad of w: x^T grad of h = [[0. 8.] [0. -16.]]
+    step 0は半分にして[[0. 4.] [0. -8.]]，step 1は[[0. 8.] [0. -16.]]
+     */
+}
 
 struct Linear<const I: usize, const O: usize> {
     weight: Nten2d<I, O, f32>,
@@ -221,8 +673,8 @@ struct Model<const B: usize, const H: usize> {
 impl<const B: usize, const H: usize> Model<B, H> {
     fn new(vs: &mut VarStore) -> Self {
         Self {
-            linear1: Linear::new(vs),
-            linear2: Linear::new(vs),
+            linear1: Linear::new(&mut vs.sub("linear1")),
+            linear2: Linear::new(&mut vs.sub("linear2")),
         }
     }
     fn forward(&self, input: &Nten2d<B, 784, f32>) -> Nten2d<B, 10, f32> {
@@ -330,11 +782,28 @@ fn mnist() {
 
 
 fn main() {
-    //raw_add();
-    //nten_add();
-    //matmul();
-    //mnist();
-
-    example::mnist()
-
+    // cargo run -- <name> で開発過程の関数を選ぶ。省略するとexampleのMNISTの学習デモ
+    match std::env::args().nth(1).as_deref() {
+        Some("raw_add") => raw_add(),
+        Some("nten_add") => nten_add(),
+        Some("matmul") => matmul(),
+        Some("matmul_t") => matmul_t(),
+        Some("broadcast") => broadcast(),
+        Some("conv") => conv(),
+        Some("lenet") => example::lenet(),
+        Some("norm") => norm(),
+        Some("index") => index(),
+        Some("concat") => concat(),
+        Some("loss") => loss(),
+        Some("double_backward") => double_backward(),
+        Some("functional") => functional(),
+        Some("hooks") => hooks(),
+        Some("tabular") => example::tabular(),
+        Some("tabular_dp") => example::tabular_data_parallel(),
+        Some("recommend") => example::recommend(),
+        Some("char_transformer") => example::char_transformer(),
+        Some("forecast") => example::forecast(),
+        Some("mnist_debug") => mnist(),
+        _ => example::mnist(),
+    }
 }
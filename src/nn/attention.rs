@@ -0,0 +1,116 @@
+use crate::{autograd::VarStore, nten::{self, Nten2d, Nten3d}};
+
+use super::Linear;
+
+
+/*
+E: 埋め込みの次元, H: head数, DH: headごとの次元 (E = H * DH)
+input (B, T, E)。const genericで積を書けないので
+BT = B * T, BH = B * H を呼び出し側で指定する（reshapeでコンパイル時に検査される）
+*/
+pub struct MultiHeadAttention<const E: usize, const H: usize, const DH: usize> {
+    pub q_proj: Linear<E, E>,
+    pub k_proj: Linear<E, E>,
+    pub v_proj: Linear<E, E>,
+    pub out_proj: Linear<E, E>,
+}
+impl<const E: usize, const H: usize, const DH: usize> MultiHeadAttention<E, H, DH> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        const { assert!(E == H * DH, "MultiHeadAttention >> E must be H * DH") };
+        Self {
+            q_proj: Linear::new(&mut vs.sub("q_proj")),
+            k_proj: Linear::new(&mut vs.sub("k_proj")),
+            v_proj: Linear::new(&mut vs.sub("v_proj")),
+            out_proj: Linear::new(&mut vs.sub("out_proj")),
+        }
+    }
+
+    // (B * T, E) -> (B * H, T, DH)
+    fn split_heads<const B: usize, const T: usize, const BT: usize, const BH: usize>(x: &Nten2d<BT, E, f32>) -> Nten3d<BH, T, DH, f32> {
+        x.reshape_4d::<B, T, H, DH>()
+            .permute::<0, 2, 1, 3, B, H, T, DH>()
+            .reshape_3d::<BH, T, DH>()
+    }
+
+    // (B * H, T, DH) -> (B * T, E)
+    fn merge_heads<const B: usize, const T: usize, const BT: usize, const BH: usize>(x: &Nten3d<BH, T, DH, f32>) -> Nten2d<BT, E, f32> {
+        x.reshape_4d::<B, H, T, DH>()
+            .permute::<0, 2, 1, 3, B, T, H, DH>()
+            .reshape_2d::<BT, E>()
+    }
+
+    // query (B, TQ, E), key/value (B, TK, E)。maskはfalseの位置に注意を向けない
+    pub fn forward<const B: usize, const TQ: usize, const TK: usize, const BTQ: usize, const BTK: usize, const BH: usize>
+        (&self, query: &Nten3d<B, TQ, E, f32>, key: &Nten3d<B, TK, E, f32>, value: &Nten3d<B, TK, E, f32>, mask: Option<&Nten2d<TQ, TK, bool>>) -> Nten3d<B, TQ, E, f32> {
+        let q = self.q_proj.forward(&query.reshape_2d::<BTQ, E>());
+        let k = self.k_proj.forward(&key.reshape_2d::<BTK, E>());
+        let v = self.v_proj.forward(&value.reshape_2d::<BTK, E>());
+
+        let q: Nten3d<BH, TQ, DH, f32> = Self::split_heads::<B, TQ, BTQ, BH>(&q);
+        let k: Nten3d<BH, TK, DH, f32> = Self::split_heads::<B, TK, BTK, BH>(&k);
+        let v: Nten3d<BH, TK, DH, f32> = Self::split_heads::<B, TK, BTK, BH>(&v);
+
+        let attention = nten::scaled_dot_product_attention(&q, &k, &v, mask);
+
+        let out = self.out_proj.forward(&Self::merge_heads::<B, TQ, BTQ, BH>(&attention));
+        out.reshape_3d::<B, TQ, E>()
+    }
+
+    // query = key = value
+    pub fn self_attention<const B: usize, const T: usize, const BT: usize, const BH: usize>
+        (&self, input: &Nten3d<B, T, E, f32>, mask: Option<&Nten2d<T, T, bool>>) -> Nten3d<B, T, E, f32> {
+        self.forward::<B, T, T, BT, BT, BH>(input, input, input, mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::VarStore, dtype::Shape, nn::Linear, nten::{Nten, Nten2d, Nten3d}, tensor::{Tensor, Tensor2d}, test_utils::{forward, gradcheck, sample}};
+
+    use super::MultiHeadAttention;
+
+    // parameterはgradcheckの入力から作る。ps[0]がinput，続いてq, k, v, outのweightとbias
+    fn attention(vs: &mut VarStore, ps: &[Nten]) -> Nten {
+        let linear = |i: usize| Linear::<4, 4> {
+            weight: ps[1 + 2 * i].clone().to_typed2d().unwrap(),
+            bias: ps[2 + 2 * i].clone().to_typed2d().unwrap(),
+        };
+        let mha = MultiHeadAttention::<4, 2, 2> { q_proj: linear(0), k_proj: linear(1), v_proj: linear(2), out_proj: linear(3) };
+        let mask = Nten2d::new_from_val(Tensor2d::<3, 3, bool>::new_causal_mask()).name("mask").as_input(vs);
+        let input: Nten3d<2, 3, 4, f32> = ps[0].clone().to_typed3d().unwrap();
+        mha.self_attention::<2, 3, 6, 4>(&input, Some(&mask)).to_untyped()
+    }
+    fn params(input: Tensor) -> Vec<Tensor> {
+        let mut params = vec![input];
+        for i in 0..4 {
+            params.push(sample(Shape::D2(4, 4), 10 + i));
+            params.push(sample(Shape::D2(1, 4), 20 + i));
+        }
+        params
+    }
+
+    #[test]
+    fn gradcheck_multi_head_attention() {
+        gradcheck(&params(sample(Shape::D3(2, 3, 4), 1)), attention);
+    }
+
+    // 後ろの位置の入力を変えても前の位置の出力は変わらない
+    #[test]
+    fn causal_self_attention() {
+        let input = sample(Shape::D3(2, 3, 4), 2);
+        let mut changed = input.to_vec_f32();
+        // 各batchの最後の位置
+        for b in 0..2 {
+            for e in 0..4 {
+                changed[b * 12 + 8 + e] += 1.0;
+            }
+        }
+        let changed = Tensor::new_from_vec(changed, input.shape).unwrap();
+        let out = forward(&params(input), attention).to_vec_f32();
+        let out_changed = forward(&params(changed), attention).to_vec_f32();
+        for b in 0..2 {
+            assert_eq!(out[b * 12..b * 12 + 8], out_changed[b * 12..b * 12 + 8]);
+            assert_ne!(out[b * 12 + 8..b * 12 + 12], out_changed[b * 12 + 8..b * 12 + 12]);
+        }
+    }
+}
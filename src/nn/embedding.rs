@@ -0,0 +1,19 @@
+use crate::{autograd::VarStore, nten::{self, Nten2d}, tensor::Tensor2d};
+
+
+// V: 語彙数, D: 埋め込みの次元
+pub struct Embedding<const V: usize, const D: usize> {
+    pub weight: Nten2d<V, D, f32>,
+}
+impl<const V: usize, const D: usize> Embedding<V, D> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        // pytorchと同じくN(0, 1)で初期化
+        let weight: Tensor2d<V, D, f32> = Tensor2d::new_normal(0.0, 1.0);
+        Self {
+            weight: Nten2d::new_from_val(weight).name("Embedding weight").as_parameter(vs),
+        }
+    }
+    pub fn forward<const N: usize>(&self, indices: &Nten2d<N, 1, u32>) -> Nten2d<N, D, f32> {
+        nten::embedding(indices, &self.weight)
+    }
+}
@@ -0,0 +1,25 @@
+use crate::{autograd::VarStore, nten::{self, Nten2d}, tensor::Tensor2d};
+
+
+// I: 入力特徴数, O: 出力特徴数. input (B, I)
+pub struct Linear<const I: usize, const O: usize> {
+    pub weight: Nten2d<I, O, f32>,
+    pub bias: Nten2d<1, O, f32>,
+}
+impl<const I: usize, const O: usize> Linear<I, O> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        // pytorchと同じ初期化
+        // U(-\sqrt{k}, \sqrt{k}), k = 1 / 入力特徴数
+        let k: f32 = 1.0 / I as f32;
+        let weight: Tensor2d<I, O, f32> = Tensor2d::new_uniform(-k.sqrt(), k.sqrt());
+        let bias: Tensor2d<1, O, f32> = Tensor2d::new_zeros();
+        Self {
+            weight: Nten2d::new_from_val(weight).name("Linear weight").as_parameter(vs),
+            bias: Nten2d::new_from_val(bias).name("Linear bias").as_parameter(vs),
+        }
+    }
+    pub fn forward<const B: usize>(&self, input: &Nten2d<B, I, f32>) -> Nten2d<B, O, f32> {
+        let out: Nten2d<B, O, f32> = nten::matmul(input, &self.weight);
+        out.add_broadcast(&self.bias)
+    }
+}
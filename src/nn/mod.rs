@@ -0,0 +1,16 @@
+
+/*-------------Level 3, layers-----------------------------------
+parameterとbufferをVarStoreに登録して持つレイヤー
+new(vs)で作成し，forward()で計算グラフを構築する
+*/
+
+mod embedding;
+pub use embedding::Embedding;
+mod norm;
+pub use norm::{BatchNorm1d, BatchNorm2d, LayerNorm};
+mod linear;
+pub use linear::Linear;
+mod attention;
+pub use attention::MultiHeadAttention;
+mod rnn;
+pub use rnn::{GRUCell, LSTMCell, RNNCell, Recurrent, RecurrentCell};
@@ -0,0 +1,83 @@
+use crate::{autograd::VarStore, nten::{self, Nten2d, Nten4d}, tensor::{Tensor2d, Tensor4d}};
+
+
+// pytorchと同じデフォルト値
+const DEFAULT_EPS: f32 = 1e-5;
+const DEFAULT_MOMENTUM: f32 = 0.1;
+
+
+// F: feature. input (B, F)
+pub struct BatchNorm1d<const F: usize> {
+    pub weight: Nten2d<1, F, f32>,
+    pub bias: Nten2d<1, F, f32>,
+    pub running_mean: Nten2d<1, F, f32>,
+    pub running_var: Nten2d<1, F, f32>,
+    pub eps: f32,
+    pub momentum: f32,
+}
+impl<const F: usize> BatchNorm1d<F> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            weight: Nten2d::new_from_val(Tensor2d::new_ones()).name("BatchNorm1d weight").as_parameter(vs),
+            bias: Nten2d::new_from_val(Tensor2d::new_zeros()).name("BatchNorm1d bias").as_parameter(vs),
+            running_mean: Nten2d::new_from_val(Tensor2d::new_zeros()).name("BatchNorm1d running_mean").as_buffer(vs),
+            running_var: Nten2d::new_from_val(Tensor2d::new_ones()).name("BatchNorm1d running_var").as_buffer(vs),
+            eps: DEFAULT_EPS,
+            momentum: DEFAULT_MOMENTUM,
+        }
+    }
+    pub fn forward<const B: usize>(&self, input: &Nten2d<B, F, f32>) -> Nten2d<B, F, f32> {
+        let x_hat = nten::batch_norm1d(input, &self.running_mean, &self.running_var, self.momentum, self.eps);
+        let x: Nten2d<B, F, f32> = x_hat.broadcast_mul(&self.weight);
+        x.broadcast_add(&self.bias)
+    }
+}
+
+
+// C: channel. input (N, C, H, W)
+pub struct BatchNorm2d<const C: usize> {
+    pub weight: Nten4d<1, C, 1, 1, f32>,
+    pub bias: Nten4d<1, C, 1, 1, f32>,
+    pub running_mean: Nten2d<1, C, f32>,
+    pub running_var: Nten2d<1, C, f32>,
+    pub eps: f32,
+    pub momentum: f32,
+}
+impl<const C: usize> BatchNorm2d<C> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            weight: Nten4d::new_from_val(Tensor4d::new_ones()).name("BatchNorm2d weight").as_parameter(vs),
+            bias: Nten4d::new_from_val(Tensor4d::new_zeros()).name("BatchNorm2d bias").as_parameter(vs),
+            running_mean: Nten2d::new_from_val(Tensor2d::new_zeros()).name("BatchNorm2d running_mean").as_buffer(vs),
+            running_var: Nten2d::new_from_val(Tensor2d::new_ones()).name("BatchNorm2d running_var").as_buffer(vs),
+            eps: DEFAULT_EPS,
+            momentum: DEFAULT_MOMENTUM,
+        }
+    }
+    pub fn forward<const N: usize, const H: usize, const W: usize>(&self, input: &Nten4d<N, C, H, W, f32>) -> Nten4d<N, C, H, W, f32> {
+        let x_hat = nten::batch_norm2d(input, &self.running_mean, &self.running_var, self.momentum, self.eps);
+        x_hat.broadcast_mul(&self.weight).broadcast_add(&self.bias)
+    }
+}
+
+
+// F: feature. input (B, F)。running statsは持たない
+pub struct LayerNorm<const F: usize> {
+    pub weight: Nten2d<1, F, f32>,
+    pub bias: Nten2d<1, F, f32>,
+    pub eps: f32,
+}
+impl<const F: usize> LayerNorm<F> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            weight: Nten2d::new_from_val(Tensor2d::new_ones()).name("LayerNorm weight").as_parameter(vs),
+            bias: Nten2d::new_from_val(Tensor2d::new_zeros()).name("LayerNorm bias").as_parameter(vs),
+            eps: DEFAULT_EPS,
+        }
+    }
+    pub fn forward<const B: usize>(&self, input: &Nten2d<B, F, f32>) -> Nten2d<B, F, f32> {
+        let x_hat = nten::layer_norm(input, self.eps);
+        let x: Nten2d<B, F, f32> = x_hat.broadcast_mul(&self.weight);
+        x.broadcast_add(&self.bias)
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{autograd::VarStore, nten::{self, Nten2d, Nten3d}, tensor::Tensor2d};
+
+use super::Linear;
+
+
+/*
+I: 入力特徴数, HD: 隠れ状態の次元
+stepごとに同じparameterのNtenを使うので，勾配は時刻ぶんadd_assign_gradで足し合わされる
+*/
+pub trait RecurrentCell<const I: usize, const HD: usize> {
+    type State<const B: usize>: Clone;
+
+    fn zero_state<const B: usize>(&self, vs: &mut VarStore) -> Self::State<B>;
+    fn step<const B: usize>(&self, input: &Nten2d<B, I, f32>, state: &Self::State<B>) -> Self::State<B>;
+    // stateの中で出力になる隠れ状態
+    fn hidden<const B: usize>(state: &Self::State<B>) -> Nten2d<B, HD, f32>;
+}
+
+fn zeros<const B: usize, const HD: usize>(name: &str, vs: &mut VarStore) -> Nten2d<B, HD, f32> {
+    Nten2d::new_from_val(Tensor2d::new_zeros()).name(name).as_input(vs)
+}
+
+
+// h' = tanh(W_ih x + b_ih + W_hh h + b_hh)
+pub struct RNNCell<const I: usize, const HD: usize> {
+    pub ih: Linear<I, HD>,
+    pub hh: Linear<HD, HD>,
+}
+impl<const I: usize, const HD: usize> RNNCell<I, HD> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            ih: Linear::new(&mut vs.sub("ih")),
+            hh: Linear::new(&mut vs.sub("hh")),
+        }
+    }
+}
+impl<const I: usize, const HD: usize> RecurrentCell<I, HD> for RNNCell<I, HD> {
+    type State<const B: usize> = Nten2d<B, HD, f32>;
+
+    fn zero_state<const B: usize>(&self, vs: &mut VarStore) -> Self::State<B> {
+        zeros("RNNCell h0", vs)
+    }
+    fn step<const B: usize>(&self, input: &Nten2d<B, I, f32>, state: &Self::State<B>) -> Self::State<B> {
+        self.ih.forward(input).add(&self.hh.forward(state)).tanh()
+    }
+    fn hidden<const B: usize>(state: &Self::State<B>) -> Nten2d<B, HD, f32> {
+        state.clone()
+    }
+}
+
+
+/*
+pytorchと同じ式
+r = sigmoid(W_ir x + b_ir + W_hr h + b_hr)
+z = sigmoid(W_iz x + b_iz + W_hz h + b_hz)
+n = tanh(W_in x + b_in + r * (W_hn h + b_hn))
+h' = (1 - z) * n + z * h = n + z * (h - n)
+*/
+pub struct GRUCell<const I: usize, const HD: usize> {
+    pub ir: Linear<I, HD>,
+    pub hr: Linear<HD, HD>,
+    pub iz: Linear<I, HD>,
+    pub hz: Linear<HD, HD>,
+    pub in_: Linear<I, HD>,
+    pub hn: Linear<HD, HD>,
+}
+impl<const I: usize, const HD: usize> GRUCell<I, HD> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            ir: Linear::new(&mut vs.sub("ir")),
+            hr: Linear::new(&mut vs.sub("hr")),
+            iz: Linear::new(&mut vs.sub("iz")),
+            hz: Linear::new(&mut vs.sub("hz")),
+            in_: Linear::new(&mut vs.sub("in")),
+            hn: Linear::new(&mut vs.sub("hn")),
+        }
+    }
+}
+impl<const I: usize, const HD: usize> RecurrentCell<I, HD> for GRUCell<I, HD> {
+    type State<const B: usize> = Nten2d<B, HD, f32>;
+
+    fn zero_state<const B: usize>(&self, vs: &mut VarStore) -> Self::State<B> {
+        zeros("GRUCell h0", vs)
+    }
+    fn step<const B: usize>(&self, input: &Nten2d<B, I, f32>, state: &Self::State<B>) -> Self::State<B> {
+        let r = self.ir.forward(input).add(&self.hr.forward(state)).sigmoid();
+        let z = self.iz.forward(input).add(&self.hz.forward(state)).sigmoid();
+        let rh: Nten2d<B, HD, f32> = r.broadcast_mul(&self.hn.forward(state));
+        let n = self.in_.forward(input).add(&rh).tanh();
+        let h_minus_n: Nten2d<B, HD, f32> = state.broadcast_sub(&n);
+        let z_h_minus_n: Nten2d<B, HD, f32> = z.broadcast_mul(&h_minus_n);
+        n.add(&z_h_minus_n)
+    }
+    fn hidden<const B: usize>(state: &Self::State<B>) -> Nten2d<B, HD, f32> {
+        state.clone()
+    }
+}
+
+
+/*
+i = sigmoid(W_ii x + b_ii + W_hi h + b_hi)
+f = sigmoid(W_if x + b_if + W_hf h + b_hf)
+g = tanh(W_ig x + b_ig + W_hg h + b_hg)
+o = sigmoid(W_io x + b_io + W_ho h + b_ho)
+c' = f * c + i * g
+h' = o * tanh(c')
+stateは(h, c)
+*/
+pub struct LSTMCell<const I: usize, const HD: usize> {
+    pub ii: Linear<I, HD>,
+    pub hi: Linear<HD, HD>,
+    pub if_: Linear<I, HD>,
+    pub hf: Linear<HD, HD>,
+    pub ig: Linear<I, HD>,
+    pub hg: Linear<HD, HD>,
+    pub io: Linear<I, HD>,
+    pub ho: Linear<HD, HD>,
+}
+impl<const I: usize, const HD: usize> LSTMCell<I, HD> {
+    pub fn new(vs: &mut VarStore) -> Self {
+        Self {
+            ii: Linear::new(&mut vs.sub("ii")),
+            hi: Linear::new(&mut vs.sub("hi")),
+            if_: Linear::new(&mut vs.sub("if")),
+            hf: Linear::new(&mut vs.sub("hf")),
+            ig: Linear::new(&mut vs.sub("ig")),
+            hg: Linear::new(&mut vs.sub("hg")),
+            io: Linear::new(&mut vs.sub("io")),
+            ho: Linear::new(&mut vs.sub("ho")),
+        }
+    }
+}
+impl<const I: usize, const HD: usize> RecurrentCell<I, HD> for LSTMCell<I, HD> {
+    type State<const B: usize> = (Nten2d<B, HD, f32>, Nten2d<B, HD, f32>);
+
+    fn zero_state<const B: usize>(&self, vs: &mut VarStore) -> Self::State<B> {
+        (zeros("LSTMCell h0", vs), zeros("LSTMCell c0", vs))
+    }
+    fn step<const B: usize>(&self, input: &Nten2d<B, I, f32>, state: &Self::State<B>) -> Self::State<B> {
+        let (h, c) = state;
+        let i = self.ii.forward(input).add(&self.hi.forward(h)).sigmoid();
+        let f = self.if_.forward(input).add(&self.hf.forward(h)).sigmoid();
+        let g = self.ig.forward(input).add(&self.hg.forward(h)).tanh();
+        let o = self.io.forward(input).add(&self.ho.forward(h)).sigmoid();
+        let fc: Nten2d<B, HD, f32> = f.broadcast_mul(c);
+        let ig: Nten2d<B, HD, f32> = i.broadcast_mul(&g);
+        let c_next = fc.add(&ig);
+        let h_next: Nten2d<B, HD, f32> = o.broadcast_mul(&c_next.tanh());
+        (h_next, c_next)
+    }
+    fn hidden<const B: usize>(state: &Self::State<B>) -> Nten2d<B, HD, f32> {
+        state.0.clone()
+    }
+}
+
+
+// 時刻方向に展開する。input (B, T, I) -> output (B, T, HD)
+pub struct Recurrent<const I: usize, const HD: usize, Cell: RecurrentCell<I, HD>> {
+    pub cell: Cell,
+}
+impl<const I: usize, const HD: usize, Cell: RecurrentCell<I, HD>> Recurrent<I, HD, Cell> {
+    pub fn new(cell: Cell) -> Self {
+        Self { cell }
+    }
+
+    // 各時刻の隠れ状態と最後のstateを返す
+    pub fn forward<const B: usize, const T: usize>(&self, input: &Nten3d<B, T, I, f32>, state: Cell::State<B>) -> (Nten3d<B, T, HD, f32>, Cell::State<B>) {
+        let steps: [Nten2d<B, I, f32>; T] = input.permute::<1, 0, 2, T, B, I>().unstack();
+
+        let mut state = state;
+        let mut hiddens: Vec<Nten2d<B, HD, f32>> = Vec::with_capacity(T);
+        for x in steps.iter() {
+            state = self.cell.step(x, &state);
+            hiddens.push(Cell::hidden(&state));
+        }
+
+        let hiddens: [&Nten2d<B, HD, f32>; T] = std::array::from_fn(|t| &hiddens[t]);
+        let output: Nten3d<T, B, HD, f32> = nten::stack(hiddens);
+        (output.permute::<1, 0, 2, B, T, HD>(), state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{autograd::VarStore, dtype::Shape, nn::Linear, nten::{Nten, Nten3d}, tensor::Tensor, test_utils::{assert_close, forward, gradcheck, sample}};
+
+    use super::{GRUCell, LSTMCell, RNNCell, Recurrent, RecurrentCell};
+
+    // ps[0]がinput (B=2, T=3, I=2)。続いてgateごとに入力側のweight, bias，隠れ状態側のweight, bias
+    fn params(gates: u64) -> Vec<Tensor> {
+        let mut params = vec![sample(Shape::D3(2, 3, 2), 1)];
+        for g in 0..gates {
+            params.push(sample(Shape::D2(2, 3), 10 + g));
+            params.push(sample(Shape::D2(1, 3), 20 + g));
+            params.push(sample(Shape::D2(3, 3), 30 + g));
+            params.push(sample(Shape::D2(1, 3), 40 + g));
+        }
+        params
+    }
+    fn input_linear(ps: &[Nten], gate: usize) -> Linear<2, 3> {
+        Linear { weight: ps[1 + 4 * gate].clone().to_typed2d().unwrap(), bias: ps[2 + 4 * gate].clone().to_typed2d().unwrap() }
+    }
+    fn hidden_linear(ps: &[Nten], gate: usize) -> Linear<3, 3> {
+        Linear { weight: ps[3 + 4 * gate].clone().to_typed2d().unwrap(), bias: ps[4 + 4 * gate].clone().to_typed2d().unwrap() }
+    }
+    fn unroll<Cell: RecurrentCell<2, 3>>(vs: &mut VarStore, ps: &[Nten], cell: Cell) -> Nten {
+        let rnn = Recurrent::new(cell);
+        let input: Nten3d<2, 3, 2, f32> = ps[0].clone().to_typed3d().unwrap();
+        let state = rnn.cell.zero_state::<2>(vs);
+        let (output, _) = rnn.forward(&input, state);
+        output.to_untyped()
+    }
+
+    fn rnn(vs: &mut VarStore, ps: &[Nten]) -> Nten {
+        unroll(vs, ps, RNNCell { ih: input_linear(ps, 0), hh: hidden_linear(ps, 0) })
+    }
+    fn gru(vs: &mut VarStore, ps: &[Nten]) -> Nten {
+        let cell = GRUCell {
+            ir: input_linear(ps, 0), hr: hidden_linear(ps, 0),
+            iz: input_linear(ps, 1), hz: hidden_linear(ps, 1),
+            in_: input_linear(ps, 2), hn: hidden_linear(ps, 2),
+        };
+        unroll(vs, ps, cell)
+    }
+    fn lstm(vs: &mut VarStore, ps: &[Nten]) -> Nten {
+        let cell = LSTMCell {
+            ii: input_linear(ps, 0), hi: hidden_linear(ps, 0),
+            if_: input_linear(ps, 1), hf: hidden_linear(ps, 1),
+            ig: input_linear(ps, 2), hg: hidden_linear(ps, 2),
+            io: input_linear(ps, 3), ho: hidden_linear(ps, 3),
+        };
+        unroll(vs, ps, cell)
+    }
+
+    #[test]
+    fn rnn_forward_matches_formula() {
+        let ps = params(1);
+        let [x, w_ih, b_ih, w_hh, b_hh] = [0, 1, 2, 3, 4].map(|i| ps[i].to_vec_f32());
+        let mut expected = vec![0.0; 2 * 3 * 3];
+        for b in 0..2 {
+            let mut h = [0.0f32; 3];
+            for t in 0..3 {
+                let next: Vec<f32> = (0..3).map(|j| {
+                    let mut sum = b_ih[j] + b_hh[j];
+                    for i in 0..2 {
+                        sum += x[b * 6 + t * 2 + i] * w_ih[i * 3 + j];
+                    }
+                    for k in 0..3 {
+                        sum += h[k] * w_hh[k * 3 + j];
+                    }
+                    sum.tanh()
+                }).collect();
+                h.copy_from_slice(&next);
+                expected[b * 9 + t * 3..b * 9 + t * 3 + 3].copy_from_slice(&h);
+            }
+        }
+        assert_close(&forward(&ps, rnn).to_vec_f32(), &expected, 1e-5);
+    }
+
+    #[test]
+    fn gradcheck_rnn() {
+        gradcheck(&params(1), rnn);
+    }
+
+    #[test]
+    fn gradcheck_gru() {
+        gradcheck(&params(3), gru);
+    }
+
+    #[test]
+    fn gradcheck_lstm() {
+        gradcheck(&params(4), lstm);
+    }
+}
@@ -4,11 +4,30 @@
 use std::fmt;
 
 mod nten_matmul;
-pub use nten_matmul::matmul;
+pub use nten_matmul::{bmm, bmm_t, matmul, matmul_t};
 mod nten;
 pub use nten::Nten;
 mod nten2d;
 pub use nten2d::Nten2d;
+mod nten3d;
+pub use nten3d::Nten3d;
+mod nten4d;
+pub use nten4d::Nten4d;
+mod nten_conv;
+pub use nten_conv::conv2d;
+mod nten_embedding;
+pub use nten_embedding::embedding;
+mod nten_concat;
+pub use nten_concat::{concat_n, stack};
+mod nten_index;
+mod nten_shape;
+mod nten_detach;
+mod nten_attention;
+pub use nten_attention::scaled_dot_product_attention;
+mod nten_norm;
+pub use nten_norm::{batch_norm1d, batch_norm2d, layer_norm};
+mod nten_checkpoint;
+pub use nten_checkpoint::checkpoint;
 
 
 pub use crate::fn_edge::relu;
@@ -25,6 +44,9 @@ pub(crate) fn get_new_nten_id() -> NtenID {
     Since parameters and gradients are managed internally using ID, 
     a model with branche swich, that have different generation order of Parameter Tensors
     occures problem at multi config execution.
+    -> idはプロセス内で一意なだけで順番には意味がない。保存，読み込みはVarStoreの名前（key）で行う
+    制限: Autograd/VarStoreごとの名前空間ではない。ntenはAutogradを持たずに作るので，
+    スレッドやモデルを作る順番によって同じparameterでもidが変わる。idを保存したり，別のプロセスと比べたりしないこと
     */
     let new_id = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
     NtenID(new_id)
@@ -1,8 +1,10 @@
 use std::fmt::Debug;
 
-use crate::{dtype::Shape, fn_edge::FnEdge, tensor::{self, Tensor}};
+use std::marker::PhantomData;
 
-use super::NtenID;
+use crate::{dtype::{Dtype, Shape}, fn_edge::FnEdge, tensor::Tensor};
+
+use super::{Nten2d, Nten3d, NtenID};
 
 // non typed version of Node
 #[derive(Clone)]
@@ -24,5 +26,35 @@ impl Nten {
     pub fn set_grad(&mut self, grad: Tensor) {
         self.grad = Some(grad);
     }
+
+    // grad_graphの結果などを型付きの演算につなぐ
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_typed2d<const R: usize, const C: usize, T: Dtype>(self) -> Result<Nten2d<R, C, T>, String> {
+        if self.shape != Shape::D2(R, C) {
+            return Err(format!("Nten cast error: expected Shape::D2({}, {}), found {}", R, C, self.shape));
+        }
+        Ok(Nten2d {
+            id: self.id,
+            name: self.name,
+            creator: self.creator,
+            val: self.val.map(|val| val.to_typed2d()).transpose()?,
+            grad: self.grad.map(|grad| grad.to_typed2d()).transpose()?,
+            _marker: PhantomData,
+        })
+    }
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_typed3d<const B: usize, const R: usize, const C: usize, T: Dtype>(self) -> Result<Nten3d<B, R, C, T>, String> {
+        if self.shape != Shape::D3(B, R, C) {
+            return Err(format!("Nten cast error: expected Shape::D3({}, {}, {}), found {}", B, R, C, self.shape));
+        }
+        Ok(Nten3d {
+            id: self.id,
+            name: self.name,
+            creator: self.creator,
+            val: self.val.map(|val| val.to_typed3d()).transpose()?,
+            grad: self.grad.map(|grad| grad.to_typed3d()).transpose()?,
+            _marker: PhantomData,
+        })
+    }
 }
 
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use crate::{autograd::VarStore, dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, Add2d, AddBroadcast2d, FnEdge, HumanCreatedFnEdge}, logger::LOGGER, tensor::Tensor2d};
+use crate::{autograd::VarStore, dtype::{is_broadcastable, BinaryOp, Dtype, Shape, UnaryOp}, fn_edge::{get_new_fn_edge_id, shared, Activation, Add2d, AddBroadcast2d, Broadcast, Dropout, FnEdge, HumanCreatedFnEdge}, logger::LOGGER, tensor::Tensor2d};
 
 use super::{get_new_nten_id, relu::Relu2d, Nten, NtenID};
 
@@ -29,6 +29,18 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         }
     }
 
+    // 計算グラフ構築中のntenを作る。valはforwardで入る
+    pub(crate) fn new_from_creator(id: NtenID, name: String, creator: Box<dyn FnEdge>) -> Self {
+        Self {
+            id,
+            name,
+            creator: shared(creator),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
     pub fn name(mut self, name: &str) -> Self {
         self.name = name.to_string();
         self
@@ -72,7 +84,28 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         }
 
         let to_resistor = self.clone();
-        vs.resister_parameter(to_resistor.to_untyped());
+        if let Err(e) = vs.resister_parameter(to_resistor.to_untyped()) {
+            LOGGER.error(format!("{}::as_parameter() >> {}", self.type_name(), e));
+            panic!("");
+        }
+
+        self
+    }
+
+    // 学習しないがモデルの状態として保存する値（BatchNormのrunning statsなど）
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_buffer(self, vs: &mut VarStore) -> Self {
+        if self.val.is_none() {
+            LOGGER.error(format!("{}::as_buffer() >> nten id: {}, name: '{}' self.val is None. \
+            buffer val must have Some.", self.type_name(), self.id, self.name));
+            panic!();
+        }
+
+        let to_resistor = self.clone();
+        if let Err(e) = vs.resister_buffer(to_resistor.to_untyped()) {
+            LOGGER.error(format!("{}::as_buffer() >> {}", self.type_name(), e));
+            panic!("");
+        }
 
         self
     }
@@ -98,7 +131,6 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         let new_id: NtenID = get_new_nten_id();
         let add2d: Add2d<R, C, T> = Add2d::<R, C, T> {
             id: get_new_fn_edge_id(),
-            name: format!("auto created by Add2d<{}, {}, {}>", R, C, T::type_name()),
             sources: vec![self.creator.clone(), other.creator.clone()],
             input1_id: self.id,
             input2_id: other.id,
@@ -108,7 +140,7 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         Self {
             id: new_id,
             name: "add2d".to_string(),
-            creator: Box::new(add2d),
+            creator: shared(Box::new(add2d)),
             val: None,
             grad: None,
             _marker: PhantomData,
@@ -129,14 +161,56 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         Self {
             id: new_id,
             name: "add broadcast".to_string(),
-            creator: Box::new(fn_edge),
+            creator: shared(Box::new(fn_edge)),
             val: None,
             grad: None,
             _marker: PhantomData,
         }
     }
 
-    
+    // NumPy style broadcasting. 出力の形状RO, COはコンパイル時に検査される
+    // ex) column-wise scaling: Nten2d<R, C> * Nten2d<1, C>, outer product: Nten2d<R, 1> * Nten2d<1, C>
+    fn broadcast_op<const R2: usize, const C2: usize, const RO: usize, const CO: usize>
+        (&self, other: &Nten2d<R2, C2, T>, op: BinaryOp) -> Nten2d<RO, CO, T> {
+        const {
+            assert!(is_broadcastable(R, R2, RO) && is_broadcastable(C, C2, CO),
+                "Nten2d::broadcast_op() >> shapes can not be broadcasted to output shape");
+        }
+        let new_id = get_new_nten_id();
+        let fn_edge = Broadcast::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), other.creator.clone()],
+            op,
+            lhs_id: self.id,
+            rhs_id: other.id,
+            output_id: new_id,
+            lhs_shape: Shape::D2(R, C),
+            rhs_shape: Shape::D2(R2, C2),
+            output_shape: Shape::D2(RO, CO),
+            _marker: PhantomData,
+        };
+        Nten2d {
+            id: new_id,
+            name: format!("auto created by Broadcast{}<{}>", op.name(), T::type_name()),
+            creator: shared(Box::new(fn_edge)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+    pub fn broadcast_add<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Nten2d<R2, C2, T>) -> Nten2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Add)
+    }
+    pub fn broadcast_sub<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Nten2d<R2, C2, T>) -> Nten2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Sub)
+    }
+    pub fn broadcast_mul<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Nten2d<R2, C2, T>) -> Nten2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Mul)
+    }
+    pub fn broadcast_div<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Nten2d<R2, C2, T>) -> Nten2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Div)
+    }
+
     pub fn relu(&self) -> Nten2d<R, C, T> {
         let new_id = get_new_nten_id();
         let relu = Relu2d::<R, C, T> {
@@ -151,7 +225,54 @@ impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
         Nten2d {
             id: new_id,
             name: format!("auto created by Relu<{}, {}, {}>", R, C, T::type_name()),
-            creator: Box::new(relu),
+            creator: shared(Box::new(relu)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    fn unary_op(&self, op: UnaryOp) -> Self {
+        let new_id = get_new_nten_id();
+        let activation = Activation::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            shape: Shape::D2(R, C),
+            op,
+            _marker: PhantomData,
+        };
+        Nten2d::new_from_creator(new_id, format!("auto created by {}<{}, {}, {}>", op.name(), R, C, T::type_name()), Box::new(activation))
+    }
+    pub fn sigmoid(&self) -> Self {
+        self.unary_op(UnaryOp::Sigmoid)
+    }
+    pub fn tanh(&self) -> Self {
+        self.unary_op(UnaryOp::Tanh)
+    }
+
+    // 学習時は確率pで0にして残りを1 / (1 - p)倍する。推論時は何もしない
+    pub fn dropout(&self, p: f32) -> Self {
+        if !(0.0..1.0).contains(&p) {
+            LOGGER.error(format!("{}::dropout() >> p must be in [0, 1), found {}", self.type_name(), p));
+            panic!("")
+        }
+        let new_id = get_new_nten_id();
+        let dropout = Dropout::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            mask_cach_id: get_new_nten_id(),
+            shape: Shape::D2(R, C),
+            p,
+            _marker: PhantomData,
+        };
+        Self {
+            id: new_id,
+            name: format!("auto created by Dropout<{}, {}, {}>", R, C, T::type_name()),
+            creator: shared(Box::new(dropout)),
             val: None,
             grad: None,
             _marker: PhantomData,
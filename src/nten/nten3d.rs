@@ -0,0 +1,122 @@
+use std::marker::PhantomData;
+
+use crate::{autograd::VarStore, dtype::{Dtype, Shape, UnaryOp}, fn_edge::{get_new_fn_edge_id, shared, Activation, FnEdge, HumanCreatedFnEdge}, logger::LOGGER, tensor::Tensor3d};
+
+use super::{get_new_nten_id, Nten, NtenID};
+
+// B: batch, R: row, C: column. batch of matrix
+#[derive(Clone)]
+pub struct Nten3d<const B: usize, const R: usize, const C: usize, T> {
+    pub id: NtenID,
+    pub name: String,
+    pub creator: Box<dyn FnEdge>,
+
+    pub(crate) val: Option<Tensor3d<B, R, C, T>>,
+    pub(crate) grad: Option<Tensor3d<B, R, C, T>>,
+
+    pub _marker: PhantomData<T>,
+}
+impl<const B: usize, const R: usize, const C: usize, T: Dtype> Nten3d<B, R, C, T> {
+    pub fn new_from_val(val: Tensor3d<B, R, C, T>) -> Self {
+        Self {
+            id: get_new_nten_id(),
+            name: "no_name".to_string(),
+            creator: Box::new(HumanCreatedFnEdge::new()),
+            val: Some(val),
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    // 計算グラフ構築中のntenを作る。valはforwardで入る
+    pub(crate) fn new_from_creator(id: NtenID, name: String, creator: Box<dyn FnEdge>) -> Self {
+        Self {
+            id,
+            name,
+            creator: shared(creator),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_untyped(self) -> Nten {
+        Nten {
+            id: self.id,
+            name: self.name,
+            creator: self.creator,
+            shape: Shape::D3(B, R, C),
+            val: self.val.map(|val| val.to_untyped()),
+            grad: self.grad.map(|grad| grad.to_untyped()),
+        }
+    }
+
+    pub fn type_name(&self) -> String {
+        format!("Nten3d<{}, {}, {}, {}>", B, R, C, T::type_name())
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_parameter(self, vs: &mut VarStore) -> Self {
+        if self.val.is_none() {
+            LOGGER.error(format!("{}::as_parameter() >> nten id: {}, name: '{}' self.val is None. \
+            parameter val must have Some.", self.type_name(), self.id, self.name));
+            panic!();
+        }
+        if self.grad.is_some() {
+            LOGGER.warning(format!("{}::as_parameter() >> nten id: {}, name: '{}' expected grad is None but has some. \
+                you may forgot clear grad or reuse nten in iteration.", self.type_name(), self.id, self.name));
+        }
+
+        let to_resistor = self.clone();
+        if let Err(e) = vs.resister_parameter(to_resistor.to_untyped()) {
+            LOGGER.error(format!("{}::as_parameter() >> {}", self.type_name(), e));
+            panic!("");
+        }
+
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_input(self, vs: &mut VarStore) -> Self {
+        if self.val.is_none() {
+            LOGGER.error(format!("{}::as_input() >> nten id: {}, name: '{}' self.val is None. \
+            parameter val must have Some.", self.type_name(), self.id, self.name));
+            panic!();
+        }
+        if self.grad.is_some() {
+            LOGGER.warning(format!("{}::as_input() >> nten id: {}, name: '{}' expected grad is None but has some. \
+                you may forgot clear grad or reuse nten in iteration.", self.type_name(), self.id, self.name));
+        }
+
+        let to_resistor = self.clone();
+        vs.resister_input(to_resistor.to_untyped());
+
+        self
+    }
+
+    fn unary_op(&self, op: UnaryOp) -> Self {
+        let new_id = get_new_nten_id();
+        let activation = Activation::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            shape: Shape::D3(B, R, C),
+            op,
+            _marker: PhantomData,
+        };
+        Self::new_from_creator(new_id, format!("auto created by {}<{}, {}, {}, {}>", op.name(), B, R, C, T::type_name()), Box::new(activation))
+    }
+    pub fn sigmoid(&self) -> Self {
+        self.unary_op(UnaryOp::Sigmoid)
+    }
+    pub fn tanh(&self) -> Self {
+        self.unary_op(UnaryOp::Tanh)
+    }
+}
@@ -0,0 +1,250 @@
+use std::marker::PhantomData;
+
+use crate::{autograd::VarStore, backend_cpu::{Pool2dGeometry, PoolWindow}, dtype::{is_broadcastable, BinaryOp, Dtype, Shape, UnaryOp}, fn_edge::{get_new_fn_edge_id, shared, Activation, AvgPool2d, Broadcast, Dropout, FnEdge, HumanCreatedFnEdge, MaxPool2d, Pool2dConfig}, logger::LOGGER, tensor::Tensor4d};
+
+use super::{get_new_nten_id, Nten, NtenID};
+
+// N: batch, C: channel, H: height, W: width
+#[derive(Clone)]
+pub struct Nten4d<const N: usize, const C: usize, const H: usize, const W: usize, T> {
+    pub id: NtenID,
+    pub name: String,
+    pub creator: Box<dyn FnEdge>,
+
+    pub(crate) val: Option<Tensor4d<N, C, H, W, T>>,
+    pub(crate) grad: Option<Tensor4d<N, C, H, W, T>>,
+
+    pub _marker: PhantomData<T>,
+}
+impl<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype> Nten4d<N, C, H, W, T> {
+    pub fn new_from_val(val: Tensor4d<N, C, H, W, T>) -> Self {
+        Self {
+            id: get_new_nten_id(),
+            name: "no_name".to_string(),
+            creator: Box::new(HumanCreatedFnEdge::new()),
+            val: Some(val),
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    // 計算グラフ構築中のntenを作る。valはforwardで入る
+    pub(crate) fn new_from_creator(id: NtenID, name: String, creator: Box<dyn FnEdge>) -> Self {
+        Self {
+            id,
+            name,
+            creator: shared(creator),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    // Nten2dと同じくselfを消費して渡す
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_untyped(self) -> Nten {
+        Nten {
+            id: self.id,
+            name: self.name,
+            creator: self.creator,
+            shape: Shape::D4(N, C, H, W),
+            val: self.val.map(|val| val.to_untyped()),
+            grad: self.grad.map(|grad| grad.to_untyped()),
+        }
+    }
+
+    pub fn type_name(&self) -> String {
+        format!("Nten4d<{}, {}, {}, {}, {}>", N, C, H, W, T::type_name())
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_parameter(self, vs: &mut VarStore) -> Self {
+        if self.val.is_none() {
+            LOGGER.error(format!("{}::as_parameter() >> nten id: {}, name: '{}' self.val is None. \
+            parameter val must have Some.", self.type_name(), self.id, self.name));
+            panic!();
+        }
+        if self.grad.is_some() {
+            LOGGER.warning(format!("{}::as_parameter() >> nten id: {}, name: '{}' expected grad is None but has some. \
+                you may forgot clear grad or reuse nten in iteration.", self.type_name(), self.id, self.name));
+        }
+
+        let to_resistor = self.clone();
+        if let Err(e) = vs.resister_parameter(to_resistor.to_untyped()) {
+            LOGGER.error(format!("{}::as_parameter() >> {}", self.type_name(), e));
+            panic!("");
+        }
+
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn as_input(self, vs: &mut VarStore) -> Self {
+        if self.val.is_none() {
+            LOGGER.error(format!("{}::as_input() >> nten id: {}, name: '{}' self.val is None. \
+            parameter val must have Some.", self.type_name(), self.id, self.name));
+            panic!();
+        }
+        if self.grad.is_some() {
+            LOGGER.warning(format!("{}::as_input() >> nten id: {}, name: '{}' expected grad is None but has some. \
+                you may forgot clear grad or reuse nten in iteration.", self.type_name(), self.id, self.name));
+        }
+
+        let to_resistor = self.clone();
+        vs.resister_input(to_resistor.to_untyped());
+
+        self
+    }
+
+    // NumPy style broadcasting. ex) channel-wise scaling: Nten4d<N, C, H, W> * Nten4d<1, C, 1, 1>
+    fn broadcast_op<const N2: usize, const C2: usize, const H2: usize, const W2: usize>
+        (&self, other: &Nten4d<N2, C2, H2, W2, T>, op: BinaryOp) -> Self {
+        const {
+            assert!(is_broadcastable(N, N2, N) && is_broadcastable(C, C2, C) && is_broadcastable(H, H2, H) && is_broadcastable(W, W2, W),
+                "Nten4d::broadcast_op() >> other can not be broadcasted to self shape");
+        }
+        let new_id = get_new_nten_id();
+        let fn_edge = Broadcast::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), other.creator.clone()],
+            op,
+            lhs_id: self.id,
+            rhs_id: other.id,
+            output_id: new_id,
+            lhs_shape: Shape::D4(N, C, H, W),
+            rhs_shape: Shape::D4(N2, C2, H2, W2),
+            output_shape: Shape::D4(N, C, H, W),
+            _marker: PhantomData,
+        };
+        Nten4d::new_from_creator(new_id, format!("auto created by Broadcast{}<{}>", op.name(), T::type_name()), Box::new(fn_edge))
+    }
+    pub fn broadcast_add<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self, other: &Nten4d<N2, C2, H2, W2, T>) -> Self {
+        self.broadcast_op(other, BinaryOp::Add)
+    }
+    pub fn broadcast_sub<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self, other: &Nten4d<N2, C2, H2, W2, T>) -> Self {
+        self.broadcast_op(other, BinaryOp::Sub)
+    }
+    pub fn broadcast_mul<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self, other: &Nten4d<N2, C2, H2, W2, T>) -> Self {
+        self.broadcast_op(other, BinaryOp::Mul)
+    }
+    pub fn broadcast_div<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self, other: &Nten4d<N2, C2, H2, W2, T>) -> Self {
+        self.broadcast_op(other, BinaryOp::Div)
+    }
+
+    // HO, WOはconfigから決まるので，合っていなければグラフ構築時にエラーにする
+    fn pool_geometry<const HO: usize, const WO: usize>(&self, config: Pool2dConfig, op_type: &str) -> Pool2dGeometry {
+        let out_height = Pool2dGeometry::out_size(H, config.kernel.0, config.stride.0, config.padding.0);
+        let out_width = Pool2dGeometry::out_size(W, config.kernel.1, config.stride.1, config.padding.1);
+        if out_height != Some(HO) || out_width != Some(WO) {
+            LOGGER.error(format!("{}::{}() >> output height and width must be {:?} and {:?} with {:?}", self.type_name(), op_type, out_height, out_width, config));
+            panic!("")
+        }
+        Pool2dGeometry {
+            batch: N,
+            channels: C,
+            in_height: H,
+            in_width: W,
+            out_height: HO,
+            out_width: WO,
+            window: PoolWindow::Fixed { kernel: config.kernel, stride: config.stride, padding: config.padding },
+        }
+    }
+
+    pub fn max_pool2d<const HO: usize, const WO: usize>(&self, config: Pool2dConfig) -> Nten4d<N, C, HO, WO, T> {
+        let new_id = get_new_nten_id();
+        let max_pool = MaxPool2d::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            argmax_cach_id: get_new_nten_id(),
+            geometry: self.pool_geometry::<HO, WO>(config, "max_pool2d"),
+            _marker: PhantomData,
+        };
+        Nten4d::new_from_creator(new_id, format!("auto created by MaxPool2d<{}, {}, {}, {}, {}>", N, C, HO, WO, T::type_name()), Box::new(max_pool))
+    }
+
+    // 平均にはpaddingも含める（pytorchのcount_include_pad=True）
+    pub fn avg_pool2d<const HO: usize, const WO: usize>(&self, config: Pool2dConfig) -> Nten4d<N, C, HO, WO, T> {
+        let geometry = self.pool_geometry::<HO, WO>(config, "avg_pool2d");
+        self.avg_pool2d_with(geometry)
+    }
+
+    // 出力サイズHO, WOに合わせて窓を決める
+    pub fn adaptive_avg_pool2d<const HO: usize, const WO: usize>(&self) -> Nten4d<N, C, HO, WO, T> {
+        const {
+            assert!(HO > 0 && WO > 0 && HO <= H && WO <= W, "Nten4d::adaptive_avg_pool2d() >> output size must be in 1..=input size");
+        }
+        self.avg_pool2d_with(Pool2dGeometry {
+            batch: N,
+            channels: C,
+            in_height: H,
+            in_width: W,
+            out_height: HO,
+            out_width: WO,
+            window: PoolWindow::Adaptive,
+        })
+    }
+
+    pub fn global_avg_pool2d(&self) -> Nten4d<N, C, 1, 1, T> {
+        self.adaptive_avg_pool2d::<1, 1>()
+    }
+
+    fn avg_pool2d_with<const HO: usize, const WO: usize>(&self, geometry: Pool2dGeometry) -> Nten4d<N, C, HO, WO, T> {
+        let new_id = get_new_nten_id();
+        let avg_pool = AvgPool2d::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            geometry,
+            _marker: PhantomData,
+        };
+        Nten4d::new_from_creator(new_id, format!("auto created by AvgPool2d<{}, {}, {}, {}, {}>", N, C, HO, WO, T::type_name()), Box::new(avg_pool))
+    }
+
+    fn unary_op(&self, op: UnaryOp) -> Self {
+        let new_id = get_new_nten_id();
+        let activation = Activation::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            shape: Shape::D4(N, C, H, W),
+            op,
+            _marker: PhantomData,
+        };
+        Self::new_from_creator(new_id, format!("auto created by {}<{}, {}, {}, {}, {}>", op.name(), N, C, H, W, T::type_name()), Box::new(activation))
+    }
+    pub fn sigmoid(&self) -> Self {
+        self.unary_op(UnaryOp::Sigmoid)
+    }
+    pub fn tanh(&self) -> Self {
+        self.unary_op(UnaryOp::Tanh)
+    }
+
+    // 学習時は確率pで0にして残りを1 / (1 - p)倍する。推論時は何もしない
+    pub fn dropout(&self, p: f32) -> Self {
+        if !(0.0..1.0).contains(&p) {
+            LOGGER.error(format!("{}::dropout() >> p must be in [0, 1), found {}", self.type_name(), p));
+            panic!("")
+        }
+        let new_id = get_new_nten_id();
+        let dropout = Dropout::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone()],
+            input_id: self.id,
+            output_id: new_id,
+            mask_cach_id: get_new_nten_id(),
+            shape: Shape::D4(N, C, H, W),
+            p,
+            _marker: PhantomData,
+        };
+        Nten4d::new_from_creator(new_id, format!("auto created by Dropout<{}, {}, {}, {}, {}>", N, C, H, W, T::type_name()), Box::new(dropout))
+    }
+}
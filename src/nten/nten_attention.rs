@@ -0,0 +1,63 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, FnEdge, Softmax}};
+
+use super::{bmm, bmm_t, get_new_nten_id, Nten2d, Nten3d, NtenID};
+
+
+// input, maskは(id, creator)
+fn softmax_edge<T: Dtype>(input: (NtenID, &dyn FnEdge), mask: Option<(NtenID, &dyn FnEdge)>, shape: Shape, scale: f32, output_id: NtenID) -> Softmax<T> {
+    let mut sources = vec![input.1.clone_box()];
+    sources.extend(mask.map(|m| m.1.clone_box()));
+    Softmax::<T> {
+        id: get_new_fn_edge_id(),
+        sources,
+        input_id: input.0,
+        mask_id: mask.map(|m| m.0),
+        output_id,
+        shape,
+        scale,
+        _marker: PhantomData,
+    }
+}
+
+impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
+    // 行ごとのsoftmax
+    pub fn softmax(&self) -> Self {
+        let new_id = get_new_nten_id();
+        let softmax = softmax_edge::<T>((self.id, self.creator.as_ref()), None, Shape::D2(R, C), 1.0, new_id);
+        Self::new_from_creator(new_id, format!("auto created by Softmax<{}, {}, {}>", R, C, T::type_name()), Box::new(softmax))
+    }
+}
+
+impl<const B: usize, const R: usize, const C: usize, T: Dtype> Nten3d<B, R, C, T> {
+    // 最後の軸でのsoftmax
+    pub fn softmax(&self) -> Self {
+        let new_id = get_new_nten_id();
+        let softmax = softmax_edge::<T>((self.id, self.creator.as_ref()), None, Shape::D3(B, R, C), 1.0, new_id);
+        Self::new_from_creator(new_id, format!("auto created by Softmax<{}, {}, {}, {}>", B, R, C, T::type_name()), Box::new(softmax))
+    }
+}
+
+/*
+softmax(q x k^T / sqrt(D)) x v
+mask: falseの位置には注意を向けない。batchにはbroadcastする
+causal maskはTensor2d::<TQ, TK, bool>::new_causal_mask()で作る
+*/
+pub fn scaled_dot_product_attention<const B: usize, const TQ: usize, const TK: usize, const D: usize, const DV: usize, T: Dtype>
+    (q: &Nten3d<B, TQ, D, T>, k: &Nten3d<B, TK, D, T>, v: &Nten3d<B, TK, DV, T>, mask: Option<&Nten2d<TQ, TK, bool>>) -> Nten3d<B, TQ, DV, T> {
+    let scores: Nten3d<B, TQ, TK, T> = bmm_t(q, k, false, true);
+
+    // 1 / sqrt(D)はsoftmaxの中でかける
+    let new_id = get_new_nten_id();
+    let softmax = softmax_edge::<T>(
+        (scores.id, scores.creator.as_ref()),
+        mask.map(|m| (m.id, m.creator.as_ref())),
+        Shape::D3(B, TQ, TK),
+        1.0 / (D as f32).sqrt(),
+        new_id,
+    );
+    let attention: Nten3d<B, TQ, TK, T> = Nten3d::new_from_creator(new_id, format!("auto created by attention Softmax<{}, {}, {}, {}>", B, TQ, TK, T::type_name()), Box::new(softmax));
+
+    bmm(&attention, v)
+}
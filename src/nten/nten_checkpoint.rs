@@ -0,0 +1,83 @@
+use std::{collections::HashSet, sync::{Arc, Mutex}};
+
+use crate::{dtype::Dtype, fn_edge::{get_new_fn_edge_id, Checkpoint, FnEdge, FnEdgeID}, logger::LOGGER};
+
+use super::{Nten2d, NtenID};
+
+
+/*
+segmentの中の値はforward後に捨てられ，backwardで再計算される
+ex) let h = checkpoint(&x, |x| block.forward(x));
+区間はsegmentの中で作られたFnEdgeだけ。外で計算された途中のntenはinputと同じく外から読む
+*/
+pub fn checkpoint<const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype, F>
+    (input: &Nten2d<R, C, T>, segment: F) -> Nten2d<RO, CO, T>
+where F: FnOnce(&Nten2d<R, C, T>) -> Nten2d<RO, CO, T> {
+    // これより後に作られたFnEdgeがsegmentの中のもの
+    let watermark = get_new_fn_edge_id();
+    let output = segment(input);
+    if output.id == input.id {
+        LOGGER.error("checkpoint() >> segment must create new nten. found segment returns its input".to_string());
+        panic!("")
+    }
+    let (fn_edges, outside) = collect_segment(output.creator.as_ref(), input.id, watermark);
+
+    // 区間の中で作られないntenは外から読んでいる
+    let produced: HashSet<NtenID> = fn_edges.iter().flat_map(|fn_edge| fn_edge.outputs()).collect();
+    let mut input_ids = vec![input.id];
+    for id in fn_edges.iter().flat_map(|fn_edge| fn_edge.inputs()) {
+        if !produced.contains(&id) && !input_ids.contains(&id) {
+            input_ids.push(id);
+        }
+    }
+
+    let fn_edge = Checkpoint {
+        id: get_new_fn_edge_id(),
+        sources: [input.creator.clone()].into_iter().chain(outside).collect(),
+        input_ids,
+        output_id: output.id,
+        segment: fn_edges,
+        rng_state: Arc::new(Mutex::new(None)),
+    };
+    // 出力のidはそのままでcreatorだけCheckpointにする
+    Nten2d::new_from_creator(output.id, output.name, Box::new(fn_edge))
+}
+
+type FnEdges = Vec<Box<dyn FnEdge>>;
+
+// outputからinputまでのFnEdgeを帰りがけ順で集める。Autograd::_build_tape()と同じ順
+// 葉（parameterなど，outputsが空のFnEdge）とinputを作ったFnEdgeは区間に入れない
+// watermarkより前に作られたFnEdgeも区間に入れず，Checkpointのsourcesにするため2つ目で返す
+fn collect_segment(output_creator: &dyn FnEdge, input_id: NtenID, watermark: FnEdgeID) -> (FnEdges, FnEdges) {
+    let mut segment = Vec::new();
+    let mut outside: FnEdges = Vec::new();
+    let mut already_seen = HashSet::new();
+    let mut stack: Vec<(Box<dyn FnEdge>, bool)> = vec![(output_creator.clone_box(), false)];
+
+    while let Some((fn_edge, expanded)) = stack.pop() {
+        if expanded {
+            segment.push(fn_edge);
+            continue;
+        }
+        let outputs = fn_edge.outputs();
+        if outputs.is_empty() || outputs.contains(&input_id) {
+            continue;
+        }
+        if fn_edge.get_id().0 < watermark.0 {
+            if !outside.iter().any(|seen| seen.get_id() == fn_edge.get_id()) {
+                outside.push(fn_edge);
+            }
+            continue;
+        }
+        if already_seen.insert(fn_edge.get_id()) {
+            let sources = fn_edge.sources();
+            stack.push((fn_edge, true));
+            for source in sources.into_iter().rev() {
+                if !already_seen.contains(&source.get_id()) {
+                    stack.push((source, false));
+                }
+            }
+        }
+    }
+    (segment, outside)
+}
@@ -0,0 +1,133 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, shared, Concat, FnEdge, Split}};
+
+use super::{get_new_nten_id, Nten2d, Nten3d, Nten4d, NtenID};
+
+// AXISは0 (行方向)か1 (列方向)。形状はコンパイル時に検査される
+
+fn concat_edge<T: Dtype>(inputs: Vec<(NtenID, Box<dyn FnEdge>, Shape)>, output_shape: Shape, outer: usize, axis_lens: Vec<usize>, inner: usize) -> (NtenID, Concat<T>) {
+    let new_id = get_new_nten_id();
+    let concat = Concat::<T> {
+        id: get_new_fn_edge_id(),
+        sources: inputs.iter().map(|(_, creator, _)| creator.clone()).collect(),
+        input_ids: inputs.iter().map(|(id, _, _)| *id).collect(),
+        input_shapes: inputs.iter().map(|(_, _, shape)| *shape).collect(),
+        output_id: new_id,
+        output_shape,
+        outer,
+        axis_lens,
+        inner,
+        _marker: PhantomData,
+    };
+    (new_id, concat)
+}
+
+fn split_edge<T: Dtype>(input_id: NtenID, source: Box<dyn FnEdge>, input_shape: Shape, output_shapes: Vec<Shape>, outer: usize, axis_lens: Vec<usize>, inner: usize) -> (Vec<NtenID>, Split<T>) {
+    let new_ids: Vec<NtenID> = output_shapes.iter().map(|_| get_new_nten_id()).collect();
+    let split = Split::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![source],
+        input_id,
+        input_shape,
+        output_ids: new_ids.clone(),
+        output_shapes,
+        outer,
+        axis_lens,
+        inner,
+        _marker: PhantomData,
+    };
+    (new_ids, split)
+}
+
+// 同じ形状のK個をAXISの方向につなげる
+pub fn concat_n<const AXIS: usize, const K: usize, const R: usize, const C: usize, const RO: usize, const CO: usize, T: Dtype>
+    (inputs: [&Nten2d<R, C, T>; K]) -> Nten2d<RO, CO, T> {
+    const {
+        assert!((AXIS == 0 && RO == K * R && CO == C) || (AXIS == 1 && RO == R && CO == K * C), "nten::concat_n() >> output shape must be (K * R, C) for AXIS 0 or (R, K * C) for AXIS 1");
+    }
+    let (outer, axis_len, inner) = Shape::D2(R, C).around_axis(AXIS).unwrap();
+    let (new_id, concat) = concat_edge::<T>(
+        inputs.iter().map(|input| (input.id, input.creator.clone(), Shape::D2(R, C))).collect(),
+        Shape::D2(RO, CO), outer, vec![axis_len; K], inner);
+    Nten2d::new_from_creator(new_id, format!("auto created by Concat<{}, {}, {}>", RO, CO, T::type_name()), Box::new(concat))
+}
+
+// K個の(R, C)を並べて(K, R, C)にする
+pub fn stack<const K: usize, const R: usize, const C: usize, T: Dtype>(inputs: [&Nten2d<R, C, T>; K]) -> Nten3d<K, R, C, T> {
+    let (new_id, concat) = concat_edge::<T>(
+        inputs.iter().map(|input| (input.id, input.creator.clone(), Shape::D2(R, C))).collect(),
+        Shape::D3(K, R, C), 1, vec![1; K], R * C);
+    Nten3d::new_from_creator(new_id, format!("auto created by Stack<{}, {}, {}, {}>", K, R, C, T::type_name()), Box::new(concat))
+}
+
+impl<const B: usize, const R: usize, const C: usize, T: Dtype> Nten3d<B, R, C, T> {
+    // stackの逆。最初の軸でB個に分ける
+    pub fn unstack(&self) -> [Nten2d<R, C, T>; B] {
+        let (new_ids, split) = split_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C),
+            vec![Shape::D2(R, C); B], 1, vec![1; B], R * C);
+        let creator = shared(Box::new(split));
+        std::array::from_fn(|i| Nten2d::new_from_creator(new_ids[i], format!("auto created by Unstack<{}, {}, {}>", R, C, T::type_name()), creator.clone()))
+    }
+}
+
+impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
+    // AXIS 0: (R + R2, C)，AXIS 1: (R, C + C2)
+    pub fn concat<const AXIS: usize, const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Nten2d<R2, C2, T>) -> Nten2d<RO, CO, T> {
+        const {
+            assert!((AXIS == 0 && C == C2 && RO == R + R2 && CO == C) || (AXIS == 1 && R == R2 && RO == R && CO == C + C2),
+                "Nten2d::concat() >> output shape must be (R + R2, C) for AXIS 0 or (R, C + C2) for AXIS 1");
+        }
+        let (outer, lhs_len, inner) = Shape::D2(R, C).around_axis(AXIS).unwrap();
+        let (_, rhs_len, _) = Shape::D2(R2, C2).around_axis(AXIS).unwrap();
+        let (new_id, concat) = concat_edge::<T>(
+            vec![(self.id, self.creator.clone(), Shape::D2(R, C)), (other.id, other.creator.clone(), Shape::D2(R2, C2))],
+            Shape::D2(RO, CO), outer, vec![lhs_len, rhs_len], inner);
+        Nten2d::new_from_creator(new_id, format!("auto created by Concat<{}, {}, {}>", RO, CO, T::type_name()), Box::new(concat))
+    }
+
+    // concatの逆。AXIS 0: (R1, C)と(R2, C)，AXIS 1: (R, C1)と(R, C2)
+    pub fn split<const AXIS: usize, const R1: usize, const C1: usize, const R2: usize, const C2: usize>(&self) -> (Nten2d<R1, C1, T>, Nten2d<R2, C2, T>) {
+        const {
+            assert!((AXIS == 0 && C1 == C && C2 == C && R1 + R2 == R) || (AXIS == 1 && R1 == R && R2 == R && C1 + C2 == C),
+                "Nten2d::split() >> outputs must be (R1, C) and (R2, C) with R1 + R2 == R for AXIS 0, or (R, C1) and (R, C2) with C1 + C2 == C for AXIS 1");
+        }
+        let (outer, _, inner) = Shape::D2(R, C).around_axis(AXIS).unwrap();
+        let (_, len1, _) = Shape::D2(R1, C1).around_axis(AXIS).unwrap();
+        let (_, len2, _) = Shape::D2(R2, C2).around_axis(AXIS).unwrap();
+        let (new_ids, split) = split_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C),
+            vec![Shape::D2(R1, C1), Shape::D2(R2, C2)], outer, vec![len1, len2], inner);
+        let creator = shared(Box::new(split));
+        (
+            Nten2d::new_from_creator(new_ids[0], format!("auto created by Split<{}, {}, {}>", R1, C1, T::type_name()), creator.clone()),
+            Nten2d::new_from_creator(new_ids[1], format!("auto created by Split<{}, {}, {}>", R2, C2, T::type_name()), creator),
+        )
+    }
+
+    // AXISの方向にK等分する
+    pub fn chunk<const AXIS: usize, const K: usize, const RO: usize, const CO: usize>(&self) -> [Nten2d<RO, CO, T>; K] {
+        const {
+            assert!(K > 0 && ((AXIS == 0 && RO * K == R && CO == C) || (AXIS == 1 && RO == R && CO * K == C)),
+                "Nten2d::chunk() >> outputs must be (R / K, C) for AXIS 0 or (R, C / K) for AXIS 1");
+        }
+        let (outer, _, inner) = Shape::D2(R, C).around_axis(AXIS).unwrap();
+        let (_, len, _) = Shape::D2(RO, CO).around_axis(AXIS).unwrap();
+        let (new_ids, split) = split_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C),
+            vec![Shape::D2(RO, CO); K], outer, vec![len; K], inner);
+        let creator = shared(Box::new(split));
+        std::array::from_fn(|i| Nten2d::new_from_creator(new_ids[i], format!("auto created by Chunk<{}, {}, {}>", RO, CO, T::type_name()), creator.clone()))
+    }
+}
+
+impl<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype> Nten4d<N, C, H, W, T> {
+    // skip connection用。channelの方向につなげる
+    pub fn concat_channels<const C2: usize, const CO: usize>(&self, other: &Nten4d<N, C2, H, W, T>) -> Nten4d<N, CO, H, W, T> {
+        const {
+            assert!(CO == C + C2, "Nten4d::concat_channels() >> output channels must be C + C2");
+        }
+        let (new_id, concat) = concat_edge::<T>(
+            vec![(self.id, self.creator.clone(), Shape::D4(N, C, H, W)), (other.id, other.creator.clone(), Shape::D4(N, C2, H, W))],
+            Shape::D4(N, CO, H, W), N, vec![C, C2], H * W);
+        Nten4d::new_from_creator(new_id, format!("auto created by Concat<{}, {}, {}, {}, {}>", N, CO, H, W, T::type_name()), Box::new(concat))
+    }
+}
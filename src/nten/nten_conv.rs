@@ -0,0 +1,58 @@
+use std::marker::PhantomData;
+
+use crate::{backend_cpu::Conv2dGeometry, dtype::Dtype, fn_edge::{get_new_fn_edge_id, Conv2d, Conv2dConfig}, logger::LOGGER};
+
+use super::{get_new_nten_id, Nten2d, Nten4d};
+
+// input: (N, C, H, W), weight: (CO, C/groups, KH, KW), bias: (1, CO) -> (N, CO, HO, WO)
+// HO, WOはconfigから決まるので，合っていなければグラフ構築時にエラーにする
+pub fn conv2d<
+    const N: usize, const C: usize, const H: usize, const W: usize,
+    const CO: usize, const CG: usize, const KH: usize, const KW: usize,
+    const HO: usize, const WO: usize, T: Dtype>
+    (input: &Nten4d<N, C, H, W, T>, weight: &Nten4d<CO, CG, KH, KW, T>, bias: Option<&Nten2d<1, CO, T>>, config: Conv2dConfig) -> Nten4d<N, CO, HO, WO, T> {
+    let type_name = format!("Conv2d<{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}>", N, C, H, W, CO, CG, KH, KW, HO, WO, T::type_name());
+
+    if config.groups == 0 || !C.is_multiple_of(config.groups) || !CO.is_multiple_of(config.groups) || CG * config.groups != C {
+        LOGGER.error(format!("{}::conv2d() >> groups: {} must divide in_channels: {} and out_channels: {}, and weight must have in_channels / groups: {} channels",
+            type_name, config.groups, C, CO, CG));
+        panic!("")
+    }
+    let out_height = Conv2dGeometry::out_size(H, KH, config.stride.0, config.padding.0, config.dilation.0);
+    let out_width = Conv2dGeometry::out_size(W, KW, config.stride.1, config.padding.1, config.dilation.1);
+    if out_height != Some(HO) || out_width != Some(WO) {
+        LOGGER.error(format!("{}::conv2d() >> output height and width must be {:?} and {:?} with {:?}", type_name, out_height, out_width, config));
+        panic!("")
+    }
+
+    let new_id = get_new_nten_id();
+    let mut sources = vec![input.creator.clone(), weight.creator.clone()];
+    if let Some(bias) = bias {
+        sources.push(bias.creator.clone());
+    }
+    let conv = Conv2d::<T> {
+        id: get_new_fn_edge_id(),
+        sources,
+        input_id: input.id,
+        weight_id: weight.id,
+        bias_id: bias.map(|bias| bias.id),
+        output_id: new_id,
+        geometry: Conv2dGeometry {
+            batch: N,
+            in_channels: C,
+            in_height: H,
+            in_width: W,
+            out_channels: CO,
+            kernel_height: KH,
+            kernel_width: KW,
+            out_height: HO,
+            out_width: WO,
+            stride: config.stride,
+            padding: config.padding,
+            dilation: config.dilation,
+            groups: config.groups,
+        },
+        _marker: PhantomData,
+    };
+    Nten4d::new_from_creator(new_id, format!("auto created by {}", type_name), Box::new(conv))
+}
@@ -0,0 +1,41 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, Detach, FnEdge}};
+
+use super::{get_new_nten_id, Nten2d, Nten3d, Nten4d, NtenID};
+
+// 値はそのままで勾配を止める。ex) straight-through: x.broadcast_add(&q.broadcast_sub(&x).detach())
+
+fn detach_edge<T: Dtype>(input_id: NtenID, source: Box<dyn FnEdge>, shape: Shape) -> (NtenID, Box<dyn FnEdge>) {
+    let new_id = get_new_nten_id();
+    let detach = Detach::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![source],
+        input_id,
+        output_id: new_id,
+        shape,
+        _marker: PhantomData,
+    };
+    (new_id, Box::new(detach))
+}
+
+impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
+    pub fn detach(&self) -> Self {
+        let (new_id, creator) = detach_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C));
+        Self::new_from_creator(new_id, format!("auto created by Detach<{}, {}, {}>", R, C, T::type_name()), creator)
+    }
+}
+
+impl<const B: usize, const R: usize, const C: usize, T: Dtype> Nten3d<B, R, C, T> {
+    pub fn detach(&self) -> Self {
+        let (new_id, creator) = detach_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C));
+        Self::new_from_creator(new_id, format!("auto created by Detach<{}, {}, {}, {}>", B, R, C, T::type_name()), creator)
+    }
+}
+
+impl<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype> Nten4d<N, C, H, W, T> {
+    pub fn detach(&self) -> Self {
+        let (new_id, creator) = detach_edge::<T>(self.id, self.creator.clone(), Shape::D4(N, C, H, W));
+        Self::new_from_creator(new_id, format!("auto created by Detach<{}, {}, {}, {}, {}>", N, C, H, W, T::type_name()), creator)
+    }
+}
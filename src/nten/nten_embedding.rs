@@ -0,0 +1,30 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::Dtype, fn_edge::{get_new_fn_edge_id, shared, Embedding}};
+
+use super::{get_new_nten_id, Nten2d};
+
+// indices: (N, 1) u32, weight: (V, D) -> (N, D)
+// indicesはas_input()でVarStoreに入れておくこと
+pub fn embedding<const N: usize, const V: usize, const D: usize, T: Dtype>(indices: &Nten2d<N, 1, u32>, weight: &Nten2d<V, D, T>) -> Nten2d<N, D, T> {
+    let new_id = get_new_nten_id();
+    let embedding = Embedding::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![indices.creator.clone(), weight.creator.clone()],
+        indices_id: indices.id,
+        weight_id: weight.id,
+        output_id: new_id,
+        num_embeddings: V,
+        embedding_dim: D,
+        num_indices: N,
+        _marker: PhantomData,
+    };
+    Nten2d {
+        id: new_id,
+        name: format!("auto created by Embedding<{}, {}, {}, {}>", N, V, D, T::type_name()),
+        creator: shared(Box::new(embedding)),
+        val: None,
+        grad: None,
+        _marker: PhantomData,
+    }
+}
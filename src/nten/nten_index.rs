@@ -0,0 +1,111 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, shared, Gather, IndexSelect, MaskedFill, ScatterAdd}};
+
+use super::{get_new_nten_id, Nten2d};
+
+// index, maskはas_input()でVarStoreに入れておくこと
+// DIMは0 (行方向)か1 (列方向)。形状はコンパイル時に検査される
+impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
+    // DIM 0: out[i][j] = self[index[i][j]][j], DIM 1: out[i][j] = self[i][index[i][j]]
+    pub fn gather<const DIM: usize, const RI: usize, const CI: usize>(&self, index: &Nten2d<RI, CI, u32>) -> Nten2d<RI, CI, T> {
+        const {
+            assert!((DIM == 0 && CI <= C) || (DIM == 1 && RI <= R), "Nten2d::gather() >> DIM must be 0 or 1 and index must not be larger than self except DIM");
+        }
+        let new_id = get_new_nten_id();
+        let gather = Gather::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), index.creator.clone()],
+            input_id: self.id,
+            index_id: index.id,
+            output_id: new_id,
+            dim: DIM,
+            input_shape: Shape::D2(R, C),
+            _marker: PhantomData,
+        };
+        Nten2d {
+            id: new_id,
+            name: format!("auto created by Gather<{}, {}, {}>", RI, CI, T::type_name()),
+            creator: shared(Box::new(gather)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    // DIM 0: out[index[i][j]][j] += src[i][j], DIM 1: out[i][index[i][j]] += src[i][j]
+    pub fn scatter_add<const DIM: usize, const RI: usize, const CI: usize>(&self, index: &Nten2d<RI, CI, u32>, src: &Nten2d<RI, CI, T>) -> Self {
+        const {
+            assert!((DIM == 0 && CI <= C) || (DIM == 1 && RI <= R), "Nten2d::scatter_add() >> DIM must be 0 or 1 and index must not be larger than self except DIM");
+        }
+        let new_id = get_new_nten_id();
+        let scatter_add = ScatterAdd::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), index.creator.clone(), src.creator.clone()],
+            input_id: self.id,
+            index_id: index.id,
+            src_id: src.id,
+            output_id: new_id,
+            dim: DIM,
+            input_shape: Shape::D2(R, C),
+            _marker: PhantomData,
+        };
+        Self {
+            id: new_id,
+            name: format!("auto created by ScatterAdd<{}, {}, {}>", R, C, T::type_name()),
+            creator: shared(Box::new(scatter_add)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    // DIM 0: (K, C)，DIM 1: (R, K)
+    pub fn index_select<const DIM: usize, const K: usize, const RO: usize, const CO: usize>(&self, indices: &Nten2d<1, K, u32>) -> Nten2d<RO, CO, T> {
+        const {
+            assert!((DIM == 0 && RO == K && CO == C) || (DIM == 1 && RO == R && CO == K), "Nten2d::index_select() >> output shape must be (K, C) for DIM 0 or (R, K) for DIM 1");
+        }
+        let new_id = get_new_nten_id();
+        let index_select = IndexSelect::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), indices.creator.clone()],
+            input_id: self.id,
+            indices_id: indices.id,
+            output_id: new_id,
+            dim: DIM,
+            input_shape: Shape::D2(R, C),
+            _marker: PhantomData,
+        };
+        Nten2d {
+            id: new_id,
+            name: format!("auto created by IndexSelect<{}, {}, {}>", RO, CO, T::type_name()),
+            creator: shared(Box::new(index_select)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+
+    // maskがtrueの要素をvalueにする
+    pub fn masked_fill(&self, mask: &Nten2d<R, C, bool>, value: f32) -> Self {
+        let new_id = get_new_nten_id();
+        let masked_fill = MaskedFill::<T> {
+            id: get_new_fn_edge_id(),
+            sources: vec![self.creator.clone(), mask.creator.clone()],
+            input_id: self.id,
+            mask_id: mask.id,
+            output_id: new_id,
+            shape: Shape::D2(R, C),
+            value,
+            _marker: PhantomData,
+        };
+        Self {
+            id: new_id,
+            name: format!("auto created by MaskedFill<{}, {}, {}>", R, C, T::type_name()),
+            creator: shared(Box::new(masked_fill)),
+            val: None,
+            grad: None,
+            _marker: PhantomData,
+        }
+    }
+}
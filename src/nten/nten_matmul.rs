@@ -1,8 +1,8 @@
 use std::marker::PhantomData;
 
-use crate::{dtype::Dtype, fn_edge::{get_new_fn_edge_id, Matmul}};
+use crate::{dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, shared, BatchedMatmul, FnEdge, Matmul}, logger::LOGGER, tensor};
 
-use super::{get_new_nten_id, Nten2d};
+use super::{get_new_nten_id, Nten2d, Nten3d, NtenID};
 
 pub fn matmul<const N: usize, const M: usize, const O: usize, T: Dtype>
     (lhs: &Nten2d<N, M, T>, rhs: &Nten2d<M, O, T>) -> Nten2d<N, O, T> {
@@ -19,9 +19,66 @@ pub fn matmul<const N: usize, const M: usize, const O: usize, T: Dtype>
     Nten2d {
         id: new_id,
         name: format!("auto created by Matmul<{}, {}, {}, {}>", N, M, O, T::type_name()),
-        creator: Box::new(matmul),
+        creator: shared(Box::new(matmul)),
         val: None,
         grad: None,
         _marker: PhantomData,
     }
-}
\ No newline at end of file
+}
+
+// lhs, rhsは(id, creator, shape)
+fn batched_matmul_edge<T: Dtype>(lhs: (NtenID, &dyn FnEdge, Shape), rhs: (NtenID, &dyn FnEdge, Shape), out_shape: Shape, transpose_lhs: bool, transpose_rhs: bool, output_id: NtenID, op_type: &str) -> BatchedMatmul<T> {
+    match tensor::matmul_geometry(lhs.2, rhs.2, transpose_lhs, transpose_rhs) {
+        Ok((_, shape)) if shape == out_shape => {},
+        Ok((_, shape)) => {
+            LOGGER.error(format!("{}() >> output shape {} is unmatched with result {}", op_type, out_shape, shape));
+            panic!("")
+        },
+        Err(e) => {
+            LOGGER.error(format!("{}() >> {}", op_type, e));
+            panic!("")
+        },
+    }
+    BatchedMatmul::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![lhs.1.clone_box(), rhs.1.clone_box()],
+        lhs_id: lhs.0,
+        rhs_id: rhs.0,
+        output_id,
+        transpose_lhs,
+        transpose_rhs,
+        _marker: PhantomData,
+    }
+}
+
+/*
+op(lhs) x op(rhs)。transposeがtrueの側は保存されている行列の転置を使う
+転置フラグは実行時なので形状は構築時に検査する
+ex) q x k^T : matmul_t::<N, N, _, _, _, _, _>(&q, &k, false, true)
+*/
+pub fn matmul_t<const N: usize, const O: usize, const R1: usize, const C1: usize, const R2: usize, const C2: usize, T: Dtype>
+    (lhs: &Nten2d<R1, C1, T>, rhs: &Nten2d<R2, C2, T>, transpose_lhs: bool, transpose_rhs: bool) -> Nten2d<N, O, T> {
+    let new_id = get_new_nten_id();
+    let edge = batched_matmul_edge::<T>(
+        (lhs.id, lhs.creator.as_ref(), Shape::D2(R1, C1)), (rhs.id, rhs.creator.as_ref(), Shape::D2(R2, C2)), Shape::D2(N, O),
+        transpose_lhs, transpose_rhs, new_id, "matmul_t",
+    );
+    Nten2d::new_from_creator(new_id, format!("auto created by matmul_t<{}, {}, {}>", N, O, T::type_name()), Box::new(edge))
+}
+
+// batchごとのmatmul
+pub fn bmm<const B: usize, const N: usize, const M: usize, const O: usize, T: Dtype>
+    (lhs: &Nten3d<B, N, M, T>, rhs: &Nten3d<B, M, O, T>) -> Nten3d<B, N, O, T> {
+    bmm_t(lhs, rhs, false, false)
+}
+
+// batchごとの op(lhs) x op(rhs)
+pub fn bmm_t<const B: usize, const N: usize, const O: usize, const R1: usize, const C1: usize, const R2: usize, const C2: usize, T: Dtype>
+    (lhs: &Nten3d<B, R1, C1, T>, rhs: &Nten3d<B, R2, C2, T>, transpose_lhs: bool, transpose_rhs: bool) -> Nten3d<B, N, O, T> {
+    let new_id = get_new_nten_id();
+    let edge = batched_matmul_edge::<T>(
+        (lhs.id, lhs.creator.as_ref(), Shape::D3(B, R1, C1)), (rhs.id, rhs.creator.as_ref(), Shape::D3(B, R2, C2)), Shape::D3(B, N, O),
+        transpose_lhs, transpose_rhs, new_id, "bmm_t",
+    );
+    Nten3d::new_from_creator(new_id, format!("auto created by bmm_t<{}, {}, {}, {}>", B, N, O, T::type_name()), Box::new(edge))
+}
@@ -0,0 +1,64 @@
+use std::marker::PhantomData;
+
+use crate::{backend_cpu::NormGeometry, dtype::{Dtype, Shape}, fn_edge::{get_new_fn_edge_id, shared, FnEdge, Normalize, RunningStats}};
+
+use super::{get_new_nten_id, Nten2d, Nten4d, NtenID};
+
+// 正規化のみ。gammaとbetaはnn::BatchNorm1dなどでbroadcastして掛ける
+
+fn normalize_edge<T: Dtype>(input_id: NtenID, source: Box<dyn FnEdge>, shape: Shape, geometry: NormGeometry, eps: f32, running: Option<RunningStats>) -> (NtenID, Normalize<T>) {
+    let new_id = get_new_nten_id();
+    let normalize = Normalize::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![source],
+        input_id,
+        output_id: new_id,
+        x_hat_cach_id: get_new_nten_id(),
+        inv_std_cach_id: get_new_nten_id(),
+        shape,
+        geometry,
+        eps,
+        running,
+        _marker: PhantomData,
+    };
+    (new_id, normalize)
+}
+
+// (B, F)をfeatureごとにbatch方向で正規化する
+pub fn batch_norm1d<const B: usize, const F: usize, T: Dtype>
+    (input: &Nten2d<B, F, T>, running_mean: &Nten2d<1, F, T>, running_var: &Nten2d<1, F, T>, momentum: f32, eps: f32) -> Nten2d<B, F, T> {
+    let geometry = NormGeometry { outer: B, channels: F, inner: 1 };
+    let running = RunningStats { mean_id: running_mean.id, var_id: running_var.id, momentum };
+    let (new_id, normalize) = normalize_edge::<T>(input.id, input.creator.clone(), Shape::D2(B, F), geometry, eps, Some(running));
+    Nten2d {
+        id: new_id,
+        name: format!("auto created by BatchNorm1d<{}, {}, {}>", B, F, T::type_name()),
+        creator: shared(Box::new(normalize)),
+        val: None,
+        grad: None,
+        _marker: PhantomData,
+    }
+}
+
+// (N, C, H, W)をchannelごとにN, H, W方向で正規化する
+pub fn batch_norm2d<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype>
+    (input: &Nten4d<N, C, H, W, T>, running_mean: &Nten2d<1, C, T>, running_var: &Nten2d<1, C, T>, momentum: f32, eps: f32) -> Nten4d<N, C, H, W, T> {
+    let geometry = NormGeometry { outer: N, channels: C, inner: H * W };
+    let running = RunningStats { mean_id: running_mean.id, var_id: running_var.id, momentum };
+    let (new_id, normalize) = normalize_edge::<T>(input.id, input.creator.clone(), Shape::D4(N, C, H, W), geometry, eps, Some(running));
+    Nten4d::new_from_creator(new_id, format!("auto created by BatchNorm2d<{}, {}, {}, {}, {}>", N, C, H, W, T::type_name()), Box::new(normalize))
+}
+
+// (B, F)をsampleごとにfeature方向で正規化する。modeによらず同じ動作
+pub fn layer_norm<const B: usize, const F: usize, T: Dtype>(input: &Nten2d<B, F, T>, eps: f32) -> Nten2d<B, F, T> {
+    let geometry = NormGeometry { outer: 1, channels: B, inner: F };
+    let (new_id, normalize) = normalize_edge::<T>(input.id, input.creator.clone(), Shape::D2(B, F), geometry, eps, None);
+    Nten2d {
+        id: new_id,
+        name: format!("auto created by LayerNorm<{}, {}, {}>", B, F, T::type_name()),
+        creator: shared(Box::new(normalize)),
+        val: None,
+        grad: None,
+        _marker: PhantomData,
+    }
+}
@@ -0,0 +1,183 @@
+use std::marker::PhantomData;
+
+use crate::{dtype::{is_permutation, Dtype, Shape}, fn_edge::{get_new_fn_edge_id, FnEdge, Permute, Reshape}};
+
+use super::{get_new_nten_id, Nten2d, Nten3d, Nten4d, NtenID};
+
+// 要素数や軸の対応はコンパイル時に検査される
+
+fn reshape_edge<T: Dtype>(input_id: NtenID, source: Box<dyn FnEdge>, input_shape: Shape, output_shape: Shape) -> (NtenID, Box<dyn FnEdge>) {
+    let new_id = get_new_nten_id();
+    let reshape = Reshape::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![source],
+        input_id,
+        output_id: new_id,
+        input_shape,
+        output_shape,
+        _marker: PhantomData,
+    };
+    (new_id, Box::new(reshape))
+}
+
+fn permute_edge<T: Dtype>(input_id: NtenID, source: Box<dyn FnEdge>, input_shape: Shape, perm: Vec<usize>) -> (NtenID, Box<dyn FnEdge>) {
+    let new_id = get_new_nten_id();
+    let permute = Permute::<T> {
+        id: get_new_fn_edge_id(),
+        sources: vec![source],
+        input_id,
+        output_id: new_id,
+        input_shape,
+        perm,
+        _marker: PhantomData,
+    };
+    (new_id, Box::new(permute))
+}
+
+
+impl<const R: usize, const C: usize, T: Dtype> Nten2d<R, C, T> {
+    pub fn reshape<const R2: usize, const C2: usize>(&self) -> Nten2d<R2, C2, T> {
+        const {
+            assert!(R * C == R2 * C2, "Nten2d::reshape() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C), Shape::D2(R2, C2));
+        Nten2d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}>", R2, C2, T::type_name()), creator)
+    }
+
+    pub fn reshape_3d<const B2: usize, const R2: usize, const C2: usize>(&self) -> Nten3d<B2, R2, C2, T> {
+        const {
+            assert!(R * C == B2 * R2 * C2, "Nten2d::reshape_3d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C), Shape::D3(B2, R2, C2));
+        Nten3d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}>", B2, R2, C2, T::type_name()), creator)
+    }
+
+    // ex) MNISTの(B, 784)を(B, 1, 28, 28)にしてconvに入れる
+    pub fn reshape_4d<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self) -> Nten4d<N2, C2, H2, W2, T> {
+        const {
+            assert!(R * C == N2 * C2 * H2 * W2, "Nten2d::reshape_4d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C), Shape::D4(N2, C2, H2, W2));
+        Nten4d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}, {}>", N2, C2, H2, W2, T::type_name()), creator)
+    }
+
+    // AXISに長さ1の軸を足す。AXIS 0: (1, R, C), AXIS 1: (R, 1, C), AXIS 2: (R, C, 1)
+    pub fn unsqueeze<const AXIS: usize, const B2: usize, const R2: usize, const C2: usize>(&self) -> Nten3d<B2, R2, C2, T> {
+        const {
+            assert!((AXIS == 0 && B2 == 1 && R2 == R && C2 == C) || (AXIS == 1 && B2 == R && R2 == 1 && C2 == C) || (AXIS == 2 && B2 == R && R2 == C && C2 == 1),
+                "Nten2d::unsqueeze() >> output shape must be self shape with 1 inserted at AXIS");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C), Shape::D3(B2, R2, C2));
+        Nten3d::new_from_creator(new_id, format!("auto created by Unsqueeze<{}, {}, {}, {}>", B2, R2, C2, T::type_name()), creator)
+    }
+
+    pub fn transpose(&self) -> Nten2d<C, R, T> {
+        let (new_id, creator) = permute_edge::<T>(self.id, self.creator.clone(), Shape::D2(R, C), vec![1, 0]);
+        Nten2d::new_from_creator(new_id, format!("auto created by Transpose<{}, {}, {}>", C, R, T::type_name()), creator)
+    }
+}
+
+
+impl<const B: usize, const R: usize, const C: usize, T: Dtype> Nten3d<B, R, C, T> {
+    pub fn reshape<const B2: usize, const R2: usize, const C2: usize>(&self) -> Nten3d<B2, R2, C2, T> {
+        const {
+            assert!(B * R * C == B2 * R2 * C2, "Nten3d::reshape() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C), Shape::D3(B2, R2, C2));
+        Nten3d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}>", B2, R2, C2, T::type_name()), creator)
+    }
+
+    pub fn reshape_2d<const R2: usize, const C2: usize>(&self) -> Nten2d<R2, C2, T> {
+        const {
+            assert!(B * R * C == R2 * C2, "Nten3d::reshape_2d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C), Shape::D2(R2, C2));
+        Nten2d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}>", R2, C2, T::type_name()), creator)
+    }
+
+    pub fn reshape_4d<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self) -> Nten4d<N2, C2, H2, W2, T> {
+        const {
+            assert!(B * R * C == N2 * C2 * H2 * W2, "Nten3d::reshape_4d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C), Shape::D4(N2, C2, H2, W2));
+        Nten4d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}, {}>", N2, C2, H2, W2, T::type_name()), creator)
+    }
+
+    // batch以外をまとめる。(B, R, C) -> (B, R * C)
+    pub fn flatten<const F: usize>(&self) -> Nten2d<B, F, T> {
+        const {
+            assert!(F == R * C, "Nten3d::flatten() >> F must be R * C");
+        }
+        self.reshape_2d()
+    }
+
+    // AXISの長さ1の軸を取り除く
+    pub fn squeeze<const AXIS: usize, const R2: usize, const C2: usize>(&self) -> Nten2d<R2, C2, T> {
+        const {
+            assert!((AXIS == 0 && B == 1 && R2 == R && C2 == C) || (AXIS == 1 && R == 1 && R2 == B && C2 == C) || (AXIS == 2 && C == 1 && R2 == B && C2 == R),
+                "Nten3d::squeeze() >> length of AXIS must be 1 and output shape must be self shape without AXIS");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C), Shape::D2(R2, C2));
+        Nten2d::new_from_creator(new_id, format!("auto created by Squeeze<{}, {}, {}>", R2, C2, T::type_name()), creator)
+    }
+
+    // 出力のi番目の軸は入力のPi番目の軸
+    pub fn permute<const P0: usize, const P1: usize, const P2: usize, const B2: usize, const R2: usize, const C2: usize>(&self) -> Nten3d<B2, R2, C2, T> {
+        const {
+            assert!(is_permutation(&[P0, P1, P2]), "Nten3d::permute() >> P0, P1, P2 must be a permutation of 0, 1, 2");
+            assert!([B, R, C][P0] == B2 && [B, R, C][P1] == R2 && [B, R, C][P2] == C2, "Nten3d::permute() >> output shape is unmatched with permutation");
+        }
+        let (new_id, creator) = permute_edge::<T>(self.id, self.creator.clone(), Shape::D3(B, R, C), vec![P0, P1, P2]);
+        Nten3d::new_from_creator(new_id, format!("auto created by Permute<{}, {}, {}, {}>", B2, R2, C2, T::type_name()), creator)
+    }
+
+    // batchごとに転置する
+    pub fn transpose(&self) -> Nten3d<B, C, R, T> {
+        self.permute::<0, 2, 1, B, C, R>()
+    }
+}
+
+
+impl<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype> Nten4d<N, C, H, W, T> {
+    pub fn reshape<const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self) -> Nten4d<N2, C2, H2, W2, T> {
+        const {
+            assert!(N * C * H * W == N2 * C2 * H2 * W2, "Nten4d::reshape() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D4(N, C, H, W), Shape::D4(N2, C2, H2, W2));
+        Nten4d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}, {}>", N2, C2, H2, W2, T::type_name()), creator)
+    }
+
+    pub fn reshape_2d<const R2: usize, const C2: usize>(&self) -> Nten2d<R2, C2, T> {
+        const {
+            assert!(N * C * H * W == R2 * C2, "Nten4d::reshape_2d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D4(N, C, H, W), Shape::D2(R2, C2));
+        Nten2d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}>", R2, C2, T::type_name()), creator)
+    }
+
+    pub fn reshape_3d<const B2: usize, const R2: usize, const C2: usize>(&self) -> Nten3d<B2, R2, C2, T> {
+        const {
+            assert!(N * C * H * W == B2 * R2 * C2, "Nten4d::reshape_3d() >> number of elements must be same");
+        }
+        let (new_id, creator) = reshape_edge::<T>(self.id, self.creator.clone(), Shape::D4(N, C, H, W), Shape::D3(B2, R2, C2));
+        Nten3d::new_from_creator(new_id, format!("auto created by Reshape<{}, {}, {}, {}>", B2, R2, C2, T::type_name()), creator)
+    }
+
+    // conv, poolの出力をlinearに入れる。(N, C, H, W) -> (N, C * H * W)
+    pub fn flatten<const F: usize>(&self) -> Nten2d<N, F, T> {
+        const {
+            assert!(F == C * H * W, "Nten4d::flatten() >> F must be C * H * W");
+        }
+        self.reshape_2d()
+    }
+
+    // 出力のi番目の軸は入力のPi番目の軸。ex) NCHW -> NHWC: permute::<0, 2, 3, 1, N, H, W, C>()
+    pub fn permute<const P0: usize, const P1: usize, const P2: usize, const P3: usize, const N2: usize, const C2: usize, const H2: usize, const W2: usize>(&self) -> Nten4d<N2, C2, H2, W2, T> {
+        const {
+            assert!(is_permutation(&[P0, P1, P2, P3]), "Nten4d::permute() >> P0, P1, P2, P3 must be a permutation of 0, 1, 2, 3");
+            assert!([N, C, H, W][P0] == N2 && [N, C, H, W][P1] == C2 && [N, C, H, W][P2] == H2 && [N, C, H, W][P3] == W2, "Nten4d::permute() >> output shape is unmatched with permutation");
+        }
+        let (new_id, creator) = permute_edge::<T>(self.id, self.creator.clone(), Shape::D4(N, C, H, W), vec![P0, P1, P2, P3]);
+        Nten4d::new_from_creator(new_id, format!("auto created by Permute<{}, {}, {}, {}, {}>", N2, C2, H2, W2, T::type_name()), creator)
+    }
+}
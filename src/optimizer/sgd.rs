@@ -1,3 +1,5 @@
+use crate::logger::LOGGER;
+
 use super::Optimizer;
 
 
@@ -15,7 +17,18 @@ impl Sgd {
 impl Optimizer for Sgd {
     fn update(&mut self, ctx: &mut crate::autograd::Context) {
         for parameter_id in ctx.varstore.parameter_ids.lock().unwrap().iter() {
-            let grad = ctx.get_grad(parameter_id);
+            // Embeddingのweightなどは勾配のある行だけを更新する
+            if let Some(sparse) = ctx.try_get_sparse_grad(parameter_id) {
+                let val = ctx.get_val(parameter_id);
+                sparse.scatter_into(&mut val.storage.write().unwrap(), -self.learning_rate);
+                continue;
+            }
+            // detachの先などlossにつながっていないparameterは更新しない
+            let Some(grad) = ctx.try_get_grad(parameter_id) else {
+                let name = ctx.varstore.body.lock().unwrap().get(parameter_id).map(|nten| nten.name.clone()).unwrap_or_default();
+                LOGGER.warning(format!("Sgd::update() >> parameter id: {}, name: '{}' has no grad. it is not connected to the loss and is not updated", parameter_id, name));
+                continue
+            };
             let val = ctx.get_val(parameter_id);
 
             // update
@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+
+use colored::Colorize;
+
+use crate::{fn_edge::FnEdge, logger::LOGGER, nten::NtenID};
+
+/*
+テープを並列に実行するときの順番。Autograd::set_parallel()で使う
+テープのindexをwaveに分け，同じwaveのFnEdgeはお互いに依存しないので同時に実行できる
+forward: 入力を作るFnEdgeがすべて前のwaveにある
+backward: 出力を使うFnEdgeがすべて前のwaveにある（forwardの依存を逆にたどる）
+bufferは実行時に書き換わる（BatchNormのrunning stats）ので，同じbufferを使うFnEdgeはテープの順に別のwaveにする
+*/
+
+// tape[start..]のwave。waveの中はテープの順
+pub(crate) fn forward_waves(tape: &[Box<dyn FnEdge>], start: usize, buffer_ids: &HashSet<NtenID>) -> Vec<Vec<usize>> {
+    // ntenのid -> それを作ったFnEdgeのindex
+    let mut producer: HashMap<NtenID, usize> = HashMap::new();
+    // bufferのid -> 最後にそれを使ったFnEdgeのindex
+    let mut last_buffer_user: HashMap<NtenID, usize> = HashMap::new();
+    let mut levels: Vec<usize> = Vec::with_capacity(tape.len() - start);
+
+    for (i, fn_edge) in tape.iter().enumerate().skip(start) {
+        let mut level = 0;
+        for id in fn_edge.inputs() {
+            let mut deps = vec![producer.get(&id).copied()];
+            if buffer_ids.contains(&id) {
+                deps.push(last_buffer_user.insert(id, i));
+            }
+            for dep in deps.into_iter().flatten() {
+                level = level.max(levels[dep - start] + 1);
+            }
+        }
+        levels.push(level);
+        for id in fn_edge.outputs() {
+            producer.insert(id, i);
+        }
+    }
+    let waves = group(&levels, start);
+    LOGGER.debug(format!("{}: {} FnEdges in {} forward waves", "scheduler".cyan(), levels.len(), waves.len()));
+    waves
+}
+
+// テープ全体のwave
+pub(crate) fn backward_waves(tape: &[Box<dyn FnEdge>]) -> Vec<Vec<usize>> {
+    // ntenのid -> それを使うFnEdgeのindex
+    let mut consumers: HashMap<NtenID, Vec<usize>> = HashMap::new();
+    for (i, fn_edge) in tape.iter().enumerate() {
+        for id in fn_edge.inputs() {
+            consumers.entry(id).or_default().push(i);
+        }
+    }
+    let mut levels = vec![0; tape.len()];
+    for i in (0..tape.len()).rev() {
+        for id in tape[i].outputs() {
+            for &consumer in consumers.get(&id).into_iter().flatten() {
+                if consumer > i {
+                    levels[i] = levels[i].max(levels[consumer] + 1);
+                }
+            }
+        }
+    }
+    let waves = group(&levels, 0);
+    LOGGER.debug(format!("{}: {} FnEdges in {} backward waves", "scheduler".cyan(), levels.len(), waves.len()));
+    waves
+}
+
+fn group(levels: &[usize], start: usize) -> Vec<Vec<usize>> {
+    let mut waves: Vec<Vec<usize>> = vec![Vec::new(); levels.iter().max().map_or(0, |max| max + 1)];
+    for (i, level) in levels.iter().enumerate() {
+        waves[*level].push(start + i);
+    }
+    waves
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{autograd::{Autograd, VarStore}, dtype::Shape, fn_edge::FnEdge, nten::{self, Nten, Nten2d}, tensor::{Tensor, Tensor2d}, test_utils::{as_parameters, assert_close, sample, tape_of}};
+
+    use super::{backward_waves, forward_waves};
+
+    // ps: 2つの枝の入力 (4, 3), 共有するweight (3, 5)
+    fn params() -> Vec<Tensor> {
+        vec![sample(Shape::D2(4, 3), 1), sample(Shape::D2(4, 3), 2), sample(Shape::D2(3, 5), 3)]
+    }
+    fn branch(ps: &[Nten], input: usize) -> Nten2d<4, 5, f32> {
+        let x: Nten2d<4, 3, f32> = ps[input].clone().to_typed2d().unwrap();
+        let h: Nten2d<4, 5, f32> = nten::matmul(&x, &ps[2].clone().to_typed2d::<3, 5, f32>().unwrap());
+        h.tanh()
+    }
+    // weightを共有する2つの枝（siamese net）の出力の積
+    fn siamese(ps: &[Nten]) -> Nten {
+        let out: Nten2d<4, 5, f32> = branch(ps, 0).broadcast_mul(&branch(ps, 1));
+        out.to_untyped()
+    }
+    fn wave_of(waves: &[Vec<usize>], i: usize) -> usize {
+        waves.iter().position(|wave| wave.contains(&i)).unwrap()
+    }
+    fn index_of(tape: &[Box<dyn FnEdge>], nten: &Nten2d<4, 5, f32>) -> usize {
+        tape.iter().position(|fn_edge| fn_edge.get_id() == nten.creator.get_id()).unwrap()
+    }
+
+    // 2つの枝は同じwaveで実行され，FnEdgeは入力を作ったFnEdgeより後のwaveにある
+    #[test]
+    fn forward_waves_run_branches_together() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let (left, right) = (branch(&ps, 0), branch(&ps, 1));
+        let out: Nten2d<4, 5, f32> = left.broadcast_mul(&right);
+        let tape = tape_of(&[out.to_untyped()]);
+        let waves = forward_waves(&tape, 0, &HashSet::new());
+
+        assert_eq!(waves.iter().map(|wave| wave.len()).sum::<usize>(), tape.len());
+        assert_eq!(wave_of(&waves, index_of(&tape, &left)), wave_of(&waves, index_of(&tape, &right)));
+        let producer: Vec<_> = tape.iter().map(|fn_edge| fn_edge.outputs()).collect();
+        for (i, fn_edge) in tape.iter().enumerate() {
+            // parameterは葉なので作ったFnEdgeがない
+            for source in fn_edge.inputs().iter().filter_map(|id| producer.iter().position(|outputs| outputs.contains(id))) {
+                assert!(wave_of(&waves, source) < wave_of(&waves, i));
+            }
+        }
+    }
+
+    // 同じbufferを書き換えるFnEdgeは別のwaveに分ける
+    #[test]
+    fn forward_waves_serialize_buffer_users() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let running_mean = Nten2d::new_from_val(Tensor2d::<1, 3, f32>::new_zeros()).name("running_mean").as_buffer(&mut vs);
+        let running_var = Nten2d::new_from_val(Tensor2d::<1, 3, f32>::new_ones()).name("running_var").as_buffer(&mut vs);
+        let norm = |input: usize| -> Nten2d<4, 3, f32> {
+            nten::batch_norm1d(&ps[input].clone().to_typed2d::<4, 3, f32>().unwrap(), &running_mean, &running_var, 0.1, 1e-5)
+        };
+        let (left, right) = (norm(0), norm(1));
+        let out: Nten2d<4, 3, f32> = left.broadcast_add(&right);
+        let tape = tape_of(&[out.to_untyped()]);
+        let buffer_ids = HashSet::from([running_mean.id, running_var.id]);
+        let waves = forward_waves(&tape, 0, &buffer_ids);
+
+        let position = |nten: &Nten2d<4, 3, f32>| tape.iter().position(|fn_edge| fn_edge.get_id() == nten.creator.get_id()).unwrap();
+        assert!(wave_of(&waves, position(&left)) < wave_of(&waves, position(&right)));
+    }
+
+    // FnEdgeは出力を使うFnEdgeより後のwaveでbackwardする
+    #[test]
+    fn backward_waves_follow_consumers() {
+        let mut vs = VarStore::new();
+        let ps = as_parameters(&mut vs, &params());
+        let tape = tape_of(&[siamese(&ps)]);
+        let waves = backward_waves(&tape);
+
+        assert_eq!(waves.iter().map(|wave| wave.len()).sum::<usize>(), tape.len());
+        for (i, fn_edge) in tape.iter().enumerate() {
+            for (j, consumer) in tape.iter().enumerate() {
+                if consumer.inputs().iter().any(|id| fn_edge.outputs().contains(id)) {
+                    assert!(wave_of(&waves, j) < wave_of(&waves, i));
+                }
+            }
+        }
+    }
+
+    // (出力, parameterの勾配)
+    fn run(parallel: bool) -> (Vec<f32>, Vec<Vec<f32>>) {
+        let mut ag = Autograd::new();
+        ag.set_parallel(parallel);
+        let mut vs = ag.get_vs();
+        let ps = as_parameters(&mut vs, &params());
+        let [out] = ag.step_forward([siamese(&ps)]);
+        let ctx = ag.backward_with(&out, sample(out.shape, 7));
+        let grads = ps.iter().map(|p| ctx.get_grad(&p.id).to_vec_f32()).collect();
+        (out.val.unwrap().to_vec_f32(), grads)
+    }
+
+    // 共有するweightへの勾配は2つの枝から同時に足されても同じになる
+    #[test]
+    fn parallel_matches_sequential() {
+        let (out, grads) = run(false);
+        for _ in 0..10 {
+            let (out_parallel, grads_parallel) = run(true);
+            assert_close(&out_parallel, &out, 1e-6);
+            for (g_parallel, g) in grads_parallel.iter().zip(grads.iter()) {
+                assert_close(g_parallel, g, 1e-5);
+            }
+        }
+    }
+}
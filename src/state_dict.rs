@@ -0,0 +1,225 @@
+use std::{collections::HashMap, fs::File, io::{Read, Write}};
+
+use crate::{dtype::Shape, logger::LOGGER, tensor::Tensor};
+
+/*
+VarStore::state_dict()をファイルに保存する。値はf32だけ
+format (little endian):
+    magic "LNTN", 要素数 u32
+    要素ごとに: 名前の長さ u32, 名前 utf8, rank u32, 次元 u64 * rank, 値 f32 * numel
+名前の順に書くので，同じ値なら同じファイルになる
+*/
+
+const MAGIC: &[u8; 4] = b"LNTN";
+
+pub fn save(path: &str, state: &HashMap<String, Tensor>) {
+    let mut keys: Vec<&String> = state.keys().collect();
+    keys.sort();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+    for key in keys {
+        let tensor = &state[key];
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(key.as_bytes());
+        let dims = tensor.shape.dims();
+        buf.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+        for dim in dims {
+            buf.extend_from_slice(&(dim as u64).to_le_bytes());
+        }
+        for x in tensor.to_vec_f32() {
+            buf.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    let result = File::create(path).and_then(|mut file| file.write_all(&buf));
+    if let Err(e) = result {
+        LOGGER.error(format!("state_dict::save() >> cannot write '{}': {}", path, e));
+        panic!("")
+    }
+}
+
+pub fn load(path: &str) -> HashMap<String, Tensor> {
+    let mut buf = Vec::new();
+    let result = File::open(path).and_then(|mut file| file.read_to_end(&mut buf));
+    if let Err(e) = result {
+        LOGGER.error(format!("state_dict::load() >> cannot read '{}': {}", path, e));
+        panic!("")
+    }
+
+    let mut reader = Reader { buf: &buf, offset: 0, path };
+    if reader.take(4) != MAGIC {
+        LOGGER.error(format!("state_dict::load() >> '{}' is not a state dict file", path));
+        panic!("")
+    }
+    let count = reader.u32();
+    let mut state = HashMap::new();
+    for _ in 0..count {
+        let key_len = reader.u32() as usize;
+        let key = String::from_utf8(reader.take(key_len).to_vec()).unwrap_or_else(|_| reader.broken());
+        let rank = reader.u32() as usize;
+        let dims: Vec<usize> = (0..rank).map(|_| reader.u64() as usize).collect();
+        let shape = Shape::from_dims(&dims).unwrap_or_else(|_| reader.broken());
+        let body: Vec<f32> = (0..shape.numel()).map(|_| reader.f32()).collect();
+        state.insert(key.clone(), Tensor::new_from_vec(body, shape).unwrap().name(&key));
+    }
+    state
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    path: &'a str,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        if self.offset + n > self.buf.len() {
+            self.broken()
+        }
+        let bytes = &self.buf[self.offset..self.offset + n];
+        self.offset += n;
+        bytes
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take(8).try_into().unwrap())
+    }
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+    fn broken(&self) -> ! {
+        LOGGER.error(format!("state_dict::load() >> '{}' is broken at byte {}", self.path, self.offset));
+        panic!("")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, thread};
+
+    use crate::{autograd::{Autograd, VarStore}, dtype::Shape, nn::{BatchNorm1d, Linear}, nten::Nten2d, optimizer::{Optimizer, Sgd}, tensor::{Tensor, Tensor2d}, test_utils::{assert_close, sample}};
+
+    // encoder -> batch norm -> relu -> head
+    struct Model {
+        encoder: Linear<3, 4>,
+        norm: BatchNorm1d<4>,
+        head: Linear<4, 2>,
+    }
+    impl Model {
+        fn new(vs: &mut VarStore) -> Self {
+            let encoder = Linear::new(&mut vs.sub("encoder"));
+            let norm = BatchNorm1d::new(&mut vs.sub("norm"));
+            let head = Linear::new(&mut vs.sub("head"));
+            Self { encoder, norm, head }
+        }
+        // ntenのidは作った順に振られるので，new()とは違うidになる
+        fn new_reversed(vs: &mut VarStore) -> Self {
+            let head = Linear::new(&mut vs.sub("head"));
+            let norm = BatchNorm1d::new(&mut vs.sub("norm"));
+            let encoder = Linear::new(&mut vs.sub("encoder"));
+            Self { encoder, norm, head }
+        }
+        fn forward(&self, vs: &mut VarStore) -> Nten2d<4, 2, f32> {
+            let x = Nten2d::new_from_val(sample(Shape::D2(4, 3), 1).to_typed2d().unwrap()).name("input").as_input(vs);
+            self.head.forward(&self.norm.forward(&self.encoder.forward(&x)).relu())
+        }
+    }
+    fn eval_output(autograd: &mut Autograd, vs: &mut VarStore, model: &Model) -> Vec<f32> {
+        autograd.eval();
+        let [y] = autograd.step_forward([model.forward(vs).to_untyped()]);
+        y.val.unwrap().to_vec_f32()
+    }
+
+    // 1 step学習してparameterとrunning statsを初期値から変えてから保存する
+    fn train_and_save(path: &str) -> (Vec<f32>, HashMap<String, Tensor>) {
+        let mut autograd = Autograd::new();
+        let mut vs = autograd.get_vs();
+        let model = Model::new(&mut vs);
+        let [y] = autograd.step_forward([model.forward(&mut vs).to_untyped()]);
+        let ctx = autograd.backward_with(&y, sample(y.shape, 7));
+        Sgd::new(0.1).update(ctx);
+        autograd.zero_grad();
+
+        vs.save(path);
+        (eval_output(&mut autograd, &mut vs, &model), vs.state_dict())
+    }
+    fn load_reversed(path: &str) -> (Vec<f32>, HashMap<String, Tensor>) {
+        let mut autograd = Autograd::new();
+        let mut vs = autograd.get_vs();
+        let model = Model::new_reversed(&mut vs);
+        vs.load(path);
+        (eval_output(&mut autograd, &mut vs, &model), vs.state_dict())
+    }
+
+    // 別のスレッドで逆の順番に作ったモデルにも，名前で読み込める
+    #[test]
+    fn state_dict_round_trips_across_threads_and_order() {
+        let path = std::env::temp_dir().join(format!("lantern_state_dict_{}.lntn", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let saved = {
+            let path = path.clone();
+            thread::spawn(move || train_and_save(&path)).join().unwrap()
+        };
+        let loaded = {
+            let path = path.clone();
+            thread::spawn(move || load_reversed(&path)).join().unwrap()
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        let (output, state) = saved;
+        let (output_loaded, state_loaded) = loaded;
+        assert_close(&output_loaded, &output, 1e-6);
+        let mut keys: Vec<&String> = state.keys().collect();
+        keys.sort();
+        let mut keys_loaded: Vec<&String> = state_loaded.keys().collect();
+        keys_loaded.sort();
+        assert_eq!(keys_loaded, keys);
+        for key in keys {
+            assert_eq!(state_loaded[key].to_vec_f32(), state[key].to_vec_f32(), "{}", key);
+        }
+    }
+
+    // sub()で分けずに同じモジュールを2つ作ると名前がぶつかる
+    #[test]
+    #[should_panic]
+    fn duplicate_name_is_rejected() {
+        let mut vs = VarStore::new();
+        let _first: Linear<3, 4> = Linear::new(&mut vs);
+        let _second: Linear<3, 4> = Linear::new(&mut vs);
+    }
+
+    // VarStoreに直接登録するときはErrで返す
+    #[test]
+    fn duplicate_name_is_err() {
+        let mut vs = VarStore::new();
+        let first = Nten2d::new_from_val(Tensor2d::<2, 2, f32>::new_zeros()).name("w").to_untyped();
+        let second = Nten2d::new_from_val(Tensor2d::<2, 2, f32>::new_zeros()).name("w").to_untyped();
+        assert!(vs.resister_parameter(first).is_ok());
+        assert!(vs.resister_parameter(second).is_err());
+    }
+
+    // 名前のないparameterはいくつあってもぶつからない。state_dictには入らない
+    #[test]
+    fn unnamed_parameters_have_no_key() {
+        let mut vs = VarStore::new();
+        let first = Nten2d::new_from_val(Tensor2d::<2, 2, f32>::new_zeros()).as_parameter(&mut vs);
+        let second = Nten2d::new_from_val(Tensor2d::<2, 2, f32>::new_zeros()).as_parameter(&mut vs);
+        assert!(vs.key_of(&first.id).is_none());
+        assert!(vs.key_of(&second.id).is_none());
+        assert!(vs.state_dict().is_empty());
+    }
+
+    #[test]
+    fn sub_scopes_names() {
+        let vs = VarStore::new();
+        let first: Linear<3, 4> = Linear::new(&mut vs.sub("first"));
+        let second: Linear<3, 4> = Linear::new(&mut vs.sub("first").sub("second"));
+        assert_eq!(vs.key_of(&first.weight.id).unwrap(), "first/Linear weight");
+        assert_eq!(vs.key_of(&second.bias.id).unwrap(), "first/second/Linear bias");
+        assert_eq!(vs.sub("first").key_of(&second.bias.id).unwrap(), "second/Linear bias");
+    }
+}
@@ -1,8 +1,8 @@
 use std::{marker::PhantomData, sync::{Arc, RwLock}};
 
-use crate::dtype::{Dtype, Shape};
+use crate::{backend_cpu::MatmulGeometry, dtype::{Dtype, Shape}};
 
-use super::{Storage, Tensor2d};
+use super::{Storage, Tensor, Tensor2d};
 
 pub fn matmul<const N: usize, const M: usize, const O: usize, T: Dtype>(
     lhs: &Tensor2d<N, M, T>,
@@ -18,3 +18,50 @@ pub fn matmul<const N: usize, const M: usize, const O: usize, T: Dtype>(
         _marker: PhantomData,
     }
 }
+
+
+/*
+op(lhs) x op(rhs)。opは転置フラグに従う。転置は作らずカーネルで読み替える
+D2 x D2，またはbatchの揃ったD3 x D3
+*/
+pub fn matmul_geometry(lhs_shape: Shape, rhs_shape: Shape, transpose_lhs: bool, transpose_rhs: bool) -> Result<(MatmulGeometry, Shape), String> {
+    let (batch, (lr, lc), (rr, rc)) = match (lhs_shape, rhs_shape) {
+        (Shape::D2(lr, lc), Shape::D2(rr, rc)) => (None, (lr, lc), (rr, rc)),
+        (Shape::D3(lb, lr, lc), Shape::D3(rb, rr, rc)) if lb == rb => (Some(lb), (lr, lc), (rr, rc)),
+        _ => return Err(format!("matmul_geometry() >> unsupported pair {} and {}", lhs_shape, rhs_shape)),
+    };
+    let (n, m) = if transpose_lhs { (lc, lr) } else { (lr, lc) };
+    let (m2, o) = if transpose_rhs { (rc, rr) } else { (rr, rc) };
+    if m != m2 {
+        return Err(format!("matmul_geometry() >> inner dims are unmatched. lhs: {} (transpose: {}), rhs: {} (transpose: {})", lhs_shape, transpose_lhs, rhs_shape, transpose_rhs));
+    }
+    let geometry = MatmulGeometry { batch: batch.unwrap_or(1), n, m, o, transpose_lhs, transpose_rhs };
+    let out_shape = match batch {
+        Some(b) => Shape::D3(b, n, o),
+        None => Shape::D2(n, o),
+    };
+    Ok((geometry, out_shape))
+}
+
+pub fn matmul_t(lhs: &Tensor, rhs: &Tensor, transpose_lhs: bool, transpose_rhs: bool) -> Result<Tensor, String> {
+    let (geometry, shape) = matmul_geometry(lhs.shape, rhs.shape, transpose_lhs, transpose_rhs)?;
+    let result_storage = Storage::matmul_t(&lhs.storage(), &rhs.storage(), &geometry);
+    Ok(Tensor {
+        name: format!("{} x {}", lhs.name, rhs.name),
+        shape,
+        storage: Arc::new(RwLock::new(result_storage)),
+    })
+}
+
+// returns (dlhs, drhs)。形状はlhs, rhsと同じ
+pub fn matmul_t_backward(dout: &Tensor, lhs: &Tensor, rhs: &Tensor, transpose_lhs: bool, transpose_rhs: bool) -> Result<(Tensor, Tensor), String> {
+    let (geometry, shape) = matmul_geometry(lhs.shape, rhs.shape, transpose_lhs, transpose_rhs)?;
+    if dout.shape != shape {
+        return Err(format!("matmul_t_backward() >> dout {} is unmatched with output {}", dout.shape, shape));
+    }
+    let (dlhs, drhs) = Storage::matmul_t_backward(&dout.storage(), &lhs.storage(), &rhs.storage(), &geometry);
+    Ok((
+        Tensor { name: "dlhs".to_string(), shape: lhs.shape, storage: Arc::new(RwLock::new(dlhs)) },
+        Tensor { name: "drhs".to_string(), shape: rhs.shape, storage: Arc::new(RwLock::new(drhs)) },
+    ))
+}
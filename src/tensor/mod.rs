@@ -1,10 +1,14 @@
 
 mod matmul;
-pub use matmul::matmul;
+pub use matmul::{matmul, matmul_geometry, matmul_t, matmul_t_backward};
 mod storage;
 pub use storage::*;
 mod tensor2d;
 pub use tensor2d::Tensor2d;
+mod tensor3d;
+pub use tensor3d::Tensor3d;
+mod tensor4d;
+pub use tensor4d::Tensor4d;
 mod tensor;
 pub use tensor::Tensor;
 
@@ -2,7 +2,9 @@
 
 use std::{fmt::Debug, sync::{Arc, RwLock}};
 
-use crate::{backend_cpu::{RawBool, RawDense}, dtype::Shape, logger::LOGGER};
+use rand::Rng;
+
+use crate::{backend_cpu::{Conv2dGeometry, CrossEntropyGeometry, LinearGeometry, MatmulGeometry, NormGeometry, Pool2dGeometry, RawBool, RawDense}, dtype::{BinaryOp, PointwiseLoss, Reduction, Shape, UnaryOp}, logger::LOGGER};
 
 use std::ops::{Add, Sub, Div, Mul, Rem, AddAssign, SubAssign, DivAssign, MulAssign, RemAssign};
 
@@ -22,6 +24,8 @@ pub enum Storage {
 
     // candle have: u8, u32, i64, bf16, f16, f32, f64
     Densef32(RawDense<f32>), // we shold not use T here not to use generic parameter
+    // index such as argmax of MaxPool2d
+    Denseu32(RawDense<u32>),
     // Sparse32(..),
     // Gpu32(..),
 }
@@ -33,10 +37,18 @@ impl Storage {
     pub fn new_bools(body: Vec<u8>, len: usize) -> Arc<RwLock<Self>> {
         Arc::new(RwLock::new(Self::DenseBool(RawBool { body, len })))
     }
+    pub fn new_u32(body: Vec<u32>) -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(Self::Denseu32(RawDense { body })))
+    }
 
     // for count parameter num in GraphBuilder's parameter: Vec<RawData>
     pub fn parameter_num(&self) -> usize {
-        todo!()
+        match self {
+            Self::None => 0,
+            Self::DenseBool(raw) => raw.len,
+            Self::Densef32(raw) => raw.body.len(),
+            Self::Denseu32(raw) => raw.body.len(),
+        }
     }
 
     pub fn info(&self) -> &str {
@@ -44,6 +56,7 @@ impl Storage {
             Self::None => "RawData::None",
             Self::DenseBool(_) => "RawData::DenseBool",
             Self::Densef32(_) => "RawData::Densef32",
+            Self::Denseu32(_) => "RawData::Denseu32",
         }
     }
 
@@ -61,6 +74,500 @@ impl Storage {
         }
     }
 
+    // NumPy style broadcasting version of Add, Sub, Mul, Div, Rem
+    pub fn broadcast_op(op: BinaryOp, lhs: &Self, lhs_shape: Shape, rhs: &Self, rhs_shape: Shape) -> Self {
+        match (lhs, rhs) {
+            (Storage::Densef32(lhs_dense), Storage::Densef32(rhs_dense)) => {
+                Storage::Densef32(RawDense::broadcast_op(op, lhs_dense, lhs_shape, rhs_dense, rhs_shape))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::broadcast_op() >> {} not supported for lhs: {}, rhs: {}", op.name(), lhs.info(), rhs.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (dlhs, drhs) which have lhs_shape and rhs_shape
+    pub fn broadcast_op_backward(op: BinaryOp, dout: &Self, lhs: &Self, lhs_shape: Shape, rhs: &Self, rhs_shape: Shape) -> (Self, Self) {
+        match (dout, lhs, rhs) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(lhs_dense), Storage::Densef32(rhs_dense)) => {
+                let (dlhs, drhs) = RawDense::broadcast_op_backward(op, dout_dense, lhs_dense, lhs_shape, rhs_dense, rhs_shape);
+                (Storage::Densef32(dlhs), Storage::Densef32(drhs))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::broadcast_op_backward() >> {} not supported for dout: {}, lhs: {}, rhs: {}", op.name(), dout.info(), lhs.info(), rhs.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn conv2d(input: &Self, weight: &Self, bias: Option<&Self>, geometry: &Conv2dGeometry) -> Self {
+        match (input, weight, bias) {
+            (Storage::Densef32(input_dense), Storage::Densef32(weight_dense), None) => {
+                Storage::Densef32(RawDense::conv2d(input_dense, weight_dense, None, geometry))
+            }
+            (Storage::Densef32(input_dense), Storage::Densef32(weight_dense), Some(Storage::Densef32(bias_dense))) => {
+                Storage::Densef32(RawDense::conv2d(input_dense, weight_dense, Some(bias_dense), geometry))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::conv2d() >> invalid pair. input: {}, weight: {}", input.info(), weight.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (dinput, dweight, dbias)
+    pub fn conv2d_backward(dout: &Self, input: &Self, weight: &Self, geometry: &Conv2dGeometry) -> (Self, Self, Self) {
+        match (dout, input, weight) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(input_dense), Storage::Densef32(weight_dense)) => {
+                let (dinput, dweight, dbias) = RawDense::conv2d_backward(dout_dense, input_dense, weight_dense, geometry);
+                (Storage::Densef32(dinput), Storage::Densef32(dweight), Storage::Densef32(dbias))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::conv2d_backward() >> invalid pair. dout: {}, input: {}, weight: {}", dout.info(), input.info(), weight.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (output, argmax)
+    pub fn max_pool2d(&self, geometry: &Pool2dGeometry) -> (Self, Self) {
+        match self {
+            Storage::Densef32(dense) => {
+                let (output, argmax) = dense.max_pool2d(geometry);
+                (Storage::Densef32(output), Storage::Denseu32(argmax))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::max_pool2d() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn max_pool2d_backward(dout: &Self, argmax: &Self, geometry: &Pool2dGeometry) -> Self {
+        match (dout, argmax) {
+            (Storage::Densef32(dout_dense), Storage::Denseu32(argmax_dense)) => {
+                Storage::Densef32(RawDense::max_pool2d_backward(dout_dense, argmax_dense, geometry))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::max_pool2d_backward() >> invalid pair. dout: {}, argmax: {}", dout.info(), argmax.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn avg_pool2d(&self, geometry: &Pool2dGeometry) -> Self {
+        match self {
+            Storage::Densef32(dense) => Storage::Densef32(dense.avg_pool2d(geometry)),
+            _ => {
+                LOGGER.error(format!("Storage::avg_pool2d() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn avg_pool2d_backward(dout: &Self, geometry: &Pool2dGeometry) -> Self {
+        match dout {
+            Storage::Densef32(dense) => Storage::Densef32(RawDense::avg_pool2d_backward(dense, geometry)),
+            _ => {
+                LOGGER.error(format!("Storage::avg_pool2d_backward() >> not supported for {}", dout.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (mean, var) of each channel
+    pub fn channel_mean_var(&self, geometry: &NormGeometry) -> (Self, Self) {
+        match self {
+            Storage::Densef32(dense) => {
+                let (mean, var) = dense.channel_mean_var(geometry);
+                (Storage::Densef32(mean), Storage::Densef32(var))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::channel_mean_var() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (x_hat, inv_std)
+    pub fn normalize(&self, geometry: &NormGeometry, mean: &Self, var: &Self, eps: f32) -> (Self, Self) {
+        match (self, mean, var) {
+            (Storage::Densef32(dense), Storage::Densef32(mean_dense), Storage::Densef32(var_dense)) => {
+                let (x_hat, inv_std) = dense.normalize(geometry, mean_dense, var_dense, eps);
+                (Storage::Densef32(x_hat), Storage::Densef32(inv_std))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::normalize() >> invalid pair. self: {}, mean: {}, var: {}", self.info(), mean.info(), var.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn normalize_backward(dx_hat: &Self, x_hat: &Self, inv_std: &Self, geometry: &NormGeometry, batch_stats: bool) -> Self {
+        match (dx_hat, x_hat, inv_std) {
+            (Storage::Densef32(dx_hat_dense), Storage::Densef32(x_hat_dense), Storage::Densef32(inv_std_dense)) => {
+                Storage::Densef32(RawDense::normalize_backward(dx_hat_dense, x_hat_dense, inv_std_dense, geometry, batch_stats))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::normalize_backward() >> invalid pair. dx_hat: {}, x_hat: {}, inv_std: {}", dx_hat.info(), x_hat.info(), inv_std.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn momentum_update(&self, batch: &Self, momentum: f32, scale: f32) -> Self {
+        match (self, batch) {
+            (Storage::Densef32(dense), Storage::Densef32(batch_dense)) => {
+                Storage::Densef32(dense.momentum_update(batch_dense, momentum, scale))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::momentum_update() >> invalid pair. self: {}, batch: {}", self.info(), batch.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn new_bernoulli_mask<R: Rng>(len: usize, p: f32, rng: &mut R) -> Self {
+        Storage::DenseBool(RawBool::new_bernoulli(len, p, rng))
+    }
+
+    // maskがtrueの要素は0，それ以外はscale倍する
+    pub fn masked_scale(&self, mask: &Self, scale: f32) -> Self {
+        match (self, mask) {
+            (Storage::Densef32(dense), Storage::DenseBool(mask_bool)) => {
+                Storage::Densef32(dense.masked_scale(mask_bool, scale))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::masked_scale() >> invalid pair. self: {}, mask: {}", self.info(), mask.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn gather_rows(&self, cols: usize, indices: &Self) -> Self {
+        match (self, indices) {
+            (Storage::Densef32(dense), Storage::Denseu32(indices_dense)) => {
+                Storage::Densef32(dense.gather_rows(cols, indices_dense))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::gather_rows() >> invalid pair. self: {}, indices: {}", self.info(), indices.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // inplace
+    pub fn scatter_add_rows(&mut self, cols: usize, indices: &Self, src: &Self) {
+        match (&mut *self, indices, src) {
+            (Storage::Densef32(dense), Storage::Denseu32(indices_dense), Storage::Densef32(src_dense)) => {
+                dense.scatter_add_rows(cols, indices_dense, src_dense);
+            }
+            _ => {
+                LOGGER.error(format!("Storage::scatter_add_rows() >> invalid pair. self: {}, indices: {}, src: {}", self.info(), indices.info(), src.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn gather2d(&self, shape: (usize, usize), dim: usize, index: &Self, index_shape: (usize, usize)) -> Self {
+        match (self, index) {
+            (Storage::Densef32(dense), Storage::Denseu32(index_dense)) => {
+                Storage::Densef32(dense.gather2d(shape, dim, index_dense, index_shape))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::gather2d() >> invalid pair. self: {}, index: {}", self.info(), index.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // inplace
+    pub fn scatter_add2d(&mut self, shape: (usize, usize), dim: usize, index: &Self, index_shape: (usize, usize), src: &Self) {
+        match (&mut *self, index, src) {
+            (Storage::Densef32(dense), Storage::Denseu32(index_dense), Storage::Densef32(src_dense)) => {
+                dense.scatter_add2d(shape, dim, index_dense, index_shape, src_dense);
+            }
+            _ => {
+                LOGGER.error(format!("Storage::scatter_add2d() >> invalid pair. self: {}, index: {}, src: {}", self.info(), index.info(), src.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn index_select2d(&self, shape: (usize, usize), dim: usize, indices: &Self) -> Self {
+        match (self, indices) {
+            (Storage::Densef32(dense), Storage::Denseu32(indices_dense)) => {
+                Storage::Densef32(dense.index_select2d(shape, dim, indices_dense))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::index_select2d() >> invalid pair. self: {}, indices: {}", self.info(), indices.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // inplace
+    pub fn index_add2d(&mut self, shape: (usize, usize), dim: usize, indices: &Self, src: &Self) {
+        match (&mut *self, indices, src) {
+            (Storage::Densef32(dense), Storage::Denseu32(indices_dense), Storage::Densef32(src_dense)) => {
+                dense.index_add2d(shape, dim, indices_dense, src_dense);
+            }
+            _ => {
+                LOGGER.error(format!("Storage::index_add2d() >> invalid pair. self: {}, indices: {}, src: {}", self.info(), indices.info(), src.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn masked_select(&self, mask: &Self) -> Self {
+        match (self, mask) {
+            (Storage::Densef32(dense), Storage::DenseBool(mask_bool)) => {
+                Storage::Densef32(dense.masked_select(mask_bool))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::masked_select() >> invalid pair. self: {}, mask: {}", self.info(), mask.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn masked_fill(&self, mask: &Self, value: f32) -> Self {
+        match (self, mask) {
+            (Storage::Densef32(dense), Storage::DenseBool(mask_bool)) => {
+                Storage::Densef32(dense.masked_fill(mask_bool, value))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::masked_fill() >> invalid pair. self: {}, mask: {}", self.info(), mask.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn concat(inputs: &[&Self], outer: usize, axis_lens: &[usize], inner: usize) -> Self {
+        let mut dense_inputs = Vec::with_capacity(inputs.len());
+        for input in inputs.iter() {
+            match input {
+                Storage::Densef32(dense) => dense_inputs.push(dense),
+                _ => {
+                    LOGGER.error(format!("Storage::concat() >> not supported for {}", input.info()));
+                    panic!("")
+                },
+            }
+        }
+        Storage::Densef32(RawDense::concat(&dense_inputs, outer, axis_lens, inner))
+    }
+
+    pub fn split(&self, outer: usize, axis_lens: &[usize], inner: usize) -> Vec<Self> {
+        match self {
+            Storage::Densef32(dense) => {
+                dense.split(outer, axis_lens, inner).into_iter().map(Storage::Densef32).collect()
+            }
+            _ => {
+                LOGGER.error(format!("Storage::split() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn permute(&self, shape: Shape, perm: &[usize]) -> Self {
+        match self {
+            Storage::Densef32(dense) => Storage::Densef32(dense.permute(&shape.dims(), perm)),
+            _ => {
+                LOGGER.error(format!("Storage::permute() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn matmul_t(lhs: &Self, rhs: &Self, geometry: &MatmulGeometry) -> Self {
+        match (lhs, rhs) {
+            (Storage::Densef32(lhs_dense), Storage::Densef32(rhs_dense)) => {
+                Storage::Densef32(RawDense::matmul_t(lhs_dense, rhs_dense, geometry))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::matmul_t() >> invalid pair. lhs: {}, rhs: {}", lhs.info(), rhs.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (dlhs, drhs)
+    pub fn matmul_t_backward(dout: &Self, lhs: &Self, rhs: &Self, geometry: &MatmulGeometry) -> (Self, Self) {
+        match (dout, lhs, rhs) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(lhs_dense), Storage::Densef32(rhs_dense)) => {
+                let (dlhs, drhs) = RawDense::matmul_t_backward(dout_dense, lhs_dense, rhs_dense, geometry);
+                (Storage::Densef32(dlhs), Storage::Densef32(drhs))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::matmul_t_backward() >> invalid pair. dout: {}, lhs: {}, rhs: {}", dout.info(), lhs.info(), rhs.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn fused_linear(input: &Self, weight: &Self, bias: &Self, geometry: &LinearGeometry) -> Self {
+        match (input, weight, bias) {
+            (Storage::Densef32(input_dense), Storage::Densef32(weight_dense), Storage::Densef32(bias_dense)) => {
+                Storage::Densef32(RawDense::fused_linear(input_dense, weight_dense, bias_dense, geometry))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::fused_linear() >> invalid set. input: {}, weight: {}, bias: {}", input.info(), weight.info(), bias.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (dinput, dweight, dbias)
+    pub fn fused_linear_backward(dout: &Self, input: &Self, weight: &Self, output: &Self, geometry: &LinearGeometry) -> (Self, Self, Self) {
+        match (dout, input, weight, output) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(input_dense), Storage::Densef32(weight_dense), Storage::Densef32(output_dense)) => {
+                let (dinput, dweight, dbias) = RawDense::fused_linear_backward(dout_dense, input_dense, weight_dense, output_dense, geometry);
+                (Storage::Densef32(dinput), Storage::Densef32(dweight), Storage::Densef32(dbias))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::fused_linear_backward() >> invalid set. dout: {}, input: {}, weight: {}, output: {}", dout.info(), input.info(), weight.info(), output.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn softmax(&self, cols: usize, scale: f32, mask: Option<&Self>) -> Self {
+        match (self, mask) {
+            (Storage::Densef32(dense), None) => {
+                Storage::Densef32(dense.softmax(cols, scale, None))
+            }
+            (Storage::Densef32(dense), Some(Storage::DenseBool(mask_bool))) => {
+                Storage::Densef32(dense.softmax(cols, scale, Some(mask_bool)))
+            }
+            (_, Some(mask)) => {
+                LOGGER.error(format!("Storage::softmax() >> invalid pair. self: {}, mask: {}", self.info(), mask.info()));
+                panic!("")
+            },
+            _ => {
+                LOGGER.error(format!("Storage::softmax() >> Storage type expection. self is {}", self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn softmax_backward(dout: &Self, output: &Self, cols: usize, scale: f32) -> Self {
+        match (dout, output) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(output_dense)) => {
+                Storage::Densef32(RawDense::softmax_backward(dout_dense, output_dense, cols, scale))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::softmax_backward() >> invalid pair. dout: {}, output: {}", dout.info(), output.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn unary_op(&self, op: UnaryOp) -> Self {
+        match self {
+            Storage::Densef32(dense) => Storage::Densef32(dense.unary_op(op)),
+            _ => {
+                LOGGER.error(format!("Storage::unary_op() >> {} is not supported for {}", op.name(), self.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn unary_op_backward(op: UnaryOp, dout: &Self, output: &Self) -> Self {
+        match (dout, output) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(output_dense)) => {
+                Storage::Densef32(RawDense::unary_op_backward(op, dout_dense, output_dense))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::unary_op_backward() >> invalid pair. dout: {}, output: {}", dout.info(), output.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn pointwise_loss(&self, target: &Self, loss: PointwiseLoss, reduction: Reduction) -> Self {
+        match (self, target) {
+            (Storage::Densef32(dense), Storage::Densef32(target_dense)) => {
+                Storage::Densef32(dense.pointwise_loss(target_dense, loss, reduction))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::pointwise_loss() >> invalid pair. self: {}, target: {}", self.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn pointwise_loss_backward(dout: &Self, predict: &Self, target: &Self, loss: PointwiseLoss, reduction: Reduction) -> Self {
+        match (dout, predict, target) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(predict_dense), Storage::Densef32(target_dense)) => {
+                Storage::Densef32(RawDense::pointwise_loss_backward(dout_dense, predict_dense, target_dense, loss, reduction))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::pointwise_loss_backward() >> invalid pair. dout: {}, predict: {}, target: {}", dout.info(), predict.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn nll_loss(&self, cols: usize, target: &Self, reduction: Reduction) -> Self {
+        match (self, target) {
+            (Storage::Densef32(dense), Storage::Denseu32(target_dense)) => {
+                Storage::Densef32(dense.nll_loss(cols, target_dense, reduction))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::nll_loss() >> invalid pair. self: {}, target: {}", self.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn nll_loss_backward(dout: &Self, shape: (usize, usize), target: &Self, reduction: Reduction) -> Self {
+        match (dout, target) {
+            (Storage::Densef32(dout_dense), Storage::Denseu32(target_dense)) => {
+                Storage::Densef32(RawDense::nll_loss_backward(dout_dense, shape, target_dense, reduction))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::nll_loss_backward() >> invalid pair. dout: {}, target: {}", dout.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    // returns (loss, softmax)
+    pub fn cross_entropy(&self, target: &Self, weight: Option<&[f32]>, geometry: &CrossEntropyGeometry) -> (Self, Self) {
+        match (self, target) {
+            (Storage::Densef32(dense), Storage::Denseu32(target_dense)) => {
+                let (loss, softmax) = dense.cross_entropy(target_dense, weight, geometry);
+                (Storage::Densef32(loss), Storage::Densef32(softmax))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::cross_entropy() >> invalid pair. self: {}, target: {}", self.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn cross_entropy_backward(dout: &Self, softmax: &Self, target: &Self, weight: Option<&[f32]>, geometry: &CrossEntropyGeometry) -> Self {
+        match (dout, softmax, target) {
+            (Storage::Densef32(dout_dense), Storage::Densef32(softmax_dense), Storage::Denseu32(target_dense)) => {
+                Storage::Densef32(RawDense::cross_entropy_backward(dout_dense, softmax_dense, target_dense, weight, geometry))
+            }
+            _ => {
+                LOGGER.error(format!("Storage::cross_entropy_backward() >> invalid pair. dout: {}, softmax: {}, target: {}", dout.info(), softmax.info(), target.info()));
+                panic!("")
+            },
+        }
+    }
+
+    pub fn sum_to_shape(&self, from_shape: Shape, to_shape: Shape) -> Self {
+        match self {
+            Storage::Densef32(dense) => Storage::Densef32(dense.sum_to_shape(from_shape, to_shape)),
+            _ => {
+                LOGGER.error(format!("Storage::sum_to_shape() >> not supported for {}", self.info()));
+                panic!("")
+            },
+        }
+    }
 }
 impl Debug for Storage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -68,23 +575,43 @@ impl Debug for Storage {
             Self::None => write!(f, "RawData::None"),
             Self::DenseBool(arg0) => f.debug_tuple("RawData::DenseBool").field(arg0).finish(),
             Self::Densef32(arg0) => f.debug_tuple("RawData::Densef32").field(arg0).finish(),
+            Self::Denseu32(arg0) => f.debug_tuple("RawData::Denseu32").field(arg0).finish(),
         }
     }
 }
 
 
 // Add, Sub, Div, Mul, Rem, AddAssign, SubAssigh, DivAssign, RemAssign
+// 非代入の演算は同じ長さどうしだけ。broadcastはshapeを知っているNten/Tensor側からbroadcast_op()を呼ぶ
+fn elementwise(op: BinaryOp, lhs: &Storage, rhs: &Storage) -> Storage {
+    if lhs.parameter_num() != rhs.parameter_num() {
+        LOGGER.error(format!("Storage::{}() >> length mismatch. lhs: {} has {} but rhs: {} has {}",
+            op.name(), lhs.info(), lhs.parameter_num(), rhs.info(), rhs.parameter_num()));
+        panic!("")
+    }
+    match (lhs, rhs) {
+        (Storage::Densef32(lhs), Storage::Densef32(rhs)) => {
+            let output = match op {
+                BinaryOp::Add => lhs + rhs,
+                BinaryOp::Sub => lhs - rhs,
+                BinaryOp::Mul => lhs * rhs,
+                BinaryOp::Div => lhs / rhs,
+                BinaryOp::Rem => lhs % rhs,
+            };
+            Storage::Densef32(output)
+        },
+        (lhs, rhs) => {
+            LOGGER.error(format!("Storage::{}() >> not supported for lhs: {}, rhs: {}", op.name(), lhs.info(), rhs.info()));
+            panic!("")
+        },
+    }
+}
+
 impl<'a> Add for &'a Storage {
     type Output = Storage;
 
     fn add(self, rhs: Self) -> Self::Output {
-        use Storage::*;
-        match (self, rhs) {
-            (Densef32(lhs), Densef32(rhs)) => {
-                return Densef32(lhs + rhs);
-            },
-            (lhs,  rhs) => panic!("Add not supported for lhs: '{}' and rhs: '{}'", lhs.info(), rhs.info()),
-        }
+        elementwise(BinaryOp::Add, self, rhs)
     }
 }
 
@@ -92,11 +619,7 @@ impl<'a> Sub for &'a Storage {
     type Output = Storage;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        use Storage::*;
-        match (self, rhs) {
-            (Densef32(lhs), Densef32(rhs)) => Densef32(lhs - rhs),
-            (lhs, rhs) => panic!("Sub not supported for lhs: '{}' and rhs: '{}'", lhs.info(), rhs.info()),
-        }
+        elementwise(BinaryOp::Sub, self, rhs)
     }
 }
 
@@ -104,11 +627,7 @@ impl<'a> Div for &'a Storage {
     type Output = Storage;
 
     fn div(self, rhs: Self) -> Self::Output {
-        use Storage::*;
-        match (self, rhs) {
-            (Densef32(lhs), Densef32(rhs)) => Densef32(lhs / rhs),
-            (lhs, rhs) => panic!("Div not supported for lhs: '{}' and rhs: '{}'", lhs.info(), rhs.info()),
-        }
+        elementwise(BinaryOp::Div, self, rhs)
     }
 }
 
@@ -116,11 +635,7 @@ impl<'a> Mul for &'a Storage {
     type Output = Storage;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        use Storage::*;
-        match (self, rhs) {
-            (Densef32(lhs), Densef32(rhs)) => Densef32(lhs * rhs),
-            (lhs, rhs) => panic!("Mul not supported for lhs: '{}' and rhs: '{}'", lhs.info(), rhs.info()),
-        }
+        elementwise(BinaryOp::Mul, self, rhs)
     }
 }
 
@@ -128,11 +643,7 @@ impl<'a> Rem for &'a Storage {
     type Output = Storage;
 
     fn rem(self, rhs: Self) -> Self::Output {
-        use Storage::*;
-        match (self, rhs) {
-            (Densef32(lhs), Densef32(rhs)) => Densef32(lhs % rhs),
-            (lhs, rhs) => panic!("Rem not supported for lhs: '{}' and rhs: '{}'", lhs.info(), rhs.info()),
-        }
+        elementwise(BinaryOp::Rem, self, rhs)
     }
 }
 
@@ -2,9 +2,9 @@ use std::{fmt::Debug, marker::PhantomData, sync::{Arc, RwLock, RwLockReadGuard,
 
 use colored::Colorize;
 
-use crate::{backend_cpu::RawDense, dtype::{Dtype, Shape}, logger::LOGGER, main};
+use crate::{backend_cpu::{buffer_pool, RawDense}, dtype::{BinaryOp, Dtype, Shape}, logger::LOGGER};
 
-use super::{storage, Storage, Tensor2d};
+use super::{Storage, Tensor2d, Tensor3d, Tensor4d};
 
 #[derive(Clone, Debug)]
 pub struct Tensor {
@@ -23,32 +23,24 @@ impl Tensor {
     }
 
     pub fn new_ones<T: Dtype>(shape: Shape) -> Self {
-        match shape {
-            Shape::D1(n) => Self {
-                name: "ones".to_string(),
-                shape,
-                storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: vec![1.0_f32;n] })))
-            },
-            Shape::D2(n, m) => Self {
-                name: "ones".to_string(),
-                shape,
-                storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: vec![1.0_f32;n * m] })))
-            }
+        Self {
+            name: "ones".to_string(),
+            shape,
+            storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: vec![1.0_f32; shape.numel()] })))
+        }
+    }
+
+    pub fn new_zeros<T: Dtype>(shape: Shape) -> Self {
+        Self {
+            name: "zeros".to_string(),
+            shape,
+            storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: vec![0.0_f32; shape.numel()] })))
         }
     }
 
     pub fn new_from_vec(data: Vec<f32>, shape: Shape) -> Result<Self, ()> {
-        match shape {
-            Shape::D1(n) => {
-                if data.len() != n {
-                    return Err(());
-                }
-            },
-            Shape::D2(r, c) => {
-                if data.len() != r * c {
-                    return Err(());
-                }
-            }
+        if data.len() != shape.numel() {
+            return Err(());
         }
         
         Ok(Self {
@@ -67,6 +59,15 @@ impl Tensor {
     }
     */
 
+    // 他にstorageを持っているTensorがなければbodyをbuffer poolに返す
+    pub fn recycle(self) {
+        if let Ok(storage) = Arc::try_unwrap(self.storage) {
+            if let Storage::Densef32(raw) = storage.into_inner().unwrap() {
+                buffer_pool::recycle(raw.body);
+            }
+        }
+    }
+
     // これはArc内部を書き換えるので注意！
     pub fn override_value(&self, new_value: Self) {
         let mut write = self.storage.write().unwrap();
@@ -101,6 +102,31 @@ impl Tensor {
         }
     }
 
+    // row majorの値を取り出す
+    pub fn to_vec_f32(&self) -> Vec<f32> {
+        match &*self.storage() {
+            Storage::Densef32(raw) => raw.body.clone(),
+            _ => {
+                LOGGER.error(format!("{}::{}() >> Storage type expection. {} is not supported",
+                    "Tensor".green(), "to_vec_f32".yellow(),
+                    self.storage().info()));
+                panic!("")
+            }
+        }
+    }
+    // class indexなど
+    pub fn to_vec_u32(&self) -> Vec<u32> {
+        match &*self.storage() {
+            Storage::Denseu32(raw) => raw.body.clone(),
+            _ => {
+                LOGGER.error(format!("{}::{}() >> Storage type expection. {} is not supported",
+                    "Tensor".green(), "to_vec_u32".yellow(),
+                    self.storage().info()));
+                panic!("")
+            }
+        }
+    }
+
     pub fn to_typed2d<const R: usize, const C: usize, T: Dtype>(&self) -> Result<Tensor2d<R, C, T>, String> {
         if let Shape::D2(r, c) = self.shape {
             if r == R && c == C {
@@ -117,6 +143,30 @@ impl Tensor {
         }
     }
 
+    pub fn to_typed3d<const B: usize, const R: usize, const C: usize, T: Dtype>(&self) -> Result<Tensor3d<B, R, C, T>, String> {
+        if self.shape == Shape::D3(B, R, C) {
+            Ok(Tensor3d::<B, R, C, T> {
+                name: self.name.clone(),
+                storage: self.storage.clone(),
+                _marker: PhantomData,
+            })
+        } else {
+            Err(format!("RawTensor cast error: expected Shape::D3({}, {}, {}), found {}", B, R, C, self.shape.to_string()))
+        }
+    }
+
+    pub fn to_typed4d<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype>(&self) -> Result<Tensor4d<N, C, H, W, T>, String> {
+        if self.shape == Shape::D4(N, C, H, W) {
+            Ok(Tensor4d::<N, C, H, W, T> {
+                name: self.name.clone(),
+                storage: self.storage.clone(),
+                _marker: PhantomData,
+            })
+        } else {
+            Err(format!("RawTensor cast error: expected Shape::D4({}, {}, {}, {}), found {}", N, C, H, W, self.shape.to_string()))
+        }
+    }
+
     pub fn name(mut self, name: &str) -> Self {
         self.name = name.to_string();
         self
@@ -133,6 +183,164 @@ impl Tensor {
         })
     }
 
+    // NumPy style broadcasting. output shape is decided by Shape::broadcast()
+    pub fn broadcast_op(&self, other: &Self, op: BinaryOp) -> Result<Self, String> {
+        let shape = self.shape.broadcast(&other.shape)?;
+        Ok(Self {
+            name: format!("broadcast {}", op.name()),
+            shape,
+            storage: Arc::new(RwLock::new(Storage::broadcast_op(op, &self.storage(), self.shape, &other.storage(), other.shape))),
+        })
+    }
+
+    // broadcastで伸ばされた軸を足し合わせてshapeに戻す
+    pub fn sum_to_shape(&self, shape: Shape) -> Self {
+        Self {
+            name: "sum_to_shape".to_string(),
+            shape,
+            storage: Arc::new(RwLock::new(self.storage().sum_to_shape(self.shape, shape))),
+        }
+    }
+
+    // 要素数が同じなら形状だけ変える。storageは共有する
+    pub fn reshape(&self, shape: Shape) -> Result<Self, String> {
+        if self.shape.numel() != shape.numel() {
+            return Err(format!("Tensor::reshape() >> can not reshape {} to {}", self.shape, shape));
+        }
+        Ok(Self {
+            name: self.name.clone(),
+            shape,
+            storage: self.storage.clone(),
+        })
+    }
+
+    // 出力のi番目の軸は入力のperm[i]番目の軸
+    pub fn permute(&self, perm: &[usize]) -> Result<Self, String> {
+        let dims = self.shape.dims();
+        if perm.len() != dims.len() {
+            return Err(format!("Tensor::permute() >> perm {:?} is unmatched with {}", perm, self.shape));
+        }
+        let out_dims: Vec<usize> = perm.iter().map(|p| dims.get(*p).copied().ok_or(format!("Tensor::permute() >> axis {} is out of range for {}", p, self.shape))).collect::<Result<_, _>>()?;
+        Ok(Self {
+            name: "permuted".to_string(),
+            shape: Shape::from_dims(&out_dims)?,
+            storage: Arc::new(RwLock::new(self.storage().permute(self.shape, perm))),
+        })
+    }
+
+    fn shape2d(&self, op_type: &str) -> Result<(usize, usize), String> {
+        match self.shape {
+            Shape::D2(rows, cols) => Ok((rows, cols)),
+            _ => Err(format!("Tensor::{}() >> only Shape::D2 is supported, found {}", op_type, self.shape)),
+        }
+    }
+    fn check_dim(dim: usize, op_type: &str) -> Result<(), String> {
+        if dim > 1 {
+            return Err(format!("Tensor::{}() >> dim must be 0 or 1, found {}", op_type, dim));
+        }
+        Ok(())
+    }
+
+    /*
+    indexはu32のTensor。index自体の範囲外はpanicする
+    dim 0: out[i][j] = self[index[i][j]][j]
+    dim 1: out[i][j] = self[i][index[i][j]]
+    */
+    pub fn gather(&self, dim: usize, index: &Self) -> Result<Self, String> {
+        Self::check_dim(dim, "gather")?;
+        let shape = self.shape2d("gather")?;
+        let index_shape = index.shape2d("gather")?;
+        // dim以外の軸はindexの方が大きくてはいけない
+        if (dim == 0 && index_shape.1 > shape.1) || (dim == 1 && index_shape.0 > shape.0) {
+            return Err(format!("Tensor::gather() >> index shape {} is too large for {} at dim {}", index.shape, self.shape, dim));
+        }
+        Ok(Self {
+            name: "gather".to_string(),
+            shape: index.shape,
+            storage: Arc::new(RwLock::new(self.storage().gather2d(shape, dim, &index.storage(), index_shape))),
+        })
+    }
+
+    // gatherの逆。selfのコピーにsrcを足す
+    // dim 0: out[index[i][j]][j] += src[i][j]
+    // dim 1: out[i][index[i][j]] += src[i][j]
+    pub fn scatter_add(&self, dim: usize, index: &Self, src: &Self) -> Result<Self, String> {
+        Self::check_dim(dim, "scatter_add")?;
+        let shape = self.shape2d("scatter_add")?;
+        let index_shape = index.shape2d("scatter_add")?;
+        if (dim == 0 && index_shape.1 > shape.1) || (dim == 1 && index_shape.0 > shape.0) {
+            return Err(format!("Tensor::scatter_add() >> index shape {} is too large for {} at dim {}", index.shape, self.shape, dim));
+        }
+        if src.shape != index.shape {
+            return Err(format!("Tensor::scatter_add() >> src shape {} must be same as index shape {}", src.shape, index.shape));
+        }
+        let mut storage = self.storage().clone();
+        storage.scatter_add2d(shape, dim, &index.storage(), index_shape, &src.storage());
+        Ok(Self {
+            name: "scatter_add".to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(storage)),
+        })
+    }
+
+    // dim 0: indicesの行を並べる，dim 1: indicesの列を並べる
+    pub fn index_select(&self, dim: usize, indices: &Self) -> Result<Self, String> {
+        Self::check_dim(dim, "index_select")?;
+        let (rows, cols) = self.shape2d("index_select")?;
+        let k = indices.shape.numel();
+        Ok(Self {
+            name: "index_select".to_string(),
+            shape: if dim == 0 { Shape::D2(k, cols) } else { Shape::D2(rows, k) },
+            storage: Arc::new(RwLock::new(self.storage().index_select2d((rows, cols), dim, &indices.storage()))),
+        })
+    }
+
+    // index_selectの逆。selfのコピーのindicesの行（列）にsrcを足す
+    pub fn index_add(&self, dim: usize, indices: &Self, src: &Self) -> Result<Self, String> {
+        Self::check_dim(dim, "index_add")?;
+        let (rows, cols) = self.shape2d("index_add")?;
+        let k = indices.shape.numel();
+        let expected = if dim == 0 { Shape::D2(k, cols) } else { Shape::D2(rows, k) };
+        if src.shape != expected {
+            return Err(format!("Tensor::index_add() >> src shape must be {}, found {}", expected, src.shape));
+        }
+        let mut storage = self.storage().clone();
+        storage.index_add2d((rows, cols), dim, &indices.storage(), &src.storage());
+        Ok(Self {
+            name: "index_add".to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(storage)),
+        })
+    }
+
+    // 長さがmaskで決まるので結果はShape::D1
+    pub fn masked_select(&self, mask: &Self) -> Result<Self, String> {
+        if self.shape != mask.shape {
+            return Err(format!("Tensor::masked_select() >> mask shape {} must be same as {}", mask.shape, self.shape));
+        }
+        let storage = self.storage().masked_select(&mask.storage());
+        let len = match &storage {
+            Storage::Densef32(dense) => dense.body.len(),
+            _ => unreachable!(),
+        };
+        Ok(Self {
+            name: "masked_select".to_string(),
+            shape: Shape::D1(len),
+            storage: Arc::new(RwLock::new(storage)),
+        })
+    }
+
+    pub fn masked_fill(&self, mask: &Self, value: f32) -> Result<Self, String> {
+        if self.shape != mask.shape {
+            return Err(format!("Tensor::masked_fill() >> mask shape {} must be same as {}", mask.shape, self.shape));
+        }
+        Ok(Self {
+            name: "masked_fill".to_string(),
+            shape: self.shape,
+            storage: Arc::new(RwLock::new(self.storage().masked_fill(&mask.storage(), value))),
+        })
+    }
+
     pub fn add_batch(&self) -> Self {
         // &*はRwLockReadGuard<'_, T>を&Tにしている
         let (body, col_num) = match &*self.storage() {
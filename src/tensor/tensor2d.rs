@@ -1,6 +1,6 @@
 use std::{fmt::{format, Debug}, marker::PhantomData, sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}};
 
-use crate::{backend_cpu::{RawBool, RawDense}, dtype::{Dtype, Shape}, logger::LOGGER};
+use crate::{backend_cpu::{RawBool, RawDense}, dtype::{is_broadcastable, BinaryOp, Dtype, Shape}, logger::LOGGER};
 
 use super::{Tensor, Storage};
 
@@ -96,45 +96,51 @@ impl<const R: usize, const C: usize, T: Dtype> Tensor2d<R, C, T> {
         }
     }
 
+    // biasの足し算。broadcast_add()と同じ
     pub fn add_broadcast(&self, bias: &Tensor2d<1, C, T>) -> Self {
-        // &*はRwLockReadGuard<'_, T>を&Tにしている
-        match (&*self.storage(), &*bias.storage()) {
-            (Storage::Densef32(raw), Storage::Densef32(raw_bias)) => {
-                let mut new = raw.clone();
-                new.add_broadcast(raw_bias, Shape::D2(R, C));
-                Self {
-                    name: "add_broadcast".to_string(),
-                    storage: Storage::new_f32(new.body),
-                    _marker: PhantomData,
-                }
-            },
-            (_, _) => {
-                LOGGER.error(format!("Tensor2d<{}, {}, {}> >> unsupported Storage type. lhs name: '{}', rhs name: '{}'", R, C, T::type_name(), self.name, bias.name));
-                panic!()
-            },
+        self.broadcast_add(bias).name("add_broadcast")
+    }
+
+    // NumPy style broadcasting. 出力の形状RO, COはコンパイル時に検査される
+    // ex) Tensor2d<R, 1> * Tensor2d<1, C> -> Tensor2d<R, C>
+    pub fn broadcast_op<const R2: usize, const C2: usize, const RO: usize, const CO: usize>
+        (&self, other: &Tensor2d<R2, C2, T>, op: BinaryOp) -> Tensor2d<RO, CO, T> {
+        const {
+            assert!(is_broadcastable(R, R2, RO) && is_broadcastable(C, C2, CO),
+                "Tensor2d::broadcast_op() >> shapes can not be broadcasted to output shape");
+        }
+        Tensor2d::<RO, CO, T> {
+            name: format!("broadcast {}", op.name()),
+            storage: Arc::new(RwLock::new(Storage::broadcast_op(op, &self.storage(), Shape::D2(R, C), &other.storage(), Shape::D2(R2, C2)))),
+            _marker: PhantomData,
         }
     }
+    pub fn broadcast_add<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Tensor2d<R2, C2, T>) -> Tensor2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Add)
+    }
+    pub fn broadcast_sub<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Tensor2d<R2, C2, T>) -> Tensor2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Sub)
+    }
+    pub fn broadcast_mul<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Tensor2d<R2, C2, T>) -> Tensor2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Mul)
+    }
+    pub fn broadcast_div<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Tensor2d<R2, C2, T>) -> Tensor2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Div)
+    }
+    pub fn broadcast_rem<const R2: usize, const C2: usize, const RO: usize, const CO: usize>(&self, other: &Tensor2d<R2, C2, T>) -> Tensor2d<RO, CO, T> {
+        self.broadcast_op(other, BinaryOp::Rem)
+    }
 
+    // broadcast_add()の逆。batch方向に足し合わせる
     pub fn sum_batch(&self) -> Tensor2d<1, C, T> {
-        // &*はRwLockReadGuard<'_, T>を&Tにしている
-        match &*self.storage() {
-            Storage::Densef32(raw) => {
-                let new = raw.sum_batch(Shape::D2(R, C));
-                Tensor2d::<1, C, T> {
-                    name: "sum_batch".to_string(),
-                    storage: Storage::new_f32(new.body),
-                    _marker: PhantomData,
-                }
-            },
-            _ => {
-                LOGGER.error(format!("{}::{}() >> Storage type expection. {} is not supported",
-                    Self::type_name().green(), "sum_batch".yellow(),
-                    self.storage().info()));
-                panic!("")
-            }
+        Tensor2d::<1, C, T> {
+            name: "sum_batch".to_string(),
+            storage: Arc::new(RwLock::new(self.storage().sum_to_shape(Shape::D2(R, C), Shape::D2(1, C)))),
+            _marker: PhantomData,
         }
     }
 
+
     /* 関数を渡すとGPUで対応できないのでこれは却下
     // TにするとTで場合分けしてrawにわたすことになって，型の変換がunsafeになる
     // とりあえずのところ，f32
@@ -251,7 +257,7 @@ impl<const R: usize, const C: usize, T: Dtype> Tensor2d<R, C, T> {
             Storage::DenseBool(raw) => {
                 todo!()
             }
-            Storage::None => {
+            Storage::None | Storage::Denseu32(_) => {
                 LOGGER.error(format!("Tensor2d<{}, {}, {}>::transpose() >> Storage type expection. {} is not supported", R, C, T::type_name(), self.storage().info()));
                 panic!("")
             },
@@ -374,6 +380,15 @@ impl<const R: usize, const C: usize> Tensor2d<R, C, bool> {
             _marker: PhantomData,
         }
     }
+    // attention用。i行目はj <= iの要素だけtrue
+    pub fn new_causal_mask() -> Self {
+        let raw_bool = RawBool::new_from_vec((0..R * C).map(|i| i % C <= i / C).collect());
+        Self {
+            name: "bool new_causal_mask".to_string(),
+            storage: Storage::new_bools(raw_bool.body, raw_bool.len),
+            _marker: PhantomData,
+        }
+    }
     pub fn new_falses() -> Self {
         let len = (R * C) / 8 + 1;
         let bools: Vec<u8> = vec![0b0000_0000; len];
@@ -403,4 +418,17 @@ impl<const R: usize, const C: usize> Tensor2d<R, C, bool> {
             }
         }
     }
-}
\ No newline at end of file
+}
+// index tensor
+impl<const R: usize, const C: usize> Tensor2d<R, C, u32> {
+    pub fn new_from_indices(data: Vec<u32>) -> Result<Self, ()> {
+        if data.len() != R * C {
+            return Err(());
+        }
+        Ok(Self {
+            name: "indices".to_string(),
+            storage: Storage::new_u32(data),
+            _marker: PhantomData,
+        })
+    }
+}
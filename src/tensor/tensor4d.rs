@@ -0,0 +1,91 @@
+use std::{marker::PhantomData, sync::{Arc, RwLock, RwLockReadGuard}};
+
+use crate::{backend_cpu::RawDense, dtype::{Dtype, Shape}, logger::LOGGER};
+
+use super::{Tensor, Storage};
+
+use colored::Colorize;
+use rand::distributions::{Distribution, Uniform};
+
+
+// N: batch, C: channel, H: height, W: width
+#[derive(Debug, Clone)]
+pub struct Tensor4d<const N: usize, const C: usize, const H: usize, const W: usize, T> {
+    pub name: String,
+    pub storage: Arc<RwLock<Storage>>,
+    pub _marker: PhantomData<T>,
+}
+
+impl<const N: usize, const C: usize, const H: usize, const W: usize, T: Dtype> Tensor4d<N, C, H, W, T> {
+    pub fn new_zeros() -> Self {
+        if T::type_name() == "f32" {
+            Self {
+                name: "no_name".to_string(),
+                storage: Storage::new_f32(vec![0.0; N * C * H * W]),
+                _marker: PhantomData,
+            }
+        } else {
+            LOGGER.error(format!("{}::{}() >> not suppoerted T", Self::type_name().green(), "new_zeros".yellow()));
+            panic!();
+        }
+    }
+
+    pub fn new_ones() -> Self {
+        if T::type_name() == "f32" {
+            Self {
+                name: "no_name".to_string(),
+                storage: Storage::new_f32(vec![1.0; N * C * H * W]),
+                _marker: PhantomData,
+            }
+        } else {
+            LOGGER.error(format!("{}::{}() >> not suppoerted T", Self::type_name().green(), "new_ones".yellow()));
+            panic!();
+        }
+    }
+
+    pub fn type_name() -> String {
+        format!("Tensor4d<{}, {}, {}, {}, {}>", N, C, H, W, T::type_name())
+    }
+
+    pub fn storage(&self) -> RwLockReadGuard<'_, Storage> {
+        self.storage.read().unwrap()
+    }
+
+    pub fn to_untyped(&self) -> Tensor {
+        Tensor {
+            name: self.name.clone(),
+            shape: Shape::D4(N, C, H, W),
+            storage: self.storage.clone(),
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+}
+
+impl<const N: usize, const C: usize, const H: usize, const W: usize> Tensor4d<N, C, H, W, f32> {
+    pub fn new_from_vec(data: Vec<f32>) -> Result<Self, ()> {
+        if data.len() != N * C * H * W {
+            return Err(());
+        }
+        Ok(Self {
+            name: String::new(),
+            storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: data }))),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn new_uniform(low: f32, high: f32) -> Self {
+        let mut rng = rand::thread_rng();
+        let uniform = Uniform::new(low, high);
+        let random: Vec<f32> = (0..N * C * H * W).map(|_| uniform.sample(&mut rng)).collect();
+
+        Self {
+            name: "created by new_uniform()".to_string(),
+            storage: Arc::new(RwLock::new(Storage::Densef32(RawDense { body: random }))),
+            _marker: PhantomData,
+        }
+    }
+}
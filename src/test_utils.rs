@@ -0,0 +1,183 @@
+use std::{collections::HashSet, marker::PhantomData};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{autograd::{Autograd, Mode, VarStore}, dtype::Shape, fn_edge::{FnEdge, FnEdgeID, HumanCreatedFnEdge}, nten::{get_new_nten_id, Nten, Nten4d}, tensor::Tensor};
+
+/*
+テスト用のヘルパー
+
+gradcheck(): 有限差分（中心差分）でbackwardの勾配を検査する
+lossは出力に固定の重みをかけて足したもの。重みをbackward_with()に渡せば解析的な勾配になる
+gradgradcheck(): grad_graph()で組み立てた勾配をさらにbackwardした2階の微分を同じように検査する
+*/
+
+// seedごとに同じ値になる[-1, 1)の一様乱数
+pub fn sample(shape: Shape, seed: u64) -> Tensor {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let body = (0..shape.numel()).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    Tensor::new_from_vec(body, shape).unwrap()
+}
+
+pub fn assert_close(actual: &[f32], expected: &[f32], tol: f32) {
+    assert_eq!(actual.len(), expected.len(), "length unmatched");
+    for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+        assert!((a - e).abs() <= tol * e.abs().max(1.0), "index {}: {} != {}", i, a, e);
+    }
+}
+
+// paramsを"p0", "p1", ...という名前のparameterにしてfに渡し，出力を返す
+pub fn forward(params: &[Tensor], f: impl Fn(&mut VarStore, &[Nten]) -> Nten) -> Tensor {
+    forward_with(params, Mode::Train, f)
+}
+
+pub fn forward_with(params: &[Tensor], mode: Mode, f: impl Fn(&mut VarStore, &[Nten]) -> Nten) -> Tensor {
+    let mut ag = Autograd::new();
+    ag.manual_seed(0);
+    if mode == Mode::Inference {
+        ag.eval();
+    }
+    let mut vs = ag.get_vs();
+    let ntens = as_parameters(&mut vs, params);
+    let [result] = ag.step_forward([f(&mut vs, &ntens)]);
+    result.val.unwrap()
+}
+
+// returns (loss, parameterの勾配)
+fn loss_and_grads(params: &[Tensor], mode: Mode, f: &dyn Fn(&mut VarStore, &[Nten]) -> Nten) -> (f64, Vec<Tensor>) {
+    let mut ag = Autograd::new();
+    ag.manual_seed(0);
+    if mode == Mode::Inference {
+        ag.eval();
+    }
+    let mut vs = ag.get_vs();
+    let ntens = as_parameters(&mut vs, params);
+    let [result] = ag.step_forward([f(&mut vs, &ntens)]);
+
+    let weight = sample(result.shape, 7);
+    let loss = result.val.as_ref().unwrap().to_vec_f32().iter().zip(weight.to_vec_f32())
+        .map(|(y, w)| (*y as f64) * (w as f64))
+        .sum();
+    let ctx = ag.backward_with(&result, weight);
+    let grads = ntens.iter()
+        .map(|nten| ctx.try_get_grad(&nten.id).unwrap_or_else(|| Tensor::new_zeros::<f32>(nten.shape)))
+        .collect();
+    (loss, grads)
+}
+
+pub fn gradcheck(params: &[Tensor], f: impl Fn(&mut VarStore, &[Nten]) -> Nten) {
+    gradcheck_with(params, 1e-2, 1e-2, Mode::Train, f)
+}
+
+pub fn gradcheck_with(params: &[Tensor], eps: f32, tol: f32, mode: Mode, f: impl Fn(&mut VarStore, &[Nten]) -> Nten) {
+    let (_, grads) = loss_and_grads(params, mode, &f);
+    for (p, grad) in grads.iter().enumerate() {
+        let grad = grad.to_vec_f32();
+        for i in 0..grad.len() {
+            let shifted = |delta: f32| {
+                let mut params = params.to_vec();
+                let mut body = params[p].to_vec_f32();
+                body[i] += delta;
+                params[p] = Tensor::new_from_vec(body, params[p].shape).unwrap();
+                loss_and_grads(&params, mode, &f).0
+            };
+            let numerical = ((shifted(eps) - shifted(-eps)) / (2.0 * eps as f64)) as f32;
+            assert!((numerical - grad[i]).abs() <= tol * numerical.abs().max(1.0),
+                "param {} index {}: numerical {} but backward {}", p, i, numerical, grad[i]);
+        }
+    }
+}
+
+// returns (sum_p <g_p, v_p>, parameterの勾配)。g_pはlossのparameter pに関する勾配をgrad_graph()で組み立てたもの
+fn grad_loss_and_grads<const N: usize>(params: &[Tensor; N], f: &dyn Fn(&mut VarStore, &[Nten]) -> Nten) -> (f64, Vec<Tensor>) {
+    let mut ag = Autograd::new();
+    let mut vs = ag.get_vs();
+    let ntens = as_parameters(&mut vs, params);
+    let [result] = ag.step_forward([f(&mut vs, &ntens)]);
+
+    let result = Nten { grad: Some(sample(result.shape, 7)), ..result };
+    let grads: [Nten; N] = ag.grad_graph(&result, std::array::from_fn(|p| ntens[p].id));
+    let grads = ag.step_forward(grads);
+
+    let weights: [Tensor; N] = std::array::from_fn(|p| sample(params[p].shape, 11 + p as u64));
+    let loss = grads.iter().zip(weights.iter())
+        .flat_map(|(grad, w)| grad.val.as_ref().unwrap().to_vec_f32().into_iter().zip(w.to_vec_f32()))
+        .map(|(g, w)| (g as f64) * (w as f64))
+        .sum();
+    let roots: [Nten; N] = std::array::from_fn(|p| Nten { grad: Some(weights[p].clone()), ..grads[p].clone() });
+    let ctx = ag.backward_roots(roots.each_ref());
+    let grads = ntens.iter()
+        .map(|nten| ctx.try_get_grad(&nten.id).unwrap_or_else(|| Tensor::new_zeros::<f32>(nten.shape)))
+        .collect();
+    (loss, grads)
+}
+
+pub fn gradgradcheck<const N: usize>(params: &[Tensor; N], f: impl Fn(&mut VarStore, &[Nten]) -> Nten) {
+    let (_, grads) = grad_loss_and_grads(params, &f);
+    let mut nonzero = false;
+    for (p, grad) in grads.iter().enumerate() {
+        let grad = grad.to_vec_f32();
+        for i in 0..grad.len() {
+            let shifted = |delta: f32| {
+                let mut params = params.clone();
+                let mut body = params[p].to_vec_f32();
+                body[i] += delta;
+                params[p] = Tensor::new_from_vec(body, params[p].shape).unwrap();
+                grad_loss_and_grads(&params, &f).0
+            };
+            let eps = 1e-2;
+            let numerical = ((shifted(eps) - shifted(-eps)) / (2.0 * eps as f64)) as f32;
+            assert!((numerical - grad[i]).abs() <= 1e-2 * numerical.abs().max(1.0),
+                "param {} index {}: numerical {} but double backward {}", p, i, numerical, grad[i]);
+            nonzero |= numerical.abs() > 1e-3;
+        }
+    }
+    // 2階の微分がすべて0だと何も検査していない
+    assert!(nonzero, "second derivative is zero everywhere");
+}
+
+// paramsを"p0", "p1", ...という名前のparameterにする
+pub fn as_parameters(vs: &mut VarStore, params: &[Tensor]) -> Vec<Nten> {
+    params.iter().enumerate().map(|(i, val)| {
+        let nten = Nten {
+            id: get_new_nten_id(),
+            name: format!("p{}", i),
+            creator: Box::new(HumanCreatedFnEdge::new()),
+            shape: val.shape,
+            val: Some(val.clone()),
+            grad: None,
+        };
+        vs.resister_parameter(nten.clone()).unwrap();
+        nten
+    }).collect()
+}
+
+// gradcheckのparameterを型付きにする
+pub fn typed4d<const N: usize, const C: usize, const H: usize, const W: usize>(nten: &Nten) -> Nten4d<N, C, H, W, f32> {
+    Nten4d {
+        id: nten.id,
+        name: nten.name.clone(),
+        creator: nten.creator.clone(),
+        val: nten.val.as_ref().map(|val| val.to_typed4d().unwrap()),
+        grad: None,
+        _marker: PhantomData,
+    }
+}
+
+// Autograd::_build_tape()と同じく，sourcesが先になる順のテープ
+pub fn tape_of(results: &[Nten]) -> Vec<Box<dyn FnEdge>> {
+    fn visit(fn_edge: Box<dyn FnEdge>, seen: &mut HashSet<FnEdgeID>, tape: &mut Vec<Box<dyn FnEdge>>) {
+        if !seen.insert(fn_edge.get_id()) {
+            return;
+        }
+        for source in fn_edge.sources() {
+            visit(source, seen, tape);
+        }
+        tape.push(fn_edge);
+    }
+    let (mut seen, mut tape) = (HashSet::new(), Vec::new());
+    for result in results {
+        visit(result.creator.clone(), &mut seen, &mut tape);
+    }
+    tape
+}